@@ -0,0 +1,45 @@
+//! Criterion benchmarks of the PostgreSQL encoding and JSON cleaning that
+//! every import runs, on the sample records `bookdata bench` uses.
+//!
+//! Run with `cargo bench`.
+use std::io;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use bookdata::cleaning::{write_pgencoded, validate_json, clean_json};
+
+/// Sample OpenLibrary dump records.
+const SAMPLE: &str = include_str!("../src/commands/bench-sample.txt");
+
+fn bench_pg_encode(c: &mut Criterion) {
+  let lines: Vec<&str> = SAMPLE.lines().collect();
+  let mut group = c.benchmark_group("pg-encode");
+  group.throughput(Throughput::Bytes(SAMPLE.len() as u64));
+  group.bench_function("sample", |b| b.iter(|| {
+    let mut out = io::sink();
+    for l in &lines {
+      write_pgencoded(&mut out, l.as_bytes()).unwrap();
+    }
+  }));
+  group.finish();
+}
+
+fn bench_json_clean(c: &mut Criterion) {
+  let json: Vec<&str> = SAMPLE.lines().filter_map(|l| l.rsplit('\t').next()).collect();
+  let mut group = c.benchmark_group("json-clean");
+  group.throughput(Throughput::Elements(json.len() as u64));
+  group.bench_function("sample", |b| b.iter(|| {
+    let mut buf = String::new();
+    let mut js = String::new();
+    for j in &json {
+      js.clear();
+      js.push_str(j);
+      validate_json(&mut js).unwrap();
+      clean_json(&js, &mut buf);
+    }
+  }));
+  group.finish();
+}
+
+criterion_group!(benches, bench_pg_encode, bench_json_clean);
+criterion_main!(benches);
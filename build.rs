@@ -0,0 +1,56 @@
+//! Build script recording where the binary came from, for `bookdata --version`.
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+include!("src/date.rs");
+
+/// Get the Git commit being built, marked `-dirty` if there are uncommitted changes.
+fn git_commit() -> String {
+  let out = match Command::new("git").args(["rev-parse", "HEAD"]).output() {
+    Ok(o) if o.status.success() => o,
+    _ => return "unknown".to_string()
+  };
+  let mut commit = String::from_utf8_lossy(&out.stdout).trim().to_string();
+  let clean = Command::new("git").args(["diff", "--quiet", "HEAD"]).status();
+  if let Ok(s) = clean {
+    if !s.success() {
+      commit.push_str("-dirty");
+    }
+  }
+  commit
+}
+
+/// Get the build date (UTC) as YYYY-MM-DD, honoring `SOURCE_DATE_EPOCH` for
+/// reproducible builds.
+fn build_date() -> String {
+  let secs = match env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok()) {
+    Some(s) => s,
+    None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+  };
+  let (year, month, day) = civil_date((secs / 86400) as i64);
+  format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Get the enabled Cargo features.
+fn features() -> String {
+  let mut feats: Vec<String> = env::vars()
+    .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase().replace('_', "-")))
+    .collect();
+  feats.sort();
+  if feats.is_empty() {
+    "none".to_string()
+  } else {
+    feats.join(", ")
+  }
+}
+
+fn main() {
+  println!("cargo:rustc-env=BOOKDATA_GIT_COMMIT={}", git_commit());
+  println!("cargo:rustc-env=BOOKDATA_BUILD_DATE={}", build_date());
+  println!("cargo:rustc-env=BOOKDATA_FEATURES={}", features());
+  println!("cargo:rerun-if-changed=.git/HEAD");
+  println!("cargo:rerun-if-changed=.git/index");
+  println!("cargo:rerun-if-changed=src");
+  println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -2,6 +2,8 @@
 --- #table viaf.record_codes
 --- #table viaf.author_name
 --- #table viaf.author_gender
+--- #table viaf.author_source_id
+--- #table viaf.author_dates
 --- #step Index MARC record IDs
 CREATE INDEX If NOT EXISTS marc_field_rec_idx ON viaf.marc_field (rec_id);
 ANALYZE viaf.marc_field;
@@ -51,6 +53,25 @@ CREATE MATERIALIZED VIEW IF NOT EXISTS viaf.author_gender
   WHERE TAG = '375' AND sf_code = 'a';
 CREATE INDEX IF NOT EXISTS gender_rec_idx ON viaf.author_gender (rec_id);
 
+--- #step Extract linked source identifiers
+CREATE MATERIALIZED VIEW IF NOT EXISTS viaf.author_source_id
+  AS SELECT rec_id, fld_no,
+       substring(contents from '^\(([^)]+)\)') AS source,
+       trim(regexp_replace(contents, '^\([^)]*\)', '')) AS source_id
+  FROM viaf.marc_field
+  WHERE TAG = '700' AND sf_code = '0';
+CREATE INDEX IF NOT EXISTS source_id_rec_idx ON viaf.author_source_id (rec_id);
+CREATE INDEX IF NOT EXISTS source_id_idx ON viaf.author_source_id (source, source_id);
+ANALYZE viaf.author_source_id;
+
+--- #step Extract author dates
+CREATE MATERIALIZED VIEW IF NOT EXISTS viaf.author_dates
+  AS SELECT rec_id, fld_no, regexp_replace(contents, '[.,]+$', '') AS dates
+  FROM viaf.marc_field
+  WHERE TAG = '700' AND sf_code = 'd';
+CREATE INDEX IF NOT EXISTS dates_rec_idx ON viaf.author_dates (rec_id);
+ANALYZE viaf.author_dates;
+
 -- CREATE INDEX viaf_author_name_id_idx ON viaf.author_name (viaf_au_id);
 -- CREATE INDEX viaf_author_name_idx ON viaf.author_name (viaf_au_name);
 -- ALTER TABLE viaf.author_name ADD CONSTRAINT viaf_au_name_fk FOREIGN KEY (viaf_au_id) REFERENCES viaf_author;
@@ -1,5 +1,6 @@
 --- #dep common-schema
 --- #table bx.raw_ratings
+--- #table bx.raw_books
 CREATE SCHEMA IF NOT EXISTS bx;
 
 DROP TABLE IF EXISTS bx.raw_ratings CASCADE;
@@ -8,3 +9,15 @@ CREATE TABLE bx.raw_ratings (
   isbn VARCHAR NOT NULL,
   rating REAL NOT NULL
 );
+
+DROP TABLE IF EXISTS bx.raw_books CASCADE;
+CREATE TABLE bx.raw_books (
+  isbn VARCHAR NOT NULL,
+  title VARCHAR,
+  author VARCHAR,
+  pub_year VARCHAR,
+  publisher VARCHAR,
+  image_url_s VARCHAR,
+  image_url_m VARCHAR,
+  image_url_l VARCHAR
+);
@@ -0,0 +1,22 @@
+--- #dep common-schema
+--- #table crossref.work
+--- #table crossref.work_isbn
+CREATE SCHEMA IF NOT EXISTS crossref;
+
+-- Book and monograph works from a Crossref snapshot (`import/crossref-works.toml`)
+DROP TABLE IF EXISTS crossref.work CASCADE;
+CREATE TABLE crossref.work (
+  cr_work_rid SERIAL NOT NULL,
+  cr_work_data JSONB NOT NULL,
+  doi VARCHAR NOT NULL,
+  work_type VARCHAR NOT NULL,
+  title VARCHAR[],
+  authors JSONB,
+  editors JSONB
+);
+
+DROP TABLE IF EXISTS crossref.work_isbn CASCADE;
+CREATE TABLE crossref.work_isbn (
+  doi VARCHAR NOT NULL,
+  isbn VARCHAR NOT NULL
+);
@@ -5,6 +5,10 @@
 --- #table gr.raw_author
 --- #table gr.raw_series
 --- #table gr.raw_book_genres
+--- #table gr.book_work_link
+--- #table gr.book_isbn_link
+--- #table gr.book_author_link
+--- #table gr.review
 
 DROP SCHEMA IF EXISTS gr CASCADE;
 CREATE SCHEMA gr;
@@ -33,3 +37,30 @@ CREATE TABLE gr.raw_book_genres (
   gr_book_genres_rid SERIAL NOT NULL,
   gr_book_genres_data JSONB NOT NULL
 );
+CREATE TABLE gr.book_work_link (
+  gr_book_id INTEGER NOT NULL,
+  gr_work_id INTEGER NOT NULL
+);
+CREATE TABLE gr.book_isbn_link (
+  gr_book_id INTEGER NOT NULL,
+  isbn VARCHAR NOT NULL
+);
+CREATE TABLE gr.book_author_link (
+  gr_book_id INTEGER NOT NULL,
+  gr_author_id INTEGER NOT NULL,
+  author_pos INTEGER NOT NULL
+);
+CREATE TABLE gr.review (
+  review_id VARCHAR NOT NULL,
+  gr_user_id VARCHAR NOT NULL,
+  gr_book_id INTEGER NOT NULL,
+  rating SMALLINT,
+  review_text TEXT NOT NULL,
+  review_lang VARCHAR,
+  date_added TIMESTAMP WITH TIME ZONE,
+  date_updated TIMESTAMP WITH TIME ZONE,
+  read_at TIMESTAMP WITH TIME ZONE,
+  started_at TIMESTAMP WITH TIME ZONE,
+  n_votes INTEGER,
+  n_comments INTEGER
+);
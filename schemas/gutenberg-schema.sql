@@ -0,0 +1,48 @@
+--- #dep common-schema
+--- #table gutenberg.ebook
+--- #table gutenberg.ebook_author
+--- #table gutenberg.ebook_language
+--- #table gutenberg.ebook_subject
+--- #table gutenberg.ebook_ids
+CREATE SCHEMA IF NOT EXISTS gutenberg;
+
+-- Ebooks in the Project Gutenberg catalog, loaded by `bookdata import-gutenberg`
+DROP TABLE IF EXISTS gutenberg.ebook CASCADE;
+CREATE TABLE gutenberg.ebook (
+  gutenberg_id INTEGER NOT NULL,
+  title VARCHAR,
+  ebook_type VARCHAR,
+  issued VARCHAR
+);
+
+DROP TABLE IF EXISTS gutenberg.ebook_author CASCADE;
+CREATE TABLE gutenberg.ebook_author (
+  gutenberg_id INTEGER NOT NULL,
+  author_pos INTEGER NOT NULL,
+  agent_id INTEGER,
+  author_name VARCHAR,
+  birth_year INTEGER,
+  death_year INTEGER
+);
+
+DROP TABLE IF EXISTS gutenberg.ebook_language CASCADE;
+CREATE TABLE gutenberg.ebook_language (
+  gutenberg_id INTEGER NOT NULL,
+  language VARCHAR NOT NULL
+);
+
+-- Subjects, with their vocabulary (LCSH or LCC)
+DROP TABLE IF EXISTS gutenberg.ebook_subject CASCADE;
+CREATE TABLE gutenberg.ebook_subject (
+  gutenberg_id INTEGER NOT NULL,
+  scheme VARCHAR,
+  subject VARCHAR NOT NULL
+);
+
+-- Other identifiers (ISBNs and LCCNs) from the catalog's MARC fields
+DROP TABLE IF EXISTS gutenberg.ebook_ids CASCADE;
+CREATE TABLE gutenberg.ebook_ids (
+  gutenberg_id INTEGER NOT NULL,
+  id_type VARCHAR NOT NULL,
+  id_value VARCHAR NOT NULL
+);
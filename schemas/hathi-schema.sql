@@ -0,0 +1,41 @@
+--- #dep common-schema
+--- #table hathi.volume
+--- #table hathi.volume_oclc
+--- #table hathi.volume_isbn
+--- #table hathi.volume_lccn
+CREATE SCHEMA IF NOT EXISTS hathi;
+
+-- HathiTrust volumes, loaded from the Hathifiles by `bookdata import-hathifiles`
+DROP TABLE IF EXISTS hathi.volume CASCADE;
+CREATE TABLE hathi.volume (
+  htid VARCHAR NOT NULL,
+  access VARCHAR NOT NULL,
+  rights VARCHAR NOT NULL,
+  ht_bib_key VARCHAR NOT NULL,
+  description VARCHAR,
+  title VARCHAR,
+  imprint VARCHAR,
+  rights_date INTEGER,
+  pub_place VARCHAR,
+  lang VARCHAR,
+  bib_fmt VARCHAR,
+  author VARCHAR
+);
+
+DROP TABLE IF EXISTS hathi.volume_oclc CASCADE;
+CREATE TABLE hathi.volume_oclc (
+  htid VARCHAR NOT NULL,
+  oclc_number BIGINT NOT NULL
+);
+
+DROP TABLE IF EXISTS hathi.volume_isbn CASCADE;
+CREATE TABLE hathi.volume_isbn (
+  htid VARCHAR NOT NULL,
+  isbn VARCHAR NOT NULL
+);
+
+DROP TABLE IF EXISTS hathi.volume_lccn CASCADE;
+CREATE TABLE hathi.volume_lccn (
+  htid VARCHAR NOT NULL,
+  lccn VARCHAR NOT NULL
+);
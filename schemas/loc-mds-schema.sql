@@ -1,6 +1,8 @@
 --- #dep common-schema
 --- #table locmds.book_marc_field
+--- #table locmds.book_oclc
 --- #table locmds.name_marc_field
+--- #table locmds.name_years
 
 CREATE SCHEMA IF NOT EXISTS locmds;
 
@@ -28,6 +30,13 @@ CREATE TABLE locmds.book_extracted_isbn (
   isbn_tag VARCHAR
 );
 
+-- Filled by `bookdata extract-oclc` from the 035 fields
+DROP TABLE IF EXISTS locmds.book_oclc CASCADE;
+CREATE TABLE locmds.book_oclc (
+  rec_id INTEGER NOT NULL,
+  oclc_number BIGINT NOT NULL
+);
+
 DROP TABLE IF EXISTS locmds.name_marc_field CASCADE;
 CREATE TABLE locmds.name_marc_field (
   rec_id INTEGER NOT NULL,
@@ -38,3 +47,11 @@ CREATE TABLE locmds.name_marc_field (
   sf_code VARCHAR,
   contents VARCHAR
 );
+
+-- Filled by `bookdata author-years` from the 046 and heading $d fields
+DROP TABLE IF EXISTS locmds.name_years CASCADE;
+CREATE TABLE locmds.name_years (
+  rec_id INTEGER NOT NULL,
+  birth_year INTEGER,
+  death_year INTEGER
+);
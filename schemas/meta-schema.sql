@@ -32,6 +32,30 @@ CREATE TABLE IF NOT EXISTS stage_table (
     st_name VARCHAR NOT NULL
 );
 
+CREATE TABLE IF NOT EXISTS import_log (
+    import_id SERIAL PRIMARY KEY,
+    tool VARCHAR NOT NULL,
+    filename VARCHAR NOT NULL,
+    sha256 VARCHAR NULL,
+    stage_name VARCHAR NULL,
+    n_rows BIGINT NULL,
+    git_rev VARCHAR NULL,
+    run_secs DOUBLE PRECISION NULL,
+    finished_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS import_log_file_idx ON import_log (filename, tool);
+
+-- Older versions logged SHA-1 checksums; they cannot match the SHA-256 skip checks
+DO $ck$
+BEGIN
+    ALTER TABLE import_log RENAME COLUMN checksum TO sha256;
+    ALTER TABLE import_log ALTER COLUMN sha256 DROP NOT NULL;
+    UPDATE import_log SET sha256 = NULL;
+EXCEPTION
+    WHEN undefined_column THEN NULL;
+END;
+$ck$;
+
 DO $cv$
 BEGIN
     CREATE VIEW stage_table_oids
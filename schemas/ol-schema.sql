@@ -2,6 +2,8 @@
 --- #table ol.author
 --- #table ol.work
 --- #table ol.edition
+--- #table ol.redirect
+--- #table ol.deleted
 
 -- Initial table creation with no constraints or indexes
 CREATE SCHEMA IF NOT EXISTS ol;
@@ -26,3 +28,48 @@ CREATE TABLE ol.edition (
     edition_key VARCHAR(100) NOT NULL,
     edition_data JSONB NOT NULL
 );
+
+-- Redirects and deletions from the full dump (import/ol-dump.toml)
+DROP TABLE IF EXISTS ol.redirect CASCADE;
+CREATE TABLE ol.redirect (
+    redirect_key VARCHAR(100) NOT NULL,
+    target_key VARCHAR(100)
+);
+
+DROP TABLE IF EXISTS ol.deleted CASCADE;
+CREATE TABLE ol.deleted (
+    deleted_key VARCHAR(100) NOT NULL
+);
+
+-- Key links extracted while importing (the [[links]] in import/*.toml)
+DROP TABLE IF EXISTS ol.edition_work_keys CASCADE;
+CREATE TABLE ol.edition_work_keys (
+    edition_key VARCHAR(100) NOT NULL,
+    work_key VARCHAR(100) NOT NULL
+);
+
+DROP TABLE IF EXISTS ol.edition_author_keys CASCADE;
+CREATE TABLE ol.edition_author_keys (
+    edition_key VARCHAR(100) NOT NULL,
+    author_key VARCHAR(100) NOT NULL,
+    author_pos INTEGER NOT NULL
+);
+
+DROP TABLE IF EXISTS ol.work_author_keys CASCADE;
+CREATE TABLE ol.work_author_keys (
+    work_key VARCHAR(100) NOT NULL,
+    author_key VARCHAR(100) NOT NULL,
+    author_pos INTEGER NOT NULL
+);
+
+DROP TABLE IF EXISTS ol.edition_isbn_keys CASCADE;
+CREATE TABLE ol.edition_isbn_keys (
+    edition_key VARCHAR(100) NOT NULL,
+    isbn VARCHAR(20) NOT NULL
+);
+
+DROP TABLE IF EXISTS ol.edition_oclc_numbers CASCADE;
+CREATE TABLE ol.edition_oclc_numbers (
+    edition_key VARCHAR(100) NOT NULL,
+    oclc_number BIGINT NOT NULL
+);
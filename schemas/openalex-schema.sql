@@ -0,0 +1,29 @@
+--- #dep common-schema
+--- #table openalex.work
+--- #table openalex.work_doi
+--- #table openalex.work_author
+CREATE SCHEMA IF NOT EXISTS openalex;
+
+-- Book and monograph works from an OpenAlex snapshot (`import/openalex-works.toml`)
+DROP TABLE IF EXISTS openalex.work CASCADE;
+CREATE TABLE openalex.work (
+  oa_work_rid SERIAL NOT NULL,
+  oa_work_data JSONB NOT NULL,
+  oa_work_id VARCHAR NOT NULL,
+  work_type VARCHAR NOT NULL,
+  title VARCHAR,
+  authorships JSONB
+);
+
+DROP TABLE IF EXISTS openalex.work_doi CASCADE;
+CREATE TABLE openalex.work_doi (
+  oa_work_id VARCHAR NOT NULL,
+  doi VARCHAR NOT NULL
+);
+
+DROP TABLE IF EXISTS openalex.work_author CASCADE;
+CREATE TABLE openalex.work_author (
+  oa_work_id VARCHAR NOT NULL,
+  oa_author_id VARCHAR NOT NULL,
+  author_pos INTEGER NOT NULL
+);
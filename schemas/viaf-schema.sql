@@ -1,5 +1,6 @@
 --- #dep common-schema
 --- #table viaf.marc_field
+--- #table viaf.author_years
 
 --- #step Create VIAF schema
 CREATE SCHEMA IF NOT EXISTS viaf;
@@ -39,3 +40,11 @@ CREATE TABLE viaf.marc_field (
 --   viaf_au_gender_end VARCHAR NULL,
 --   viaf_au_gender_source VARCHAR NULL
 -- );
+
+-- Filled by `bookdata author-years` from the 046 and heading $d fields
+DROP TABLE IF EXISTS viaf.author_years CASCADE;
+CREATE TABLE viaf.author_years (
+  rec_id INTEGER NOT NULL,
+  birth_year INTEGER,
+  death_year INTEGER
+);
@@ -0,0 +1,19 @@
+--- #dep common-schema
+--- #table wikidata.entity_ids
+--- #table wikidata.entity_labels
+CREATE SCHEMA IF NOT EXISTS wikidata;
+
+-- Identifier statements of the entities kept by `bookdata import-wikidata`
+DROP TABLE IF EXISTS wikidata.entity_ids CASCADE;
+CREATE TABLE wikidata.entity_ids (
+  entity_id VARCHAR NOT NULL,
+  property VARCHAR NOT NULL,
+  value VARCHAR NOT NULL
+);
+
+DROP TABLE IF EXISTS wikidata.entity_labels CASCADE;
+CREATE TABLE wikidata.entity_labels (
+  entity_id VARCHAR NOT NULL,
+  lang VARCHAR NOT NULL,
+  label VARCHAR NOT NULL
+);
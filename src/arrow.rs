@@ -0,0 +1,470 @@
+//! Write tables as Arrow IPC streams.
+//!
+//! A stream is a schema message followed by record batch messages, each a
+//! FlatBuffers-encoded header followed by the batch's column buffers, and
+//! ends with an end-of-stream marker.  This implements just the parts of
+//! FlatBuffers that the message headers need.
+use std::io::Write;
+
+use anyhow::{Result, anyhow};
+
+use crate::columnar::{ColType, Value};
+
+/// Number of rows to buffer in each record batch.
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Bytes of text to buffer in a column before writing a batch early, to keep
+/// its offsets in range.
+const BATCH_BYTES: usize = 1 << 30;
+
+/// Arrow metadata version 5.
+const METADATA_V5: i16 = 4;
+
+/// Message header types.
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+
+/// A field of a FlatBuffers table.
+enum Slot {
+  Absent,
+  U8(u8),
+  Bool(bool),
+  I16(i16),
+  I32(i32),
+  I64(i64),
+  Ref(Obj)
+}
+
+/// A FlatBuffers object, stored out of line and referenced by offset.
+enum Obj {
+  Table(Vec<Slot>),
+  Str(String),
+  Tables(Vec<Obj>),
+  /// A vector of structs holding two `i64`s
+  Pairs(Vec<(i64, i64)>)
+}
+
+/// Encode a FlatBuffer front to back, writing each object before the objects
+/// it refers to and filling in the offsets after.
+struct FbBuilder {
+  buf: Vec<u8>
+}
+
+impl FbBuilder {
+  fn encode(root: Obj) -> Vec<u8> {
+    let mut fb = FbBuilder { buf: vec![0; 4] };
+    let pos = fb.write(root);
+    fb.patch(0, pos);
+    fb.pad(8);
+    fb.buf
+  }
+
+  fn pad(&mut self, align: usize) {
+    while !self.buf.len().is_multiple_of(align) {
+      self.buf.push(0);
+    }
+  }
+
+  /// Fill in the offset at `at` to point to `target`.
+  fn patch(&mut self, at: usize, target: usize) {
+    let off = (target - at) as u32;
+    self.buf[at..at + 4].copy_from_slice(&off.to_le_bytes());
+  }
+
+  /// Write an object, returning its position.
+  fn write(&mut self, obj: Obj) -> usize {
+    match obj {
+      Obj::Table(slots) => self.write_table(slots),
+      Obj::Str(s) => {
+        self.pad(4);
+        let pos = self.buf.len();
+        self.buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+        pos
+      },
+      Obj::Tables(objs) => {
+        self.pad(4);
+        let pos = self.buf.len();
+        self.buf.extend_from_slice(&(objs.len() as u32).to_le_bytes());
+        let start = self.buf.len();
+        self.buf.resize(start + 4 * objs.len(), 0);
+        for (i, obj) in objs.into_iter().enumerate() {
+          let target = self.write(obj);
+          self.patch(start + 4 * i, target);
+        }
+        pos
+      },
+      Obj::Pairs(pairs) => {
+        // the structs are 8-byte aligned, after the length
+        while !(self.buf.len() + 4).is_multiple_of(8) {
+          self.buf.push(0);
+        }
+        let pos = self.buf.len();
+        self.buf.extend_from_slice(&(pairs.len() as u32).to_le_bytes());
+        for (a, b) in pairs {
+          self.buf.extend_from_slice(&a.to_le_bytes());
+          self.buf.extend_from_slice(&b.to_le_bytes());
+        }
+        pos
+      }
+    }
+  }
+
+  /// Write a table and its vtable, which comes first.
+  fn write_table(&mut self, slots: Vec<Slot>) -> usize {
+    self.pad(2);
+    let vtable = self.buf.len();
+    let vt_len = 4 + 2 * slots.len();
+    self.buf.resize(vtable + vt_len, 0);
+    self.pad(8);
+    let table = self.buf.len();
+    self.buf.extend_from_slice(&((table - vtable) as i32).to_le_bytes());
+
+    let mut offsets = Vec::with_capacity(slots.len());
+    let mut refs = Vec::new();
+    for slot in slots {
+      let size = match slot {
+        Slot::Absent => 0,
+        Slot::U8(_) | Slot::Bool(_) => 1,
+        Slot::I16(_) => 2,
+        Slot::I32(_) | Slot::Ref(_) => 4,
+        Slot::I64(_) => 8
+      };
+      if size == 0 {
+        offsets.push(0u16);
+        continue;
+      }
+      self.pad(size);
+      offsets.push((self.buf.len() - table) as u16);
+      match slot {
+        Slot::Absent => (),
+        Slot::U8(v) => self.buf.push(v),
+        Slot::Bool(v) => self.buf.push(v as u8),
+        Slot::I16(v) => self.buf.extend_from_slice(&v.to_le_bytes()),
+        Slot::I32(v) => self.buf.extend_from_slice(&v.to_le_bytes()),
+        Slot::I64(v) => self.buf.extend_from_slice(&v.to_le_bytes()),
+        Slot::Ref(obj) => {
+          refs.push((self.buf.len(), obj));
+          self.buf.extend_from_slice(&[0; 4]);
+        }
+      }
+    }
+    let table_len = self.buf.len() - table;
+
+    let mut vt = Vec::with_capacity(vt_len);
+    vt.extend_from_slice(&(vt_len as u16).to_le_bytes());
+    vt.extend_from_slice(&(table_len as u16).to_le_bytes());
+    for off in offsets {
+      vt.extend_from_slice(&off.to_le_bytes());
+    }
+    self.buf[vtable..vtable + vt_len].copy_from_slice(&vt);
+
+    for (at, obj) in refs {
+      let target = self.write(obj);
+      self.patch(at, target);
+    }
+    table
+  }
+}
+
+/// Get the Arrow type of a column, as its type union tag and type table.
+fn arrow_type(ct: ColType) -> (u8, Obj) {
+  match ct {
+    ColType::Text | ColType::Json => (5, Obj::Table(vec![])),
+    ColType::Bool => (6, Obj::Table(vec![])),
+    ColType::Int32 => (2, Obj::Table(vec![Slot::I32(32), Slot::Bool(true)])),
+    ColType::Int64 => (2, Obj::Table(vec![Slot::I32(64), Slot::Bool(true)])),
+    ColType::Float => (3, Obj::Table(vec![Slot::I16(1)])),
+    ColType::Double => (3, Obj::Table(vec![Slot::I16(2)])),
+    ColType::Date => (8, Obj::Table(vec![Slot::I16(0)])),
+    ColType::Timestamp => (10, Obj::Table(vec![Slot::I16(2), Slot::Ref(Obj::Str("UTC".to_string()))]))
+  }
+}
+
+/// Encode a message header.
+fn message(header_type: u8, header: Obj, body_len: usize) -> Vec<u8> {
+  FbBuilder::encode(Obj::Table(vec![
+    Slot::I16(METADATA_V5),
+    Slot::U8(header_type),
+    Slot::Ref(header),
+    Slot::I64(body_len as i64)
+  ]))
+}
+
+/// Append a bit to a bitmap.
+fn push_bit(bits: &mut Vec<u8>, i: usize, val: bool) {
+  if i.is_multiple_of(8) {
+    bits.push(0);
+  }
+  if val {
+    bits[i / 8] |= 1 << (i % 8);
+  }
+}
+
+/// The buffered values of a column in the current batch.
+struct Column {
+  ctype: ColType,
+  valid: Vec<u8>,
+  nulls: usize,
+  /// Text offsets, for text columns
+  offsets: Vec<u8>,
+  values: Vec<u8>
+}
+
+impl Column {
+  fn new(ctype: ColType) -> Column {
+    let mut col = Column { ctype, valid: Vec::new(), nulls: 0, offsets: Vec::new(), values: Vec::new() };
+    col.clear();
+    col
+  }
+
+  fn is_text(&self) -> bool {
+    self.ctype == ColType::Text || self.ctype == ColType::Json
+  }
+
+  fn clear(&mut self) {
+    self.valid.clear();
+    self.nulls = 0;
+    self.offsets.clear();
+    self.values.clear();
+    if self.is_text() {
+      self.offsets.extend_from_slice(&0i32.to_le_bytes());
+    }
+  }
+
+  fn end_text(&mut self) {
+    self.offsets.extend_from_slice(&(self.values.len() as i32).to_le_bytes());
+  }
+
+  /// Get the column's buffers.
+  fn buffers(&self) -> Vec<&[u8]> {
+    if self.is_text() {
+      vec![&self.valid, &self.offsets, &self.values]
+    } else {
+      vec![&self.valid, &self.values]
+    }
+  }
+}
+
+/// Writer for a table in an Arrow IPC stream.
+///
+/// Fields are written one at a time, and each row is closed with `end_row`,
+/// as with [TableWriter](crate::columnar::TableWriter).  Rows are buffered and
+/// written out in record batches, so a reader can start on the stream before
+/// it is finished.
+pub struct StreamWriter<W: Write> {
+  out: W,
+  columns: Vec<Column>,
+  field: usize,
+  rows: usize,
+  total: usize
+}
+
+impl <W: Write> StreamWriter<W> {
+  /// Create a stream writer with the given columns, and write the schema.
+  pub fn create(mut out: W, columns: &[(&str, ColType)]) -> Result<StreamWriter<W>> {
+    let fields = columns.iter().map(|(name, ct)| {
+      let (tag, ty) = arrow_type(*ct);
+      Obj::Table(vec![
+        Slot::Ref(Obj::Str(name.to_string())),
+        Slot::Bool(true),
+        Slot::U8(tag),
+        Slot::Ref(ty),
+        Slot::Absent,
+        Slot::Ref(Obj::Tables(vec![]))
+      ])
+    }).collect();
+    let schema = Obj::Table(vec![Slot::I16(0), Slot::Ref(Obj::Tables(fields))]);
+    write_message(&mut out, &message(HEADER_SCHEMA, schema, 0), &[])?;
+    Ok(StreamWriter {
+      out,
+      columns: columns.iter().map(|(_, ct)| Column::new(*ct)).collect(),
+      field: 0,
+      rows: 0,
+      total: 0
+    })
+  }
+
+  fn next_column(&mut self) -> Result<&mut Column> {
+    let row = self.total + 1;
+    self.columns.get_mut(self.field).ok_or_else(|| anyhow!("too many fields in row {}", row))
+  }
+
+  /// Write the next field of the current row to a text or JSON column.
+  pub fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.write_value(Value::Bytes(val))
+  }
+
+  /// Write the next field of the current row.  The value must match the
+  /// column's type.
+  pub fn write_value(&mut self, val: Value) -> Result<()> {
+    let (i, field, row) = (self.rows, self.field + 1, self.total + 1);
+    let col = self.next_column()?;
+    match (col.ctype, val) {
+      (ColType::Text, Value::Bytes(v)) | (ColType::Json, Value::Bytes(v)) => {
+        col.values.extend_from_slice(v);
+        col.end_text();
+      },
+      (ColType::Bool, Value::Bool(v)) => push_bit(&mut col.values, i, v),
+      (ColType::Int32, Value::Int32(v)) | (ColType::Date, Value::Int32(v)) => col.values.extend_from_slice(&v.to_le_bytes()),
+      (ColType::Int64, Value::Int64(v)) | (ColType::Timestamp, Value::Int64(v)) => col.values.extend_from_slice(&v.to_le_bytes()),
+      (ColType::Float, Value::Float(v)) => col.values.extend_from_slice(&v.to_le_bytes()),
+      (ColType::Double, Value::Double(v)) => col.values.extend_from_slice(&v.to_le_bytes()),
+      (_, v) => return Err(anyhow!("field {} of row {}: {:?} does not match column type", field, row, v))
+    }
+    push_bit(&mut col.valid, i, true);
+    self.field += 1;
+    Ok(())
+  }
+
+  /// Write the next field of the current row, converted to the column's type
+  /// with [ColType::cast].
+  pub fn write_cast(&mut self, val: Value) -> Result<()> {
+    let (field, row) = (self.field + 1, self.total + 1);
+    let ct = self.next_column()?.ctype;
+    let mut buf = Vec::new();
+    let val = ct.cast(val, &mut buf).map_err(|e| anyhow!("field {} of row {}: {}", field, row, e))?;
+    self.write_value(val)
+  }
+
+  /// Write a null as the next field of the current row.
+  pub fn write_null(&mut self) -> Result<()> {
+    let i = self.rows;
+    let col = self.next_column()?;
+    match col.ctype {
+      ColType::Text | ColType::Json => col.end_text(),
+      ColType::Bool => push_bit(&mut col.values, i, false),
+      ColType::Int32 | ColType::Date | ColType::Float => col.values.extend_from_slice(&[0; 4]),
+      ColType::Int64 | ColType::Timestamp | ColType::Double => col.values.extend_from_slice(&[0; 8])
+    }
+    push_bit(&mut col.valid, i, false);
+    col.nulls += 1;
+    self.field += 1;
+    Ok(())
+  }
+
+  /// Finish the current row.
+  pub fn end_row(&mut self) -> Result<()> {
+    if self.field != self.columns.len() {
+      return Err(anyhow!("row {} has {} fields, expected {}", self.total + 1, self.field, self.columns.len()));
+    }
+    self.field = 0;
+    self.rows += 1;
+    self.total += 1;
+    if self.rows >= BATCH_SIZE || self.columns.iter().any(|c| c.values.len() >= BATCH_BYTES) {
+      self.write_batch()?;
+    }
+    Ok(())
+  }
+
+  fn write_batch(&mut self) -> Result<()> {
+    let mut nodes = Vec::with_capacity(self.columns.len());
+    let mut buffers = Vec::new();
+    let mut body = Vec::new();
+    for col in &self.columns {
+      nodes.push((self.rows as i64, col.nulls as i64));
+      for buf in col.buffers() {
+        buffers.push((body.len() as i64, buf.len() as i64));
+        body.extend_from_slice(buf);
+        while !body.len().is_multiple_of(8) {
+          body.push(0);
+        }
+      }
+    }
+    let batch = Obj::Table(vec![
+      Slot::I64(self.rows as i64),
+      Slot::Ref(Obj::Pairs(nodes)),
+      Slot::Ref(Obj::Pairs(buffers))
+    ]);
+    write_message(&mut self.out, &message(HEADER_RECORD_BATCH, batch, body.len()), &body)?;
+    for col in &mut self.columns {
+      col.clear();
+    }
+    self.rows = 0;
+    Ok(())
+  }
+
+  /// Write any buffered rows and end the stream, returning the number of rows.
+  pub fn finish(mut self) -> Result<usize> {
+    if self.field > 0 {
+      return Err(anyhow!("unfinished row {}", self.total + 1));
+    }
+    if self.rows > 0 {
+      self.write_batch()?;
+    }
+    self.out.write_all(&[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0])?;
+    self.out.flush()?;
+    Ok(self.total)
+  }
+}
+
+/// Write an encapsulated message.
+fn write_message<W: Write>(out: &mut W, meta: &[u8], body: &[u8]) -> Result<()> {
+  out.write_all(&[0xFF, 0xFF, 0xFF, 0xFF])?;
+  out.write_all(&(meta.len() as i32).to_le_bytes())?;
+  out.write_all(meta)?;
+  out.write_all(body)?;
+  Ok(())
+}
+
+#[test]
+fn test_stream() {
+  // read FlatBuffers fields back, to check the encoding
+  let u32_at = |b: &[u8], p: usize| u32::from_le_bytes([b[p], b[p + 1], b[p + 2], b[p + 3]]) as usize;
+  let field = |b: &[u8], table: usize, id: usize| -> Option<usize> {
+    let vtable = table - u32_at(b, table);
+    let vt_len = u16::from_le_bytes([b[vtable], b[vtable + 1]]) as usize;
+    if 4 + 2 * id >= vt_len {
+      return None;
+    }
+    let off = u16::from_le_bytes([b[vtable + 4 + 2 * id], b[vtable + 5 + 2 * id]]) as usize;
+    if off == 0 { None } else { Some(table + off) }
+  };
+  let deref = |b: &[u8], p: usize| p + u32_at(b, p);
+
+  let mut buf = Vec::new();
+  let mut w = StreamWriter::create(&mut buf, &[("id", ColType::Int64), ("title", ColType::Text)]).unwrap();
+  w.write_value(Value::Int64(7)).unwrap();
+  w.write_field(b"Dune").unwrap();
+  w.end_row().unwrap();
+  w.write_value(Value::Int64(8)).unwrap();
+  w.write_null().unwrap();
+  w.end_row().unwrap();
+  assert!(w.write_field(b"9").is_err());
+  assert_eq!(w.finish().unwrap(), 2);
+
+  // the schema message
+  assert_eq!(&buf[..4], &[0xFF; 4]);
+  let len = u32_at(&buf, 4);
+  assert_eq!(len % 8, 0);
+  let meta = &buf[8..8 + len];
+  let msg = deref(meta, 0);
+  assert_eq!(meta[field(meta, msg, 1).unwrap()], HEADER_SCHEMA);
+  let schema = deref(meta, field(meta, msg, 2).unwrap());
+  let fields = deref(meta, field(meta, schema, 1).unwrap());
+  assert_eq!(u32_at(meta, fields), 2);
+  let title = deref(meta, fields + 8);
+  let name = deref(meta, field(meta, title, 0).unwrap());
+  assert_eq!(&meta[name + 4..name + 4 + u32_at(meta, name)], b"title");
+  assert_eq!(meta[field(meta, title, 2).unwrap()], 5);
+
+  // the record batch
+  let rest = &buf[8 + len..];
+  let len = u32_at(rest, 4);
+  let meta = &rest[8..8 + len];
+  let msg = deref(meta, 0);
+  assert_eq!(meta[field(meta, msg, 1).unwrap()], HEADER_RECORD_BATCH);
+  let body_len = u32_at(meta, field(meta, msg, 3).unwrap());
+  let batch = deref(meta, field(meta, msg, 2).unwrap());
+  assert_eq!(u32_at(meta, field(meta, batch, 0).unwrap()), 2);
+  let nodes = deref(meta, field(meta, batch, 1).unwrap());
+  assert_eq!(u32_at(meta, nodes), 2);
+  assert_eq!(u32_at(meta, nodes + 4 + 16 + 8), 1);
+  let body = &rest[8 + len..8 + len + body_len];
+  let buffers = deref(meta, field(meta, batch, 2).unwrap());
+  assert_eq!(u32_at(meta, buffers), 5);
+  let (off, blen) = (u32_at(meta, buffers + 4 + 4 * 16), u32_at(meta, buffers + 12 + 4 * 16));
+  assert_eq!(&body[off..off + blen], b"Dune");
+  assert_eq!(&rest[8 + len + body_len..], &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+}
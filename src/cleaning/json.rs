@@ -1,3 +1,10 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use serde::de::IgnoredAny;
+use serde_json::Value;
+use serde_json::value::RawValue;
+
 /// Some of our JSON objects have \u0000, which PostgreSQL doesn't like.
 /// Clean those out while we import.
 /// This uses a reusable buffer to reduce allocations.
@@ -26,6 +33,197 @@ pub fn clean_json(json: &str, buf: &mut String) {
   }
 }
 
+/// Parse a `\\u` escape's hex digits.
+fn hex4(s: &str) -> Option<u32> {
+  if s.len() >= 4 && s.is_char_boundary(4) && s[..4].chars().all(|c| c.is_ascii_hexdigit()) {
+    u32::from_str_radix(&s[..4], 16).ok()
+  } else {
+    None
+  }
+}
+
+/// Repair string escapes that PostgreSQL's `jsonb` rejects: invalid escape
+/// characters have their backslash escaped, and lone surrogates are replaced
+/// with U+FFFD.
+fn repair_escapes(json: &str, buf: &mut String) {
+  buf.clear();
+  let mut rest = json;
+  while let Some(i) = rest.find('\\') {
+    buf.push_str(&rest[..i]);
+    let esc = &rest[i+1..];
+    match esc.chars().next() {
+      Some(c) if "\"\\/bfnrt".contains(c) => {
+        buf.push('\\');
+        buf.push(c);
+        rest = &esc[1..];
+      },
+      Some('u') => match hex4(&esc[1..]) {
+        Some(hi) if (0xD800..0xDC00).contains(&hi) => {
+          let lo = esc[5..].strip_prefix("\\u").and_then(hex4);
+          match lo {
+            Some(lo) if (0xDC00..0xE000).contains(&lo) => {
+              buf.push('\\');
+              buf.push_str(&esc[..11]);
+              rest = &esc[11..];
+            },
+            _ => {
+              buf.push_str("\\ufffd");
+              rest = &esc[5..];
+            }
+          }
+        },
+        Some(cp) if (0xDC00..0xE000).contains(&cp) => {
+          buf.push_str("\\ufffd");
+          rest = &esc[5..];
+        },
+        Some(_) => {
+          buf.push('\\');
+          buf.push_str(&esc[..5]);
+          rest = &esc[5..];
+        },
+        None => {
+          buf.push_str("\\\\");
+          rest = esc;
+        }
+      },
+      _ => {
+        buf.push_str("\\\\");
+        rest = esc;
+      }
+    }
+  }
+  buf.push_str(rest);
+}
+
+/// Check that cleaned JSON parses, so PostgreSQL will accept it as `jsonb`.
+/// Invalid escapes are repaired in place; JSON that still does not parse is
+/// an error.
+pub fn validate_json(buf: &mut String) -> Result<()> {
+  if serde_json::from_str::<IgnoredAny>(buf).is_ok() {
+    return Ok(());
+  }
+  let mut fixed = String::with_capacity(buf.len());
+  repair_escapes(buf, &mut fixed);
+  match serde_json::from_str::<IgnoredAny>(&fixed) {
+    Ok(_) => {
+      *buf = fixed;
+      Ok(())
+    },
+    Err(e) => Err(anyhow!("invalid JSON: {}", e))
+  }
+}
+
+/// Get a projected JSON value as column text.  Strings are unquoted, nulls are
+/// missing, and other values are kept as JSON.
+fn field_text(raw: &RawValue) -> Result<Option<String>> {
+  let text = raw.get();
+  if text == "null" {
+    Ok(None)
+  } else if text.starts_with('"') {
+    Ok(Some(serde_json::from_str(text)?))
+  } else {
+    Ok(Some(text.to_string()))
+  }
+}
+
+/// Look up a dotted path (e.g. `languages.key`) in a JSON object, without
+/// parsing the values that are not on the path.
+fn lookup_path<'a>(json: &'a str, path: &str) -> Result<Option<&'a RawValue>> {
+  let mut cur: &'a str = json;
+  let mut found = None;
+  for key in path.split('.') {
+    if !cur.trim_start().starts_with('{') {
+      return Ok(None);
+    }
+    let obj: HashMap<String, &'a RawValue> = serde_json::from_str(cur)?;
+    match obj.get(key) {
+      Some(v) => {
+        cur = v.get();
+        found = Some(*v);
+      },
+      None => return Ok(None)
+    }
+  }
+  Ok(found)
+}
+
+/// Extract fields from a JSON object by dotted path.  Missing and null fields
+/// are extracted as `None`.
+///
+/// ```
+/// use bookdata::cleaning::extract_fields;
+/// let fields = extract_fields(r#"{"title": "Dune", "isbn_13": ["9780441013593"]}"#, &["title", "isbn_13", "subtitle"]).unwrap();
+/// assert_eq!(fields, vec![Some("Dune".to_string()), Some(r#"["9780441013593"]"#.to_string()), None]);
+/// ```
+pub fn extract_fields<S: AsRef<str>>(json: &str, paths: &[S]) -> Result<Vec<Option<String>>> {
+  let mut fields = Vec::with_capacity(paths.len());
+  for path in paths {
+    let val = match lookup_path(json, path.as_ref())? {
+      Some(raw) => field_text(raw)?,
+      None => None
+    };
+    fields.push(val);
+  }
+  Ok(fields)
+}
+
+/// Extract a JSON value by dotted path.  Missing and null values are `None`.
+///
+/// ```
+/// use bookdata::cleaning::extract_value;
+/// let val = extract_value(r#"{"created": {"value": "2009-10-15"}}"#, "created").unwrap();
+/// assert_eq!(val.unwrap()["value"], "2009-10-15");
+/// ```
+pub fn extract_value(json: &str, path: &str) -> Result<Option<Value>> {
+  match lookup_path(json, path)? {
+    Some(raw) if raw.get() != "null" => Ok(Some(serde_json::from_str(raw.get())?)),
+    _ => Ok(None)
+  }
+}
+
+/// Extract the values of a JSON array by dotted path.  If `element` is given,
+/// it is the path of the value within each (object) element.  Missing arrays
+/// have no values, and null and missing elements are skipped.
+///
+/// ```
+/// use bookdata::cleaning::extract_list;
+/// let json = r#"{"authors": [{"author": {"key": "/authors/A1"}}, {"author": {"key": "/authors/A2"}}]}"#;
+/// let keys = extract_list(json, "authors", Some("author.key")).unwrap();
+/// assert_eq!(keys, vec!["/authors/A1", "/authors/A2"]);
+/// ```
+pub fn extract_list(json: &str, path: &str, element: Option<&str>) -> Result<Vec<String>> {
+  let raw = match lookup_path(json, path)? {
+    Some(r) if r.get().starts_with('[') => r,
+    _ => return Ok(Vec::new())
+  };
+  let elts: Vec<&RawValue> = serde_json::from_str(raw.get())?;
+  let mut vals = Vec::with_capacity(elts.len());
+  for elt in elts {
+    let val = match element {
+      Some(ep) => match lookup_path(elt.get(), ep)? {
+        Some(v) => field_text(v)?,
+        None => None
+      },
+      None => field_text(elt)?
+    };
+    if let Some(v) = val {
+      vals.push(v);
+    }
+  }
+  Ok(vals)
+}
+
+/// Extract the values at a dotted path like [extract_list], except that a
+/// scalar at the path is a single value.  Empty strings are skipped.
+pub fn extract_values(json: &str, path: &str, element: Option<&str>) -> Result<Vec<String>> {
+  let vals = match lookup_path(json, path)? {
+    Some(r) if r.get().starts_with('[') => extract_list(json, path, element)?,
+    Some(r) if !r.get().starts_with('{') => field_text(r)?.into_iter().collect(),
+    _ => Vec::new()
+  };
+  Ok(vals.into_iter().filter(|v| !v.is_empty()).collect())
+}
+
 #[test]
 fn clean_empty_is_empty() {
   let mut buf = String::new();
@@ -77,3 +275,59 @@ fn clean_reuse_buffer() {
   clean_json("pizza fish", &mut buf);
   assert_eq!(buf, "pizza fish");
 }
+
+#[test]
+fn extract_nested() {
+  let json = r#"{"key": "/works/W1", "created": {"type": "/type/datetime", "value": "2009-10-15"}, "n": 5}"#;
+  let fields = extract_fields(json, &["created.value", "n", "created.missing", "key.value"]).unwrap();
+  assert_eq!(fields, vec![Some("2009-10-15".to_string()), Some("5".to_string()), None, None]);
+}
+
+#[test]
+fn extract_escaped_null() {
+  let fields = extract_fields(r#"{"title": "A \"quoted\" title", "subtitle": null}"#, &["title", "subtitle"]).unwrap();
+  assert_eq!(fields, vec![Some("A \"quoted\" title".to_string()), None]);
+}
+
+#[test]
+fn extract_bad_json() {
+  assert!(extract_fields("{\"title\": ", &["title"]).is_err());
+}
+
+#[test]
+fn validate_good() {
+  let mut buf = r#"{"title": "caf\u00e9 \ud83d\ude00"}"#.to_string();
+  validate_json(&mut buf).unwrap();
+  assert_eq!(buf, r#"{"title": "caf\u00e9 \ud83d\ude00"}"#);
+}
+
+#[test]
+fn validate_repair() {
+  let mut buf = r#"{"title": "bad \x escape", "s": "lone \ud800 surrogate \udc00", "u": "\u12"}"#.to_string();
+  validate_json(&mut buf).unwrap();
+  assert_eq!(buf, r#"{"title": "bad \\x escape", "s": "lone \ufffd surrogate \ufffd", "u": "\\u12"}"#);
+}
+
+#[test]
+fn validate_irreparable() {
+  let mut buf = r#"{"title": "truncated"#.to_string();
+  assert!(validate_json(&mut buf).is_err());
+}
+
+#[test]
+fn extract_list_strings() {
+  let json = r#"{"isbn_13": ["9780441013593", null], "title": "Dune"}"#;
+  assert_eq!(extract_list(json, "isbn_13", None).unwrap(), vec!["9780441013593"]);
+  assert!(extract_list(json, "isbn_10", None).unwrap().is_empty());
+  assert!(extract_list(json, "title", None).unwrap().is_empty());
+}
+
+#[test]
+fn extract_values_scalars() {
+  let json = r#"{"work_id": "10", "isbn": "", "count": 3, "authors": [{"author_id": "7"}], "shelf": {"id": 1}}"#;
+  assert_eq!(extract_values(json, "work_id", None).unwrap(), vec!["10"]);
+  assert_eq!(extract_values(json, "count", None).unwrap(), vec!["3"]);
+  assert!(extract_values(json, "isbn", None).unwrap().is_empty());
+  assert!(extract_values(json, "shelf", None).unwrap().is_empty());
+  assert_eq!(extract_values(json, "authors", Some("author_id")).unwrap(), vec!["7"]);
+}
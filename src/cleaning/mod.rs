@@ -1,5 +1,10 @@
 mod pg;
 mod json;
+mod text;
+mod binary;
 
-pub use self::pg::write_pgencoded;
-pub use self::json::clean_json;
+pub use self::pg::{write_pgencoded, write_nullable, write_null, decode_pgencoded, PG_NULL};
+pub use self::pg::{write_pgarray, write_pgjson};
+pub use self::json::{clean_json, validate_json, extract_fields, extract_list, extract_values, extract_value};
+pub use self::text::SanitizeOpts;
+pub use self::binary::BinaryCopyWrite;
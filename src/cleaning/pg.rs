@@ -2,32 +2,52 @@
 use std::str;
 use std::io::{self, Write};
 
+use anyhow::{anyhow, Result};
+use memchr::{memchr, memchr3};
+use serde_json::Value;
+
+use super::json::clean_json;
+
+/// The PostgreSQL text format representation of a SQL NULL.
+pub const PG_NULL: &[u8] = b"\\N";
+
+/// Write a NULL field in PostgreSQL text format.
+pub fn write_null<W: Write>(w: &mut W) -> io::Result<()> {
+  w.write_all(PG_NULL)
+}
+
+/// Write an optional field with PostgreSQL text format encoding, writing
+/// `None` as NULL.
+pub fn write_nullable<W: Write>(w: &mut W, buf: Option<&[u8]>) -> io::Result<()> {
+  match buf {
+    Some(b) => write_pgencoded(w, b),
+    None => write_null(w)
+  }
+}
+
 /// Write text with PostgreSQL text format encoding.
+///
+/// This scans for bytes needing escapes with `memchr`, so text without them
+/// is written in one bulk copy.  Carriage returns are dropped.
 pub fn write_pgencoded<W: Write>(w: &mut W, buf: &[u8]) -> io::Result<()> {
   let mut start = 0;
-  for i in 0..buf.len() {
+  let mut next_cr = memchr(b'\r', buf);
+  loop {
+    let esc = memchr3(b'\\', b'\n', b'\t', &buf[start..]).map(|i| start + i);
+    let i = match esc.into_iter().chain(next_cr).min() {
+      Some(i) => i,
+      None => break
+    };
+    if i > start {
+      w.write_all(&buf[start..i])?;
+    }
     match buf[i] {
-      b'\\' => {
-        w.write_all(&buf[start..i])?;
-        start = i + 1;
-        w.write_all(b"\\\\")?;
-      },
-      b'\r' => {
-        w.write_all(&buf[start..i])?;
-        start = i + 1;
-      },
-      b'\n' => {
-        w.write_all(&buf[start..i])?;
-        start = i + 1;
-        w.write_all(b"\\n")?;
-      },
-      b'\t' => {
-        w.write_all(&buf[start..i])?;
-        start = i + 1;
-        w.write_all(b"\\t")?;
-      },
-      _ => ()
+      b'\\' => w.write_all(b"\\\\")?,
+      b'\n' => w.write_all(b"\\n")?,
+      b'\t' => w.write_all(b"\\t")?,
+      _ => next_cr = memchr(b'\r', &buf[i+1..]).map(|j| i + 1 + j)
     }
+    start = i + 1;
   }
   if start < buf.len() {
     w.write_all(&buf[start..])?;
@@ -35,6 +55,103 @@ pub fn write_pgencoded<W: Write>(w: &mut W, buf: &[u8]) -> io::Result<()> {
   Ok(())
 }
 
+/// Format strings as a PostgreSQL array literal, such as `{"a","b"}`.  Every
+/// element is quoted, so no element needs special-casing.  The literal still
+/// needs PostgreSQL text format encoding to go in a `COPY` stream.
+pub fn array_literal<S: AsRef<str>>(vals: &[S]) -> String {
+  let mut lit = String::from("{");
+  for (i, v) in vals.iter().enumerate() {
+    if i > 0 {
+      lit.push(',');
+    }
+    lit.push('"');
+    for c in v.as_ref().chars() {
+      if c == '"' || c == '\\' {
+        lit.push('\\');
+      }
+      lit.push(c);
+    }
+    lit.push('"');
+  }
+  lit.push('}');
+  lit
+}
+
+/// Write strings as a PostgreSQL array in text format.
+pub fn write_pgarray<W: Write, S: AsRef<str>>(w: &mut W, vals: &[S]) -> io::Result<()> {
+  write_pgencoded(w, array_literal(vals).as_bytes())
+}
+
+/// Write a JSON value in PostgreSQL text format, for a `json` or `jsonb`
+/// column.  NUL characters, which `jsonb` rejects, are removed.
+pub fn write_pgjson<W: Write>(w: &mut W, val: &Value) -> io::Result<()> {
+  let mut buf = String::new();
+  clean_json(&val.to_string(), &mut buf);
+  write_pgencoded(w, buf.as_bytes())
+}
+
+fn hex_val(b: u8) -> Option<u8> {
+  match b {
+    b'0'..=b'9' => Some(b - b'0'),
+    b'a'..=b'f' => Some(b - b'a' + 10),
+    b'A'..=b'F' => Some(b - b'A' + 10),
+    _ => None
+  }
+}
+
+/// Decode a field in PostgreSQL text format, reversing `write_pgencoded`.
+///
+/// This understands all the backslash escapes PostgreSQL accepts in `COPY` text
+/// format, including octal (`\123`) and hex (`\x53`) byte escapes.  The decoded
+/// bytes are written to a reusable buffer.  A field consisting only of `\N` is a
+/// SQL NULL, and is not handled here.
+pub fn decode_pgencoded(buf: &[u8], out: &mut Vec<u8>) -> Result<()> {
+  out.clear();
+  let mut i = 0;
+  while i < buf.len() {
+    let b = buf[i];
+    i += 1;
+    if b != b'\\' {
+      out.push(b);
+      continue;
+    }
+    if i >= buf.len() {
+      return Err(anyhow!("trailing backslash in encoded field"));
+    }
+    let e = buf[i];
+    i += 1;
+    match e {
+      b'b' => out.push(8),
+      b'f' => out.push(12),
+      b'n' => out.push(b'\n'),
+      b'r' => out.push(b'\r'),
+      b't' => out.push(b'\t'),
+      b'v' => out.push(11),
+      b'0'..=b'7' => {
+        let mut v = (e - b'0') as u32;
+        let mut n = 1;
+        while n < 3 && i < buf.len() && buf[i] >= b'0' && buf[i] <= b'7' {
+          v = v * 8 + (buf[i] - b'0') as u32;
+          i += 1;
+          n += 1;
+        }
+        out.push((v & 0xFF) as u8);
+      },
+      b'x' if i < buf.len() && hex_val(buf[i]).is_some() => {
+        let mut v = hex_val(buf[i]).unwrap();
+        i += 1;
+        if let Some(d) = buf.get(i).and_then(|b| hex_val(*b)) {
+          v = v * 16 + d;
+          i += 1;
+        }
+        out.push(v);
+      },
+      c => out.push(c)
+    }
+  }
+  Ok(())
+}
+
 #[test]
 fn it_writes_empty() {
   let mut vec = Vec::new();
@@ -90,3 +207,150 @@ fn embedded() {
 
   assert_eq!(str::from_utf8(&vec).unwrap(), "foo\\nbar\\\\wombat");
 }
+
+#[test]
+fn embedded_cr() {
+  let mut vec = Vec::new();
+  write_pgencoded(&mut vec, b"a\r\rb\tc\r\\d\r").unwrap();
+
+  assert_eq!(str::from_utf8(&vec).unwrap(), "ab\\tc\\\\d");
+}
+
+#[test]
+fn encode_nullable() {
+  let mut vec = Vec::new();
+  write_nullable(&mut vec, Some(b"a\tb")).unwrap();
+  vec.push(b'\t');
+  write_nullable(&mut vec, None).unwrap();
+  vec.push(b'\t');
+  write_nullable(&mut vec, Some(b"")).unwrap();
+
+  assert_eq!(str::from_utf8(&vec).unwrap(), "a\\tb\t\\N\t");
+}
+
+#[test]
+fn array_empty() {
+  let vals: Vec<String> = Vec::new();
+  assert_eq!(array_literal(&vals), "{}");
+}
+
+#[test]
+fn array_quoting() {
+  let lit = array_literal(&["Fiction", "a,b", "say \"hi\"", "back\\slash", "{x}", "NULL"]);
+  assert_eq!(lit, r#"{"Fiction","a,b","say \"hi\"","back\\slash","{x}","NULL"}"#);
+}
+
+#[test]
+fn encode_array() {
+  let mut vec = Vec::new();
+  write_pgarray(&mut vec, &["a\tb", "c\\d", "e\"f"]).unwrap();
+
+  assert_eq!(str::from_utf8(&vec).unwrap(), r#"{"a\tb","c\\\\d","e\\"f"}"#);
+}
+
+#[test]
+fn encode_json() {
+  let val = serde_json::json!({"title": "Tab\there", "path": "C:\\x", "nul": "a\u{0}b", "n": [1, 2]});
+  let mut vec = Vec::new();
+  write_pgjson(&mut vec, &val).unwrap();
+
+  let enc = str::from_utf8(&vec).unwrap();
+  assert!(!enc.contains('\t') && !enc.contains('\n'));
+  let mut dec = Vec::new();
+  decode_pgencoded(&vec, &mut dec).unwrap();
+  let back: Value = serde_json::from_slice(&dec).unwrap();
+  assert_eq!(back["title"], "Tab\there");
+  assert_eq!(back["path"], "C:\\x");
+  assert_eq!(back["nul"], "ab");
+  assert_eq!(back["n"], serde_json::json!([1, 2]));
+}
+
+#[test]
+fn decode_empty() {
+  let mut out = Vec::new();
+  decode_pgencoded(b"", &mut out).unwrap();
+  assert!(out.is_empty());
+}
+
+#[test]
+fn decode_plain() {
+  let mut out = Vec::new();
+  decode_pgencoded(b"foo", &mut out).unwrap();
+  assert_eq!(out, b"foo");
+}
+
+#[test]
+fn decode_escapes() {
+  let mut out = Vec::new();
+  decode_pgencoded(b"a\\tb\\nc\\\\d\\re", &mut out).unwrap();
+  assert_eq!(out, b"a\tb\nc\\d\re");
+}
+
+#[test]
+fn decode_numeric_escapes() {
+  let mut out = Vec::new();
+  decode_pgencoded(b"\\101\\x42\\7z\\x4", &mut out).unwrap();
+  assert_eq!(out, b"AB\x07z\x04");
+}
+
+#[test]
+fn decode_other_escapes() {
+  let mut out = Vec::new();
+  decode_pgencoded(b"\\b\\f\\v\\q\\xg", &mut out).unwrap();
+  assert_eq!(out, b"\x08\x0c\x0bqxg");
+}
+
+#[test]
+fn decode_trailing_backslash() {
+  let mut out = Vec::new();
+  assert!(decode_pgencoded(b"foo\\", &mut out).is_err());
+}
+
+#[cfg(test)]
+fn check_round_trip(data: &[u8], enc: &mut Vec<u8>, dec: &mut Vec<u8>) {
+  enc.clear();
+  write_pgencoded(enc, data).unwrap();
+  assert!(!enc.contains(&b'\t') && !enc.contains(&b'\n'));
+  decode_pgencoded(enc, dec).unwrap();
+  // the encoder drops carriage returns
+  let expected: Vec<u8> = data.iter().cloned().filter(|b| *b != b'\r').collect();
+  assert_eq!(*dec, expected, "round trip of {:?}", data);
+}
+
+#[test]
+fn round_trip_short() {
+  // every byte string of length up to 2
+  let mut enc = Vec::new();
+  let mut dec = Vec::new();
+  check_round_trip(b"", &mut enc, &mut dec);
+  for a in 0..=255u8 {
+    check_round_trip(&[a], &mut enc, &mut dec);
+    for b in 0..=255u8 {
+      check_round_trip(&[a, b], &mut enc, &mut dec);
+    }
+  }
+}
+
+#[test]
+fn round_trip_random() {
+  // pseudo-random strings biased towards the bytes that need escaping
+  let alphabet = b"\\\t\n\rNx07aZ ";
+  let mut state: u64 = 0x853c49e6748fea9b;
+  let mut enc = Vec::new();
+  let mut dec = Vec::new();
+  for _i in 0..2000 {
+    state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    let len = (state >> 59) as usize;
+    let mut data = Vec::with_capacity(len);
+    for _j in 0..len {
+      state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      let r = (state >> 33) as usize;
+      if r & 3 == 0 {
+        data.push((r >> 8) as u8);
+      } else {
+        data.push(alphabet[r % alphabet.len()]);
+      }
+    }
+    check_round_trip(&data, &mut enc, &mut dec);
+  }
+}
@@ -0,0 +1,102 @@
+use std::str;
+
+use structopt::StructOpt;
+use unicode_normalization::UnicodeNormalization;
+use anyhow::Result;
+
+/// Options for sanitizing input text
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct SanitizeOpts {
+  /// Normalize text to Unicode NFC
+  #[structopt(long="nfc")]
+  nfc: bool,
+
+  /// Strip control characters (except tab)
+  #[structopt(long="strip-control")]
+  strip_control: bool,
+
+  /// Replace invalid UTF-8 sequences instead of failing
+  #[structopt(long="replace-invalid")]
+  replace_invalid: bool
+}
+
+impl SanitizeOpts {
+  /// Is this sanitizer a no-op on valid UTF-8?
+  fn is_passthrough(&self) -> bool {
+    !self.nfc && !self.strip_control
+  }
+
+  /// Sanitize a line of input into a reusable buffer.
+  pub fn sanitize(&self, input: &[u8], buf: &mut String) -> Result<()> {
+    buf.clear();
+    let text = if self.replace_invalid {
+      String::from_utf8_lossy(input)
+    } else {
+      str::from_utf8(input)?.into()
+    };
+    if self.is_passthrough() {
+      buf.push_str(&text);
+    } else if self.nfc {
+      self.push_chars(text.nfc(), buf);
+    } else {
+      self.push_chars(text.chars(), buf);
+    }
+    Ok(())
+  }
+
+  fn push_chars<I: Iterator<Item=char>>(&self, chars: I, buf: &mut String) {
+    for c in chars {
+      if self.strip_control && c.is_control() && c != '\t' {
+        continue;
+      }
+      buf.push(c);
+    }
+  }
+}
+
+#[test]
+fn passthrough() {
+  let mut buf = String::new();
+  SanitizeOpts::default().sanitize(b"foo\tbar\x07", &mut buf).unwrap();
+  assert_eq!(buf, "foo\tbar\x07");
+}
+
+#[test]
+fn invalid_fails_by_default() {
+  let mut buf = String::new();
+  assert!(SanitizeOpts::default().sanitize(b"foo\xff", &mut buf).is_err());
+}
+
+#[test]
+fn replace_invalid() {
+  let mut buf = String::new();
+  let opts = SanitizeOpts { replace_invalid: true, ..SanitizeOpts::default() };
+  opts.sanitize(b"foo\xffbar", &mut buf).unwrap();
+  assert_eq!(buf, "foo\u{FFFD}bar");
+}
+
+#[test]
+fn nfc_composes() {
+  let mut buf = String::new();
+  let opts = SanitizeOpts { nfc: true, ..SanitizeOpts::default() };
+  opts.sanitize("Cafe\u{301}".as_bytes(), &mut buf).unwrap();
+  assert_eq!(buf, "Caf\u{e9}");
+}
+
+#[test]
+fn strip_control_keeps_tab() {
+  let mut buf = String::new();
+  let opts = SanitizeOpts { strip_control: true, ..SanitizeOpts::default() };
+  opts.sanitize(b"a\tb\x00c\x1bd\r", &mut buf).unwrap();
+  assert_eq!(buf, "a\tbcd");
+}
+
+#[test]
+fn sanitize_reuses_buffer() {
+  let mut buf = String::new();
+  let opts = SanitizeOpts { nfc: true, strip_control: true, replace_invalid: true };
+  opts.sanitize(b"wombat\x01", &mut buf).unwrap();
+  assert_eq!(buf, "wombat");
+  opts.sanitize(b"fish", &mut buf).unwrap();
+  assert_eq!(buf, "fish");
+}
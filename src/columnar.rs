@@ -0,0 +1,343 @@
+//! Write tables to Parquet files.
+use std::convert::TryInto;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use parquet::basic::{Compression, ConvertedType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, BoolType, Int32Type, Int64Type, FloatType, DoubleType};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use anyhow::{Result, anyhow};
+
+/// Number of rows to buffer in each row group.
+const GROUP_SIZE: usize = 64 * 1024;
+
+/// The type of a column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColType {
+  Text,
+  Json,
+  Bool,
+  Int32,
+  Int64,
+  Float,
+  Double,
+  /// Days since the Unix epoch
+  Date,
+  /// Microseconds since the Unix epoch
+  Timestamp
+}
+
+impl ColType {
+  fn physical(&self) -> PhysicalType {
+    match self {
+      ColType::Text | ColType::Json => PhysicalType::BYTE_ARRAY,
+      ColType::Bool => PhysicalType::BOOLEAN,
+      ColType::Int32 | ColType::Date => PhysicalType::INT32,
+      ColType::Int64 | ColType::Timestamp => PhysicalType::INT64,
+      ColType::Float => PhysicalType::FLOAT,
+      ColType::Double => PhysicalType::DOUBLE
+    }
+  }
+
+  fn annotation(&self) -> ConvertedType {
+    match self {
+      ColType::Text => ConvertedType::UTF8,
+      ColType::Json => ConvertedType::JSON,
+      ColType::Date => ConvertedType::DATE,
+      ColType::Timestamp => ConvertedType::TIMESTAMP_MICROS,
+      _ => ConvertedType::NONE
+    }
+  }
+
+  fn buffer(&self) -> Values {
+    match self.physical() {
+      PhysicalType::BOOLEAN => Values::Bool(Vec::with_capacity(GROUP_SIZE)),
+      PhysicalType::INT32 => Values::Int32(Vec::with_capacity(GROUP_SIZE)),
+      PhysicalType::INT64 => Values::Int64(Vec::with_capacity(GROUP_SIZE)),
+      PhysicalType::FLOAT => Values::Float(Vec::with_capacity(GROUP_SIZE)),
+      PhysicalType::DOUBLE => Values::Double(Vec::with_capacity(GROUP_SIZE)),
+      _ => Values::Bytes(Vec::with_capacity(GROUP_SIZE))
+    }
+  }
+
+  /// Convert a value to this column type.  Text is parsed for numeric and
+  /// boolean columns, and other values are formatted into `buf` for text
+  /// columns.  Dates and timestamps take their integer values unchanged.
+  pub fn cast<'a>(&self, val: Value<'a>, buf: &'a mut Vec<u8>) -> Result<Value<'a>> {
+    let bad = || anyhow!("cannot convert {:?} to {:?}", val, self);
+    Ok(match (self, val) {
+      (ColType::Text | ColType::Json, Value::Bytes(v)) => Value::Bytes(v),
+      (ColType::Text | ColType::Json, v) => {
+        buf.clear();
+        match v {
+          Value::Bool(b) => buf.push(if b { b't' } else { b'f' }),
+          Value::Int32(n) => write!(buf, "{}", n)?,
+          Value::Int64(n) => write!(buf, "{}", n)?,
+          Value::Float(n) => write!(buf, "{}", n)?,
+          Value::Double(n) => write!(buf, "{}", n)?,
+          Value::Bytes(_) => unreachable!()
+        }
+        let text: &'a [u8] = buf;
+        Value::Bytes(text)
+      },
+      (ColType::Bool, Value::Bool(b)) => Value::Bool(b),
+      (ColType::Bool, Value::Bytes(v)) => match trimmed(v).ok_or_else(bad)? {
+        "t" | "true" | "1" => Value::Bool(true),
+        "f" | "false" | "0" => Value::Bool(false),
+        _ => return Err(bad())
+      },
+      (ColType::Int32 | ColType::Date, Value::Int32(n)) => Value::Int32(n),
+      (ColType::Int32, Value::Int64(n)) => Value::Int32(n.try_into().map_err(|_| bad())?),
+      (ColType::Int32, Value::Bytes(v)) => Value::Int32(parse(v).ok_or_else(bad)?),
+      (ColType::Int64 | ColType::Timestamp, Value::Int64(n)) => Value::Int64(n),
+      (ColType::Int64, Value::Int32(n)) => Value::Int64(n.into()),
+      (ColType::Int64, Value::Bytes(v)) => Value::Int64(parse(v).ok_or_else(bad)?),
+      (ColType::Float, Value::Float(n)) => Value::Float(n),
+      (ColType::Float, Value::Double(n)) => Value::Float(n as f32),
+      (ColType::Float, Value::Int32(n)) => Value::Float(n as f32),
+      (ColType::Float, Value::Int64(n)) => Value::Float(n as f32),
+      (ColType::Float, Value::Bytes(v)) => Value::Float(parse(v).ok_or_else(bad)?),
+      (ColType::Double, Value::Double(n)) => Value::Double(n),
+      (ColType::Double, Value::Float(n)) => Value::Double(n.into()),
+      (ColType::Double, Value::Int32(n)) => Value::Double(n.into()),
+      (ColType::Double, Value::Int64(n)) => Value::Double(n as f64),
+      (ColType::Double, Value::Bytes(v)) => Value::Double(parse(v).ok_or_else(bad)?),
+      _ => return Err(bad())
+    })
+  }
+}
+
+/// Get text as a trimmed string.
+fn trimmed(v: &[u8]) -> Option<&str> {
+  std::str::from_utf8(v).ok().map(str::trim)
+}
+
+/// Parse text as a number.
+fn parse<T: FromStr>(v: &[u8]) -> Option<T> {
+  trimmed(v)?.parse().ok()
+}
+
+/// A field value.  Dates and timestamps are written as `Int32` and `Int64`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a> {
+  Bytes(&'a [u8]),
+  Bool(bool),
+  Int32(i32),
+  Int64(i64),
+  Float(f32),
+  Double(f64)
+}
+
+/// The buffered values of a column.
+enum Values {
+  Bytes(Vec<ByteArray>),
+  Bool(Vec<bool>),
+  Int32(Vec<i32>),
+  Int64(Vec<i64>),
+  Float(Vec<f32>),
+  Double(Vec<f64>)
+}
+
+/// Writer for a table in a Parquet file.
+///
+/// Fields are written one at a time, and each row is closed with `end_row`.
+/// Rows are buffered and written out in row groups.  Columns are optional, so
+/// fields may be null.
+pub struct TableWriter<W: Write + Send> {
+  writer: SerializedFileWriter<W>,
+  types: Vec<ColType>,
+  columns: Vec<Values>,
+  defs: Vec<Vec<i16>>,
+  field: usize,
+  rows: usize
+}
+
+impl <W: Write + Send> TableWriter<W> {
+  /// Create a table writer with the given columns.
+  pub fn create(out: W, name: &str, columns: &[(&str, ColType)]) -> Result<TableWriter<W>> {
+    let mut fields = Vec::with_capacity(columns.len());
+    for (col, ct) in columns {
+      let field = Type::primitive_type_builder(col, ct.physical())
+        .with_repetition(Repetition::OPTIONAL)
+        .with_converted_type(ct.annotation())
+        .build()?;
+      fields.push(Arc::new(field));
+    }
+    let schema = Type::group_type_builder(name).with_fields(fields).build()?;
+    let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let writer = SerializedFileWriter::new(out, Arc::new(schema), Arc::new(props))?;
+    Ok(TableWriter {
+      writer,
+      types: columns.iter().map(|(_, ct)| *ct).collect(),
+      columns: columns.iter().map(|(_, ct)| ct.buffer()).collect(),
+      defs: columns.iter().map(|_| Vec::with_capacity(GROUP_SIZE)).collect(),
+      field: 0,
+      rows: 0
+    })
+  }
+
+  /// Write the next field of the current row to a text or JSON column.
+  pub fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.write_value(Value::Bytes(val))
+  }
+
+  /// Write the next field of the current row.  The value must match the
+  /// column's type.
+  pub fn write_value(&mut self, val: Value) -> Result<()> {
+    let col = self.columns.get_mut(self.field).ok_or(anyhow!("too many fields in row {}", self.rows + 1))?;
+    match (col, val) {
+      (Values::Bytes(c), Value::Bytes(v)) => c.push(v.to_vec().into()),
+      (Values::Bool(c), Value::Bool(v)) => c.push(v),
+      (Values::Int32(c), Value::Int32(v)) => c.push(v),
+      (Values::Int64(c), Value::Int64(v)) => c.push(v),
+      (Values::Float(c), Value::Float(v)) => c.push(v),
+      (Values::Double(c), Value::Double(v)) => c.push(v),
+      (_, v) => return Err(anyhow!("field {} of row {}: {:?} does not match column type", self.field + 1, self.rows + 1, v))
+    }
+    self.defs[self.field].push(1);
+    self.field += 1;
+    Ok(())
+  }
+
+  /// Write the next field of the current row, converted to the column's type
+  /// with [ColType::cast].
+  pub fn write_cast(&mut self, val: Value) -> Result<()> {
+    let ct = *self.types.get(self.field).ok_or(anyhow!("too many fields in row {}", self.rows + 1))?;
+    let mut buf = Vec::new();
+    let val = ct.cast(val, &mut buf).map_err(|e| anyhow!("field {} of row {}: {}", self.field + 1, self.rows + 1, e))?;
+    self.write_value(val)
+  }
+
+  /// Write a null as the next field of the current row.
+  pub fn write_null(&mut self) -> Result<()> {
+    let defs = self.defs.get_mut(self.field).ok_or(anyhow!("too many fields in row {}", self.rows + 1))?;
+    defs.push(0);
+    self.field += 1;
+    Ok(())
+  }
+
+  /// Finish the current row.
+  pub fn end_row(&mut self) -> Result<()> {
+    if self.field != self.columns.len() {
+      return Err(anyhow!("row {} has {} fields, expected {}", self.rows + 1, self.field, self.columns.len()));
+    }
+    self.field = 0;
+    self.rows += 1;
+    if self.defs[0].len() >= GROUP_SIZE {
+      self.write_group()?;
+    }
+    Ok(())
+  }
+
+  fn write_group(&mut self) -> Result<()> {
+    let mut group = self.writer.next_row_group()?;
+    for (col, defs) in self.columns.iter_mut().zip(self.defs.iter_mut()) {
+      let mut cw = group.next_column()?.ok_or(anyhow!("schema has too few columns"))?;
+      match col {
+        Values::Bytes(c) => { cw.typed::<ByteArrayType>().write_batch(c, Some(defs), None)?; c.clear(); },
+        Values::Bool(c) => { cw.typed::<BoolType>().write_batch(c, Some(defs), None)?; c.clear(); },
+        Values::Int32(c) => { cw.typed::<Int32Type>().write_batch(c, Some(defs), None)?; c.clear(); },
+        Values::Int64(c) => { cw.typed::<Int64Type>().write_batch(c, Some(defs), None)?; c.clear(); },
+        Values::Float(c) => { cw.typed::<FloatType>().write_batch(c, Some(defs), None)?; c.clear(); },
+        Values::Double(c) => { cw.typed::<DoubleType>().write_batch(c, Some(defs), None)?; c.clear(); }
+      }
+      cw.close()?;
+      defs.clear();
+    }
+    group.close()?;
+    Ok(())
+  }
+
+  /// Write any buffered rows and close the file, returning the number of rows.
+  pub fn finish(mut self) -> Result<usize> {
+    if self.field > 0 {
+      return Err(anyhow!("unfinished row {}", self.rows + 1));
+    }
+    if !self.defs[0].is_empty() {
+      self.write_group()?;
+    }
+    let meta = self.writer.close()?;
+    Ok(meta.file_metadata().num_rows() as usize)
+  }
+}
+
+#[test]
+fn test_write_table() {
+  let mut buf = Vec::new();
+  let mut w = TableWriter::create(&mut buf, "books", &[("id", ColType::Text), ("data", ColType::Json)]).unwrap();
+  for i in 0..3 {
+    w.write_field(format!("{}", i).as_bytes()).unwrap();
+    w.write_field(b"{\"a\": 1}").unwrap();
+    w.end_row().unwrap();
+  }
+  assert_eq!(w.finish().unwrap(), 3);
+  assert_eq!(&buf[0..4], b"PAR1");
+  assert_eq!(&buf[buf.len() - 4..], b"PAR1");
+}
+
+#[test]
+fn test_write_nulls() {
+  let mut buf = Vec::new();
+  let mut w = TableWriter::create(&mut buf, "books", &[("id", ColType::Text), ("title", ColType::Text)]).unwrap();
+  w.write_field(b"1").unwrap();
+  w.write_null().unwrap();
+  w.end_row().unwrap();
+  w.write_field(b"2").unwrap();
+  w.write_field(b"Dune").unwrap();
+  w.end_row().unwrap();
+  assert_eq!(w.finish().unwrap(), 2);
+}
+
+#[test]
+fn test_empty_table() {
+  let mut buf = Vec::new();
+  let w = TableWriter::create(&mut buf, "books", &[("id", ColType::Text)]).unwrap();
+  assert_eq!(w.finish().unwrap(), 0);
+}
+
+#[test]
+fn test_field_count() {
+  let mut buf = Vec::new();
+  let mut w = TableWriter::create(&mut buf, "books", &[("id", ColType::Text), ("title", ColType::Text)]).unwrap();
+  w.write_field(b"1").unwrap();
+  assert!(w.end_row().is_err());
+  w.write_field(b"x").unwrap();
+  assert!(w.write_field(b"y").is_err());
+}
+
+#[test]
+fn test_typed_columns() {
+  use parquet::file::reader::{FileReader, SerializedFileReader};
+  use parquet::record::Field;
+  let path = std::env::temp_dir().join(format!("bookdata-columnar-{}.parquet", std::process::id()));
+  let cols = [("id", ColType::Int64), ("score", ColType::Double), ("ok", ColType::Bool), ("added", ColType::Date)];
+  let mut w = TableWriter::create(std::fs::File::create(&path).unwrap(), "books", &cols).unwrap();
+  w.write_value(Value::Int64(7)).unwrap();
+  w.write_value(Value::Double(3.5)).unwrap();
+  w.write_null().unwrap();
+  w.write_value(Value::Int32(1)).unwrap();
+  w.end_row().unwrap();
+  assert!(w.write_field(b"8").is_err());
+  assert_eq!(w.finish().unwrap(), 1);
+  let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+  let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(Result::unwrap).collect();
+  std::fs::remove_file(&path).unwrap();
+  let fields: Vec<&Field> = rows[0].get_column_iter().map(|(_, f)| f).collect();
+  assert_eq!(fields, vec![&Field::Long(7), &Field::Double(3.5), &Field::Null, &Field::Date(1)]);
+}
+
+#[test]
+fn test_cast() {
+  let mut buf = Vec::new();
+  assert_eq!(ColType::Int32.cast(Value::Bytes(b"276725"), &mut buf).unwrap(), Value::Int32(276725));
+  assert_eq!(ColType::Float.cast(Value::Bytes(b"5"), &mut buf).unwrap(), Value::Float(5.0));
+  assert_eq!(ColType::Bool.cast(Value::Bytes(b"t"), &mut buf).unwrap(), Value::Bool(true));
+  assert_eq!(ColType::Double.cast(Value::Int64(3), &mut buf).unwrap(), Value::Double(3.0));
+  assert!(ColType::Int32.cast(Value::Bytes(b"x"), &mut buf).is_err());
+  assert!(ColType::Int32.cast(Value::Int64(1 << 40), &mut buf).is_err());
+  assert!(ColType::Date.cast(Value::Bytes(b"2020-01-01"), &mut buf).is_err());
+  assert_eq!(ColType::Text.cast(Value::Int64(42), &mut buf).unwrap(), Value::Bytes(b"42"));
+}
@@ -0,0 +1,189 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::names::{life_years, edtf_year};
+use crate::io::{open_input, decompress};
+use crate::tsv::{FieldParser, write_row};
+use crate::db::{DbOpts, CopyRequest};
+use super::Command;
+
+/// The earliest birth or death year we believe.
+const MIN_YEAR: i32 = -3000;
+/// The longest lifespan we believe.
+const MAX_LIFESPAN: i32 = 125;
+
+/// Extract authors' birth and death years from MARC authority fields.
+#[derive(StructOpt, Debug)]
+#[structopt(name="author-years")]
+pub struct AuthorYears {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  /// Copy the years into this database table instead of writing them to stdout.
+  #[structopt(short="t", long="table")]
+  table: Option<String>,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Input file of MARC field rows, as written by parse-marc (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// The dates found in one authority record.
+#[derive(Debug, Default)]
+struct RecDates {
+  /// Birth year from 046 $f
+  birth: Option<i32>,
+  /// Death year from 046 $g
+  death: Option<i32>,
+  /// Dates from the first heading's $d (100, or 700 in VIAF clusters)
+  heading: Option<String>
+}
+
+/// Counts from extracting years.
+#[derive(Debug, Default, PartialEq)]
+struct YearCounts {
+  records: usize,
+  written: usize,
+  implausible: usize
+}
+
+/// The current year, near enough for checking dates.
+fn this_year() -> i32 {
+  let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+  1970 + (secs / 31_556_952) as i32
+}
+
+/// Drop implausible years: years outside the believable range, and both years
+/// if they do not make a believable lifespan.  Returns the checked years and
+/// whether anything was dropped.
+fn check_years(birth: Option<i32>, death: Option<i32>, max_year: i32) -> (Option<i32>, Option<i32>, bool) {
+  let ok = |y: &i32| (MIN_YEAR..=max_year).contains(y);
+  let (b, d) = (birth.filter(ok), death.filter(ok));
+  match (b, d) {
+    (Some(b), Some(d)) if d < b || d - b > MAX_LIFESPAN => (None, None, true),
+    _ => (b, d, b != birth || d != death)
+  }
+}
+
+/// Write a record's years, if it has any, updating the counts.
+fn finish_record<W: Write>(dst: &mut W, rec_id: i64, dates: &RecDates, max_year: i32, counts: &mut YearCounts) -> Result<()> {
+  counts.records += 1;
+  let (birth, death) = if dates.birth.is_some() || dates.death.is_some() {
+    (dates.birth, dates.death)
+  } else {
+    dates.heading.as_deref().map(life_years).unwrap_or((None, None))
+  };
+  let (birth, death, dropped) = check_years(birth, death, max_year);
+  if dropped {
+    counts.implausible += 1;
+  }
+  if birth.is_some() || death.is_some() {
+    write_row(dst, &(rec_id, birth, death))?;
+    counts.written += 1;
+  }
+  Ok(())
+}
+
+/// Extract each record's birth and death years from a stream of MARC field
+/// rows, preferring the 046 field's dates to the heading's.
+fn extract_stream<R: BufRead, W: Write>(src: R, dst: &mut W, max_year: i32) -> Result<YearCounts> {
+  let parser = FieldParser::postgres();
+  let mut counts = YearCounts::default();
+  let mut cur: Option<(i64, RecDates)> = None;
+  for (lno, line) in src.lines().enumerate() {
+    let line = line?;
+    let fields = parser.split(&line)?;
+    if fields.len() != 7 {
+      return Err(anyhow!("line {}: expected 7 fields, found {}", lno + 1, fields.len()));
+    }
+    let rec_id: i64 = fields[0].as_deref().unwrap_or("").parse()?;
+    match cur {
+      Some((id, _)) if id == rec_id => (),
+      _ => {
+        if let Some((id, ref dates)) = cur {
+          finish_record(dst, id, dates, max_year, &mut counts)?;
+        }
+        cur = Some((rec_id, RecDates::default()));
+      }
+    }
+    let dates = &mut cur.as_mut().unwrap().1;
+    let text = fields[6].as_deref().unwrap_or("");
+    match (fields[2].as_deref(), fields[5].as_deref()) {
+      (Some("046"), Some("f")) if dates.birth.is_none() => dates.birth = edtf_year(text),
+      (Some("046"), Some("g")) if dates.death.is_none() => dates.death = edtf_year(text),
+      (Some("100"), Some("d")) | (Some("700"), Some("d")) if dates.heading.is_none() => {
+        dates.heading = Some(text.to_string())
+      },
+      _ => ()
+    }
+  }
+  if let Some((id, ref dates)) = cur {
+    finish_record(dst, id, dates, max_year, &mut counts)?;
+  }
+  Ok(counts)
+}
+
+impl Command for AuthorYears {
+  fn exec(self) -> Result<()> {
+    let (read, _len) = open_input(&self.infile)?;
+    let src = BufReader::new(decompress(BufReader::new(read))?);
+    let max_year = this_year();
+    let counts = match self.table {
+      Some(ref t) => {
+        let req = CopyRequest::new(&self.db, t)?.truncate(self.truncate);
+        info!("copying to table {}", t);
+        let mut out = BufWriter::new(req.open()?);
+        extract_stream(src, &mut out, max_year)?
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        extract_stream(src, &mut out, max_year)?
+      }
+    };
+    info!("found years for {} of {} records", counts.written, counts.records);
+    if counts.implausible > 0 {
+      warn!("dropped implausible years from {} records", counts.implausible);
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_check_years() {
+  assert_eq!(check_years(Some(1892), Some(1973), 2020), (Some(1892), Some(1973), false));
+  assert_eq!(check_years(Some(1892), Some(2973), 2020), (Some(1892), None, true));
+  assert_eq!(check_years(Some(1973), Some(1892), 2020), (None, None, true));
+  assert_eq!(check_years(Some(1700), Some(1900), 2020), (None, None, true));
+  assert_eq!(check_years(None, Some(-5000), 2020), (None, None, true));
+}
+
+#[test]
+fn test_extract_stream() {
+  let src = concat!(
+    "1\t1\tLDR\t\\N\t\\N\t\\N\tnz\n",
+    "1\t2\t046\t \t \tf\t18920103\n",
+    "1\t2\t046\t \t \tg\t19730902\n",
+    "1\t3\t100\t1\t \ta\tTolkien, J. R. R.\n",
+    "1\t3\t100\t1\t \td\t1890-1970\n",
+    "2\t1\t100\t1\t \ta\tAusten, Jane\n",
+    "2\t1\t100\t1\t \td\t1775-1817.\n",
+    "3\t1\t100\t1\t \td\tfl. 1200\n",
+    "4\t1\t700\t1\t \td\t1950-\n",
+    "4\t2\t700\t1\t \td\t1951-\n",
+    "5\t1\t100\t1\t \td\t1817-1775\n");
+  let mut out = Vec::new();
+  let counts = extract_stream(src.as_bytes(), &mut out, 2020).unwrap();
+  assert_eq!(counts, YearCounts { records: 5, written: 3, implausible: 1 });
+  assert_eq!(String::from_utf8(out).unwrap(), "1\t1892\t1973\n2\t1775\t1817\n4\t1950\t\\N\n");
+}
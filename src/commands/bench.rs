@@ -0,0 +1,143 @@
+//! Measure the throughput of the import stages on a bundled sample.
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::process;
+use std::time::Instant;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use flate2::bufread::MultiGzDecoder;
+
+use structopt::StructOpt;
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::cleaning::{write_pgencoded, validate_json, clean_json};
+use super::import_json::import_discard;
+use super::Command;
+
+/// Sample OpenLibrary dump records, repeated to make the benchmark input.
+const SAMPLE: &str = include_str!("bench-sample.txt");
+/// The spec for the sample records.
+const SAMPLE_SPEC: &str = include_str!("../../import/ol-dump.toml");
+
+/// Benchmark the import stages on a bundled sample.
+#[derive(StructOpt, Debug)]
+#[structopt(name="bench")]
+pub struct Bench {
+  /// The number of sample records to process in each benchmark
+  #[structopt(short="n", long="records", default_value="100000")]
+  records: usize,
+
+  /// Only run the benchmarks whose names contain this string
+  #[structopt(name="FILTER")]
+  filter: Option<String>
+}
+
+/// A benchmark: its name and a function to process the input lines, returning
+/// the number of lines processed.
+type Bench1 = (&'static str, fn(&[&str]) -> Result<usize>);
+
+fn bench_pg_encode(lines: &[&str]) -> Result<usize> {
+  let mut out = io::sink();
+  for l in lines {
+    write_pgencoded(&mut out, l.as_bytes())?;
+  }
+  Ok(lines.len())
+}
+
+fn bench_json_clean(lines: &[&str]) -> Result<usize> {
+  let mut buf = String::new();
+  let mut json = String::new();
+  for l in lines {
+    let js = l.rsplit('\t').next().ok_or(anyhow!("sample line has no JSON"))?;
+    json.clear();
+    json.push_str(js);
+    validate_json(&mut json)?;
+    clean_json(&json, &mut buf);
+  }
+  Ok(lines.len())
+}
+
+fn bench_import(lines: &[&str]) -> Result<usize> {
+  let mut input = String::new();
+  for l in lines {
+    input.push_str(l);
+    input.push('\n');
+  }
+  import_discard(SAMPLE_SPEC, input.as_bytes())
+}
+
+/// Write the lines to a gzipped temporary file and read them back through
+/// buffers of `size` bytes, the way `import-json` reads its input.
+fn bench_buffer(lines: &[&str], size: usize) -> Result<usize> {
+  let path = env::temp_dir().join(format!("bookdata-bench-{}.gz", process::id()));
+  let res = (|| {
+    let mut out = GzEncoder::new(BufWriter::with_capacity(size, File::create(&path)?), Compression::fast());
+    for l in lines {
+      writeln!(out, "{}", l)?;
+    }
+    out.finish()?.flush()?;
+    let read = BufReader::with_capacity(size, File::open(&path)?);
+    let read = BufReader::with_capacity(size, MultiGzDecoder::new(read));
+    let mut n = 0;
+    for line in read.lines() {
+      line?;
+      n += 1;
+    }
+    Ok(n)
+  })();
+  fs::remove_file(&path)?;
+  res
+}
+
+fn bench_buffer_8k(lines: &[&str]) -> Result<usize> {
+  bench_buffer(lines, 8 << 10)
+}
+
+fn bench_buffer_64k(lines: &[&str]) -> Result<usize> {
+  bench_buffer(lines, 64 << 10)
+}
+
+fn bench_buffer_1m(lines: &[&str]) -> Result<usize> {
+  bench_buffer(lines, 1 << 20)
+}
+
+static BENCHES: &[Bench1] = &[
+  ("pg-encode", bench_pg_encode),
+  ("json-clean", bench_json_clean),
+  ("import", bench_import),
+  ("buffer-8K", bench_buffer_8k),
+  ("buffer-64K", bench_buffer_64k),
+  ("buffer-1M", bench_buffer_1m)
+];
+
+impl Command for Bench {
+  fn exec(self) -> Result<()> {
+    let sample: Vec<&str> = SAMPLE.lines().collect();
+    let lines: Vec<&str> = sample.iter().cycle().take(self.records).cloned().collect();
+    info!("benchmarking with {} records", lines.len());
+    println!("{:<12} {:>10} {:>8} {:>12}", "bench", "records", "secs", "records/sec");
+    for (name, func) in BENCHES {
+      if let Some(ref f) = self.filter {
+        if !name.contains(f.as_str()) {
+          continue;
+        }
+      }
+      let start = Instant::now();
+      let n = func(&lines)?;
+      let secs = start.elapsed().as_secs_f64();
+      println!("{:<12} {:>10} {:>8.3} {:>12.0}", name, n, secs, n as f64 / secs.max(1e-9));
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_bench_sample() {
+  let lines: Vec<&str> = SAMPLE.lines().collect();
+  for (name, func) in BENCHES {
+    assert_eq!(func(&lines).unwrap(), lines.len(), "{}", name);
+  }
+}
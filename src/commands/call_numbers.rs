@@ -0,0 +1,93 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::fs::File;
+use std::path::PathBuf;
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::ids::parse_call_number;
+use crate::io::{open_input, decompress};
+use crate::cleaning::PG_NULL;
+use super::Command;
+
+/// Parse a column of LCC or Dewey call numbers in a tab-separated stream,
+/// adding columns for the scheme, class, subclass, and sort key.
+#[derive(StructOpt, Debug)]
+#[structopt(name="parse-call-numbers")]
+pub struct CallNumbers {
+  /// The column with the call numbers (counting from 1).
+  #[structopt(short="c", long="column", default_value="1")]
+  column: usize,
+
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Input file (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// Add the parsed call number columns to each row in a stream, returning the
+/// number of rows read and call numbers that did not parse.
+fn augment_stream<R: BufRead, W: Write>(src: R, dst: &mut W, col: usize) -> Result<(usize, usize)> {
+  let mut n = 0;
+  let mut bad = 0;
+  let null = std::str::from_utf8(PG_NULL)?;
+  for line in src.lines() {
+    let line = line?;
+    n += 1;
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < col {
+      return Err(anyhow!("line {}: no column {}", n, col));
+    }
+    dst.write_all(line.as_bytes())?;
+    // parsed parts never need encoding
+    match parse_call_number(fields[col - 1]) {
+      Some(cn) => writeln!(dst, "\t{}\t{}\t{}\t{}", cn.scheme.name(), cn.class, cn.subclass, cn.sort_key)?,
+      None => {
+        bad += 1;
+        writeln!(dst, "\t{0}\t{0}\t{0}\t{0}", null)?;
+      }
+    }
+  }
+  Ok((n, bad))
+}
+
+impl Command for CallNumbers {
+  fn exec(self) -> Result<()> {
+    if self.column == 0 {
+      return Err(anyhow!("columns count from 1"));
+    }
+    let (read, _len) = open_input(&self.infile)?;
+    let src = BufReader::new(decompress(BufReader::new(read))?);
+    let (n, bad) = match self.output {
+      Some(ref p) => {
+        let mut out = BufWriter::new(File::create(p)?);
+        augment_stream(src, &mut out, self.column)?
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        augment_stream(src, &mut out, self.column)?
+      }
+    };
+    info!("parsed {} rows, {} without valid call numbers", n, bad);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_augment_stream() {
+  let src = b"1\tQA76.73.C153 2008\n2\t813/.54 H\n3\t\\N\n";
+  let mut out = Vec::new();
+  let (n, bad) = augment_stream(&src[..], &mut out, 2).unwrap();
+  assert_eq!((n, bad), (3, 1));
+  assert_eq!(String::from_utf8(out).unwrap(), concat!(
+    "1\tQA76.73.C153 2008\tlcc\tQ\tQA\tQA 0076.73 C153 2008\n",
+    "2\t813/.54 H\tddc\t800\t810\t813.54 H\n",
+    "3\t\\N\t\\N\t\\N\t\\N\t\\N\n"));
+}
@@ -0,0 +1,287 @@
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use log::*;
+
+use structopt::StructOpt;
+use postgres::Connection;
+use fallible_iterator::FallibleIterator;
+use sha1::Sha1;
+use anyhow::{Result, anyhow};
+
+use crate::tsv::split_first;
+use crate::io::HashWrite;
+use crate::db::{DbOpts, CopyRequest};
+use crate::tracking::StageOpts;
+use super::Command;
+
+/// Queries for the ISBN/record/edition/work edges in the database, as book codes.
+static EDGE_QUERIES: &[&str] = &[
+  "SELECT bc_of_isbn(isbn_id), bc_of_loc_rec(rec_id) FROM locmds.book_rec_isbn",
+  "SELECT bc_of_isbn(isbn_id), bc_of_edition(edition_id) FROM ol.isbn_link",
+  "SELECT bc_of_edition(edition_id), bc_of_work(work_id) FROM ol.isbn_link WHERE work_id IS NOT NULL",
+  "SELECT bc_of_isbn(isbn_id), bc_of_gr_book(gr_book_id) FROM gr.book_isbn",
+  "SELECT bc_of_gr_book(gr_book_id), bc_of_gr_work(gr_work_id)
+   FROM gr.book_isbn JOIN gr.book_ids USING (gr_book_id)
+   WHERE gr_work_id IS NOT NULL"
+];
+
+/// Compute connected components of the book identifier graph.
+#[derive(StructOpt, Debug)]
+#[structopt(name="cluster-books")]
+pub struct ClusterBooks {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  /// Read edges from tab-separated book code pairs instead of the database.
+  #[structopt(short="e", long="edges", parse(from_os_str))]
+  edge_files: Vec<PathBuf>,
+
+  /// The file to write the cluster assignments.
+  #[structopt(short="o", long="out-file", parse(from_os_str))]
+  out_file: Option<PathBuf>,
+
+  /// The table to write the cluster assignments.
+  #[structopt(long="out-table")]
+  out_table: Option<String>
+}
+
+/// Disjoint-set forest over book codes.
+pub struct Clusters {
+  index: HashMap<i64, usize>,
+  codes: Vec<i64>,
+  parent: Vec<usize>,
+  size: Vec<usize>,
+  edges: usize
+}
+
+impl Clusters {
+  pub fn new() -> Clusters {
+    Clusters {
+      index: HashMap::new(),
+      codes: Vec::new(),
+      parent: Vec::new(),
+      size: Vec::new(),
+      edges: 0
+    }
+  }
+
+  /// Get the position of a node, adding it if it is new.
+  pub fn node(&mut self, code: i64) -> usize {
+    if let Some(i) = self.index.get(&code) {
+      return *i;
+    }
+    let i = self.codes.len();
+    self.index.insert(code, i);
+    self.codes.push(code);
+    self.parent.push(i);
+    self.size.push(1);
+    i
+  }
+
+  /// Find the root of a node's set, halving paths as we go.
+  fn find(&mut self, mut i: usize) -> usize {
+    while self.parent[i] != i {
+      let gp = self.parent[self.parent[i]];
+      self.parent[i] = gp;
+      i = gp;
+    }
+    i
+  }
+
+  /// Add an edge, merging the sets of its endpoints.
+  pub fn add_edge(&mut self, left: i64, right: i64) {
+    let l = self.node(left);
+    let r = self.node(right);
+    let l = self.find(l);
+    let r = self.find(r);
+    self.edges += 1;
+    if l == r {
+      return;
+    }
+    let (big, small) = if self.size[l] >= self.size[r] { (l, r) } else { (r, l) };
+    self.parent[small] = big;
+    self.size[big] += self.size[small];
+  }
+
+  pub fn n_nodes(&self) -> usize {
+    self.codes.len()
+  }
+
+  pub fn n_edges(&self) -> usize {
+    self.edges
+  }
+
+  /// Compute the cluster of every node.  Clusters are identified by the
+  /// smallest book code they contain, so the result does not depend on
+  /// the order in which edges were read.
+  pub fn assignments(&mut self) -> Vec<(i64, i64)> {
+    let n = self.codes.len();
+    let mut roots = Vec::with_capacity(n);
+    let mut ids: HashMap<usize, i64> = HashMap::new();
+    for i in 0..n {
+      let r = self.find(i);
+      let code = self.codes[i];
+      let e = ids.entry(r).or_insert(code);
+      if code < *e {
+        *e = code;
+      }
+      roots.push(r);
+    }
+    let mut result = Vec::with_capacity(n);
+    for i in 0..n {
+      result.push((self.codes[i], ids[&roots[i]]));
+    }
+    result.sort();
+    result
+  }
+}
+
+/// Read edges from a tab-separated file of book code pairs.
+fn read_edge_file(path: &Path, clusters: &mut Clusters) -> Result<usize> {
+  info!("reading edges from {:?}", path);
+  let input = BufReader::new(File::open(path)?);
+  let mut n = 0;
+  for line in input.lines() {
+    let line = line?;
+    let (left, right) = split_first(&line).ok_or(anyhow!("invalid edge line: {}", line))?;
+    clusters.add_edge(left.parse()?, right.trim_end().parse()?);
+    n += 1;
+  }
+  Ok(n)
+}
+
+/// Read edges from the database.
+fn read_edge_query(db: &Connection, query: &str, clusters: &mut Clusters) -> Result<usize> {
+  info!("querying {}", query);
+  let txn = db.transaction()?;
+  let stmt = txn.prepare(query)?;
+  let mut rows = stmt.lazy_query(&txn, &[], 10000)?;
+  let mut n = 0;
+  while let Some(row) = rows.next()? {
+    let left: i32 = row.get(0);
+    let right: i32 = row.get(1);
+    clusters.add_edge(left.into(), right.into());
+    n += 1;
+  }
+  drop(rows);
+  drop(stmt);
+  txn.commit()?;
+  Ok(n)
+}
+
+impl ClusterBooks {
+  fn open_output(&self) -> Result<Box<dyn Write>> {
+    if let Some(ref tbl) = self.out_table {
+      info!("writing clusters to table {}", tbl);
+      let req = CopyRequest::new(&self.db, tbl)?;
+      let req = req.with_columns(&["book_code", "cluster"]);
+      let req = req.truncate(true);
+      Ok(Box::new(req.open()?))
+    } else if let Some(ref path) = self.out_file {
+      info!("writing clusters to file {:?}", path);
+      Ok(Box::new(OpenOptions::new().write(true).create(true).truncate(true).open(path)?))
+    } else {
+      Ok(Box::new(io::stdout()))
+    }
+  }
+}
+
+impl Command for ClusterBooks {
+  fn exec(self) -> Result<()> {
+    let db = self.db.open()?;
+    let mut stage = self.stage.begin_stage(&db)?;
+    let mut clusters = Clusters::new();
+
+    if self.edge_files.is_empty() {
+      for q in EDGE_QUERIES {
+        let n = read_edge_query(&db, q, &mut clusters)?;
+        info!("read {} edges", n);
+      }
+    } else {
+      for path in &self.edge_files {
+        let n = read_edge_file(path, &mut clusters)?;
+        writeln!(stage, "READ {:?} {}", path, n)?;
+      }
+    }
+
+    info!("clustering {} nodes with {} edges", clusters.n_nodes(), clusters.n_edges());
+    writeln!(stage, "NODES {}", clusters.n_nodes())?;
+    writeln!(stage, "EDGES {}", clusters.n_edges())?;
+    let assignments = clusters.assignments();
+
+    let out = self.open_output()?;
+    let mut hash = Sha1::new();
+    let mut ncl = 0;
+    {
+      let out = HashWrite::create(out, &mut hash);
+      let mut out = BufWriter::new(out);
+      for (code, cluster) in &assignments {
+        if code == cluster {
+          ncl += 1;
+        }
+        writeln!(out, "{}\t{}", code, cluster)?;
+      }
+      out.flush()?;
+    }
+    let hash = hash.hexdigest();
+    info!("found {} clusters", ncl);
+    writeln!(stage, "COMPONENTS {}", ncl)?;
+    writeln!(stage, "WRITE CLUSTERS {}", hash)?;
+    stage.end(&Some(hash))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_singleton() {
+  let mut cl = Clusters::new();
+  cl.node(5);
+  assert_eq!(cl.assignments(), vec![(5, 5)]);
+}
+
+#[test]
+fn test_one_edge() {
+  let mut cl = Clusters::new();
+  cl.add_edge(9, 3);
+  assert_eq!(cl.n_edges(), 1);
+  assert_eq!(cl.assignments(), vec![(3, 3), (9, 3)]);
+}
+
+#[test]
+fn test_components() {
+  let mut cl = Clusters::new();
+  cl.add_edge(900000001, 200000001);
+  cl.add_edge(200000001, 100000001);
+  cl.add_edge(900000002, 500000007);
+  cl.add_edge(900000003, 200000001);
+  let a = cl.assignments();
+  assert_eq!(cl.n_nodes(), 6);
+  assert_eq!(a, vec![
+    (100000001, 100000001),
+    (200000001, 100000001),
+    (500000007, 500000007),
+    (900000001, 100000001),
+    (900000002, 500000007),
+    (900000003, 100000001)
+  ]);
+}
+
+#[test]
+fn test_merge_order_independent() {
+  let mut c1 = Clusters::new();
+  c1.add_edge(1, 2);
+  c1.add_edge(3, 4);
+  c1.add_edge(2, 3);
+  let mut c2 = Clusters::new();
+  c2.add_edge(4, 3);
+  c2.add_edge(3, 2);
+  c2.add_edge(2, 1);
+  assert_eq!(c1.assignments(), c2.assignments());
+}
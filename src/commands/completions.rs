@@ -0,0 +1,45 @@
+use structopt::StructOpt;
+use structopt::clap::Shell;
+
+use std::io::{self, Write};
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use super::Command;
+
+/// Write a shell completion script for bookdata.
+#[derive(StructOpt, Debug)]
+#[structopt(name="completions", raw(setting="structopt::clap::AppSettings::Hidden"))]
+pub struct Completions {
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// The shell (bash, zsh, fish, powershell, or elvish)
+  #[structopt(name="SHELL")]
+  shell: Shell
+}
+
+impl Command for Completions {
+  fn exec(self) -> Result<()> {
+    let mut app = crate::app();
+    let mut out: Box<dyn Write> = match self.output {
+      Some(ref p) => Box::new(File::create(p)?),
+      None => Box::new(io::stdout())
+    };
+    app.gen_completions_to("bookdata", self.shell, &mut out);
+    out.flush()?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_completions() {
+  let mut out = Vec::new();
+  crate::app().gen_completions_to("bookdata", Shell::Bash, &mut out);
+  let script = String::from_utf8(out).unwrap();
+  assert!(script.contains("import-hathi"));
+  assert!(script.contains("--log-format"));
+}
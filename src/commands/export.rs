@@ -0,0 +1,148 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::File;
+use std::path::PathBuf;
+
+use log::*;
+use anyhow::{Result, anyhow};
+use fallible_iterator::FallibleIterator;
+use postgres::rows::Row;
+use postgres::types::{self, Type};
+
+use crate::db::DbOpts;
+use crate::columnar::{TableWriter, ColType, Value};
+use super::Command;
+
+/// Export a table or query result from the database to a Parquet file.
+///
+/// Numeric, boolean, date, and timestamp columns keep their types; other
+/// columns are exported as text.  Rows are fetched and written in chunks, so
+/// large tables do not need to fit in memory.
+#[derive(StructOpt, Debug)]
+#[structopt(name="export")]
+pub struct Export {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  /// Export this table.
+  #[structopt(short="t", long="table")]
+  table: Option<String>,
+
+  /// Export the results of this query.
+  #[structopt(short="q", long="query")]
+  query: Option<String>,
+
+  /// Number of rows to fetch from the database at a time.
+  #[structopt(long="chunk-size", default_value="10000")]
+  chunk_size: i32,
+
+  /// The Parquet file to write.
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: PathBuf
+}
+
+/// Quote an SQL identifier.
+fn quote_ident(name: &str) -> String {
+  format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Get a column's Parquet type and the SQL expression that selects it in a
+/// form the Parquet writer can read.
+fn export_column(name: &str, ty: &Type) -> (ColType, String) {
+  let col = quote_ident(name);
+  match *ty {
+    types::BOOL => (ColType::Bool, col),
+    types::INT2 => (ColType::Int32, format!("{}::int4", col)),
+    types::INT4 => (ColType::Int32, col),
+    types::INT8 => (ColType::Int64, col),
+    types::FLOAT4 => (ColType::Float, col),
+    types::FLOAT8 | types::NUMERIC => (ColType::Double, format!("{}::float8", col)),
+    types::DATE => (ColType::Date, format!("({} - DATE '1970-01-01')", col)),
+    types::TIMESTAMP | types::TIMESTAMPTZ => (ColType::Timestamp, format!("(EXTRACT(EPOCH FROM {}) * 1000000)::int8", col)),
+    types::JSON | types::JSONB => (ColType::Json, format!("{}::text", col)),
+    _ => (ColType::Text, format!("{}::text", col))
+  }
+}
+
+/// Write a row's field, as read by the expression from `export_column`.
+fn write_field<W: Write + Send>(out: &mut TableWriter<W>, row: &Row, i: usize, ct: ColType) -> Result<()> {
+  let val = match ct {
+    ColType::Bool => row.get::<_, Option<bool>>(i).map(Value::Bool),
+    ColType::Int32 | ColType::Date => row.get::<_, Option<i32>>(i).map(Value::Int32),
+    ColType::Int64 | ColType::Timestamp => row.get::<_, Option<i64>>(i).map(Value::Int64),
+    ColType::Float => row.get::<_, Option<f32>>(i).map(Value::Float),
+    ColType::Double => row.get::<_, Option<f64>>(i).map(Value::Double),
+    ColType::Text | ColType::Json => {
+      let text: Option<String> = row.get(i);
+      return match text {
+        Some(t) => out.write_field(t.as_bytes()),
+        None => out.write_null()
+      };
+    }
+  };
+  match val {
+    Some(v) => out.write_value(v),
+    None => out.write_null()
+  }
+}
+
+impl Export {
+  /// Get the query to export.
+  fn base_query(&self) -> Result<String> {
+    match (&self.table, &self.query) {
+      (Some(t), None) => Ok(format!("SELECT * FROM {}", t)),
+      (None, Some(q)) => Ok(q.trim().trim_end_matches(';').to_string()),
+      _ => Err(anyhow!("specify exactly one of --table and --query"))
+    }
+  }
+}
+
+impl Command for Export {
+  fn exec(self) -> Result<()> {
+    let query = self.base_query()?;
+    let db = self.db.open()?;
+    let txn = db.transaction()?;
+    let columns: Vec<(String, ColType, String)> = txn.prepare(&query)?.columns().iter().map(|c| {
+      let (ct, expr) = export_column(c.name(), c.type_());
+      (c.name().to_string(), ct, expr)
+    }).collect();
+    if columns.is_empty() {
+      return Err(anyhow!("query returns no columns"));
+    }
+    let exprs: Vec<&str> = columns.iter().map(|(_, _, e)| e.as_str()).collect();
+    let query = format!("SELECT {} FROM ({}) AS export", exprs.join(", "), query);
+    debug!("export query: {}", query);
+
+    let schema: Vec<(&str, ColType)> = columns.iter().map(|(n, ct, _)| (n.as_str(), *ct)).collect();
+    let name = self.output.file_stem().and_then(|s| s.to_str()).unwrap_or("export");
+    let mut out = TableWriter::create(BufWriter::new(File::create(&self.output)?), name, &schema)?;
+    info!("exporting {} columns to {:?}", schema.len(), self.output);
+    let stmt = txn.prepare(&query)?;
+    let mut rows = stmt.lazy_query(&txn, &[], self.chunk_size)?;
+    while let Some(row) = rows.next()? {
+      for (i, (_, ct)) in schema.iter().enumerate() {
+        write_field(&mut out, &row, i, *ct)?;
+      }
+      out.end_row()?;
+    }
+    drop(rows);
+    drop(stmt);
+    txn.commit()?;
+    let n = out.finish()?;
+    info!("exported {} rows", n);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_export_column() {
+  assert_eq!(export_column("id", &types::INT4), (ColType::Int32, "\"id\"".to_string()));
+  assert_eq!(export_column("n", &types::INT2), (ColType::Int32, "\"n\"::int4".to_string()));
+  assert_eq!(export_column("score", &types::NUMERIC), (ColType::Double, "\"score\"::float8".to_string()));
+  assert_eq!(export_column("pub\"date", &types::DATE), (ColType::Date, "(\"pub\"\"date\" - DATE '1970-01-01')".to_string()));
+  assert_eq!(export_column("at", &types::TIMESTAMPTZ).0, ColType::Timestamp);
+  assert_eq!(export_column("rec", &types::JSONB), (ColType::Json, "\"rec\"::text".to_string()));
+  assert_eq!(export_column("key", &types::UUID), (ColType::Text, "\"key\"::text".to_string()));
+}
@@ -0,0 +1,231 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::interactions::{Interaction, read_interactions};
+use super::Command;
+
+/// Export interactions for recommender toolkits.
+#[derive(StructOpt, Debug)]
+#[structopt(name="export-ratings")]
+pub struct ExportRatings {
+  /// The output format (csv, mtx, or bin)
+  #[structopt(short="f", long="format", default_value="csv")]
+  format: ExportFormat,
+
+  /// Export implicit feedback, without rating values.
+  #[structopt(long="implicit")]
+  implicit: bool,
+
+  /// Only export interactions with a rating of at least this value.
+  #[structopt(long="min-rating")]
+  min_rating: Option<f64>,
+
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Input file of interactions (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// Export formats for interactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExportFormat {
+  /// LensKit CSV, with a header
+  Csv,
+  /// Matrix Market coordinate matrix, with 1-based user and item indexes
+  Mtx,
+  /// Little-endian binary triples: `i64` user, `i64` item, and `f32` rating
+  Bin
+}
+
+impl FromStr for ExportFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<ExportFormat> {
+    match s {
+      "csv" => Ok(ExportFormat::Csv),
+      "mtx" => Ok(ExportFormat::Mtx),
+      "bin" => Ok(ExportFormat::Bin),
+      _ => Err(anyhow!("unknown export format {}", s))
+    }
+  }
+}
+
+/// Write interactions as LensKit CSV.
+fn write_csv<W: Write>(out: &mut W, ints: &[Interaction], implicit: bool) -> Result<()> {
+  let has_ts = ints.iter().any(|i| i.timestamp.is_some());
+  let fmt = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+  match (implicit, has_ts) {
+    (true, false) => writeln!(out, "user,item")?,
+    (true, true) => writeln!(out, "user,item,timestamp")?,
+    (false, false) => writeln!(out, "user,item,rating")?,
+    (false, true) => writeln!(out, "user,item,rating,timestamp")?
+  }
+  for i in ints {
+    write!(out, "{},{}", i.user, i.item)?;
+    if !implicit {
+      write!(out, ",{}", fmt(i.rating))?;
+    }
+    if has_ts {
+      write!(out, ",{}", fmt(i.timestamp))?;
+    }
+    writeln!(out)?;
+  }
+  Ok(())
+}
+
+/// Assign each distinct ID a 0-based index, in order of first appearance.
+fn index_ids<'a, I: Iterator<Item=&'a str>>(ids: I) -> (HashMap<&'a str, usize>, Vec<&'a str>) {
+  let mut index = HashMap::new();
+  let mut list = Vec::new();
+  for id in ids {
+    index.entry(id).or_insert_with(|| {
+      list.push(id);
+      list.len() - 1
+    });
+  }
+  (index, list)
+}
+
+/// Write interactions as a Matrix Market coordinate matrix, returning the
+/// user and item IDs in index order.
+fn write_mtx<'a, W: Write>(out: &mut W, ints: &'a [Interaction], implicit: bool) -> Result<(Vec<&'a str>, Vec<&'a str>)> {
+  let (uidx, users) = index_ids(ints.iter().map(|i| i.user.as_str()));
+  let (iidx, items) = index_ids(ints.iter().map(|i| i.item.as_str()));
+  let field = if implicit { "pattern" } else { "real" };
+  writeln!(out, "%%MatrixMarket matrix coordinate {} general", field)?;
+  writeln!(out, "{} {} {}", users.len(), items.len(), ints.len())?;
+  for i in ints {
+    let (u, it) = (uidx[i.user.as_str()] + 1, iidx[i.item.as_str()] + 1);
+    if implicit {
+      writeln!(out, "{} {}", u, it)?;
+    } else {
+      let r = i.rating.ok_or_else(|| anyhow!("interaction of {} with {} has no rating", i.user, i.item))?;
+      writeln!(out, "{} {} {}", u, it, r)?;
+    }
+  }
+  Ok((users, items))
+}
+
+/// Write interactions as binary triples.  Implicit interactions have rating 1.
+fn write_bin<W: Write>(out: &mut W, ints: &[Interaction], implicit: bool) -> Result<()> {
+  for i in ints {
+    let user: i64 = i.user.parse().map_err(|_| anyhow!("binary export needs integer IDs, found user {:?}", i.user))?;
+    let item: i64 = i.item.parse().map_err(|_| anyhow!("binary export needs integer IDs, found item {:?}", i.item))?;
+    let rating = match (implicit, i.rating) {
+      (true, _) => 1.0,
+      (false, Some(r)) => r as f32,
+      (false, None) => f32::NAN
+    };
+    out.write_all(&user.to_le_bytes())?;
+    out.write_all(&item.to_le_bytes())?;
+    out.write_all(&rating.to_le_bytes())?;
+  }
+  Ok(())
+}
+
+/// Write a list of IDs, one per line.
+fn write_ids(path: &Path, ids: &[&str]) -> Result<()> {
+  let mut out = BufWriter::new(File::create(path)?);
+  for id in ids {
+    writeln!(out, "{}", id)?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+impl ExportRatings {
+  /// Write interactions in the requested format, returning the user and item
+  /// ID lists for formats that index them.
+  fn write<'a, W: Write>(&self, out: &mut W, ints: &'a [Interaction]) -> Result<Option<(Vec<&'a str>, Vec<&'a str>)>> {
+    match self.format {
+      ExportFormat::Csv => write_csv(out, ints, self.implicit)?,
+      ExportFormat::Mtx => return Ok(Some(write_mtx(out, ints, self.implicit)?)),
+      ExportFormat::Bin => write_bin(out, ints, self.implicit)?
+    }
+    Ok(None)
+  }
+}
+
+impl Command for ExportRatings {
+  fn exec(self) -> Result<()> {
+    if self.format == ExportFormat::Mtx && self.output.is_none() {
+      return Err(anyhow!("Matrix Market export needs an output file for its ID lists"));
+    }
+    let mut ints = Vec::new();
+    let mut dropped = 0;
+    for i in read_interactions(&self.infile)? {
+      let i = i?;
+      match (self.min_rating, i.rating) {
+        (Some(min), Some(r)) if r < min => dropped += 1,
+        (Some(_), None) => dropped += 1,
+        _ => ints.push(i)
+      }
+    }
+    if dropped > 0 {
+      info!("dropped {} interactions below minimum rating", dropped);
+    }
+    info!("exporting {} interactions", ints.len());
+
+    let ids = match self.output {
+      Some(ref p) => {
+        let mut out = BufWriter::new(File::create(p)?);
+        let ids = self.write(&mut out, &ints)?;
+        out.flush()?;
+        ids
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let ids = self.write(&mut out, &ints)?;
+        out.flush()?;
+        ids
+      }
+    };
+    if let (Some((users, items)), Some(p)) = (ids, &self.output) {
+      write_ids(&p.with_extension("users"), &users)?;
+      write_ids(&p.with_extension("items"), &items)?;
+      info!("indexed {} users and {} items", users.len(), items.len());
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_export_formats() {
+  let ints: Vec<Interaction> = ["u1\t10\t4\t100", "u2\t10\t2.5\t200", "u1\t20\t5\t300"].iter()
+    .map(|l| Interaction::parse(l).unwrap()).collect();
+  let mut out = Vec::new();
+  write_csv(&mut out, &ints, false).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(), "user,item,rating,timestamp\nu1,10,4,100\nu2,10,2.5,200\nu1,20,5,300\n");
+  let mut out = Vec::new();
+  write_csv(&mut out, &ints, true).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(), "user,item,timestamp\nu1,10,100\nu2,10,200\nu1,20,300\n");
+
+  let mut out = Vec::new();
+  let (users, items) = write_mtx(&mut out, &ints, false).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(), "%%MatrixMarket matrix coordinate real general\n2 2 3\n1 1 4\n2 1 2.5\n1 2 5\n");
+  assert_eq!((users, items), (vec!["u1", "u2"], vec!["10", "20"]));
+  let mut out = Vec::new();
+  write_mtx(&mut out, &ints, true).unwrap();
+  assert!(String::from_utf8(out).unwrap().starts_with("%%MatrixMarket matrix coordinate pattern general\n2 2 3\n1 1\n"));
+
+  let numeric: Vec<Interaction> = ["7\t10\t4"].iter().map(|l| Interaction::parse(l).unwrap()).collect();
+  let mut out = Vec::new();
+  write_bin(&mut out, &numeric, false).unwrap();
+  assert_eq!(out.len(), 20);
+  assert_eq!(&out[..8], &7i64.to_le_bytes());
+  assert_eq!(&out[16..], &4f32.to_le_bytes());
+  assert!(write_bin(&mut Vec::new(), &ints, false).is_err());
+}
@@ -0,0 +1,111 @@
+use structopt::StructOpt;
+
+use std::collections::HashSet;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::ids::normalize_oclc;
+use crate::io::{open_input, decompress};
+use crate::tsv::{FieldParser, write_row};
+use crate::db::{DbOpts, CopyRequest};
+use super::Command;
+
+/// Extract a record-to-OCLC number table from MARC fields.
+#[derive(StructOpt, Debug)]
+#[structopt(name="extract-oclc")]
+pub struct ExtractOCLC {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  /// Copy the table into this database table instead of writing it to stdout.
+  #[structopt(short="t", long="table")]
+  table: Option<String>,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Input file of MARC field rows, as written by parse-marc (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// Write each record's distinct OCLC numbers from the 035 fields in a stream of
+/// MARC field rows, returning the number of numbers and unusable 035s.
+fn extract_stream<R: BufRead, W: Write>(src: R, dst: &mut W) -> Result<(usize, usize)> {
+  let parser = FieldParser::postgres();
+  let mut rec = None;
+  let mut seen = HashSet::new();
+  let mut n = 0;
+  let mut bad = 0;
+  for (lno, line) in src.lines().enumerate() {
+    let line = line?;
+    let fields = parser.split(&line)?;
+    if fields.len() != 7 {
+      return Err(anyhow!("line {}: expected 7 fields, found {}", lno + 1, fields.len()));
+    }
+    if fields[2].as_deref() != Some("035") || fields[5].as_deref() != Some("a") {
+      continue;
+    }
+    let rec_id: i64 = fields[0].as_deref().unwrap_or("").parse()?;
+    if rec != Some(rec_id) {
+      rec = Some(rec_id);
+      seen.clear();
+    }
+    // 035 also holds other systems' numbers, which do not count as unusable
+    let text = fields[6].as_deref().unwrap_or("");
+    match normalize_oclc(text) {
+      Some(oclc) if seen.insert(oclc) => {
+        write_row(dst, &(rec_id, oclc))?;
+        n += 1;
+      },
+      Some(_) => (),
+      None if text.contains("OCoLC") => bad += 1,
+      None => ()
+    }
+  }
+  Ok((n, bad))
+}
+
+impl Command for ExtractOCLC {
+  fn exec(self) -> Result<()> {
+    let (read, _len) = open_input(&self.infile)?;
+    let src = BufReader::new(decompress(BufReader::new(read))?);
+    let (n, bad) = match self.table {
+      Some(ref t) => {
+        let req = CopyRequest::new(&self.db, t)?.truncate(self.truncate);
+        info!("copying to table {}", t);
+        let mut out = BufWriter::new(req.open()?);
+        extract_stream(src, &mut out)?
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        extract_stream(src, &mut out)?
+      }
+    };
+    info!("extracted {} OCLC numbers, {} unusable", n, bad);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_extract_stream() {
+  let src = concat!(
+    "1\t1\t001\t\\N\t\\N\t\\N\t12345\n",
+    "1\t2\t035\t \t \ta\t(OCoLC)ocm00012345\n",
+    "1\t3\t035\t \t \ta\t(OCoLC)12345\n",
+    "1\t4\t035\t \t \tz\t(OCoLC)99999\n",
+    "2\t2\t035\t \t \ta\t(DLC)   85000002\n",
+    "2\t3\t035\t \t \ta\t(OCoLC)ocn123456789\n",
+    "2\t4\t035\t \t \ta\t(OCoLC)garbage\n",
+    "3\t2\t035\t \t \ta\t(OCoLC)12345\n");
+  let mut out = Vec::new();
+  let (n, bad) = extract_stream(src.as_bytes(), &mut out).unwrap();
+  assert_eq!((n, bad), (3, 1));
+  assert_eq!(String::from_utf8(out).unwrap(), "1\t12345\n2\t123456789\n3\t12345\n");
+  assert!(extract_stream(&b"1\t2\t035\n"[..], &mut Vec::new()).is_err());
+}
@@ -0,0 +1,309 @@
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::fs::{self, File, OpenOptions, read_to_string};
+use std::path::{Path, PathBuf};
+
+use log::*;
+
+use structopt::StructOpt;
+use serde::Deserialize;
+use sha2::{Sha256, Digest};
+use md5::Md5;
+use anyhow::{Result, anyhow};
+
+use crate::io::{HashRead, sha256_hex};
+use crate::logging::{set_progress, progress_bar, wrap_read};
+use super::Command;
+
+/// Download source data files listed in a manifest, verifying their checksums.
+#[derive(StructOpt, Debug)]
+#[structopt(name="fetch")]
+pub struct Fetch {
+  /// The manifest of files to download
+  #[structopt(short="m", long="manifest", default_value="data/manifest.toml", parse(from_os_str))]
+  manifest: PathBuf,
+
+  /// The directory in which to store downloaded files
+  #[structopt(short="d", long="data-dir", default_value="data", parse(from_os_str))]
+  data_dir: PathBuf,
+
+  /// Only verify files that are already present
+  #[structopt(long="verify")]
+  verify_only: bool,
+
+  /// Download files even if they are already present
+  #[structopt(long="force")]
+  force: bool,
+
+  /// Paths (from the manifest) to fetch; fetches all files if omitted
+  #[structopt(name="FILE")]
+  files: Vec<String>
+}
+
+/// The download manifest.
+#[derive(Deserialize, Debug)]
+struct Manifest {
+  #[serde(rename="file")]
+  files: Vec<FileSpec>
+}
+
+/// Checksums in the manifest: one for a single file, or a list with one for
+/// each part of a series.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum Checksums {
+  One(String),
+  Parts(Vec<String>)
+}
+
+impl Checksums {
+  /// Get the checksum of a single file, or of part `i` (from 0) of a series.
+  fn get(&self, path: &str, part: Option<usize>, nparts: usize) -> Result<String> {
+    match (self, part) {
+      (Checksums::One(h), None) => Ok(h.clone()),
+      (Checksums::Parts(hs), Some(i)) if hs.len() == nparts => Ok(hs[i].clone()),
+      (Checksums::Parts(hs), Some(_)) => Err(anyhow!("{}: {} checksums for {} parts", path, hs.len(), nparts)),
+      (Checksums::One(_), Some(_)) => Err(anyhow!("{}: a series needs a list of checksums", path)),
+      (Checksums::Parts(_), None) => Err(anyhow!("{}: a single file needs a single checksum", path))
+    }
+  }
+}
+
+/// A file in the manifest.  If `parts` is set, the file is a numbered series,
+/// and `{part}` in the path and URL is replaced with the two-digit part number.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct FileSpec {
+  path: String,
+  url: String,
+  #[serde(default)]
+  sha256: Option<Checksums>,
+  #[serde(default)]
+  md5: Option<Checksums>,
+  #[serde(default)]
+  parts: Option<u32>
+}
+
+/// A single file to fetch, with its expected checksums.
+#[derive(Debug, Clone, PartialEq)]
+struct FetchFile {
+  path: String,
+  url: String,
+  sha256: Option<String>,
+  md5: Option<String>
+}
+
+impl FileSpec {
+  /// Expand a series into its individual files.
+  fn expand(&self) -> Result<Vec<FetchFile>> {
+    let n = self.parts.unwrap_or(0) as usize;
+    let file = |part: Option<usize>| -> Result<FetchFile> {
+      let pstr = part.map(|i| format!("{:02}", i + 1)).unwrap_or_default();
+      let check = |c: &Option<Checksums>| c.as_ref().map(|c| c.get(&self.path, part, n)).transpose();
+      Ok(FetchFile {
+        path: self.path.replace("{part}", &pstr),
+        url: self.url.replace("{part}", &pstr),
+        sha256: check(&self.sha256)?,
+        md5: check(&self.md5)?
+      })
+    };
+    match self.parts {
+      None => Ok(vec![file(None)?]),
+      Some(_) => (0..n).map(|i| file(Some(i))).collect()
+    }
+  }
+}
+
+impl FetchFile {
+  fn has_checksum(&self) -> bool {
+    self.sha256.is_some() || self.md5.is_some()
+  }
+
+  /// Compare a file's checksums with the expected ones, returning a description
+  /// of the first mismatch.
+  fn mismatch(&self, sha256: &str, md5: &str) -> Option<String> {
+    let checks = [("sha256", &self.sha256, sha256), ("md5", &self.md5, md5)];
+    checks.iter().find_map(|(name, exp, found)| match exp {
+      Some(e) if !e.eq_ignore_ascii_case(found) => Some(format!("{} mismatch (expected {}, found {})", name, e, found)),
+      _ => None
+    })
+  }
+}
+
+/// Compute the SHA-256 and MD5 checksums of a file in one pass.
+fn file_checksums(path: &Path) -> Result<(String, String)> {
+  let mut hashes = (Sha256::new(), Md5::new());
+  io::copy(&mut HashRead::create(File::open(path)?, &mut hashes), &mut io::sink())?;
+  let md5 = hashes.1.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+  Ok((sha256_hex(hashes.0), md5))
+}
+
+/// Get the partial-download path for a file.
+fn part_path(path: &Path) -> PathBuf {
+  let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+  name.push(".part");
+  path.with_file_name(name)
+}
+
+/// Download a URL to a file, resuming a previous partial download if there is one.
+fn download(url: &str, dest: &Path) -> Result<()> {
+  let part = part_path(dest);
+  let have = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+  let mut req = ureq::get(url);
+  if have > 0 {
+    info!("resuming {} at byte {}", url, have);
+    req = req.set("Range", &format!("bytes={}-", have));
+  } else {
+    info!("downloading {}", url);
+  }
+  let resp = match req.call() {
+    Ok(r) => r,
+    Err(ureq::Error::Status(416, _)) if have > 0 => {
+      info!("{:?} is already complete", part);
+      fs::rename(&part, dest)?;
+      return Ok(());
+    },
+    Err(e) => return Err(anyhow!("{}: {}", url, e))
+  };
+  let append = resp.status() == 206;
+  let start = if append { have } else { 0 };
+  let len: Option<u64> = resp.header("Content-Length").and_then(|l| l.parse().ok());
+
+  let pb = progress_bar(len.map(|l| l + start), "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})");
+  pb.set_position(start);
+  let _pbl = set_progress(&pb);
+
+  if let Some(dir) = dest.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  let out = if append {
+    OpenOptions::new().append(true).open(&part)?
+  } else {
+    OpenOptions::new().write(true).create(true).truncate(true).open(&part)?
+  };
+  let mut out = BufWriter::new(out);
+  let mut read = wrap_read(&pb, resp.into_reader());
+  io::copy(&mut read, &mut out)?;
+  out.flush()?;
+  drop(out);
+  pb.finish_and_clear();
+  fs::rename(&part, dest)?;
+  Ok(())
+}
+
+impl Fetch {
+  /// Fetch (or verify) a single file.
+  fn fetch_file(&self, spec: &FetchFile) -> Result<()> {
+    let dest = self.data_dir.join(&spec.path);
+    let present = dest.exists();
+    if self.verify_only && !present {
+      return Err(anyhow!("{:?} is missing", dest));
+    }
+    if present && !self.force && !spec.has_checksum() && !self.verify_only {
+      info!("{:?} already present", dest);
+      return Ok(());
+    }
+    let downloaded = !self.verify_only && (!present || self.force);
+    if downloaded {
+      download(&spec.url, &dest)?;
+    }
+
+    let (sha256, md5) = file_checksums(&dest)?;
+    match spec.mismatch(&sha256, &md5) {
+      Some(msg) if downloaded => {
+        error!("{:?}: {}", dest, msg);
+        fs::remove_file(&dest)?;
+        Err(anyhow!("downloaded file {:?} failed verification", dest))
+      },
+      Some(msg) => Err(anyhow!("existing file {:?}: {}", dest, msg)),
+      None if spec.has_checksum() => {
+        info!("{:?}: verified {}", dest, sha256);
+        Ok(())
+      },
+      None => {
+        info!("{:?}: sha256 {}", dest, sha256);
+        Ok(())
+      }
+    }
+  }
+}
+
+impl Command for Fetch {
+  fn exec(self) -> Result<()> {
+    info!("reading manifest {:?}", &self.manifest);
+    let manifest: Manifest = toml::from_str(&read_to_string(&self.manifest)?)?;
+    let mut specs = Vec::new();
+    for f in &manifest.files {
+      specs.extend(f.expand()?);
+    }
+    if !self.files.is_empty() {
+      specs.retain(|s| self.files.iter().any(|f| s.path == *f || s.path.starts_with(&format!("{}/", f))));
+      if specs.is_empty() {
+        return Err(anyhow!("no manifest entries match {:?}", self.files));
+      }
+    }
+
+    let mut nfail = 0;
+    for spec in &specs {
+      if let Err(e) = self.fetch_file(spec) {
+        error!("{}: {}", spec.path, e);
+        nfail += 1;
+      }
+    }
+    if nfail > 0 {
+      Err(anyhow!("{} of {} files failed", nfail, specs.len()))
+    } else {
+      info!("{} files ready", specs.len());
+      Ok(())
+    }
+  }
+}
+
+#[test]
+fn test_parse_manifest() {
+  let m: Manifest = toml::from_str(r#"
+[[file]]
+path = "ol_dump_works.txt.gz"
+url = "https://openlibrary.org/data/ol_dump_works_2020-02-29.txt.gz"
+sha256 = "abc"
+
+[[file]]
+path = "loc-books/BooksAll.2016.part{part}.xml.gz"
+url = "https://www.loc.gov/cds/downloads/MDSConnect/BooksAll.2016.part{part}.xml.gz"
+parts = 3
+md5 = ["a1", "b2", "c3"]
+"#).unwrap();
+  assert_eq!(m.files.len(), 2);
+  let files = m.files[0].expand().unwrap();
+  assert_eq!(files.len(), 1);
+  assert_eq!(files[0].sha256, Some("abc".to_string()));
+  assert_eq!(files[0].path, "ol_dump_works.txt.gz");
+  let parts = m.files[1].expand().unwrap();
+  assert_eq!(parts.len(), 3);
+  assert_eq!(parts[0].path, "loc-books/BooksAll.2016.part01.xml.gz");
+  assert_eq!(parts[2].url, "https://www.loc.gov/cds/downloads/MDSConnect/BooksAll.2016.part03.xml.gz");
+  assert_eq!(parts[1].md5, Some("b2".to_string()));
+  assert_eq!(parts[1].sha256, None);
+  assert_eq!(parts[2].mismatch("x", "C3"), None);
+  assert!(parts[2].mismatch("x", "a1").unwrap().starts_with("md5 mismatch"));
+
+  let mut bad = m.files[1].clone();
+  bad.md5 = Some(Checksums::Parts(vec!["a1".to_string()]));
+  assert!(bad.expand().is_err());
+  bad.md5 = Some(Checksums::One("a1".to_string()));
+  assert!(bad.expand().is_err());
+}
+
+#[test]
+fn test_file_checksums() {
+  let path = std::env::temp_dir().join(format!("bookdata-fetch-{}.txt", std::process::id()));
+  fs::write(&path, b"abc").unwrap();
+  let (sha256, md5) = file_checksums(&path).unwrap();
+  fs::remove_file(&path).unwrap();
+  assert_eq!(sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+  assert_eq!(md5, "900150983cd24fb0d6963f7d28e17f72");
+}
+
+#[test]
+fn test_part_path() {
+  assert_eq!(part_path(Path::new("data/foo.xml.gz")), PathBuf::from("data/foo.xml.gz.part"));
+}
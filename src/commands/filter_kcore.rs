@@ -0,0 +1,161 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::fs::File;
+use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::interactions::{Interaction, read_interactions};
+use super::Command;
+
+/// Filter interactions to their k-core.
+///
+/// Users and items with fewer than k interactions are removed, repeatedly,
+/// until every remaining user and item has at least k.  The input is re-read
+/// on each pass, so only the user and item IDs are kept in memory.
+#[derive(StructOpt, Debug)]
+#[structopt(name="filter-kcore")]
+pub struct FilterKCore {
+  /// The minimum interactions for each user and item.
+  #[structopt(short="k", long="k", default_value="5")]
+  k: usize,
+
+  /// The minimum interactions for each user (default k).
+  #[structopt(long="user-k")]
+  user_k: Option<usize>,
+
+  /// The minimum interactions for each item (default k).
+  #[structopt(long="item-k")]
+  item_k: Option<usize>,
+
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Input file of interactions (TSV or Parquet)
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// The users and items remaining in the core.
+#[derive(Debug, Default)]
+struct Core {
+  users: HashSet<String>,
+  items: HashSet<String>
+}
+
+impl Core {
+  fn contains(&self, i: &Interaction) -> bool {
+    self.users.contains(&i.user) && self.items.contains(&i.item)
+  }
+}
+
+/// Count the interactions of each user and item, among those in the current
+/// core (or all of them, on the first pass), and keep those with enough.
+fn prune<I>(ints: I, core: Option<&Core>, user_k: usize, item_k: usize) -> Result<Core>
+where I: Iterator<Item=Result<Interaction>>
+{
+  let mut ucounts: HashMap<String, usize> = HashMap::new();
+  let mut icounts: HashMap<String, usize> = HashMap::new();
+  for i in ints {
+    let i = i?;
+    if core.map(|c| c.contains(&i)).unwrap_or(true) {
+      *ucounts.entry(i.user).or_default() += 1;
+      *icounts.entry(i.item).or_default() += 1;
+    }
+  }
+  Ok(Core {
+    users: ucounts.into_iter().filter(|(_, n)| *n >= user_k).map(|(u, _)| u).collect(),
+    items: icounts.into_iter().filter(|(_, n)| *n >= item_k).map(|(i, _)| i).collect()
+  })
+}
+
+/// Find the k-core, calling `open` to read the interactions for each pass.
+/// Returns the core and the number of passes.
+fn find_core<F, I>(open: F, user_k: usize, item_k: usize) -> Result<(Core, usize)>
+where F: Fn() -> Result<I>, I: Iterator<Item=Result<Interaction>>
+{
+  let mut core = prune(open()?, None, user_k, item_k)?;
+  let mut passes = 1;
+  loop {
+    info!("pass {}: {} users and {} items remain", passes, core.users.len(), core.items.len());
+    let next = prune(open()?, Some(&core), user_k, item_k)?;
+    passes += 1;
+    // the core only shrinks, so it has converged when its size stops changing
+    let done = next.users.len() == core.users.len() && next.items.len() == core.items.len();
+    core = next;
+    if done {
+      return Ok((core, passes));
+    }
+  }
+}
+
+/// Write the interactions in the core, returning how many were written.
+fn write_core<I, W>(ints: I, core: &Core, out: &mut W) -> Result<usize>
+where I: Iterator<Item=Result<Interaction>>, W: Write
+{
+  let mut n = 0;
+  for i in ints {
+    let i = i?;
+    if core.contains(&i) {
+      i.write_tsv(out)?;
+      n += 1;
+    }
+  }
+  Ok(n)
+}
+
+impl Command for FilterKCore {
+  fn exec(self) -> Result<()> {
+    if self.infile.to_str() == Some("-") {
+      return Err(anyhow!("k-core filtering reads its input several times, and cannot use stdin"));
+    }
+    let user_k = self.user_k.unwrap_or(self.k);
+    let item_k = self.item_k.unwrap_or(self.k);
+    let open = || read_interactions(&self.infile);
+    let (core, passes) = find_core(open, user_k, item_k)?;
+    info!("converged after {} passes with {} users and {} items", passes, core.users.len(), core.items.len());
+    let n = match self.output {
+      Some(ref p) => {
+        let mut out = BufWriter::new(File::create(p)?);
+        let n = write_core(open()?, &core, &mut out)?;
+        out.flush()?;
+        n
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let n = write_core(open()?, &core, &mut out)?;
+        out.flush()?;
+        n
+      }
+    };
+    info!("wrote {} interactions", n);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_kcore() {
+  // u3 and u5 have one interaction each; removing u5 leaves item e with only one
+  let lines = ["u1\ta", "u1\tb", "u2\ta", "u2\tb", "u1\tc", "u2\tc", "u3\tc", "u2\td", "u4\tc", "u4\td", "u1\te", "u5\te"];
+  let open = || -> Result<_> { Ok(lines.iter().map(|l| Interaction::parse(l))) };
+  let (core, passes) = find_core(open, 2, 2).unwrap();
+  let mut users: Vec<&str> = core.users.iter().map(String::as_str).collect();
+  users.sort_unstable();
+  assert_eq!(users, vec!["u1", "u2", "u4"]);
+  let mut items: Vec<&str> = core.items.iter().map(String::as_str).collect();
+  items.sort_unstable();
+  assert_eq!(items, vec!["a", "b", "c", "d"]);
+  assert_eq!(passes, 3);
+  let mut out = Vec::new();
+  let n = write_core(open().unwrap(), &core, &mut out).unwrap();
+  assert_eq!(n, 9);
+
+  let (core, _) = find_core(open, 3, 3).unwrap();
+  assert!(core.users.is_empty() && core.items.is_empty());
+}
@@ -5,13 +5,13 @@ use std::fs::File;
 use std::path::PathBuf;
 use std::mem::drop;
 use log::*;
-use indicatif::{ProgressBar, ProgressStyle};
 use postgres::Connection;
 use sha1::Sha1;
 use anyhow::Result;
 
 use super::Command;
 use crate::db;
+use crate::logging::{progress_bar, wrap_read};
 
 const PB_STYLE: &'static str = "{prefix}: {elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})";
 
@@ -70,10 +70,9 @@ impl Command for Hash {
       let fstr = inf.to_str().unwrap();
       info!("opening file {}", fstr);
       let fs = File::open(inf)?;
-      let pb = ProgressBar::new(fs.metadata().unwrap().len());
-      pb.set_style(ProgressStyle::default_bar().template(PB_STYLE));
+      let pb = progress_bar(Some(fs.metadata().unwrap().len()), PB_STYLE);
       pb.set_prefix(fstr);
-      let mut pbr = pb.wrap_read(fs);
+      let mut pbr = wrap_read(&pb, fs);
       let mut hash = H { sha: Sha1::new() };
       io::copy(&mut pbr, &mut hash)?;
       drop(pbr);
@@ -0,0 +1,259 @@
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+
+use structopt::StructOpt;
+use sha1::Sha1;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+use crate::ids::{normalize_asin, isbn10_valid};
+use crate::io::HashWrite;
+use crate::tsv::write_record;
+use crate::columnar::ColType::{self, *};
+use crate::sink::{RowSink, BinarySink};
+use crate::db::{DbOpts, CopyRequest, CopyFormat};
+use crate::output::OutputOpts;
+use crate::tracking::StageOpts;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
+use super::Command;
+
+/// The columns of the ratings table.
+const COLUMNS: &[(&str, ColType)] = &[
+  ("user_key", Text), ("user_hash", Int64), ("asin", Text), ("rating", Float), ("rating_time", Int64)
+];
+
+/// Import Amazon ratings CSV files into a PostgreSQL table.
+#[derive(StructOpt, Debug)]
+#[structopt(name="import-az-ratings")]
+pub struct ImportAZ {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  #[structopt(flatten)]
+  output: OutputOpts,
+
+  /// The table to write the ratings.
+  #[structopt(short="t", long="table", default_value="az.raw_ratings")]
+  table: String,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// The COPY format (text or binary) for writing to the table.
+  #[structopt(long="format", default_value="text")]
+  format: CopyFormat,
+
+  /// Write malformed rows to this file instead of failing.
+  #[structopt(long="rejects", parse(from_os_str))]
+  rejects: Option<PathBuf>,
+
+  /// Input file
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// A single cleaned rating row.
+#[derive(Serialize, Debug, PartialEq)]
+struct AZRating<'a> {
+  user_key: &'a str,
+  user_hash: i64,
+  asin: String,
+  rating: f32,
+  time: i64
+}
+
+/// Hash a user key to a stable non-negative 64-bit integer.
+fn hash_user(key: &str) -> i64 {
+  let mut h = Sha1::new();
+  h.update(key.as_bytes());
+  let digest = h.digest().bytes();
+  let mut v: u64 = 0;
+  for b in &digest[0..8] {
+    v = (v << 8) | (*b as u64);
+  }
+  (v >> 1) as i64
+}
+
+/// Parse a line of the ratings CSV.
+fn parse_line(line: &str) -> Result<AZRating<'_>> {
+  let fields: Vec<&str> = line.trim_end().split(',').collect();
+  if fields.len() != 4 {
+    return Err(anyhow!("expected 4 fields, found {}", fields.len()));
+  }
+  let user_key = fields[0].trim();
+  if user_key.is_empty() {
+    return Err(anyhow!("empty user key"));
+  }
+  let asin = normalize_asin(fields[1]).ok_or(anyhow!("invalid ASIN {}", fields[1]))?;
+  if asin.len() == 10 && asin.as_bytes()[0].is_ascii_digit() && !isbn10_valid(&asin) {
+    debug!("ASIN {} has a bad ISBN check digit", asin);
+  }
+  let rating: f32 = fields[2].trim().parse()?;
+  if !(0.0..=5.0).contains(&rating) {
+    return Err(anyhow!("rating {} out of range", rating));
+  }
+  let time: i64 = fields[3].trim().parse()?;
+  Ok(AZRating {
+    user_key,
+    user_hash: hash_user(user_key),
+    asin,
+    rating,
+    time
+  })
+}
+
+impl Command for ImportAZ {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    if self.format == CopyFormat::Binary && !self.output.is_db() {
+      return Err(anyhow!("binary format only works with database output"));
+    }
+    let dbc = self.output.open_tracking(&self.db)?;
+    if self.stage.should_skip(dbc.as_ref(), "import-az-ratings", &self.infile)? {
+      return Ok(());
+    }
+    let mut stage = self.output.begin_stage(&self.stage, dbc.as_ref())?;
+
+    let infn = &self.infile;
+    info!("reading from {:?}", infn);
+    let fs = File::open(infn)?;
+    let pb = progress_bar(Some(fs.metadata()?.len()), "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})");
+    let _pbl = set_progress(&pb);
+    let mut in_sf = stage.source_file(infn);
+    let read = in_sf.wrap_read(fs);
+    let read = BufReader::new(wrap_read(&pb, read));
+
+    let mut rejects = match self.rejects {
+      Some(ref p) => {
+        info!("writing rejects to {:?}", p);
+        let f = OpenOptions::new().write(true).create(true).truncate(true).open(p)?;
+        Some(BufWriter::new(f))
+      },
+      None => None
+    };
+
+    // dry runs count text rows, whatever the format
+    let format = if self.output.is_dry_run() { CopyFormat::Text } else { self.format };
+    let mut out_hash = Sha1::new();
+    let mut out: Box<dyn RowSink> = if format == CopyFormat::Binary {
+      let names: Vec<&str> = COLUMNS.iter().map(|(c, _)| *c).collect();
+      let types: Vec<ColType> = COLUMNS.iter().map(|(_, t)| *t).collect();
+      let req = CopyRequest::new(&self.db, &self.table)?;
+      let req = req.with_columns(&names);
+      let req = req.truncate(self.truncate);
+      let req = req.with_copy_format(format);
+      let out = BufWriter::new(HashWrite::create(req.open()?, &mut out_hash));
+      Box::new(BinarySink::create(out, &types)?)
+    } else {
+      self.output.open(&self.db, &self.table, COLUMNS, self.truncate, &mut out_hash)?
+    };
+
+    let mut n = 0;
+    let mut nbad = 0;
+    for (i, line) in read.lines().enumerate() {
+      let line = line?;
+      match parse_line(&line) {
+        Ok(r) => {
+          write_record(&mut out, &r)?;
+          n += 1;
+          count_records(1);
+        },
+        Err(e) => {
+          nbad += 1;
+          match rejects {
+            Some(ref mut w) => writeln!(w, "{}\t{}\t{}", i + 1, e, line)?,
+            None => {
+              error!("line {}: {}", i + 1, e);
+              return Err(e);
+            }
+          }
+        }
+      }
+    }
+    out.finish()?;
+    if let Some(mut w) = rejects {
+      w.flush()?;
+    }
+
+    let in_hash = in_sf.record()?;
+    let out_hash = out_hash.hexdigest();
+    info!("imported {} ratings, rejected {}", n, nbad);
+    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
+    writeln!(&mut stage, "{} RATINGS", n)?;
+    writeln!(&mut stage, "{} REJECTED", nbad)?;
+    writeln!(&mut stage, "HASH {}", out_hash)?;
+    stage.log_import("import-az-ratings", infn, &in_hash, n, started)?;
+    stage.end(&Some(out_hash))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_parse_good() {
+  let r = parse_line("AH2L9G3DQHHAJ,0000000116,4.0,1019865600").unwrap();
+  assert_eq!(r.user_key, "AH2L9G3DQHHAJ");
+  assert_eq!(r.user_hash, hash_user("AH2L9G3DQHHAJ"));
+  assert_eq!(r.asin, "0000000116");
+  assert_eq!(r.rating, 4.0);
+  assert_eq!(r.time, 1019865600);
+}
+
+#[test]
+fn test_parse_normalizes() {
+  let r = parse_line("A2IIIDRK3PRRZY,0-8044-2957-x,1.0,1395619200\r").unwrap();
+  assert_eq!(r.asin, "080442957X");
+}
+
+#[test]
+fn test_parse_bad_fields() {
+  assert!(parse_line("AH2L9G3DQHHAJ,0000000116,4.0").is_err());
+  assert!(parse_line("AH2L9G3DQHHAJ,0000000116,4.0,10198,7").is_err());
+}
+
+#[test]
+fn test_parse_bad_values() {
+  assert!(parse_line("AH2L9G3DQHHAJ,0000000116,wombat,1019865600").is_err());
+  assert!(parse_line("AH2L9G3DQHHAJ,0000000116,7.0,1019865600").is_err());
+  assert!(parse_line("AH2L9G3DQHHAJ,00116,4.0,1019865600").is_err());
+  assert!(parse_line(",0000000116,4.0,1019865600").is_err());
+}
+
+#[test]
+fn test_hash_stable() {
+  let h = hash_user("AH2L9G3DQHHAJ");
+  assert!(h >= 0);
+  assert_eq!(h, hash_user("AH2L9G3DQHHAJ"));
+  assert_ne!(h, hash_user("A2IIIDRK3PRRZY"));
+}
+
+#[test]
+fn test_write_text() {
+  use crate::sink::PGSink;
+  let r = parse_line("AH2L9G3DQHHAJ,0000000116,4.5,1019865600").unwrap();
+  let mut out = PGSink::new(Vec::new());
+  write_record(&mut out, &r).unwrap();
+  let expected = format!("AH2L9G3DQHHAJ\t{}\t0000000116\t4.5\t1019865600\n", r.user_hash);
+  assert_eq!(String::from_utf8(out.out).unwrap(), expected);
+}
+
+#[test]
+fn test_write_binary() {
+  let r = parse_line("U1,0000000116,4.0,7").unwrap();
+  let types: Vec<ColType> = COLUMNS.iter().map(|(_, t)| *t).collect();
+  let mut out = Vec::new();
+  let mut sink = Box::new(BinarySink::create(&mut out, &types).unwrap());
+  write_record(&mut sink, &r).unwrap();
+  sink.finish().unwrap();
+  // 19 (header), then 2 (count) + 4+2 (user) + 4+8 (hash) + 4+10 (asin) + 4+4 (rating) + 4+8 (time), then 2 (trailer)
+  assert_eq!(out.len(), 19 + 54 + 2);
+  assert_eq!(&out[19..27], &[0, 5, 0, 0, 0, 2, b'U', b'1']);
+  assert_eq!(&out[65..73], &[0, 0, 0, 0, 0, 0, 0, 7]);
+}
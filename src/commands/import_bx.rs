@@ -0,0 +1,231 @@
+use std::borrow::Cow;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+
+use structopt::StructOpt;
+use sha1::Sha1;
+use anyhow::Result;
+
+use crate::tsv::{FieldParser, Escapes};
+use crate::columnar::ColType;
+use crate::sink::RowSink;
+use crate::db::{DbOpts, ddl_columns};
+use crate::output::{OutputOpts, OutputTarget};
+use crate::tracking::StageOpts;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
+use super::Command;
+
+/// Import BookCrossing CSV files (BX-Books, BX-Book-Ratings) as clean UTF-8.
+#[derive(StructOpt, Debug)]
+#[structopt(name="import-bx")]
+pub struct ImportBX {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  #[structopt(flatten)]
+  output: OutputOpts,
+
+  /// The table to write the records; if omitted, write TSV to standard output.
+  #[structopt(short="t", long="table")]
+  table: Option<String>,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Input file
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// Decode a Latin-1 line, dropping carriage returns.
+fn decode_latin1(bytes: &[u8]) -> String {
+  bytes.iter().filter(|b| **b != b'\r' && **b != b'\n').map(|b| *b as char).collect()
+}
+
+/// Split a BookCrossing line into its fields.
+///
+/// Fields are separated by semicolons and wrapped in double quotes.  Inside a
+/// quoted field, a quote is escaped either by doubling it or, in the books
+/// file, with a backslash; embedded semicolons are kept.  Some book titles end
+/// in a stray backslash, so `\"` before a delimiter closes the field.
+fn split_bx_line(line: &str) -> Result<Vec<String>> {
+  let parser = FieldParser::default().delim(';').quote('"').escapes(Escapes::Backslash);
+  Ok(parser.split(line)?.into_iter().flatten().map(Cow::into_owned).collect())
+}
+
+/// Convert a BookCrossing file, returning the number of records and skipped lines.
+fn convert<R: BufRead, W: RowSink + ?Sized>(src: &mut R, dst: &mut W) -> Result<(usize, usize)> {
+  let mut buf = Vec::new();
+  let mut lno = 0;
+  let mut ncols = 0;
+  let mut n = 0;
+  let mut nskip = 0;
+  loop {
+    buf.clear();
+    if src.read_until(b'\n', &mut buf)? == 0 {
+      break;
+    }
+    lno += 1;
+    let line = decode_latin1(&buf);
+    if line.is_empty() {
+      continue;
+    }
+    let fields = match split_bx_line(&line) {
+      Ok(f) => f,
+      Err(e) if lno == 1 => return Err(e.context("cannot parse header")),
+      Err(e) => {
+        warn!("line {}: {}", lno, e);
+        nskip += 1;
+        continue;
+      }
+    };
+    if lno == 1 {
+      debug!("header: {:?}", fields);
+      ncols = fields.len();
+      continue;
+    }
+    if fields.len() != ncols {
+      warn!("line {}: expected {} fields, found {}", lno, ncols, fields.len());
+      nskip += 1;
+      continue;
+    }
+    for f in &fields {
+      dst.write_field(f.as_bytes())?;
+    }
+    dst.end_row()?;
+    n += 1;
+  }
+  Ok((n, nskip))
+}
+
+impl Command for ImportBX {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    let output = match self.table {
+      Some(_) => self.output.clone(),
+      None => self.output.clone().with_target(OutputTarget::Stdout)
+    };
+    let dbc = output.open_tracking(&self.db)?;
+    if self.stage.should_skip(dbc.as_ref(), "import-bx", &self.infile)? {
+      return Ok(());
+    }
+    let mut stage = output.begin_stage(&self.stage, dbc.as_ref())?;
+
+    let infn = &self.infile;
+    info!("reading from {:?}", infn);
+    let fs = File::open(infn)?;
+    let pb = progress_bar(Some(fs.metadata()?.len()), "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})");
+    let _pbl = set_progress(&pb);
+    let mut in_sf = stage.source_file(infn);
+    let read = in_sf.wrap_read(fs);
+    let mut read = BufReader::new(wrap_read(&pb, read));
+
+    // the schema has the column types; other tables get all their columns
+    let table = self.table.as_deref().unwrap_or("stdout");
+    let columns = ddl_columns(table).unwrap_or_else(|| {
+      if self.table.is_some() {
+        warn!("table {} is not in the schema", table);
+      }
+      Vec::new()
+    });
+    let columns: Vec<(&str, ColType)> = columns.iter().map(|(c, t)| (c.as_str(), *t)).collect();
+    let mut out_hash = Sha1::new();
+    let mut out = output.open(&self.db, table, &columns, self.truncate, &mut out_hash)?;
+
+    let (n, nskip) = convert(&mut read, &mut *out)?;
+    count_records(n as u64);
+    out.finish()?;
+
+    let in_hash = in_sf.record()?;
+    let out_hash = out_hash.hexdigest();
+    info!("imported {} records, skipped {} bad lines", n, nskip);
+    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
+    writeln!(&mut stage, "{} RECORDS", n)?;
+    writeln!(&mut stage, "{} SKIPPED", nskip)?;
+    writeln!(&mut stage, "HASH {}", out_hash)?;
+    stage.log_import("import-bx", infn, &in_hash, n, started)?;
+    stage.end(&Some(out_hash))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_latin1() {
+  assert_eq!(decode_latin1(b"Caf\xe9\r\n"), "Café");
+}
+
+#[test]
+fn test_split_rating() {
+  let f = split_bx_line("\"276725\";\"034545104X\";\"0\"").unwrap();
+  assert_eq!(f, vec!["276725", "034545104X", "0"]);
+}
+
+#[test]
+fn test_split_embedded_semi() {
+  let f = split_bx_line("\"0195153448\";\"Classical Mythology; A Reader\";\"Mark P. O. Morford\"").unwrap();
+  assert_eq!(f, vec!["0195153448", "Classical Mythology; A Reader", "Mark P. O. Morford"]);
+}
+
+#[test]
+fn test_split_backslash_quote() {
+  let f = split_bx_line("\"078946697X\";\"DK Readers: Creating the X-Men, How It All Began (Level 4: Proficient Readers)\\\";\"Michael Teitelbaum\"").unwrap();
+  assert_eq!(f.len(), 3);
+  assert_eq!(f[1], "DK Readers: Creating the X-Men, How It All Began (Level 4: Proficient Readers)");
+  let f = split_bx_line("\"0789466953\";\"A \\\"Special\\\" Book\";\"DK\"").unwrap();
+  assert_eq!(f, vec!["0789466953", "A \"Special\" Book", "DK"]);
+}
+
+#[test]
+fn test_split_doubled_quote() {
+  let f = split_bx_line("\"1\";\"say \"\"hi\"\"\"").unwrap();
+  assert_eq!(f, vec!["1", "say \"hi\""]);
+}
+
+#[test]
+fn test_split_unquoted() {
+  let f = split_bx_line("1;;x").unwrap();
+  assert_eq!(f, vec!["1", "", "x"]);
+}
+
+#[test]
+fn test_split_unterminated() {
+  assert!(split_bx_line("\"1\";\"oops").is_err());
+}
+
+#[test]
+fn test_convert() {
+  use crate::sink::PGSink;
+  let src = b"\"User-ID\";\"ISBN\";\"Book-Rating\"\r\n\"276725\";\"034545104X\";\"0\"\r\n\"2\";\"bad\"\r\n\"3\";\"Caf\xe9\tx\";\"5\"\r\n";
+  let mut out = PGSink::new(Vec::new());
+  let (n, nskip) = convert(&mut &src[..], &mut out).unwrap();
+  assert_eq!(n, 2);
+  assert_eq!(nskip, 1);
+  assert_eq!(String::from_utf8(out.out).unwrap(), "276725\t034545104X\t0\n3\tCafé\\tx\t5\n");
+}
+
+#[test]
+fn test_convert_bad_header() {
+  use crate::sink::PGSink;
+  let src = b"\"User-ID\";\"ISBN\r\n\"276725\";\"034545104X\"\r\n";
+  assert!(convert(&mut &src[..], &mut PGSink::new(Vec::new())).is_err());
+}
+
+#[test]
+fn test_convert_typed() {
+  use crate::arrow::StreamWriter;
+  let src = b"\"User-ID\";\"ISBN\";\"Book-Rating\"\r\n\"276725\";\"034545104X\";\"0\"\r\n";
+  let cols = ddl_columns("bx.raw_ratings").unwrap();
+  let cols: Vec<(&str, ColType)> = cols.iter().map(|(c, t)| (c.as_str(), *t)).collect();
+  let mut out = StreamWriter::create(Vec::new(), &cols).unwrap();
+  assert_eq!(convert(&mut &src[..], &mut out).unwrap(), (1, 0));
+  let src = b"\"User-ID\";\"ISBN\";\"Book-Rating\"\r\n\"nobody\";\"034545104X\";\"0\"\r\n";
+  assert!(convert(&mut &src[..], &mut out).is_err());
+}
@@ -0,0 +1,220 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+use sha1::Sha1;
+use anyhow::{Result, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::io::{open_input, decompress};
+use crate::tsv::write_record;
+use crate::columnar::ColType::{self, *};
+use crate::sink::RowSink;
+use crate::langid::detect_language;
+use crate::db::DbOpts;
+use crate::output::OutputOpts;
+use crate::tracking::StageOpts;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
+use super::Command;
+
+/// The columns of the review table.  Timestamps keep their text, with the
+/// time zone.
+const COLUMNS: &[(&str, ColType)] = &[
+  ("review_id", Text), ("gr_user_id", Text), ("gr_book_id", Int32), ("rating", Int32), ("review_text", Text),
+  ("review_lang", Text), ("date_added", Text), ("date_updated", Text), ("read_at", Text), ("started_at", Text),
+  ("n_votes", Int32), ("n_comments", Int32)
+];
+
+const MONTHS: &[&str] = &["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Import the GoodReads reviews file.
+#[derive(StructOpt, Debug)]
+#[structopt(name="import-gr-reviews")]
+pub struct ImportGrReviews {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  #[structopt(flatten)]
+  output: OutputOpts,
+
+  /// The table to import reviews into.
+  #[structopt(short="t", long="table", default_value="gr.review")]
+  table: String,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Identify the language of each review's text.
+  #[structopt(long="detect-language")]
+  detect_language: bool,
+
+  /// Truncate review text to at most N characters.
+  #[structopt(long="max-length")]
+  max_length: Option<usize>,
+
+  /// Input file (the reviews JSON, usually compressed)
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// A review from the reviews file.
+#[derive(Deserialize, Debug)]
+struct Review {
+  review_id: String,
+  user_id: String,
+  book_id: String,
+  #[serde(default)]
+  rating: Option<i32>,
+  #[serde(default)]
+  review_text: String,
+  #[serde(default)]
+  date_added: String,
+  #[serde(default)]
+  date_updated: String,
+  #[serde(default)]
+  read_at: String,
+  #[serde(default)]
+  started_at: String,
+  #[serde(default)]
+  n_votes: Option<i32>,
+  #[serde(default)]
+  n_comments: Option<i32>
+}
+
+/// A row of the reviews table.
+#[derive(Serialize, Debug)]
+struct ReviewRow<'a> {
+  review_id: &'a str,
+  user_id: &'a str,
+  book_id: &'a str,
+  rating: Option<i32>,
+  review_text: &'a str,
+  review_lang: Option<&'static str>,
+  date_added: Option<String>,
+  date_updated: Option<String>,
+  read_at: Option<String>,
+  started_at: Option<String>,
+  n_votes: Option<i32>,
+  n_comments: Option<i32>
+}
+
+/// Convert a GoodReads timestamp (`Fri Aug 25 13:55:02 -0700 2017`) to ISO
+/// form for PostgreSQL.  Empty and unrecognized timestamps are `None`.
+fn gr_timestamp(ts: &str) -> Option<String> {
+  let parts: Vec<&str> = ts.split_whitespace().collect();
+  if parts.len() != 6 {
+    return None;
+  }
+  let month = MONTHS.iter().position(|m| *m == parts[1])? + 1;
+  let day: u32 = parts[2].parse().ok()?;
+  let (time, zone, year) = (parts[3], parts[4], parts[5]);
+  if time.len() != 8 || zone.len() != 5 || year.len() != 4 || !year.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  Some(format!("{}-{:02}-{:02} {}{}:{}", year, month, day, time, &zone[..3], &zone[3..]))
+}
+
+/// Truncate text to at most `max` characters.
+fn truncate_chars(text: &str, max: usize) -> &str {
+  match text.char_indices().nth(max) {
+    Some((i, _)) => &text[..i],
+    None => text
+  }
+}
+
+impl ImportGrReviews {
+  /// Write a line of the reviews file as a row.
+  fn write_review<W: RowSink + ?Sized>(&self, out: &mut W, line: &str) -> Result<()> {
+    let review: Review = serde_json::from_str(line)?;
+    // PostgreSQL text cannot hold NUL characters
+    let text = review.review_text.replace('\0', "");
+    let text = match self.max_length {
+      Some(n) => truncate_chars(&text, n),
+      None => &text
+    };
+    let lang = match self.detect_language {
+      true => detect_language(text),
+      false => None
+    };
+    write_record(out, &ReviewRow {
+      review_id: &review.review_id,
+      user_id: &review.user_id,
+      book_id: &review.book_id,
+      rating: review.rating,
+      review_text: text,
+      review_lang: lang,
+      date_added: gr_timestamp(&review.date_added),
+      date_updated: gr_timestamp(&review.date_updated),
+      read_at: gr_timestamp(&review.read_at),
+      started_at: gr_timestamp(&review.started_at),
+      n_votes: review.n_votes,
+      n_comments: review.n_comments
+    })
+  }
+}
+
+impl Command for ImportGrReviews {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    let dbc = self.output.open_tracking(&self.db)?;
+    if self.stage.should_skip(dbc.as_ref(), "import-gr-reviews", &self.infile)? {
+      return Ok(());
+    }
+    let mut stage = self.output.begin_stage(&self.stage, dbc.as_ref())?;
+
+    let infn = &self.infile;
+    let (read, len) = open_input(infn)?;
+    let pb = progress_bar(len, "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta}) {msg}");
+    let _pbl = set_progress(&pb);
+    let mut in_sf = stage.source_file(infn);
+    let read = in_sf.wrap_read(wrap_read(&pb, read));
+    let read = BufReader::new(decompress(BufReader::new(read))?);
+
+    let mut hash = Sha1::new();
+    let mut out = self.output.open(&self.db, &self.table, COLUMNS, self.truncate, &mut hash)?;
+
+    let mut n = 0;
+    for (i, line) in read.lines().enumerate() {
+      let line = line?;
+      if line.trim().is_empty() {
+        continue;
+      }
+      self.write_review(&mut *out, &line).with_context(|| format!("line {}", i + 1))?;
+      n += 1;
+      count_records(1);
+    }
+    out.finish()?;
+
+    let in_hash = in_sf.record()?;
+    info!("imported {} reviews", n);
+    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
+    writeln!(&mut stage, "COPY {} {} {}", self.table, n, hash.hexdigest())?;
+    stage.log_import("import-gr-reviews", infn, &in_hash, n, started)?;
+    stage.end(&Some(hash.hexdigest()))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_write_review() {
+  use crate::sink::PGSink;
+  let line = r#"{"user_id": "8842281e1d1347389f2ab93d60773d4d", "book_id": "24375664", "review_id": "5cd416f3efc3f944fce4ce2db2290d5e", "rating": 5, "review_text": "Mind blowing.\nThis was the best book I have read this year, and I loved it.", "date_added": "Fri Aug 25 13:55:02 -0700 2017", "date_updated": "Mon Oct 09 08:55:59 -0700 2017", "read_at": "Sat Oct 07 00:00:00 -0700 2017", "started_at": "", "n_votes": 16, "n_comments": 0}"#;
+  let mut cmd = ImportGrReviews::from_iter(vec!["import-gr-reviews", "--detect-language", "--max-length", "13", "reviews.json.gz"]);
+  let mut out = PGSink::new(Vec::new());
+  cmd.write_review(&mut out, line).unwrap();
+  assert_eq!(String::from_utf8(out.out).unwrap(), "5cd416f3efc3f944fce4ce2db2290d5e\t8842281e1d1347389f2ab93d60773d4d\t24375664\t5\tMind blowing.\t\\N\t2017-08-25 13:55:02-07:00\t2017-10-09 08:55:59-07:00\t2017-10-07 00:00:00-07:00\t\\N\t16\t0\n");
+  cmd.max_length = None;
+  let mut out = PGSink::new(Vec::new());
+  cmd.write_review(&mut out, line).unwrap();
+  assert!(String::from_utf8(out.out).unwrap().contains("\tMind blowing.\\nThis was the best book I have read this year, and I loved it.\ten\t"));
+  assert!(cmd.write_review(&mut PGSink::new(Vec::new()), "{\"review_id\": 3}").is_err());
+  assert_eq!(gr_timestamp("Sun Jul 30 07:44:10 +0200 2017").as_deref(), Some("2017-07-30 07:44:10+02:00"));
+  assert_eq!(gr_timestamp("yesterday"), None);
+}
@@ -0,0 +1,346 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+use sha1::Sha1;
+use anyhow::{Result, Context, anyhow};
+use quick_xml::Reader;
+use quick_xml::events::{Event, BytesStart};
+use serde::Serialize;
+
+use crate::io::{TarEntries, open_input, decompress};
+use crate::tsv::write_record;
+use crate::columnar::ColType::{self, *};
+use crate::sink::RowSink;
+use crate::ids::{normalize_isbn, normalize_lccn};
+use crate::db::DbOpts;
+use crate::output::OutputOpts;
+use crate::tracking::StageOpts;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
+use super::Command;
+
+/// The tables written, in the order of [Outputs]'s writers.
+const TABLES: &[&str] = &["ebook", "ebook_author", "ebook_language", "ebook_subject", "ebook_ids"];
+
+/// The columns of each table in [TABLES].
+const COLUMNS: &[&[(&str, ColType)]] = &[
+  &[("gutenberg_id", Int32), ("title", Text), ("ebook_type", Text), ("issued", Text)],
+  &[("gutenberg_id", Int32), ("author_pos", Int32), ("agent_id", Int32), ("author_name", Text),
+    ("birth_year", Int32), ("death_year", Int32)],
+  &[("gutenberg_id", Int32), ("language", Text)],
+  &[("gutenberg_id", Int32), ("scheme", Text), ("subject", Text)],
+  &[("gutenberg_id", Int32), ("id_type", Text), ("id_value", Text)]
+];
+
+/// Import the Project Gutenberg RDF catalog.
+#[derive(StructOpt, Debug)]
+#[structopt(name="import-gutenberg")]
+pub struct ImportGutenberg {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  #[structopt(flatten)]
+  output: OutputOpts,
+
+  /// The schema to import into.
+  #[structopt(long="schema", default_value="gutenberg")]
+  schema: String,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Input file (the catalog's `rdf-files.tar`, optionally compressed or zipped)
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// An author (creator) of an ebook.
+#[derive(Debug, Default, PartialEq)]
+struct Agent {
+  agent_id: Option<u32>,
+  name: Option<String>,
+  birth_year: Option<i32>,
+  death_year: Option<i32>
+}
+
+/// The parts of an ebook's catalog record that we import.
+#[derive(Debug, Default, PartialEq)]
+struct EBook {
+  id: u32,
+  title: Option<String>,
+  kind: Option<String>,
+  issued: Option<String>,
+  authors: Vec<Agent>,
+  languages: Vec<String>,
+  /// Subjects, with the vocabulary (e.g. `LCSH` or `LCC`) they are from
+  subjects: Vec<(Option<String>, String)>,
+  /// Other identifiers, as type and value
+  ids: Vec<(&'static str, String)>
+}
+
+/// Get the value of an element's attribute.
+fn attr(e: &BytesStart, key: &[u8]) -> Result<Option<String>> {
+  for a in e.attributes() {
+    let a = a?;
+    if a.key == key {
+      return Ok(Some(String::from_utf8(a.unescaped_value()?.into_owned())?));
+    }
+  }
+  Ok(None)
+}
+
+/// Get the number at the end of an `rdf:about` path like `ebooks/1342`.
+fn about_id(about: &str) -> Option<u32> {
+  about.rsplit('/').next().and_then(|s| s.parse().ok())
+}
+
+/// Parse an ebook's RDF/XML record.  Returns `None` if the file has no ebook.
+fn parse_ebook(xml: &[u8]) -> Result<Option<EBook>> {
+  let mut rdr = Reader::from_reader(xml);
+  rdr.trim_text(true);
+  let mut buf = Vec::new();
+  let mut path: Vec<Vec<u8>> = Vec::new();
+  let mut book: Option<EBook> = None;
+  // the vocabulary of the current subject, which can come before or after its value
+  let mut scheme: Option<String> = None;
+  let mut subject: Option<usize> = None;
+  loop {
+    match rdr.read_event(&mut buf)? {
+      Event::Start(ref e) => {
+        match e.name() {
+          b"pgterms:ebook" => {
+            let about = attr(e, b"rdf:about")?.unwrap_or_default();
+            let id = about_id(&about).ok_or_else(|| anyhow!("invalid ebook {:?}", about))?;
+            book = Some(EBook { id, ..EBook::default() });
+          },
+          b"pgterms:agent" if path.last().map(|p| p == b"dcterms:creator").unwrap_or(false) => {
+            if let Some(b) = book.as_mut() {
+              let agent_id = attr(e, b"rdf:about")?.as_deref().and_then(about_id);
+              b.authors.push(Agent { agent_id, ..Agent::default() });
+            }
+          },
+          b"rdf:Description" => {
+            scheme = None;
+            subject = None;
+          },
+          _ => ()
+        }
+        path.push(e.name().to_vec());
+      },
+      Event::Empty(ref e) if e.name() == b"dcam:memberOf" => {
+        scheme = attr(e, b"rdf:resource")?.and_then(|r| r.rsplit('/').next().map(str::to_string));
+        if let (Some(b), Some(i)) = (book.as_mut(), subject) {
+          b.subjects[i].0 = scheme.clone();
+        }
+      },
+      Event::End(_) => {
+        path.pop();
+      },
+      Event::Text(ref e) if book.is_some() => {
+        let b = book.as_mut().unwrap();
+        let text = String::from_utf8(e.unescaped()?.into_owned())?;
+        let n = path.len();
+        let tail = |k: usize| path.get(n.wrapping_sub(k)).map(|p| p.as_slice()).unwrap_or(b"");
+        match (tail(3), tail(2), tail(1)) {
+          (_, b"pgterms:ebook", b"dcterms:title") if b.title.is_none() => b.title = Some(text),
+          (_, b"pgterms:ebook", b"dcterms:issued") => b.issued = Some(text),
+          (_, b"pgterms:ebook", b"pgterms:marc010") => {
+            if let Some(l) = normalize_lccn(&text) {
+              b.ids.push(("lccn", l));
+            }
+          },
+          (_, b"pgterms:ebook", b"pgterms:marc020") => {
+            if let Some(isbn) = normalize_isbn(&text) {
+              b.ids.push(("isbn", isbn));
+            }
+          },
+          (b"dcterms:creator", b"pgterms:agent", fld) => {
+            let agent = b.authors.last_mut().ok_or_else(|| anyhow!("creator without agent"))?;
+            match fld {
+              b"pgterms:name" => agent.name = Some(text),
+              b"pgterms:birthdate" => agent.birth_year = text.parse().ok(),
+              b"pgterms:deathdate" => agent.death_year = text.parse().ok(),
+              _ => ()
+            }
+          },
+          (b"dcterms:language", b"rdf:Description", b"rdf:value") => b.languages.push(text),
+          (b"dcterms:type", b"rdf:Description", b"rdf:value") => b.kind = Some(text),
+          (b"dcterms:subject", b"rdf:Description", b"rdf:value") => {
+            subject = Some(b.subjects.len());
+            b.subjects.push((scheme.clone(), text));
+          },
+          _ => ()
+        }
+      },
+      Event::Eof => break,
+      _ => ()
+    }
+    buf.clear();
+  }
+  Ok(book)
+}
+
+/// Destinations for the rows of each table, in the order of [TABLES].
+struct Outputs<W: RowSink> {
+  writers: Vec<W>,
+  counts: Vec<usize>
+}
+
+impl <W: RowSink> Outputs<W> {
+  fn new(writers: Vec<W>) -> Outputs<W> {
+    let counts = vec![0; writers.len()];
+    Outputs { writers, counts }
+  }
+
+  /// Finish the writers, returning the row count of each table.
+  fn finish(self) -> Result<Vec<usize>> {
+    for w in self.writers {
+      Box::new(w).finish()?;
+    }
+    Ok(self.counts)
+  }
+
+  fn write<T: Serialize>(&mut self, table: usize, row: &T) -> Result<()> {
+    write_record(&mut self.writers[table], row)?;
+    self.counts[table] += 1;
+    Ok(())
+  }
+
+  fn write_ebook(&mut self, book: &EBook) -> Result<()> {
+    let id = book.id;
+    self.write(0, &(id, &book.title, &book.kind, &book.issued))?;
+    for (pos, a) in book.authors.iter().enumerate() {
+      self.write(1, &(id, pos, a.agent_id, &a.name, a.birth_year, a.death_year))?;
+    }
+    for lang in &book.languages {
+      self.write(2, &(id, lang))?;
+    }
+    for (scheme, subj) in &book.subjects {
+      self.write(3, &(id, scheme, subj))?;
+    }
+    for (kind, val) in &book.ids {
+      self.write(4, &(id, kind, val))?;
+    }
+    Ok(())
+  }
+}
+
+impl Command for ImportGutenberg {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    let dbc = self.output.open_tracking(&self.db)?;
+    if self.stage.should_skip(dbc.as_ref(), "import-gutenberg", &self.infile)? {
+      return Ok(());
+    }
+    let mut stage = self.output.begin_stage(&self.stage, dbc.as_ref())?;
+
+    let infn = &self.infile;
+    let (read, len) = open_input(infn)?;
+    let pb = progress_bar(len, "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta}) {msg}");
+    let _pbl = set_progress(&pb);
+    let mut in_sf = stage.source_file(infn);
+    let read = in_sf.wrap_read(wrap_read(&pb, read));
+    let mut entries = TarEntries::new(decompress(BufReader::new(read))?);
+
+    let names: Vec<String> = TABLES.iter().map(|t| format!("{}.{}", self.schema, t)).collect();
+    let mut hashes: Vec<Sha1> = TABLES.iter().map(|_| Sha1::new()).collect();
+    let mut writers = Vec::with_capacity(TABLES.len());
+    for ((name, cols), hash) in names.iter().zip(COLUMNS).zip(hashes.iter_mut()) {
+      writers.push(self.output.open(&self.db, name, cols, self.truncate, hash)?);
+    }
+    let mut out = Outputs::new(writers);
+
+    let mut nfiles = 0;
+    while let Some((name, data)) = entries.next_file()? {
+      if !name.ends_with(".rdf") {
+        continue;
+      }
+      nfiles += 1;
+      if let Some(book) = parse_ebook(&data).with_context(|| format!("parsing {}", name))? {
+        out.write_ebook(&book)?;
+        count_records(1);
+      }
+    }
+    drop(entries);
+    let counts = out.finish()?;
+
+    let in_hash = in_sf.record()?;
+    info!("imported {} ebooks from {} files", counts[0], nfiles);
+    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
+    let mut key = Sha1::new();
+    for ((name, n), hash) in names.iter().zip(&counts).zip(&hashes) {
+      writeln!(&mut stage, "COPY {} {} {}", name, n, hash.hexdigest())?;
+      key.update(hash.hexdigest().as_bytes());
+    }
+    stage.log_import("import-gutenberg", infn, &in_hash, counts[0], started)?;
+    stage.end(&Some(key.hexdigest()))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_parse_ebook() {
+  use crate::sink::PGSink;
+  let xml = br#"<?xml version="1.0" encoding="utf-8"?>
+<rdf:RDF xml:base="http://www.gutenberg.org/" xmlns:dcterms="http://purl.org/dc/terms/" xmlns:pgterms="http://www.gutenberg.org/2009/pgterms/" xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns:dcam="http://purl.org/dc/dcam/">
+  <pgterms:ebook rdf:about="ebooks/1342">
+    <dcterms:creator>
+      <pgterms:agent rdf:about="2009/agents/68">
+        <pgterms:birthdate rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">1775</pgterms:birthdate>
+        <pgterms:deathdate rdf:datatype="http://www.w3.org/2001/XMLSchema#integer">1817</pgterms:deathdate>
+        <pgterms:name>Austen, Jane</pgterms:name>
+      </pgterms:agent>
+    </dcterms:creator>
+    <dcterms:title>Pride and Prejudice</dcterms:title>
+    <pgterms:marc010>  12345678 </pgterms:marc010>
+    <pgterms:marc020>0-8044-2957-x (pbk.)</pgterms:marc020>
+    <dcterms:issued rdf:datatype="http://www.w3.org/2001/XMLSchema#date">1998-06-01</dcterms:issued>
+    <dcterms:language>
+      <rdf:Description rdf:nodeID="N1">
+        <rdf:value rdf:datatype="http://purl.org/dc/terms/RFC4646">en</rdf:value>
+      </rdf:Description>
+    </dcterms:language>
+    <dcterms:subject>
+      <rdf:Description rdf:nodeID="N2">
+        <dcam:memberOf rdf:resource="http://purl.org/dc/terms/LCSH"/>
+        <rdf:value>Courtship -- Fiction</rdf:value>
+      </rdf:Description>
+    </dcterms:subject>
+    <dcterms:subject>
+      <rdf:Description rdf:nodeID="N3">
+        <rdf:value>PR</rdf:value>
+        <dcam:memberOf rdf:resource="http://purl.org/dc/terms/LCC"/>
+      </rdf:Description>
+    </dcterms:subject>
+    <dcterms:type>
+      <rdf:Description rdf:nodeID="N4">
+        <rdf:value>Text</rdf:value>
+        <dcam:memberOf rdf:resource="http://purl.org/dc/terms/DCMIType"/>
+      </rdf:Description>
+    </dcterms:type>
+  </pgterms:ebook>
+</rdf:RDF>"#;
+  let book = parse_ebook(xml).unwrap().unwrap();
+  assert_eq!(book.id, 1342);
+  assert_eq!(book.title.as_deref(), Some("Pride and Prejudice"));
+  assert_eq!(book.kind.as_deref(), Some("Text"));
+  assert_eq!(book.issued.as_deref(), Some("1998-06-01"));
+  assert_eq!(book.authors, vec![Agent { agent_id: Some(68), name: Some("Austen, Jane".to_string()), birth_year: Some(1775), death_year: Some(1817) }]);
+  assert_eq!(book.languages, vec!["en"]);
+  assert_eq!(book.subjects, vec![(Some("LCSH".to_string()), "Courtship -- Fiction".to_string()), (Some("LCC".to_string()), "PR".to_string())]);
+  assert_eq!(book.ids, vec![("lccn", "12345678".to_string()), ("isbn", "080442957X".to_string())]);
+
+  let mut out = Outputs::new((0..TABLES.len()).map(|_| PGSink::new(Vec::new())).collect());
+  out.write_ebook(&book).unwrap();
+  assert_eq!(out.counts, vec![1, 1, 1, 2, 2]);
+  assert_eq!(String::from_utf8(out.writers[0].out.clone()).unwrap(), "1342\tPride and Prejudice\tText\t1998-06-01\n");
+  assert_eq!(String::from_utf8(out.writers[1].out.clone()).unwrap(), "1342\t0\t68\tAusten, Jane\t1775\t1817\n");
+  assert!(parse_ebook(b"<rdf:RDF></rdf:RDF>").unwrap().is_none());
+}
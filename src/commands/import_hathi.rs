@@ -0,0 +1,220 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+use sha1::Sha1;
+use anyhow::{Result, Context, anyhow};
+use serde::Serialize;
+
+use crate::io::{open_input, decompress};
+use crate::tsv::write_record;
+use crate::columnar::ColType::{self, *};
+use crate::sink::RowSink;
+use crate::ids::{normalize_oclc, normalize_lccn, normalize_isbn};
+use crate::db::DbOpts;
+use crate::output::OutputOpts;
+use crate::tracking::StageOpts;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
+use super::Command;
+
+/// The tables written, in the order of [Outputs]'s writers.
+const TABLES: &[&str] = &["volume", "volume_oclc", "volume_isbn", "volume_lccn"];
+
+/// The columns of each table in [TABLES].
+const COLUMNS: &[&[(&str, ColType)]] = &[
+  &[("htid", Text), ("access", Text), ("rights", Text), ("ht_bib_key", Text), ("description", Text),
+    ("title", Text), ("imprint", Text), ("rights_date", Int32), ("pub_place", Text), ("lang", Text),
+    ("bib_fmt", Text), ("author", Text)],
+  &[("htid", Text), ("oclc_number", Int64)],
+  &[("htid", Text), ("isbn", Text)],
+  &[("htid", Text), ("lccn", Text)]
+];
+
+/// The number of fields in a Hathifile line.
+const MIN_FIELDS: usize = 26;
+
+/// Import the HathiTrust Hathifiles.
+#[derive(StructOpt, Debug)]
+#[structopt(name="import-hathifiles")]
+pub struct ImportHathi {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  #[structopt(flatten)]
+  output: OutputOpts,
+
+  /// The schema to import into.
+  #[structopt(long="schema", default_value="hathi")]
+  schema: String,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Input file (a full or update Hathifile, usually gzip'd)
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// A row of the volume table.
+#[derive(Serialize, Debug)]
+struct Volume<'a> {
+  htid: &'a str,
+  access: &'a str,
+  rights: &'a str,
+  ht_bib_key: &'a str,
+  description: Option<&'a str>,
+  title: Option<&'a str>,
+  imprint: Option<&'a str>,
+  rights_date: Option<i32>,
+  pub_place: Option<&'a str>,
+  lang: Option<&'a str>,
+  bib_fmt: Option<&'a str>,
+  author: Option<&'a str>
+}
+
+/// Treat an empty field as missing.
+fn opt(field: &str) -> Option<&str> {
+  let f = field.trim();
+  if f.is_empty() {
+    None
+  } else {
+    Some(f)
+  }
+}
+
+/// Split a comma-separated multi-value field.
+fn values(field: &str) -> impl Iterator<Item=&str> {
+  field.split(',').map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Destinations for the rows of each table, in the order of [TABLES].
+struct Outputs<W: RowSink> {
+  writers: Vec<W>,
+  counts: Vec<usize>
+}
+
+impl <W: RowSink> Outputs<W> {
+  fn new(writers: Vec<W>) -> Outputs<W> {
+    let counts = vec![0; writers.len()];
+    Outputs { writers, counts }
+  }
+
+  /// Finish the writers, returning the row count of each table.
+  fn finish(self) -> Result<Vec<usize>> {
+    for w in self.writers {
+      Box::new(w).finish()?;
+    }
+    Ok(self.counts)
+  }
+
+  fn write<T: Serialize>(&mut self, table: usize, row: &T) -> Result<()> {
+    write_record(&mut self.writers[table], row)?;
+    self.counts[table] += 1;
+    Ok(())
+  }
+
+  /// Write a Hathifile line's volume and its identifier links.
+  fn write_line(&mut self, line: &str) -> Result<()> {
+    let f: Vec<&str> = line.split('\t').collect();
+    if f.len() < MIN_FIELDS {
+      return Err(anyhow!("expected {} fields, found {}", MIN_FIELDS, f.len()));
+    }
+    let htid = f[0];
+    self.write(0, &Volume {
+      htid,
+      access: f[1],
+      rights: f[2],
+      ht_bib_key: f[3],
+      description: opt(f[4]),
+      title: opt(f[11]),
+      imprint: opt(f[12]),
+      // 9999 marks an unknown date
+      rights_date: f[16].parse().ok().filter(|y| *y != 9999),
+      pub_place: opt(f[17]),
+      lang: opt(f[18]),
+      bib_fmt: opt(f[19]),
+      author: opt(f[25])
+    })?;
+    for oclc in values(f[7]).filter_map(normalize_oclc) {
+      self.write(1, &(htid, oclc))?;
+    }
+    for isbn in values(f[8]).filter_map(normalize_isbn) {
+      self.write(2, &(htid, isbn))?;
+    }
+    for lccn in values(f[10]).filter_map(normalize_lccn) {
+      self.write(3, &(htid, lccn))?;
+    }
+    Ok(())
+  }
+}
+
+impl Command for ImportHathi {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    let dbc = self.output.open_tracking(&self.db)?;
+    if self.stage.should_skip(dbc.as_ref(), "import-hathifiles", &self.infile)? {
+      return Ok(());
+    }
+    let mut stage = self.output.begin_stage(&self.stage, dbc.as_ref())?;
+
+    let infn = &self.infile;
+    let (read, len) = open_input(infn)?;
+    let pb = progress_bar(len, "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta}) {msg}");
+    let _pbl = set_progress(&pb);
+    let mut in_sf = stage.source_file(infn);
+    let read = in_sf.wrap_read(wrap_read(&pb, read));
+    let read = BufReader::new(decompress(BufReader::new(read))?);
+
+    let names: Vec<String> = TABLES.iter().map(|t| format!("{}.{}", self.schema, t)).collect();
+    let mut hashes: Vec<Sha1> = TABLES.iter().map(|_| Sha1::new()).collect();
+    let mut writers = Vec::with_capacity(TABLES.len());
+    for ((name, cols), hash) in names.iter().zip(COLUMNS).zip(hashes.iter_mut()) {
+      writers.push(self.output.open(&self.db, name, cols, self.truncate, hash)?);
+    }
+    let mut out = Outputs::new(writers);
+
+    for (i, line) in read.lines().enumerate() {
+      let line = line?;
+      if line.is_empty() {
+        continue;
+      }
+      out.write_line(&line).with_context(|| format!("line {}", i + 1))?;
+      count_records(1);
+    }
+    let counts = out.finish()?;
+
+    let in_hash = in_sf.record()?;
+    info!("imported {} volumes", counts[0]);
+    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
+    let mut key = Sha1::new();
+    for ((name, n), hash) in names.iter().zip(&counts).zip(&hashes) {
+      writeln!(&mut stage, "COPY {} {} {}", name, n, hash.hexdigest())?;
+      key.update(hash.hexdigest().as_bytes());
+    }
+    stage.log_import("import-hathifiles", infn, &in_hash, counts[0], started)?;
+    stage.end(&Some(key.hexdigest()))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_write_line() {
+  use crate::sink::PGSink;
+  let line = "mdp.39015012345678\tallow\tpd\t001234567\tv.2\tMIU\t990012345670106381\t1234567,ocm00012345\t0394517881 (pbk.),978-0-394-51788-9\t\t  76012345 \tThe hobbit, or, There and back again /\tBoston : Houghton Mifflin, 1966.\tbib\t2008-06-01 00:00:00\t0\t1966\tmau\teng\tBK\tMIU\tumich\tumich\tgoogle\tgoogle\tTolkien, J. R. R. (John Ronald Reuel), 1892-1973.";
+  let mut out = Outputs::new((0..TABLES.len()).map(|_| PGSink::new(Vec::new())).collect());
+  out.write_line(line).unwrap();
+  assert_eq!(out.counts, vec![1, 2, 2, 1]);
+  assert_eq!(String::from_utf8(out.writers[0].out.clone()).unwrap(),
+             "mdp.39015012345678\tallow\tpd\t001234567\tv.2\tThe hobbit, or, There and back again /\tBoston : Houghton Mifflin, 1966.\t1966\tmau\teng\tBK\tTolkien, J. R. R. (John Ronald Reuel), 1892-1973.\n");
+  assert_eq!(String::from_utf8(out.writers[1].out.clone()).unwrap(), "mdp.39015012345678\t1234567\nmdp.39015012345678\t12345\n");
+  assert_eq!(String::from_utf8(out.writers[2].out.clone()).unwrap(), "mdp.39015012345678\t0394517881\nmdp.39015012345678\t9780394517889\n");
+  assert_eq!(String::from_utf8(out.writers[3].out.clone()).unwrap(), "mdp.39015012345678\t76012345\n");
+  assert!(out.write_line("mdp.1\tallow").is_err());
+}
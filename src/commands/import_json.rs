@@ -1,23 +1,29 @@
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
-use std::fs::{File, read_to_string};
-use std::path::PathBuf;
+use std::io::{self, BufReader};
+use std::fs::{read_to_string, create_dir_all, metadata};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::str::FromStr;
+use std::time::Instant;
 
 use log::*;
 
 use structopt::StructOpt;
-use flate2::bufread::MultiGzDecoder;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, HumanBytes, HumanDuration};
 use sha1::Sha1;
-use anyhow::{Result};
-use serde::{Deserialize};
-use toml;
+use sha2::{Sha256, Digest};
+use anyhow::{Result, Context, anyhow};
 
-use crate::io::{HashWrite, DelimPrinter};
+use crate::io::{BufferOpts, HashRead, sha256_hex, open_input, decompress, read_in_thread, is_url};
 use crate::cleaning::*;
-use crate::db::{DbOpts, CopyRequest};
+use crate::db::{DbOpts, staging_table, create_staging, merge_staging};
+use crate::columnar::{ColType, Value};
+use crate::sink::{RowSink, PGSink};
+use crate::output::{OutputOpts, OutputTarget};
+use crate::import::{ImportSpec, Target, TargetKind, Rejects, OnError, SubsetOpts};
 use crate::tracking::StageOpts;
-use crate::logging::set_progress;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
 use super::Command;
 
 /// Process OpenLib data into format suitable for PostgreSQL import.
@@ -30,144 +36,526 @@ pub struct ImportJson {
   #[structopt(flatten)]
   stage: StageOpts,
 
+  #[structopt(flatten)]
+  sanitize: SanitizeOpts,
+
+  #[structopt(flatten)]
+  subset: SubsetOpts,
+
+  #[structopt(flatten)]
+  buffers: BufferOpts,
+
   /// Truncate the table before importing
   #[structopt(long="truncate")]
   truncate: bool,
 
-  /// TOML spec file that describes the input
-  #[structopt(name="SPEC")]
-  spec: PathBuf,
+  /// The output format (pg, parquet, or arrow)
+  #[structopt(long="format", default_value="pg")]
+  format: OutputFormat,
 
-  /// Input file
-  #[structopt(name = "INPUT", parse(from_os_str))]
-  infile: PathBuf
+  /// The output file for Parquet or Arrow output (a directory with --split-types or several
+  /// inputs); Arrow streams go to stdout without it
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// What to do with bad records (skip, log, or fail)
+  #[structopt(long="on-error", default_value="log")]
+  on_error: OnError,
+
+  /// Fail if more than this many records are rejected
+  #[structopt(long="max-errors")]
+  max_errors: Option<usize>,
+
+  /// Save rejected records to this file (a directory with several inputs)
+  #[structopt(long="rejects", parse(from_os_str))]
+  rejects: Option<PathBuf>,
+
+  /// Merge the input into the loaded tables by key and revision, instead of appending
+  #[structopt(long="delta")]
+  delta: bool,
+
+  /// Route records to separate tables by type, as listed in the spec's `split` entries
+  #[structopt(long="split-types")]
+  split_types: bool,
+
+  /// Read and parse the input and check the output tables, without writing anything
+  #[structopt(long="dry-run")]
+  dry_run: bool,
+
+  /// The number of input files to import at once (default: all of them)
+  #[structopt(short="j", long="jobs")]
+  jobs: Option<usize>,
+
+  /// TOML spec files and the input files (or HTTP(S) or S3 URLs) they describe, in pairs
+  #[structopt(name = "SPEC INPUT", parse(from_os_str), raw(required="true", min_values="2"))]
+  files: Vec<PathBuf>
 }
 
-#[derive(Deserialize, Debug)]
-enum ColOp {
-  #[serde(rename="_")]
-  Skip,
-  #[serde(rename="str")]
-  String,
-  #[serde(rename="json")]
-  JSON
+/// Output formats for imported records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+  /// Copy into a PostgreSQL table
+  PG,
+  /// Write a Parquet file
+  Parquet,
+  /// Write an Arrow IPC stream
+  Arrow
 }
 
-/// Import specification read from TOML
-#[derive(Deserialize, Debug)]
-struct ImportSpec {
-  schema: String,
-  table: String,
-  columns: Vec<String>,
-  #[serde(default)]
-  format: Vec<ColOp>
+impl OutputFormat {
+  /// Get the output target for the format.
+  fn target(&self) -> OutputTarget {
+    match self {
+      OutputFormat::PG => OutputTarget::DB,
+      OutputFormat::Parquet => OutputTarget::Parquet,
+      OutputFormat::Arrow => OutputTarget::Arrow
+    }
+  }
 }
 
-impl ImportSpec {
-  fn import<R: BufRead, W: Write>(&self, src: &mut R, dst: &mut W) -> Result<usize> {
-    if self.format.is_empty() {
-      self.import_raw(src, dst)
-    } else {
-      self.import_delim(src, dst)
+impl FromStr for OutputFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<OutputFormat> {
+    match s {
+      "pg" => Ok(OutputFormat::PG),
+      "parquet" => Ok(OutputFormat::Parquet),
+      "arrow" => Ok(OutputFormat::Arrow),
+      _ => Err(anyhow!("unknown output format {}", s))
     }
   }
+}
+
+/// Row sink wrapper that shows the record count on a progress bar.
+struct ProgressSink<'a, W: RowSink> {
+  inner: W,
+  pb: &'a ProgressBar,
+  label: Option<String>,
+  rows: u64
+}
+
+impl <'a, W: RowSink> ProgressSink<'a, W> {
+  fn new(inner: W, pb: &'a ProgressBar) -> ProgressSink<'a, W> {
+    ProgressSink { inner, pb, label: None, rows: 0 }
+  }
+
+  /// Label the count, to tell split tables apart.
+  fn labeled(inner: W, pb: &'a ProgressBar, label: &str) -> ProgressSink<'a, W> {
+    ProgressSink { label: Some(label.to_string()), ..ProgressSink::new(inner, pb) }
+  }
+}
+
+impl <'a, W: RowSink> RowSink for ProgressSink<'a, W> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.inner.write_field(val)
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    self.inner.write_value(val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    self.inner.write_null()
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    self.inner.write_array(vals)
+  }
 
-  fn import_raw<R: BufRead, W: Write>(&self, src: &mut R, dst: &mut W) -> Result<usize> {
-    let mut jsbuf = String::new();
-    let mut n = 0;
-    for line in src.lines() {
-      let json = line?;
-      clean_json(&json, &mut jsbuf);
-      write_pgencoded(dst, jsbuf.as_bytes())?;
-      dst.write_all(b"\n")?;
-      n += 1;
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    self.inner.write_json(val)
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    self.inner.end_row()?;
+    self.rows += 1;
+    count_records(1);
+    if self.rows.is_multiple_of(10000) {
+      match self.label {
+        Some(ref l) => self.pb.set_message(&format!("{}: {} records", l, self.rows)),
+        None => self.pb.set_message(&format!("{} records", self.rows))
+      }
     }
+    Ok(())
+  }
 
-    Ok(n)
+  fn finish(self: Box<Self>) -> Result<usize> {
+    Box::new(self.inner).finish()
   }
+}
 
-  fn import_delim<R: BufRead, W: Write>(&self, src: &mut R, dst: &mut W) -> Result<usize> {
-    let mut jsbuf = String::new();
-    let mut n = 0;
-    for line in src.lines() {
-      let line = line?;
-      let mut delim = DelimPrinter::new("\t", "\n");
-      let split = line.split("\t");
-      for (fld, fc) in split.zip(&self.format) {
-        match fc {
-          ColOp::Skip => (),
-          ColOp::String => {
-            delim.preface(dst)?;
-            write_pgencoded(dst, fld.as_bytes())?;
-          },
-          ColOp::JSON => {
-            delim.preface(dst)?;
-            clean_json(&fld, &mut jsbuf);
-            write_pgencoded(dst, jsbuf.as_bytes())?;
-          }
+impl ImportJson {
+  /// Get the output options for the output format.
+  fn output_opts(&self) -> OutputOpts {
+    OutputOpts::new(self.format.target()).with_buffer(self.buffers.write).with_dry_run(self.dry_run)
+  }
+
+  /// Import records into tables routed by type.  Returns the record count and
+  /// output hash for each table, and the number of records skipped.
+  fn import_routed<R: BufRead>(&self, spec: &ImportSpec, targets: &[Target], dbo: &DbOpts, src: &mut R, pb: &ProgressBar, rejects: &mut Rejects) -> Result<(Vec<(usize, String)>, usize)> {
+    let output = self.output_opts();
+    // File output is a directory of tables
+    let dir = match (self.format.target().extension(), &self.output) {
+      (None, _) => None,
+      (Some(_), Some(dir)) => {
+        if !self.dry_run {
+          create_dir_all(dir)?;
         }
-      }
-      delim.end(dst)?;
-      n += 1;
+        Some(dir)
+      },
+      (Some(_), None) => return Err(anyhow!("{:?} output with several tables requires --output", self.format))
+    };
+    let mut hashes: Vec<Sha1> = targets.iter().map(|_| Sha1::new()).collect();
+    let mut sinks = Vec::with_capacity(targets.len());
+    for (t, hash) in targets.iter().zip(hashes.iter_mut()) {
+      let cols = spec.target_columns(t)?;
+      let path = dir.zip(self.format.target().extension()).map(|(d, ext)| d.join(format!("{}.{}", t.table, ext)));
+      let table = format!("{}.{}", dbo.schema(), t.table);
+      let sink = output.open_path(dbo, &table, &cols, self.truncate, path.as_deref(), hash)?;
+      sinks.push(ProgressSink::labeled(sink, pb, &t.table));
     }
-    info!("processed {} lines", n);
-    Ok(n)
+    let (counts, skipped) = spec.import_routed(src, targets, &mut sinks, &self.sanitize, &self.subset, rejects)?;
+    for sink in sinks {
+      Box::new(sink).finish()?;
+    }
+    let hashes = hashes.iter().map(Sha1::hexdigest);
+    Ok((counts.into_iter().zip(hashes).collect(), skipped))
   }
 }
 
-impl Command for ImportJson {
-  fn exec(self) -> Result<()> {
-    info!("reading spec from {:?}", &self.spec);
-    let spec = read_to_string(&self.spec)?;
-    let spec: ImportSpec = toml::from_str(&spec)?;
+/// How many blocks of decompressed input to read ahead of the processing.
+const READ_AHEAD: usize = 4;
+
+/// Get the length of a local input file, to size the progress bar before reading.
+fn input_len(path: &Path) -> Option<u64> {
+  if is_url(path) {
+    None
+  } else {
+    metadata(path).ok().map(|m| m.len())
+  }
+}
+
+/// An input file and the spec that describes it.
+struct Job {
+  spec: ImportSpec,
+  infile: PathBuf
+}
+
+/// The results of importing one input file.
+struct FileResult {
+  len: Option<u64>,
+  n: usize,
+  in_hash: String,
+  out_hash: String,
+  targets: Vec<Target>,
+  split_results: Vec<(usize, String)>,
+  merged: Vec<(String, u64, u64)>,
+  nrejected: usize
+}
 
-    let dbo = self.db.default_schema(&spec.schema);
+impl ImportJson {
+  /// Read the specs and pair them with their input files.
+  fn jobs(&self) -> Result<Vec<Job>> {
+    if !self.files.len().is_multiple_of(2) {
+      return Err(anyhow!("expected SPEC INPUT pairs, got {} arguments", self.files.len()));
+    }
+    let mut jobs = Vec::new();
+    for pair in self.files.chunks(2) {
+      info!("reading spec from {:?}", &pair[0]);
+      let spec = read_to_string(&pair[0])?;
+      let spec: ImportSpec = spec.parse()?;
+      if self.split_types && !spec.has_split() {
+        return Err(anyhow!("--split-types requires split tables in {:?}", &pair[0]));
+      } else if !self.split_types && spec.table.is_empty() {
+        return Err(anyhow!("spec {:?} has no table", &pair[0]));
+      }
+      jobs.push(Job { spec, infile: pair[1].clone() });
+    }
+    Ok(jobs)
+  }
+
+  /// Get the rejects file for an input.  With several inputs, `--rejects` is a
+  /// directory holding a rejects file for each input.
+  fn rejects_file(&self, job: &Job, multi: bool) -> Result<Option<PathBuf>> {
+    match self.rejects {
+      Some(ref r) if self.dry_run => {
+        info!("dry run: not saving rejected records to {:?}", r);
+        Ok(None)
+      },
+      Some(ref dir) if multi => {
+        create_dir_all(dir)?;
+        let name = job.infile.file_name().ok_or(anyhow!("input {:?} has no file name", job.infile))?;
+        let mut name = name.to_os_string();
+        name.push(".rejects");
+        Ok(Some(dir.join(name)))
+      },
+      ref r => Ok(r.clone())
+    }
+  }
 
-    let dbc = dbo.open()?;
-    let mut stage = self.stage.begin_stage(&dbc)?;
+  /// Import one input file.
+  fn import_file(&self, job: &Job, pb: &ProgressBar, multi: bool) -> Result<FileResult> {
+    let spec = &job.spec;
+    let dbo = self.db.clone().default_schema(&spec.schema);
 
     // Set up the input file, tracking read progress
-    let infn = &self.infile;
+    let infn = &job.infile;
     info!("reading from {:?}", infn);
-    let fs = File::open(infn)?;
-    let pb = ProgressBar::new(fs.metadata()?.len());
-    pb.set_style(ProgressStyle::default_bar().template("{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})"));
-    let _pbl = set_progress(&pb);
+    let (fs, len) = open_input(infn)?;
 
     // We want to hash the file while we read it
-    let mut in_sf = stage.source_file(infn);
-    let read = in_sf.wrap_read(fs);
-    // And wrap it in progress
-    let pbr = pb.wrap_read(read);
-    let pbr = BufReader::new(pbr);
-    let gzf = MultiGzDecoder::new(pbr);
-    let mut bfs = BufReader::new(gzf);
-
-    // Set up the output stream, writing to the database
-    let req = CopyRequest::new(&dbo, &spec.table)?;
-    let req = req.with_schema(dbo.schema());
-    let cref: Vec<&str> = spec.columns.iter().map(String::as_str).collect();
-    let req = req.with_columns(&cref);
-    let req = req.truncate(self.truncate);
-    let out = req.open()?;
+    let mut in_hash = Sha256::new();
+    let mut res = thread::scope(|s| {
+      let read = HashRead::create(fs, &mut in_hash);
+      // And wrap it in progress
+      let pbr = wrap_read(pb, read);
+      let pbr = BufReader::with_capacity(self.buffers.read, pbr);
+      // Decompress on a separate thread, so it overlaps with processing
+      let gzf = decompress(pbr)?;
+      let gzf = read_in_thread(s, gzf, self.buffers.read, READ_AHEAD);
+      let mut bfs = BufReader::with_capacity(self.buffers.read, gzf);
+      self.import_records(job, &dbo, &mut bfs, pb, multi)
+    })?;
+    res.len = len;
+    res.in_hash = sha256_hex(in_hash);
+    Ok(res)
+  }
+
+  /// Import the decompressed records of one input file.  The caller fills in
+  /// the input length and hash.
+  fn import_records<R: BufRead>(&self, job: &Job, dbo: &DbOpts, bfs: &mut R, pb: &ProgressBar, multi: bool) -> Result<FileResult> {
+    let spec = &job.spec;
+    let infn = &job.infile;
     let mut out_hash = Sha1::new();
-    let hout = HashWrite::create(out, &mut out_hash);
-    let mut buf_out = BufWriter::new(hout);
+    let routed = self.split_types || self.delta || spec.is_routed();
+    let targets = spec.targets(self.split_types);
+    // Delta imports copy into staging tables, merged after the copy; dry runs
+    // check the tables themselves
+    let db = if self.delta && !self.dry_run { Some(dbo.open()?) } else { None };
+    let mut copy_targets = targets.clone();
+    if let Some(ref db) = db {
+      for t in &mut copy_targets {
+        let cols: Vec<&str> = spec.target_columns(t)?.iter().map(|(c, _)| *c).collect();
+        create_staging(db, &format!("{}.{}", dbo.schema(), t.table), &cols)?;
+        t.table = staging_table(&t.table);
+      }
+    }
+    let mut split_results = Vec::new();
+    let mut rejects = Rejects::new(self.on_error, self.rejects_file(job, multi)?.as_ref())?.with_max(self.max_errors);
+    let n = match self.format {
+      _ if routed => {
+        let (results, skipped) = self.import_routed(spec, &copy_targets, dbo, bfs, pb, &mut rejects)?;
+        if skipped > 0 {
+          info!("skipped {} records of other types", skipped);
+        }
+        for (_, h) in &results {
+          out_hash.update(h.as_bytes());
+        }
+        split_results = results;
+        split_results.iter().map(|(n, _)| n).sum()
+      },
+      _ => {
+        let cols = spec.out_columns(&spec.columns)?;
+        let ext = self.format.target().extension();
+        let path = match (ext, &self.output) {
+          (None, _) => None,
+          // With several inputs, the output is a directory of tables
+          (Some(ext), Some(path)) if multi => {
+            if !self.dry_run {
+              create_dir_all(path)?;
+            }
+            Some(path.join(format!("{}.{}", spec.table, ext)))
+          },
+          (Some(_), Some(path)) => Some(path.clone()),
+          (Some(_), None) if multi || self.format == OutputFormat::Parquet => {
+            return Err(anyhow!("{:?} output requires --output", self.format));
+          },
+          // Arrow streams can go to standard output
+          (Some(_), None) => None
+        };
+        let table = format!("{}.{}", dbo.schema(), spec.table);
+        let sink = self.output_opts().open_path(dbo, &table, &cols, self.truncate, path.as_deref(), &mut out_hash)?;
+        let mut sink = if multi {
+          ProgressSink::labeled(sink, pb, &spec.table)
+        } else {
+          ProgressSink::new(sink, pb)
+        };
 
-    // Actually run the import
-    let n = spec.import(&mut bfs, &mut buf_out)?;
-    buf_out.flush()?;
-    drop(buf_out);
+        // Actually run the import
+        let n = spec.import(bfs, &mut sink, &self.sanitize, &self.subset, &mut rejects)?;
+        Box::new(sink).finish()?;
+        n
+      }
+    };
+
+    let nrejected = rejects.finish()?;
+    let mut merged = Vec::new();
+    if self.delta && self.dry_run {
+      for t in &targets {
+        info!("dry run: would merge into {}.{}", dbo.schema(), t.table);
+      }
+    }
+    if let Some(ref db) = db {
+      for (t, ct) in targets.iter().zip(&copy_targets) {
+        let cols = spec.target_columns(t)?;
+        let json = cols.iter().find(|(_, ct)| *ct == ColType::Json).map(|(c, _)| *c);
+        let cols: Vec<&str> = cols.iter().map(|(c, _)| *c).collect();
+        let table = format!("{}.{}", dbo.schema(), t.table);
+        let staging = format!("{}.{}", dbo.schema(), ct.table);
+        let (replaced, inserted) = merge_staging(db, &table, &staging, &cols, json)?;
+        info!("{}: replaced {} and inserted {} records", table, replaced, inserted);
+        merged.push((table, replaced, inserted));
+      }
+    }
 
-    // Grab the hashes and save them to the transcript
-    let in_hash = in_sf.record()?;
     let out_hash = out_hash.hexdigest();
-    info!("loaded {} records with hash {}", n, out_hash);
-    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
-    writeln!(&mut stage, "HASH {}", out_hash)?;
+    info!("loaded {} records from {:?} with hash {}", n, infn, out_hash);
+    Ok(FileResult {
+      len: None, n,
+      in_hash: String::new(),
+      out_hash, targets, split_results, merged, nrejected
+    })
+  }
+
+  /// Import the input files on worker threads, returning the results in input order.
+  /// `multi` says whether there were several inputs before skipping any, so the
+  /// per-input output and rejects paths stay the same.
+  fn import_all(&self, jobs: &[Job], pb: &ProgressBar, multi: bool) -> Result<Vec<FileResult>> {
+    let nthreads = self.jobs.unwrap_or(jobs.len()).clamp(1, jobs.len().max(1));
+    if nthreads == 1 {
+      return jobs.iter().map(|j| self.import_file(j, pb, multi)).collect();
+    }
+    info!("importing {} files with {} threads", jobs.len(), nthreads);
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<FileResult>)> = thread::scope(|s| {
+      let workers: Vec<_> = (0..nthreads).map(|_| s.spawn(|| {
+        let mut done = Vec::new();
+        loop {
+          let i = next.fetch_add(1, Ordering::SeqCst);
+          if i >= jobs.len() {
+            break;
+          }
+          done.push((i, self.import_file(&jobs[i], pb, multi)));
+        }
+        done
+      })).collect();
+      workers.into_iter().flat_map(|w| w.join().expect("import thread panicked")).collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(i, r)| r.with_context(|| format!("failed to import {:?}", jobs[i].infile))).collect()
+  }
+}
+
+/// Drop the jobs whose input files have already been imported.
+fn drop_imported<F: FnMut(&Path) -> Result<bool>>(jobs: Vec<Job>, mut imported: F) -> Result<Vec<Job>> {
+  let mut todo = Vec::with_capacity(jobs.len());
+  for j in jobs {
+    if !imported(&j.infile)? {
+      todo.push(j);
+    }
+  }
+  Ok(todo)
+}
+
+/// Import records with a spec into sinks that discard them, returning the
+/// number of records imported.  This measures the import pipeline alone.
+pub(crate) fn import_discard(spec: &str, src: &[u8]) -> Result<usize> {
+  let spec: ImportSpec = spec.parse()?;
+  let targets = spec.targets(spec.has_split());
+  let mut sinks: Vec<_> = targets.iter().map(|_| PGSink::new(io::sink())).collect();
+  let mut rejects = Rejects::new(OnError::Fail, None)?;
+  let (counts, _) = spec.import_routed(&mut &src[..], &targets, &mut sinks, &SanitizeOpts::default(), &SubsetOpts::default(), &mut rejects)?;
+  Ok(targets.iter().zip(counts).filter(|(t, _)| t.kind != TargetKind::Links).map(|(_, n)| n).sum())
+}
+
+impl Command for ImportJson {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    let mut jobs = self.jobs()?;
+    let multi = jobs.len() > 1;
+    if self.delta && (self.format != OutputFormat::PG || self.truncate) {
+      return Err(anyhow!("--delta requires PostgreSQL output without --truncate"));
+    }
+
+    let dbo = self.db.clone().default_schema(&jobs[0].spec.schema);
+
+    // Parquet and Arrow output do not need the database
+    let output = self.output_opts();
+    let dbc = output.open_tracking(&dbo)?;
+    let mut stage = match self.format {
+      OutputFormat::PG => {
+        jobs = drop_imported(jobs, |p| self.stage.should_skip(dbc.as_ref(), "import-json", p))?;
+        if jobs.is_empty() {
+          return Ok(());
+        }
+        output.begin_stage(&self.stage, dbc.as_ref())?
+      },
+      OutputFormat::Parquet | OutputFormat::Arrow => self.stage.empty()
+    };
+
+    // One progress bar tracks all the inputs
+    let lens: Option<Vec<u64>> = jobs.iter().map(|j| input_len(&j.infile)).collect();
+    let len = lens.map(|ls| ls.iter().sum());
+    let pb = progress_bar(len, "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta}) {msg}");
+    let _pbl = set_progress(&pb);
+
+    let results = self.import_all(&jobs, &pb, multi)?;
+    pb.finish_and_clear();
+
+    let n: usize = results.iter().map(|r| r.n).sum();
+    let len: Option<u64> = results.iter().map(|r| r.len).sum();
+    let elapsed = started.elapsed();
+    let rate = n as f64 / elapsed.as_secs_f64().max(0.001);
+    match len {
+      Some(l) => info!("processed {} records ({} compressed) in {} ({:.0} records/sec, {}/sec)",
+                       n, HumanBytes(l), HumanDuration(elapsed), rate, HumanBytes((l as f64 / elapsed.as_secs_f64().max(0.001)) as u64)),
+      None => info!("processed {} records in {} ({:.0} records/sec)", n, HumanDuration(elapsed), rate)
+    }
+
+    // Save the hashes to the transcript, one block per input file
+    let mut all_hash = Sha1::new();
+    for (job, res) in jobs.iter().zip(&results) {
+      let infn = &job.infile;
+      stage.record_file(infn, &res.in_hash)?;
+      writeln!(&mut stage, "READ {:?} {}", infn, res.in_hash)?;
+      for (t, (n, h)) in res.targets.iter().zip(&res.split_results) {
+        let rt = t.rec_type.as_deref().unwrap_or("*");
+        info!("{}: {} records into {}", rt, n, t.table);
+        writeln!(&mut stage, "SPLIT {} {} {} {}", rt, t.table, n, h)?;
+      }
+      for (table, replaced, inserted) in &res.merged {
+        writeln!(&mut stage, "DELTA {} {} {}", table, replaced, inserted)?;
+      }
+      if res.nrejected > 0 {
+        writeln!(&mut stage, "REJECTED {}", res.nrejected)?;
+      }
+      writeln!(&mut stage, "HASH {}", res.out_hash)?;
+      stage.log_import("import-json", infn, &res.in_hash, res.n, started)?;
+      all_hash.update(res.out_hash.as_bytes());
+    }
+    // A single input keeps its own output hash as the stage key
+    let key = if results.len() == 1 {
+      results[0].out_hash.clone()
+    } else {
+      all_hash.hexdigest()
+    };
 
     // All done! Record success and exit.
-    stage.end(&Some(out_hash))?;
+    stage.end(&Some(key))?;
     Ok(())
   }
 }
+
+#[test]
+fn test_drop_imported() {
+  let spec = "schema = \"gr\"\ntable = \"raw_book\"\ncolumns = [\"gr_book_data\"]\n";
+  let jobs: Vec<Job> = ["old.json.gz", "new.json.gz", "old2.json.gz"].iter().map(|f| Job {
+    spec: spec.parse().unwrap(),
+    infile: PathBuf::from(f)
+  }).collect();
+  let todo = drop_imported(jobs, |p| Ok(p.to_string_lossy().starts_with("old"))).unwrap();
+  let files: Vec<&Path> = todo.iter().map(|j| j.infile.as_path()).collect();
+  assert_eq!(files, vec![Path::new("new.json.gz")]);
+  let todo = drop_imported(todo, |_| Ok(true)).unwrap();
+  assert!(todo.is_empty());
+}
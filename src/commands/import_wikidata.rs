@@ -0,0 +1,259 @@
+use structopt::StructOpt;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use log::*;
+use sha1::Sha1;
+use anyhow::{Result, Context};
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::value::RawValue;
+
+use crate::io::{open_input, decompress};
+use crate::tsv::write_record;
+use crate::columnar::ColType;
+use crate::sink::RowSink;
+use crate::db::DbOpts;
+use crate::output::OutputOpts;
+use crate::tracking::StageOpts;
+use crate::logging::{set_progress, progress_bar, wrap_read, count_records};
+use super::Command;
+
+/// The identifier properties kept by default: ISBN-13, ISBN-10, Library of
+/// Congress authority ID, VIAF ID, BnF ID, and OpenLibrary ID.
+const DEFAULT_PROPS: &[&str] = &["P212", "P957", "P244", "P214", "P268", "P648"];
+
+/// The columns of the identifier table.
+const ID_COLUMNS: &[(&str, ColType)] = &[("entity_id", ColType::Text), ("property", ColType::Text), ("value", ColType::Text)];
+
+/// The columns of the label table.
+const LABEL_COLUMNS: &[(&str, ColType)] = &[("entity_id", ColType::Text), ("lang", ColType::Text), ("label", ColType::Text)];
+
+/// Import entities with identifiers from the Wikidata JSON dump.
+#[derive(StructOpt, Debug)]
+#[structopt(name="import-wikidata")]
+pub struct ImportWikidata {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  #[structopt(flatten)]
+  output: OutputOpts,
+
+  /// Keep entities with this property (repeatable; defaults to book and author identifiers).
+  #[structopt(short="P", long="property")]
+  props: Vec<String>,
+
+  /// Import labels in this language (repeatable; default en).
+  #[structopt(long="lang")]
+  langs: Vec<String>,
+
+  /// The table for entity identifiers.
+  #[structopt(long="ids-table", default_value="wikidata.entity_ids")]
+  ids_table: String,
+
+  /// The table for entity labels.
+  #[structopt(long="labels-table", default_value="wikidata.entity_labels")]
+  labels_table: String,
+
+  #[structopt(long="truncate")]
+  truncate: bool,
+
+  /// Input file (the compressed JSON dump)
+  #[structopt(name = "INPUT", parse(from_os_str))]
+  infile: PathBuf
+}
+
+/// An entity from the dump.  Only the parts we look at are parsed.
+#[derive(Deserialize, Debug)]
+struct Entity<'a> {
+  #[serde(borrow)]
+  id: Cow<'a, str>,
+  #[serde(borrow, default)]
+  labels: HashMap<Cow<'a, str>, &'a RawValue>,
+  #[serde(borrow, default)]
+  claims: HashMap<Cow<'a, str>, &'a RawValue>
+}
+
+#[derive(Deserialize, Debug)]
+struct Label {
+  value: String
+}
+
+#[derive(Deserialize, Debug)]
+struct Statement {
+  mainsnak: Snak,
+  #[serde(default)]
+  rank: String
+}
+
+#[derive(Deserialize, Debug)]
+struct Snak {
+  #[serde(default)]
+  datavalue: Option<DataValue>
+}
+
+#[derive(Deserialize, Debug)]
+struct DataValue {
+  value: Value
+}
+
+impl Statement {
+  /// Get the statement's value as text, if it is a string or an entity.
+  fn text(&self) -> Option<&str> {
+    if self.rank == "deprecated" {
+      return None;
+    }
+    match self.mainsnak.datavalue.as_ref().map(|dv| &dv.value) {
+      Some(Value::String(s)) => Some(s),
+      Some(Value::Object(o)) => o.get("id").and_then(Value::as_str),
+      _ => None
+    }
+  }
+}
+
+/// Destinations for the rows of entities we keep.
+struct Outputs<W: RowSink> {
+  ids: W,
+  labels: W,
+  nids: usize,
+  nlabels: usize
+}
+
+impl <W: RowSink> Outputs<W> {
+  fn new(ids: W, labels: W) -> Outputs<W> {
+    Outputs { ids, labels, nids: 0, nlabels: 0 }
+  }
+
+  /// Finish the writers, returning the identifier and label counts.
+  fn finish(self) -> Result<(usize, usize)> {
+    Box::new(self.ids).finish()?;
+    Box::new(self.labels).finish()?;
+    Ok((self.nids, self.nlabels))
+  }
+
+  /// Process a line of the dump.  The dump is one big JSON array, with an
+  /// entity on each line; returns whether the line's entity was kept.
+  fn process_line<S: AsRef<str>>(&mut self, line: &str, props: &[S], langs: &[S]) -> Result<bool> {
+    let json = line.trim().trim_end_matches(',');
+    if json.is_empty() || json == "[" || json == "]" {
+      return Ok(false);
+    }
+    let ent: Entity = serde_json::from_str(json)?;
+    let mut found = false;
+    for p in props {
+      let stmts = match ent.claims.get(p.as_ref()) {
+        Some(s) => s,
+        None => continue
+      };
+      let stmts: Vec<Statement> = serde_json::from_str(stmts.get())?;
+      for val in stmts.iter().filter_map(Statement::text) {
+        write_record(&mut self.ids, &(&ent.id, p.as_ref(), val))?;
+        self.nids += 1;
+        found = true;
+      }
+    }
+    if found {
+      for lang in langs {
+        if let Some(raw) = ent.labels.get(lang.as_ref()) {
+          let label: Label = serde_json::from_str(raw.get())?;
+          write_record(&mut self.labels, &(&ent.id, lang.as_ref(), &label.value))?;
+          self.nlabels += 1;
+        }
+      }
+    }
+    Ok(found)
+  }
+}
+
+impl Command for ImportWikidata {
+  fn exec(self) -> Result<()> {
+    let started = Instant::now();
+    let dbc = self.output.open_tracking(&self.db)?;
+    if self.stage.should_skip(dbc.as_ref(), "import-wikidata", &self.infile)? {
+      return Ok(());
+    }
+    let mut stage = self.output.begin_stage(&self.stage, dbc.as_ref())?;
+    let props: Vec<&str> = if self.props.is_empty() {
+      DEFAULT_PROPS.to_vec()
+    } else {
+      self.props.iter().map(String::as_str).collect()
+    };
+    let langs: Vec<&str> = if self.langs.is_empty() {
+      vec!["en"]
+    } else {
+      self.langs.iter().map(String::as_str).collect()
+    };
+    info!("keeping entities with {}", props.join(", "));
+
+    let infn = &self.infile;
+    let (read, len) = open_input(infn)?;
+    let pb = progress_bar(len, "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta}) {msg}");
+    let _pbl = set_progress(&pb);
+    let mut in_sf = stage.source_file(infn);
+    let read = in_sf.wrap_read(wrap_read(&pb, read));
+    let read = BufReader::new(decompress(BufReader::new(read))?);
+
+    let mut ids_hash = Sha1::new();
+    let mut labels_hash = Sha1::new();
+    let ids = self.output.open(&self.db, &self.ids_table, ID_COLUMNS, self.truncate, &mut ids_hash)?;
+    let labels = self.output.open(&self.db, &self.labels_table, LABEL_COLUMNS, self.truncate, &mut labels_hash)?;
+    let mut out = Outputs::new(ids, labels);
+
+    let mut n: usize = 0;
+    let mut kept = 0;
+    for (i, line) in read.lines().enumerate() {
+      let line = line?;
+      if out.process_line(&line, &props, &langs).with_context(|| format!("line {}", i + 1))? {
+        kept += 1;
+      }
+      n += 1;
+      count_records(1);
+      if n.is_multiple_of(100000) {
+        pb.set_message(&format!("kept {} of {} entities", kept, n));
+      }
+    }
+    let (nids, nlabels) = out.finish()?;
+
+    let in_hash = in_sf.record()?;
+    info!("kept {} of {} entities, with {} identifiers and {} labels", kept, n, nids, nlabels);
+    writeln!(&mut stage, "READ {:?} {}", infn, in_hash)?;
+    writeln!(&mut stage, "{} ENTITIES", kept)?;
+    writeln!(&mut stage, "COPY {} {} {}", self.ids_table, nids, ids_hash.hexdigest())?;
+    writeln!(&mut stage, "COPY {} {} {}", self.labels_table, nlabels, labels_hash.hexdigest())?;
+    stage.log_import("import-wikidata", infn, &in_hash, kept, started)?;
+    let mut key = Sha1::new();
+    key.update(ids_hash.hexdigest().as_bytes());
+    key.update(labels_hash.hexdigest().as_bytes());
+    stage.end(&Some(key.hexdigest()))?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_process_lines() {
+  let dump = r#"[
+{"type":"item","id":"Q190192","labels":{"en":{"language":"en","value":"The Hobbit"},"fr":{"language":"fr","value":"Le Hobbit"}},"claims":{"P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"value":{"entity-type":"item","numeric-id":7725634,"id":"Q7725634"},"type":"wikibase-entityid"}},"rank":"normal"}],"P212":[{"mainsnak":{"snaktype":"value","property":"P212","datavalue":{"value":"978-0-261-10221-7","type":"string"}},"rank":"normal"},{"mainsnak":{"snaktype":"value","property":"P212","datavalue":{"value":"978-0-000-00000-0","type":"string"}},"rank":"deprecated"},{"mainsnak":{"snaktype":"novalue","property":"P212"},"rank":"normal"}]}},
+{"type":"item","id":"Q42","labels":{"en":{"language":"en","value":"Douglas Adams"}},"claims":{"P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"value":{"id":"Q5"},"type":"wikibase-entityid"}},"rank":"normal"}]}},
+{"type":"item","id":"Q892","labels":{"de":{"language":"de","value":"J. R. R. Tolkien"}},"claims":{"P244":[{"mainsnak":{"snaktype":"value","property":"P244","datavalue":{"value":"n79005673","type":"string"}},"rank":"preferred"}]}}
+]"#;
+  use crate::sink::PGSink;
+  let mut out = Outputs::new(PGSink::new(Vec::new()), PGSink::new(Vec::new()));
+  let mut kept = 0;
+  for line in dump.lines() {
+    if out.process_line(line, &["P212", "P244"], &["en", "fr"]).unwrap() {
+      kept += 1;
+    }
+  }
+  assert_eq!(kept, 2);
+  assert!(out.process_line("{\"id\": ", &["P212"], &["en"]).is_err());
+  assert_eq!(String::from_utf8(out.ids.out).unwrap(), "Q190192\tP212\t978-0-261-10221-7\nQ892\tP244\tn79005673\n");
+  assert_eq!(String::from_utf8(out.labels.out).unwrap(), "Q190192\ten\tThe Hobbit\nQ190192\tfr\tLe Hobbit\n");
+}
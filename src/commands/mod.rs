@@ -9,6 +9,30 @@ pub mod parse_isbns;
 pub mod pcat;
 pub mod hash;
 pub mod info;
+pub mod cluster_books;
+pub mod import_az;
+pub mod import_bx;
+pub mod status;
+pub mod sql;
+pub mod fetch;
+pub mod bench;
+pub mod norm_lccn;
+pub mod extract_oclc;
+pub mod call_numbers;
+pub mod name_keys;
+pub mod author_years;
+pub mod import_wikidata;
+pub mod import_gr_reviews;
+pub mod import_gutenberg;
+pub mod import_hathi;
+pub mod report;
+pub mod export_ratings;
+pub mod split_ratings;
+pub mod filter_kcore;
+pub mod pseudonymize;
+pub mod export;
+pub mod completions;
+pub mod validate;
 
 pub fn commands<'a>() -> Vec<CmdEntry<'a>> {
   vec![
@@ -18,6 +42,30 @@ pub fn commands<'a>() -> Vec<CmdEntry<'a>> {
     parse_marc::ParseMarc::get_entry(),
     parse_isbns::ParseISBNs::get_entry(),
     hash::Hash::get_entry(),
-    info::Info::get_entry()
+    info::Info::get_entry(),
+    cluster_books::ClusterBooks::get_entry(),
+    import_az::ImportAZ::get_entry(),
+    import_bx::ImportBX::get_entry(),
+    status::Status::get_entry(),
+    sql::RunSql::get_entry(),
+    fetch::Fetch::get_entry(),
+    bench::Bench::get_entry(),
+    norm_lccn::NormLCCN::get_entry(),
+    extract_oclc::ExtractOCLC::get_entry(),
+    call_numbers::CallNumbers::get_entry(),
+    name_keys::NameKeys::get_entry(),
+    author_years::AuthorYears::get_entry(),
+    import_wikidata::ImportWikidata::get_entry(),
+    import_gr_reviews::ImportGrReviews::get_entry(),
+    import_gutenberg::ImportGutenberg::get_entry(),
+    import_hathi::ImportHathi::get_entry(),
+    report::Report::get_entry(),
+    export_ratings::ExportRatings::get_entry(),
+    split_ratings::SplitRatings::get_entry(),
+    filter_kcore::FilterKCore::get_entry(),
+    pseudonymize::Pseudonymize::get_entry(),
+    export::Export::get_entry(),
+    completions::Completions::get_entry(),
+    validate::Validate::get_entry()
   ]
 }
@@ -0,0 +1,103 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::fs::File;
+use std::path::PathBuf;
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::names::parse_name;
+use crate::io::{open_input, decompress};
+use crate::cleaning::decode_pgencoded;
+use super::Command;
+
+/// Add name match keys to a column of personal names in a tab-separated stream.
+#[derive(StructOpt, Debug)]
+#[structopt(name="name-keys")]
+pub struct NameKeys {
+  /// The column with the names (counting from 1).
+  #[structopt(short="c", long="column", default_value="1")]
+  column: usize,
+
+  /// Write a row for each of the name's variant keys, instead of only its key.
+  #[structopt(long="variants")]
+  variants: bool,
+
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Input file (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// Add match keys to each row in a stream, returning the number of rows read
+/// and written.  Rows whose name has no key (or is NULL) are dropped.
+fn key_stream<R: BufRead, W: Write>(src: R, dst: &mut W, col: usize, variants: bool) -> Result<(usize, usize)> {
+  let mut n = 0;
+  let mut nout = 0;
+  let mut buf = Vec::new();
+  for line in src.lines() {
+    let line = line?;
+    n += 1;
+    let field = line.split('\t').nth(col - 1).ok_or(anyhow!("line {}: no column {}", n, col))?;
+    if field == "\\N" {
+      continue;
+    }
+    decode_pgencoded(field.as_bytes(), &mut buf)?;
+    let name = parse_name(&String::from_utf8_lossy(&buf));
+    let keys = if variants {
+      name.variants()
+    } else {
+      vec![name.match_key()]
+    };
+    // keys are only letters, digits, and spaces, so they need no encoding
+    for key in keys.iter().filter(|k| !k.is_empty()) {
+      writeln!(dst, "{}\t{}", line, key)?;
+      nout += 1;
+    }
+  }
+  Ok((n, nout))
+}
+
+impl Command for NameKeys {
+  fn exec(self) -> Result<()> {
+    if self.column == 0 {
+      return Err(anyhow!("columns count from 1"));
+    }
+    let (read, _len) = open_input(&self.infile)?;
+    let src = BufReader::new(decompress(BufReader::new(read))?);
+    let (n, nout) = match self.output {
+      Some(ref p) => {
+        let mut out = BufWriter::new(File::create(p)?);
+        key_stream(src, &mut out, self.column, self.variants)?
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        key_stream(src, &mut out, self.column, self.variants)?
+      }
+    };
+    info!("read {} names, wrote {} keys", n, nout);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_key_stream() {
+  let src = "A1\tTolkien, J. R. R. (John Ronald Reuel), 1892-1973\nA2\t\\N\nA3\tHomer\nA4\t1999\n";
+  let mut out = Vec::new();
+  let (n, nout) = key_stream(src.as_bytes(), &mut out, 2, false).unwrap();
+  assert_eq!((n, nout), (4, 2));
+  assert_eq!(String::from_utf8(out).unwrap(), concat!(
+    "A1\tTolkien, J. R. R. (John Ronald Reuel), 1892-1973\ttolkien j r r\n",
+    "A3\tHomer\thomer\n"));
+
+  let mut out = Vec::new();
+  let (_, nout) = key_stream("A5\tJohn Smith\n".as_bytes(), &mut out, 2, true).unwrap();
+  assert_eq!(nout, 3);
+  assert_eq!(String::from_utf8(out).unwrap(), "A5\tJohn Smith\tsmith john\nA5\tJohn Smith\tsmith j\nA5\tJohn Smith\tjohn smith\n");
+}
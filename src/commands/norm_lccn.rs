@@ -0,0 +1,101 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::fs::File;
+use std::path::PathBuf;
+
+use log::*;
+use anyhow::{Result, anyhow};
+
+use crate::ids::normalize_lccn;
+use crate::io::{open_input, decompress};
+use crate::cleaning::PG_NULL;
+use super::Command;
+
+/// Normalize a column of LCCNs in a tab-separated stream.
+#[derive(StructOpt, Debug)]
+#[structopt(name="normalize-lccn")]
+pub struct NormLCCN {
+  /// The column with the LCCNs (counting from 1).
+  #[structopt(short="c", long="column", default_value="1")]
+  column: usize,
+
+  /// Drop rows without a valid LCCN, instead of writing NULL.
+  #[structopt(long="drop-invalid")]
+  drop_invalid: bool,
+
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Input file (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// Normalize the LCCNs in a stream, returning the number of rows read and
+/// invalid LCCNs found.
+fn normalize_stream<R: BufRead, W: Write>(src: R, dst: &mut W, col: usize, drop_invalid: bool) -> Result<(usize, usize)> {
+  let mut n = 0;
+  let mut bad = 0;
+  // a normalized LCCN never needs encoding
+  let null = std::str::from_utf8(PG_NULL)?;
+  for line in src.lines() {
+    let line = line?;
+    n += 1;
+    let mut fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < col {
+      return Err(anyhow!("line {}: no column {}", n, col));
+    }
+    let norm = normalize_lccn(fields[col - 1]);
+    if norm.is_none() {
+      bad += 1;
+      if drop_invalid {
+        continue;
+      }
+    }
+    fields[col - 1] = norm.as_deref().unwrap_or(null);
+    dst.write_all(fields.join("\t").as_bytes())?;
+    dst.write_all(b"\n")?;
+  }
+  Ok((n, bad))
+}
+
+impl Command for NormLCCN {
+  fn exec(self) -> Result<()> {
+    if self.column == 0 {
+      return Err(anyhow!("columns count from 1"));
+    }
+    let (read, _len) = open_input(&self.infile)?;
+    let src = BufReader::new(decompress(BufReader::new(read))?);
+    let (n, bad) = match self.output {
+      Some(ref p) => {
+        let mut out = BufWriter::new(File::create(p)?);
+        normalize_stream(src, &mut out, self.column, self.drop_invalid)?
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        normalize_stream(src, &mut out, self.column, self.drop_invalid)?
+      }
+    };
+    info!("normalized {} rows, {} without valid LCCNs", n, bad);
+    Ok(())
+  }
+}
+
+#[test]
+fn test_normalize_stream() {
+  let src = b"1\t n78-89035 \tx\n2\t85-2 /r86\ty\n3\tbogus\tz\n4\t\\N\tw\n";
+  let mut out = Vec::new();
+  let (n, bad) = normalize_stream(&src[..], &mut out, 2, false).unwrap();
+  assert_eq!((n, bad), (4, 2));
+  assert_eq!(String::from_utf8(out).unwrap(), "1\tn78089035\tx\n2\t85000002\ty\n3\t\\N\tz\n4\t\\N\tw\n");
+
+  let mut out = Vec::new();
+  normalize_stream(&src[..], &mut out, 2, true).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(), "1\tn78089035\tx\n2\t85000002\ty\n");
+
+  assert!(normalize_stream(&src[..], &mut Vec::new(), 4, false).is_err());
+}
@@ -13,7 +13,7 @@ use structopt::{StructOpt};
 use fallible_iterator::FallibleIterator;
 
 use super::Command;
-use crate::db::DbOpts;
+use crate::db::{DbOpts, CopyFormat};
 use crate::tracking::{StageOpts};
 
 mod parsers;
@@ -50,6 +50,10 @@ pub struct ParseISBNs {
   #[structopt(long="out-table")]
   out_table: Option<String>,
 
+  /// The COPY format (text or binary) for writing to the output table.
+  #[structopt(long="format", default_value="text")]
+  format: CopyFormat,
+
   /// Print unmatched entries
   #[structopt(short="-U", long="print-unmatched")]
   print_unmatched: bool,
@@ -169,7 +173,7 @@ impl Command for ParseISBNs {
     let writer: Box<dyn WriteISBNs> = if let Some(ref tbl) = self.out_table {
       info!("opening output table {}", tbl);
       writeln!(stage, "DEST TABLE {}", tbl)?;
-      Box::new(DBWriter::new(&self.db, tbl)?)
+      Box::new(DBWriter::new(&self.db, tbl, self.format)?)
     } else if let Some(ref path) = self.out_file {
       info!("opening output file {:?}", path);
       writeln!(stage, "DEST FILE {:?}", path)?;
@@ -295,6 +295,20 @@ fn test_parse_isbn() {
   }
 }
 
+#[test]
+fn test_parse_isbn_lower_x() {
+  use crate::ids::normalize_isbn;
+  // parsed ISBNs keep their case; normalize_isbn would upper-case the X
+  let defs = ParserDefs::new();
+  match defs.parse("0-8044-2957-x") {
+    ParseResult::Valid(isbns, _) => {
+      assert_eq!(isbns[0].text, "080442957x");
+      assert_eq!(normalize_isbn(&isbns[0].text), Some("080442957X".to_string()));
+    },
+    x => panic!("bad parse: {:?}", x)
+  }
+}
+
 #[test]
 fn test_parse_isbn_trail() {
   let src = "349224010X :";
@@ -1,10 +1,11 @@
 use std::io::prelude::*;
+use std::convert::TryInto;
 use anyhow::Result;
 use sha1::Sha1;
 
 use super::parsers::ISBN;
-use crate::db::{ConnectInfo, CopyRequest, CopyTarget};
-use crate::cleaning::write_pgencoded;
+use crate::db::{ConnectInfo, CopyRequest, CopyTarget, CopyFormat};
+use crate::cleaning::{write_pgencoded, BinaryCopyWrite};
 
 pub trait WriteISBNs {
   fn write_isbn(&mut self, id: i64, isbn: &ISBN) -> Result<()>;
@@ -41,25 +42,56 @@ impl WriteISBNs for FileWriter {
 
 pub struct DBWriter {
   hash: Sha1,
+  format: CopyFormat,
   target: CopyTarget
 }
 
 impl DBWriter {
-  pub fn new<C: ConnectInfo>(db: &C, table: &str) -> Result<DBWriter> {
+  pub fn new<C: ConnectInfo>(db: &C, table: &str, format: CopyFormat) -> Result<DBWriter> {
     let req = CopyRequest::new(db, table)?;
     let req = req.truncate(true);
+    let req = req.with_copy_format(format);
+    let mut target = req.open()?;
+    if format == CopyFormat::Binary {
+      target.write_copy_header()?;
+    }
 
     Ok(DBWriter {
       hash: Sha1::new(),
-      target: req.open()?
+      format,
+      target
     })
   }
+
+  fn write_binary(&mut self, id: i64, isbn: &ISBN) -> Result<()> {
+    let id: i32 = id.try_into()?;
+    if isbn.tags.is_empty() {
+      self.write_binary_row(id, &isbn.text, "")?;
+    } else {
+      for tag in &isbn.tags {
+        self.hash.update(tag.as_bytes());
+        self.write_binary_row(id, &isbn.text, tag)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn write_binary_row(&mut self, id: i32, isbn: &str, tag: &str) -> Result<()> {
+    self.target.begin_tuple(3)?;
+    self.target.write_int4_field(id)?;
+    self.target.write_text_field(isbn.as_bytes())?;
+    self.target.write_text_field(tag.as_bytes())?;
+    Ok(())
+  }
 }
 
 impl WriteISBNs for DBWriter {
   fn write_isbn(&mut self, id: i64, isbn: &ISBN) -> Result<()> {
     self.hash.update(id.to_string().as_bytes());
     self.hash.update(isbn.text.as_bytes());
+    if self.format == CopyFormat::Binary {
+      return self.write_binary(id, isbn);
+    }
     if isbn.tags.len() > 0 {
       for tag in &isbn.tags {
         self.hash.update(tag.as_bytes());
@@ -74,6 +106,9 @@ impl WriteISBNs for DBWriter {
   }
 
   fn finish(&mut self) -> Result<String> {
+    if self.format == CopyFormat::Binary {
+      self.target.write_copy_trailer()?;
+    }
     let hash = self.hash.hexdigest();
     Ok(hash.to_owned())
   }
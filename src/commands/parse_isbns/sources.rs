@@ -7,7 +7,7 @@ use fallible_iterator::FallibleIterator;
 
 use log::*;
 
-use crate::tsv::split_first;
+use crate::tsv::read_row_prefix;
 use super::parsers::*;
 
 pub type IdPR = (i64, ParseResult);
@@ -31,15 +31,17 @@ impl <B: BufRead> FallibleIterator for FileSource<B> {
   type Error = anyhow::Error;
 
   fn next(&mut self) -> Result<Option<IdPR>> {
-    let nl = self.lines.next();
-    match nl {
-      None => Ok(None),
-      Some(line) => {
-        let text = line?;
-        let (id, isbn) = split_first(&text).unwrap();
-        Ok(Some((id.parse::<i64>()?, self.parsers.parse(isbn))))
+    for line in &mut self.lines {
+      let text = line?;
+      // source files are usually COPY output, so undo its encoding; fields
+      // after the ISBN text are ignored
+      let (id, isbn): (i64, Option<String>) = read_row_prefix(&text)?;
+      match isbn {
+        Some(isbn) => return Ok(Some((id, self.parsers.parse(&isbn)))),
+        None => debug!("{}: no ISBN text, skipping", id)
       }
     }
+    Ok(None)
   }
 }
 
@@ -73,3 +75,10 @@ impl <'t, 's> FallibleIterator for DBSource<'t, 's> {
     }
   }
 }
+
+#[test]
+fn test_file_source_lenient() {
+  let src = "1\t0-8044-2957-X\textra\n2\t\\N\n3\t9780441013593\n";
+  let ids: Vec<i64> = FileSource::create(src.as_bytes()).unwrap().map(|(id, _)| id).collect().unwrap();
+  assert_eq!(ids, vec![1, 3]);
+}
@@ -1,5 +1,5 @@
 use std::io::prelude::*;
-use std::io::{self, BufReader, BufWriter};
+use std::io::BufReader;
 use std::fs::File;
 use std::path::PathBuf;
 use std::str;
@@ -11,18 +11,23 @@ use glob::glob;
 use structopt::StructOpt;
 use quick_xml::Reader;
 use quick_xml::events::Event;
-use flate2::bufread::MultiGzDecoder;
-use indicatif::{ProgressBar, ProgressStyle};
 use anyhow::{Result, anyhow};
 
-use crate::cleaning::write_pgencoded;
 use crate::tsv::split_first;
 use crate::tracking::StageOpts;
-use crate::io::{HashWrite};
-use crate::db::{DbOpts, CopyRequest};
-use crate::logging;
+use crate::io::decompress;
+use crate::columnar::{ColType::{self, *}, Value};
+use crate::sink::RowSink;
+use crate::db::DbOpts;
+use crate::output::OutputOpts;
+use crate::logging::{self, progress_bar, wrap_read};
 use super::Command;
 
+/// The columns of a MARC field table.
+const COLUMNS: &[(&str, ColType)] = &[
+  ("rec_id", Int32), ("fld_no", Int32), ("tag", Text), ("ind1", Text), ("ind2", Text), ("sf_code", Text), ("contents", Text)
+];
+
 /// Parse MARC files into records for a PostgreSQL table.
 #[derive(StructOpt, Debug)]
 #[structopt(name="parse-marc")]
@@ -33,6 +38,9 @@ pub struct ParseMarc {
   #[structopt(flatten)]
   stage: StageOpts,
 
+  #[structopt(flatten)]
+  output: OutputOpts,
+
   #[structopt(short="-t", long="table")]
   table: String,
 
@@ -62,7 +70,7 @@ struct Field<'a> {
 
 /// Process a tab-delimited line file.  VIAF provides their files in this format;
 /// each line is a tab-separated pair of the VIAF ID and a single `record` instance.
-fn process_delim_file<R: BufRead, W: Write>(r: &mut R, w: &mut W, init: usize) -> Result<usize> {
+fn process_delim_file<R: BufRead, W: RowSink + ?Sized>(r: &mut R, w: &mut W, init: usize) -> Result<usize> {
   let mut rec_count = 0;
   for line in r.lines() {
     let lstr = line?;
@@ -78,39 +86,32 @@ fn process_delim_file<R: BufRead, W: Write>(r: &mut R, w: &mut W, init: usize) -
 }
 
 /// Process a file containing a MARC collection.
-fn process_marc_file<R: BufRead, W: Write>(r: &mut R, w: &mut W, init: usize) -> Result<usize> {
+fn process_marc_file<R: BufRead, W: RowSink + ?Sized>(r: &mut R, w: &mut W, init: usize) -> Result<usize> {
   let mut parse = Reader::from_reader(r);
   let count = process_records(&mut parse, w, init)?;
   Ok(count)
 }
 
-fn write_codes<W: Write>(w: &mut W, rno: usize, fno: i32, tag: &[u8], fld: Option<&Field>) -> Result<()> {
-  let ids = format!("{}\t{}\t", rno, fno);
-  w.write_all(ids.as_str().as_bytes())?;
-  w.write_all(tag)?;
-  w.write_all(b"\t")?;
-  match fld {
-    Some(f) => {
-      w.write_all(f.ind1)?;
-      w.write_all(b"\t")?;
-      w.write_all(f.ind2)?;
-      w.write_all(b"\t")?;
-      w.write_all(f.code)?;
-      w.write_all(b"\t")?;
-    },
-    None => {
-      w.write_all(b"\\N\t\\N\t\\N\t")?;
+fn write_codes<W: RowSink + ?Sized>(w: &mut W, rno: usize, fno: i32, tag: &[u8], fld: Option<&Field>) -> Result<()> {
+  w.write_value(Value::Int64(rno as i64))?;
+  w.write_value(Value::Int32(fno))?;
+  w.write_field(tag)?;
+  let codes = match fld {
+    Some(f) => [Some(f.ind1), Some(f.ind2), Some(f.code)],
+    None => [None, None, None]
+  };
+  for c in &codes {
+    match c {
+      Some(c) => w.write_field(c)?,
+      None => w.write_null()?
     }
   }
   Ok(())
 }
 
-fn write_nl<W: Write>(w: &mut W) -> io::Result<()> {
-  w.write_all(b"\n")
-}
-
-fn process_records<B: BufRead, W: Write>(rdr: &mut Reader<B>, out: &mut W, start: usize) -> Result<usize> {
+fn process_records<B: BufRead, W: RowSink + ?Sized>(rdr: &mut Reader<B>, out: &mut W, start: usize) -> Result<usize> {
   let mut buf = Vec::new();
+  let mut contents = Vec::new();
   let mut output = false;
   let mut fno = 0;
   let mut tag = Vec::with_capacity(5);
@@ -180,7 +181,9 @@ fn process_records<B: BufRead, W: Write>(rdr: &mut Reader<B>, out: &mut W, start
         let name = str::from_utf8(e.local_name())?;
         match name {
           "leader" | "controlfield" | "subfield" => {
-            write_nl(out)?;
+            out.write_field(&contents)?;
+            out.end_row()?;
+            contents.clear();
             output =  false;
           },
           "datafield" => {
@@ -194,7 +197,7 @@ fn process_records<B: BufRead, W: Write>(rdr: &mut Reader<B>, out: &mut W, start
       Event::Text(e) => {
         if output {
           let t = e.unescaped()?;
-          write_pgencoded(out, &t)?
+          contents.extend_from_slice(&t);
         }
       },
       Event::Eof => break,
@@ -206,16 +209,12 @@ fn process_records<B: BufRead, W: Write>(rdr: &mut Reader<B>, out: &mut W, start
 
 impl Command for ParseMarc {
   fn exec(self) -> Result<()> {
-    let db = self.db.open()?;
-    let req = CopyRequest::new(&self.db, &self.table)?;
-    let req = req.with_schema(self.db.schema());
-    let req = req.truncate(self.truncate);
-    let out = req.open()?;
+    let db = self.output.open_tracking(&self.db)?;
+    let table = format!("{}.{}", self.db.schema(), self.table);
     let mut out_h = Sha1::new();
-    let out = HashWrite::create(out, &mut out_h);
-    let mut out = BufWriter::new(out);
+    let mut out = self.output.open(&self.db, &table, COLUMNS, self.truncate, &mut out_h)?;
 
-    let mut stage = self.stage.begin_stage(&db)?;
+    let mut stage = self.output.begin_stage(&self.stage, db.as_ref())?;
 
     let mut count = 0;
 
@@ -223,19 +222,18 @@ impl Command for ParseMarc {
       let inf = inf.as_path();
       info!("reading from compressed file {:?}", inf);
       let fs = File::open(inf)?;
-      let pb = ProgressBar::new(fs.metadata()?.len());
-      pb.set_style(ProgressStyle::default_bar().template("{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})"));
+      let pb = progress_bar(Some(fs.metadata()?.len()), "{elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})");
       let _pbs = logging::set_progress(&pb);
       let mut in_sf = stage.source_file(inf);
-      let pbr = pb.wrap_read(fs);
+      let pbr = wrap_read(&pb, fs);
       let pbr = BufReader::new(pbr);
-      let gzf = MultiGzDecoder::new(pbr);
+      let gzf = decompress(pbr)?;
       let gzf = in_sf.wrap_read(gzf);
       let mut bfs = BufReader::new(gzf);
       let nrecs = if self.linemode {
-        process_delim_file(&mut bfs, &mut out, count)
+        process_delim_file(&mut bfs, &mut *out, count)
       } else {
-        process_marc_file(&mut bfs, &mut out, count)
+        process_marc_file(&mut bfs, &mut *out, count)
       };
       drop(bfs);
       match nrecs {
@@ -244,6 +242,7 @@ impl Command for ParseMarc {
           let hash = in_sf.record()?;
           writeln!(&mut stage, "READ {:?} {} {}", inf, n, hash)?;
           count += n;
+          logging::count_records(n as u64);
         },
         Err(e) => {
           error!("error in {:?}: {}", inf, e);
@@ -252,7 +251,7 @@ impl Command for ParseMarc {
       }
     }
 
-    drop(out);
+    out.finish()?;
     let out_h = out_h.hexdigest();
     writeln!(&mut stage, "COPY {}", out_h)?;
     stage.end(&Some(out_h))?;
@@ -5,14 +5,13 @@ use std::io;
 use std::fs::File;
 use std::path::{PathBuf, Path};
 use log::*;
-use indicatif::{ProgressBar, ProgressStyle};
 use sha1::Sha1;
 use anyhow::Result;
 
 use crate::db::{DbOpts, CopyRequest};
 use crate::tracking::{Stage, StageOpts};
 use crate::io::{HashWrite};
-use crate::logging::set_progress;
+use crate::logging::{set_progress, progress_bar, wrap_read};
 use super::Command;
 
 const PB_STYLE: &'static str = "{prefix}: {elapsed_precise} {bar} {percent}% {bytes}/{total_bytes} (eta: {eta})";
@@ -46,13 +45,12 @@ fn cat_file<'o, 'c, P: AsRef<Path>, W: Write>(stage: &mut Stage<'o, 'c>, inf: P,
   let fstr = inf.to_string_lossy();
   info!("opening file {:?}", inf);
   let fs = File::open(inf)?;
-  let pb = ProgressBar::new(fs.metadata().unwrap().len());
-  pb.set_style(ProgressStyle::default_bar().template(PB_STYLE));
+  let pb = progress_bar(Some(fs.metadata().unwrap().len()), PB_STYLE);
   pb.set_prefix(&fstr);
   let _pbs = set_progress(&pb);
   let mut sf = stage.source_file(inf);
   let read = sf.wrap_read(fs);
-  let mut pbr = pb.wrap_read(read);
+  let mut pbr = wrap_read(&pb, read);
   io::copy(&mut pbr, out)?;
   drop(pbr);
   let hash = sf.record()?;
@@ -82,8 +80,8 @@ impl PCat {
   }
 
   fn db_cat(&self, table: &str) -> Result<()> {
-    let db = self.dbo.open()?;
-    let mut stage = self.stage.begin_stage(&db)?;
+    let db = self.dbo.open_tracking()?;
+    let mut stage = self.stage.begin_stage_opt(db.as_ref())?;
     let mut req = CopyRequest::new(&self.dbo, table)?.truncate(true);
     if let Some(ref fmt) = self.format {
       req = req.with_format(fmt);
@@ -0,0 +1,253 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use log::*;
+use anyhow::{Result, anyhow};
+use ring::hmac;
+use ring::aead::{LessSafeKey, UnboundKey, Nonce, Aad, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::io::{open_input, decompress};
+use super::Command;
+
+/// The number of bytes of the keyed hash in a pseudonym.
+const HASH_BYTES: usize = 16;
+
+/// Replace user IDs in a tab-separated stream with pseudonyms.
+///
+/// By default, users are numbered in order of first appearance.  With
+/// `--key-file`, each user's pseudonym is a keyed hash (HMAC-SHA256) of their
+/// ID, so the same key gives the same pseudonyms across runs and datasets.
+#[derive(StructOpt, Debug)]
+#[structopt(name="pseudonymize")]
+pub struct Pseudonymize {
+  /// The column with the user IDs (counting from 1).
+  #[structopt(short="c", long="column", default_value="1")]
+  column: usize,
+
+  /// Hash IDs with the key in this file, instead of numbering them.
+  #[structopt(long="key-file", parse(from_os_str))]
+  key_file: Option<PathBuf>,
+
+  /// Write the ID-to-pseudonym mapping to this file.  An existing mapping is
+  /// read first, so sequential pseudonyms stay the same across runs.
+  #[structopt(long="map", parse(from_os_str))]
+  map_file: Option<PathBuf>,
+
+  /// Encrypt the mapping with the 32-byte AES-256-GCM key in this file.
+  #[structopt(long="map-key-file", parse(from_os_str))]
+  map_key_file: Option<PathBuf>,
+
+  /// Output file (default stdout)
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Input file (default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// How pseudonyms are made.
+enum Scheme {
+  Sequential,
+  Keyed(hmac::Key)
+}
+
+/// The mapping from IDs to pseudonyms.
+struct Pseudonyms {
+  scheme: Scheme,
+  map: HashMap<String, String>,
+  /// The IDs in the order they were mapped
+  order: Vec<String>
+}
+
+impl Pseudonyms {
+  fn new(scheme: Scheme) -> Pseudonyms {
+    Pseudonyms { scheme, map: HashMap::new(), order: Vec::new() }
+  }
+
+  /// Get an ID's pseudonym, making one if it is new.
+  fn get(&mut self, id: &str) -> &str {
+    if !self.map.contains_key(id) {
+      let pseudo = match self.scheme {
+        Scheme::Sequential => (self.map.len() + 1).to_string(),
+        Scheme::Keyed(ref key) => {
+          let tag = hmac::sign(key, id.as_bytes());
+          tag.as_ref()[..HASH_BYTES].iter().map(|b| format!("{:02x}", b)).collect()
+        }
+      };
+      self.map.insert(id.to_string(), pseudo);
+      self.order.push(id.to_string());
+    }
+    &self.map[id]
+  }
+
+  /// Load a mapping written by `write_map`.
+  fn read_map(&mut self, data: &[u8]) -> Result<()> {
+    for line in std::str::from_utf8(data)?.lines() {
+      let (id, pseudo) = line.split_once('\t').ok_or_else(|| anyhow!("invalid mapping line {:?}", line))?;
+      self.map.insert(id.to_string(), pseudo.to_string());
+      self.order.push(id.to_string());
+    }
+    Ok(())
+  }
+
+  /// Write the mapping as tab-separated ID and pseudonym.
+  fn write_map(&self) -> Vec<u8> {
+    let mut out = Vec::new();
+    for id in &self.order {
+      out.extend_from_slice(id.as_bytes());
+      out.push(b'\t');
+      out.extend_from_slice(self.map[id].as_bytes());
+      out.push(b'\n');
+    }
+    out
+  }
+}
+
+/// Replace the IDs in a column of a stream, returning the number of rows.
+fn pseudonymize_stream<R: BufRead, W: Write>(src: R, dst: &mut W, col: usize, ps: &mut Pseudonyms) -> Result<usize> {
+  let mut n = 0;
+  for line in src.lines() {
+    let line = line?;
+    n += 1;
+    let mut fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < col {
+      return Err(anyhow!("line {}: no column {}", n, col));
+    }
+    let pseudo = ps.get(fields[col - 1]).to_string();
+    fields[col - 1] = &pseudo;
+    dst.write_all(fields.join("\t").as_bytes())?;
+    dst.write_all(b"\n")?;
+  }
+  Ok(n)
+}
+
+/// Load an AES-256-GCM key.
+fn aead_key(path: &Path) -> Result<LessSafeKey> {
+  let bytes = fs::read(path)?;
+  let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| anyhow!("{:?} does not hold a 32-byte key", path))?;
+  Ok(LessSafeKey::new(key))
+}
+
+/// Encrypt data, prefixing it with a random nonce.
+fn encrypt(key: &LessSafeKey, mut data: Vec<u8>) -> Result<Vec<u8>> {
+  let mut nonce = [0u8; NONCE_LEN];
+  SystemRandom::new().fill(&mut nonce).map_err(|_| anyhow!("cannot generate nonce"))?;
+  key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+    .map_err(|_| anyhow!("encryption failed"))?;
+  let mut out = nonce.to_vec();
+  out.extend(data);
+  Ok(out)
+}
+
+/// Decrypt data written by `encrypt`.
+fn decrypt(key: &LessSafeKey, data: &[u8]) -> Result<Vec<u8>> {
+  if data.len() < NONCE_LEN {
+    return Err(anyhow!("encrypted data is too short"));
+  }
+  let (nonce, data) = data.split_at(NONCE_LEN);
+  let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("invalid nonce"))?;
+  let mut buf = data.to_vec();
+  let plain = key.open_in_place(nonce, Aad::empty(), &mut buf)
+    .map_err(|_| anyhow!("cannot decrypt mapping (wrong key?)"))?;
+  Ok(plain.to_vec())
+}
+
+impl Command for Pseudonymize {
+  fn exec(self) -> Result<()> {
+    if self.column == 0 {
+      return Err(anyhow!("columns count from 1"));
+    }
+    if self.map_key_file.is_some() && self.map_file.is_none() {
+      return Err(anyhow!("--map-key-file needs a --map file to encrypt"));
+    }
+    let scheme = match self.key_file {
+      Some(ref p) => Scheme::Keyed(hmac::Key::new(hmac::HMAC_SHA256, &fs::read(p)?)),
+      None => Scheme::Sequential
+    };
+    let mut ps = Pseudonyms::new(scheme);
+    let map_key = self.map_key_file.as_deref().map(aead_key).transpose()?;
+    if let Some(ref mp) = self.map_file {
+      if mp.exists() {
+        let data = fs::read(mp)?;
+        let data = match map_key {
+          Some(ref k) => decrypt(k, &data)?,
+          None => data
+        };
+        ps.read_map(&data)?;
+        info!("read {} existing pseudonyms from {:?}", ps.map.len(), mp);
+      }
+    }
+
+    let (read, _len) = open_input(&self.infile)?;
+    let src = BufReader::new(decompress(BufReader::new(read))?);
+    let n = match self.output {
+      Some(ref p) => {
+        let mut out = BufWriter::new(File::create(p)?);
+        let n = pseudonymize_stream(src, &mut out, self.column, &mut ps)?;
+        out.flush()?;
+        n
+      },
+      None => {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        let n = pseudonymize_stream(src, &mut out, self.column, &mut ps)?;
+        out.flush()?;
+        n
+      }
+    };
+    info!("pseudonymized {} rows with {} users", n, ps.map.len());
+
+    if let Some(ref mp) = self.map_file {
+      let data = ps.write_map();
+      let data = match map_key {
+        Some(ref k) => encrypt(k, data)?,
+        None => data
+      };
+      fs::write(mp, data)?;
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_pseudonymize() {
+  let src = "alice\t10\nbob\t20\nalice\t30\n";
+  let mut ps = Pseudonyms::new(Scheme::Sequential);
+  let mut out = Vec::new();
+  assert_eq!(pseudonymize_stream(src.as_bytes(), &mut out, 1, &mut ps).unwrap(), 3);
+  assert_eq!(String::from_utf8(out).unwrap(), "1\t10\n2\t20\n1\t30\n");
+  let map = ps.write_map();
+  assert_eq!(String::from_utf8(map.clone()).unwrap(), "alice\t1\nbob\t2\n");
+
+  // an existing mapping keeps its numbers
+  let mut ps = Pseudonyms::new(Scheme::Sequential);
+  ps.read_map(&map).unwrap();
+  assert_eq!(ps.get("carol"), "3");
+  assert_eq!(ps.get("bob"), "2");
+
+  let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+  let mut ps = Pseudonyms::new(Scheme::Keyed(key));
+  let a = ps.get("alice").to_string();
+  assert_eq!(a.len(), HASH_BYTES * 2);
+  let mut ps2 = Pseudonyms::new(Scheme::Keyed(hmac::Key::new(hmac::HMAC_SHA256, b"secret")));
+  assert_eq!(ps2.get("alice"), a);
+  assert_ne!(ps2.get("bob"), a);
+  assert!(pseudonymize_stream("x\n".as_bytes(), &mut Vec::new(), 2, &mut ps).is_err());
+}
+
+#[test]
+fn test_encrypt_map() {
+  let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[7u8; 32]).unwrap());
+  let enc = encrypt(&key, b"alice\t1\n".to_vec()).unwrap();
+  assert!(!enc.windows(5).any(|w| w == b"alice"));
+  assert_eq!(decrypt(&key, &enc).unwrap(), b"alice\t1\n");
+  let other = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[8u8; 32]).unwrap());
+  assert!(decrypt(&other, &enc).is_err());
+}
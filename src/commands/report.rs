@@ -0,0 +1,157 @@
+use std::io::prelude::*;
+use std::io;
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use structopt::StructOpt;
+use serde::Serialize;
+use log::*;
+
+use super::Command;
+use crate::db::DbOpts;
+
+/// Report statistics about the integrated data.
+#[derive(StructOpt, Debug)]
+#[structopt(name="report")]
+pub struct Report {
+  #[structopt(subcommand)]
+  report: ReportKind
+}
+
+#[derive(StructOpt, Debug)]
+enum ReportKind {
+  /// Report how many ISBNs and clusters each source covers.
+  #[structopt(name="coverage")]
+  Coverage(Coverage)
+}
+
+#[derive(StructOpt, Debug)]
+struct Coverage {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  /// Write the report as JSON.
+  #[structopt(long="json")]
+  json: bool,
+
+  /// Write the report to a file instead of standard output.
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>
+}
+
+/// The sources to report on: their name, the table whose presence shows they
+/// have been imported, and a query for the ISBN IDs they link to.
+const SOURCES: &[(&str, &str, &str)] = &[
+  ("LOC", "locmds.book_rec_isbn", "SELECT isbn_id FROM locmds.book_rec_isbn"),
+  ("OpenLibrary", "ol.isbn_link", "SELECT isbn_id FROM ol.isbn_link"),
+  ("GoodReads", "gr.book_isbn", "SELECT isbn_id FROM gr.book_isbn"),
+  ("Amazon ratings", "az.raw_ratings", "SELECT isbn_id FROM az.raw_ratings JOIN isbn_id ON (isbn = asin)"),
+  ("BookCrossing", "bx.raw_ratings", "SELECT isbn_id FROM bx.raw_ratings JOIN isbn_id USING (isbn)")
+];
+
+/// A source's coverage.  The counts are missing if the source has not been
+/// imported.
+#[derive(Serialize, Debug, PartialEq)]
+struct SourceCoverage {
+  source: String,
+  isbns: Option<i64>,
+  clusters: Option<i64>
+}
+
+/// The coverage report.
+#[derive(Serialize, Debug, PartialEq)]
+struct CoverageReport {
+  isbns: i64,
+  clusters: i64,
+  sources: Vec<SourceCoverage>
+}
+
+/// Format a count and its share of a total.
+fn share(n: Option<i64>, total: i64) -> String {
+  match n {
+    Some(n) if total > 0 => format!("{} ({:.1}%)", n, n as f64 * 100.0 / total as f64),
+    Some(n) => n.to_string(),
+    None => "-".to_string()
+  }
+}
+
+impl CoverageReport {
+  fn write_text<W: Write>(&self, out: &mut W) -> Result<()> {
+    writeln!(out, "{:<20} {:>24} {:>24}", "SOURCE", "ISBNS", "CLUSTERS")?;
+    for s in &self.sources {
+      writeln!(out, "{:<20} {:>24} {:>24}", s.source, share(s.isbns, self.isbns), share(s.clusters, self.clusters))?;
+    }
+    writeln!(out, "{:<20} {:>24} {:>24}", "TOTAL", self.isbns, self.clusters)?;
+    Ok(())
+  }
+}
+
+impl Coverage {
+  fn query(&self) -> Result<CoverageReport> {
+    let db = self.db.open()?;
+    let rows = db.query("SELECT COUNT(isbn_id), COUNT(DISTINCT cluster) FROM isbn_cluster", &[])?;
+    let row = rows.get(0);
+    let mut report = CoverageReport { isbns: row.get(0), clusters: row.get(1), sources: Vec::new() };
+    for (name, table, query) in SOURCES {
+      let rows = db.query("SELECT to_regclass($1) IS NOT NULL", &[table])?;
+      let present: bool = rows.get(0).get(0);
+      let mut cov = SourceCoverage { source: name.to_string(), isbns: None, clusters: None };
+      if present {
+        info!("counting coverage of {}", name);
+        let q = format!("SELECT COUNT(DISTINCT isbn_id), COUNT(DISTINCT cluster) FROM ({}) src JOIN isbn_cluster USING (isbn_id)", query);
+        let rows = db.query(&q, &[])?;
+        let row = rows.get(0);
+        cov.isbns = Some(row.get(0));
+        cov.clusters = Some(row.get(1));
+      } else {
+        warn!("{} is not imported ({} is missing)", name, table);
+      }
+      report.sources.push(cov);
+    }
+    Ok(report)
+  }
+}
+
+impl Command for Report {
+  fn exec(self) -> Result<()> {
+    match self.report {
+      ReportKind::Coverage(opts) => {
+        let report = opts.query()?;
+        let mut out: Box<dyn Write> = match opts.output {
+          Some(ref p) => Box::new(File::create(p)?),
+          None => Box::new(io::stdout())
+        };
+        if opts.json {
+          serde_json::to_writer_pretty(&mut out, &report)?;
+          writeln!(out)?;
+        } else {
+          report.write_text(&mut out)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+#[test]
+fn test_write_report() {
+  let report = CoverageReport {
+    isbns: 200,
+    clusters: 80,
+    sources: vec![
+      SourceCoverage { source: "LOC".to_string(), isbns: Some(50), clusters: Some(20) },
+      SourceCoverage { source: "BookCrossing".to_string(), isbns: None, clusters: None }
+    ]
+  };
+  let mut out = Vec::new();
+  report.write_text(&mut out).unwrap();
+  let text = String::from_utf8(out).unwrap();
+  let lines: Vec<&str> = text.lines().collect();
+  assert_eq!(lines.len(), 4);
+  assert_eq!(lines[1].split_whitespace().collect::<Vec<_>>(), vec!["LOC", "50", "(25.0%)", "20", "(25.0%)"]);
+  assert_eq!(lines[2].split_whitespace().collect::<Vec<_>>(), vec!["BookCrossing", "-", "-"]);
+  let json = serde_json::to_value(&report).unwrap();
+  assert_eq!(json["sources"][0]["isbns"], 50);
+  assert!(json["sources"][1]["clusters"].is_null());
+}
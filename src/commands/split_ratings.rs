@@ -0,0 +1,205 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::{File, create_dir_all};
+use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+
+use log::*;
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+use crate::interactions::{Interaction, read_interactions};
+use super::Command;
+
+/// Split interactions into train and test partitions by time.
+#[derive(StructOpt, Debug)]
+#[structopt(name="split-ratings")]
+pub struct SplitRatings {
+  /// Put interactions at or after this timestamp in the test partition.
+  #[structopt(long="before")]
+  before: Option<f64>,
+
+  /// Put each user's last N interactions in the test partition.
+  #[structopt(long="last")]
+  last: Option<usize>,
+
+  /// Directory for the partitions and manifest.
+  #[structopt(short="o", long="output-dir", parse(from_os_str))]
+  outdir: PathBuf,
+
+  /// Input file of interactions (TSV or Parquet; default stdin)
+  #[structopt(name = "INPUT", parse(from_os_str), default_value="-")]
+  infile: PathBuf
+}
+
+/// Statistics for a partition in the manifest.
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct PartStats {
+  file: String,
+  interactions: usize,
+  users: usize,
+  items: usize
+}
+
+/// The manifest describing a split.
+#[derive(Serialize, Debug)]
+struct Manifest {
+  input: String,
+  method: String,
+  train: PartStats,
+  test: PartStats
+}
+
+/// A partition being written.
+struct Partition<W: Write> {
+  out: W,
+  stats: PartStats,
+  users: HashSet<String>,
+  items: HashSet<String>
+}
+
+impl <W: Write> Partition<W> {
+  fn new(out: W, file: &str) -> Partition<W> {
+    Partition {
+      out,
+      stats: PartStats { file: file.to_string(), ..PartStats::default() },
+      users: HashSet::new(),
+      items: HashSet::new()
+    }
+  }
+
+  fn write(&mut self, i: &Interaction) -> Result<()> {
+    i.write_tsv(&mut self.out)?;
+    self.stats.interactions += 1;
+    if !self.users.contains(&i.user) {
+      self.users.insert(i.user.clone());
+    }
+    if !self.items.contains(&i.item) {
+      self.items.insert(i.item.clone());
+    }
+    Ok(())
+  }
+
+  fn finish(mut self) -> Result<PartStats> {
+    self.out.flush()?;
+    self.stats.users = self.users.len();
+    self.stats.items = self.items.len();
+    Ok(self.stats)
+  }
+}
+
+/// Get an interaction's timestamp, which splitting needs.
+fn timestamp(i: &Interaction) -> Result<f64> {
+  match i.timestamp {
+    Some(t) if t.is_finite() => Ok(t),
+    Some(t) => Err(anyhow!("interaction of {} with {} has invalid timestamp {}", i.user, i.item, t)),
+    None => Err(anyhow!("interaction of {} with {} has no timestamp", i.user, i.item))
+  }
+}
+
+/// Split interactions at a timestamp, streaming them to the partitions.
+fn split_before<I, W>(ints: I, cutoff: f64, train: &mut Partition<W>, test: &mut Partition<W>) -> Result<()>
+where I: Iterator<Item=Result<Interaction>>, W: Write
+{
+  for i in ints {
+    let i = i?;
+    if timestamp(&i)? < cutoff {
+      train.write(&i)?;
+    } else {
+      test.write(&i)?;
+    }
+  }
+  Ok(())
+}
+
+/// Put each user's last `n` interactions in the test partition.  Users with
+/// `n` or fewer interactions are left entirely in the training partition.
+/// Interactions with the same timestamp keep their input order.
+fn split_last<I, W>(ints: I, n: usize, train: &mut Partition<W>, test: &mut Partition<W>) -> Result<()>
+where I: Iterator<Item=Result<Interaction>>, W: Write
+{
+  let mut users: HashMap<String, Vec<Interaction>> = HashMap::new();
+  let mut order = Vec::new();
+  for i in ints {
+    let i = i?;
+    timestamp(&i)?;
+    if !users.contains_key(&i.user) {
+      order.push(i.user.clone());
+    }
+    users.entry(i.user.clone()).or_default().push(i);
+  }
+  for u in order {
+    let mut uis = users.remove(&u).unwrap_or_default();
+    uis.sort_by(|a, b| a.timestamp.unwrap_or_default().total_cmp(&b.timestamp.unwrap_or_default()));
+    let ntrain = if uis.len() > n { uis.len() - n } else { uis.len() };
+    for (k, i) in uis.iter().enumerate() {
+      if k < ntrain {
+        train.write(i)?;
+      } else {
+        test.write(i)?;
+      }
+    }
+  }
+  Ok(())
+}
+
+impl Command for SplitRatings {
+  fn exec(self) -> Result<()> {
+    let method = match (self.before, self.last) {
+      (Some(t), None) => format!("before {}", t),
+      (None, Some(n)) => format!("last {}", n),
+      _ => return Err(anyhow!("specify exactly one of --before and --last"))
+    };
+    create_dir_all(&self.outdir)?;
+    let open = |name: &str| -> Result<Partition<BufWriter<File>>> {
+      Ok(Partition::new(BufWriter::new(File::create(self.outdir.join(name))?), name))
+    };
+    let mut train = open("train.tsv")?;
+    let mut test = open("test.tsv")?;
+    let ints = read_interactions(&self.infile)?;
+    match (self.before, self.last) {
+      (Some(t), _) => split_before(ints, t, &mut train, &mut test)?,
+      (_, Some(n)) => split_last(ints, n, &mut train, &mut test)?,
+      _ => unreachable!()
+    }
+    let manifest = Manifest {
+      input: self.infile.to_string_lossy().to_string(),
+      method,
+      train: train.finish()?,
+      test: test.finish()?
+    };
+    info!("split into {} training and {} test interactions", manifest.train.interactions, manifest.test.interactions);
+    let mut mf = File::create(self.outdir.join("manifest.json"))?;
+    serde_json::to_writer_pretty(&mut mf, &manifest)?;
+    writeln!(mf)?;
+    Ok(())
+  }
+}
+
+#[test]
+fn test_split() {
+  let lines = ["u1\ta\t4\t10", "u1\tb\t3\t30", "u2\ta\t5\t20", "u1\tc\t2\t20", "u3\tb\t1\t5"];
+  let ints = || lines.iter().map(|l| Interaction::parse(l));
+  let text = |p: Partition<Vec<u8>>| String::from_utf8(p.out).unwrap();
+
+  let mut train = Partition::new(Vec::new(), "train.tsv");
+  let mut test = Partition::new(Vec::new(), "test.tsv");
+  split_before(ints(), 20.0, &mut train, &mut test).unwrap();
+  assert_eq!(text(train), "u1\ta\t4\t10\nu3\tb\t1\t5\n");
+  assert_eq!(text(test), "u1\tb\t3\t30\nu2\ta\t5\t20\nu1\tc\t2\t20\n");
+
+  let mut train = Partition::new(Vec::new(), "train.tsv");
+  let mut test = Partition::new(Vec::new(), "test.tsv");
+  split_last(ints(), 1, &mut train, &mut test).unwrap();
+  assert_eq!(test.stats.interactions, 1);
+  assert_eq!(text(test), "u1\tb\t3\t30\n");
+  let stats = train.finish().unwrap();
+  assert_eq!((stats.interactions, stats.users, stats.items), (4, 3, 3));
+
+  let mut train = Partition::new(Vec::new(), "train.tsv");
+  let mut test = Partition::new(Vec::new(), "test.tsv");
+  assert!(split_before(vec![Interaction::parse("u1\ta\t4")].into_iter(), 1.0, &mut train, &mut test).is_err());
+  assert!(split_last(vec![Interaction::parse("u1\ta\t4\tNaN")].into_iter(), 1, &mut train, &mut test).is_err());
+}
@@ -0,0 +1,174 @@
+use std::io::prelude::*;
+use std::fs::{read_dir, read_to_string};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{Result, anyhow};
+use structopt::StructOpt;
+use postgres::{Connection, GenericConnection};
+use sha1::Sha1;
+use log::*;
+
+use super::Command;
+use crate::db::DbOpts;
+use crate::tracking::{StageOpts, Stage};
+
+mod script;
+
+use script::*;
+
+/// Run SQL scripts, committing each step in its own transaction.
+#[derive(StructOpt, Debug)]
+#[structopt(name="sql")]
+pub struct RunSql {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  #[structopt(flatten)]
+  stage: StageOpts,
+
+  /// Describe the scripts without running them
+  #[structopt(long="dry-run")]
+  dry_run: bool,
+
+  /// SQL scripts, or directories of `.sql` files, to run
+  #[structopt(name="SCRIPT", parse(from_os_str))]
+  scripts: Vec<PathBuf>
+}
+
+impl RunSql {
+  /// Expand directories into their sorted SQL files.
+  fn script_files(&self) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in &self.scripts {
+      if path.is_dir() {
+        let mut dfs = Vec::new();
+        for e in read_dir(path)? {
+          let p = e?.path();
+          if p.extension().map(|e| e == "sql").unwrap_or(false) {
+            dfs.push(p);
+          }
+        }
+        dfs.sort();
+        files.extend(dfs);
+      } else {
+        files.push(path.clone());
+      }
+    }
+    Ok(files)
+  }
+}
+
+fn describe(path: &PathBuf, script: &SqlScript) {
+  info!("script {:?}", path);
+  for dep in &script.deps {
+    info!("dependency ‘{}’", dep);
+  }
+  for chunk in &script.chunks {
+    info!("chunk ‘{}’", chunk.label);
+    for s in &chunk.statements {
+      info!("statement {}", s.describe());
+    }
+  }
+}
+
+fn run_statements(db: &dyn GenericConnection, chunk: &ScriptChunk, stage: &mut Stage) -> Result<()> {
+  for stmt in &chunk.statements {
+    let desc = stmt.describe();
+    debug!("executing {}", stmt.sql);
+    writeln!(stage, "STMT {}", desc)?;
+    let start = Instant::now();
+    let rows = db.execute(&stmt.sql, &[])?;
+    let elapsed = start.elapsed().as_secs_f64();
+    writeln!(stage, "ELAPSED {:.3}", elapsed)?;
+    writeln!(stage, "ROWS {}", rows)?;
+    info!("finished {} in {:.2}s ({} rows)", desc, elapsed, rows);
+  }
+  Ok(())
+}
+
+fn run_chunk(db: &Connection, chunk: &ScriptChunk, stage: &mut Stage) -> Result<()> {
+  info!("running ‘{}’", chunk.label);
+  writeln!(stage, "CHUNK {}", chunk.label)?;
+  let start = Instant::now();
+  let res = if chunk.use_transaction {
+    let tx = db.transaction()?;
+    let res = run_statements(&tx, chunk, stage);
+    if res.is_ok() {
+      tx.commit()?;
+    }
+    res
+  } else {
+    run_statements(db, chunk, stage)
+  };
+
+  if let Err(e) = res {
+    let code = e.downcast_ref::<postgres::Error>().and_then(|pe| pe.code()).map(|c| c.code().to_string());
+    match code {
+      Some(ref c) if chunk.allowed_errors.contains(c) => {
+        info!("‘{}’ failed with acceptable error {}", chunk.label, c);
+        writeln!(stage, "ERROR {}", c)?;
+      },
+      _ => {
+        error!("error in ‘{}’: {}", chunk.label, e);
+        return Err(e);
+      }
+    }
+  }
+
+  let elapsed = start.elapsed().as_secs_f64();
+  writeln!(stage, "CHUNK ELAPSED {:.3}", elapsed)?;
+  info!("finished ‘{}’ in {:.2}s", chunk.label, elapsed);
+  Ok(())
+}
+
+impl Command for RunSql {
+  fn exec(self) -> Result<()> {
+    let files = self.script_files()?;
+    if files.is_empty() {
+      return Err(anyhow!("no SQL scripts found"));
+    }
+    let mut scripts = Vec::with_capacity(files.len());
+    for path in &files {
+      info!("reading {:?}", path);
+      let text = read_to_string(path)?;
+      let mut hash = Sha1::new();
+      hash.update(text.as_bytes());
+      let script = SqlScript::read(text.as_bytes())?;
+      info!("{:?} has {} chunks", path, script.chunks.len());
+      scripts.push((path, script, hash.hexdigest()));
+    }
+
+    if self.dry_run {
+      for (path, script, _) in &scripts {
+        describe(path, script);
+      }
+      return Ok(());
+    }
+
+    let db = self.db.open()?;
+    let mut stage = self.stage.begin_stage(&db)?;
+    let mut key = Sha1::new();
+    let all_start = Instant::now();
+    for (path, script, hash) in &scripts {
+      for dep in &script.deps {
+        if let Some(dk) = stage.record_dep(dep)? {
+          key.update(dk.as_bytes());
+        }
+      }
+      stage.record_file(path, hash)?;
+      key.update(hash.as_bytes());
+      writeln!(stage, "SCRIPT {:?} {}", path, hash)?;
+      for chunk in &script.chunks {
+        run_chunk(&db, chunk, &mut stage)?;
+      }
+      for (ns, tbl) in &script.tables {
+        stage.record_table(ns, tbl)?;
+      }
+    }
+    info!("scripts completed in {:.2}s", all_start.elapsed().as_secs_f64());
+
+    stage.end(&Some(key.hexdigest()))?;
+    Ok(())
+  }
+}
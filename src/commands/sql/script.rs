@@ -0,0 +1,361 @@
+use std::io::prelude::*;
+
+use anyhow::{Result, anyhow};
+
+/// Keywords kept when describing a statement.
+static KEYWORDS: &[&str] = &[
+  "CREATE", "DROP", "ALTER", "OR", "REPLACE", "UNIQUE", "MATERIALIZED", "TABLE", "VIEW",
+  "INDEX", "SCHEMA", "FUNCTION", "AGGREGATE", "TYPE", "SEQUENCE", "EXTENSION", "INSERT",
+  "INTO", "UPDATE", "DELETE", "FROM", "TRUNCATE", "ANALYZE", "VACUUM", "REFRESH", "CLUSTER",
+  "SELECT", "DO", "GRANT", "COMMENT", "ON", "COLUMN"
+];
+
+/// Error names accepted by `#allow`, with their SQLSTATE codes.
+static ERROR_NAMES: &[(&str, &str)] = &[
+  ("duplicate_object", "42710"),
+  ("duplicate_table", "42P07"),
+  ("duplicate_column", "42701"),
+  ("duplicate_schema", "42P06"),
+  ("invalid_table_definition", "42P16"),
+  ("undefined_object", "42704"),
+  ("undefined_table", "42P01"),
+  ("undefined_column", "42703")
+];
+
+/// A single statement in a script chunk.
+#[derive(Debug, PartialEq)]
+pub struct Statement {
+  /// The statement text as written
+  pub sql: String,
+  /// The statement text without comments
+  code: String
+}
+
+/// A chunk of a script, run in its own transaction.
+#[derive(Debug)]
+pub struct ScriptChunk {
+  pub label: String,
+  /// SQLSTATE codes the chunk is allowed to fail with
+  pub allowed_errors: Vec<String>,
+  pub use_transaction: bool,
+  pub statements: Vec<Statement>
+}
+
+/// An SQL script with dependency and table metadata, split into chunks.
+///
+/// Scripts are annotated with `--- #` instructions: `#dep` and `#table` lines at
+/// the top of the file, and `#step`, `#allow`, and `#notx` lines at the start
+/// of each chunk.
+#[derive(Debug)]
+pub struct SqlScript {
+  pub deps: Vec<String>,
+  pub tables: Vec<(String, String)>,
+  pub chunks: Vec<ScriptChunk>
+}
+
+impl Statement {
+  /// Short description of the statement, e.g. `CREATE TABLE az.user_ids`.
+  pub fn describe(&self) -> String {
+    let mut parts = Vec::new();
+    let mut skipping = false;
+    for w in self.code.split_whitespace() {
+      let uw = w.to_uppercase();
+      if uw == "IF" {
+        skipping = true;
+        continue;
+      } else if skipping && (uw == "NOT" || uw == "EXISTS") {
+        continue;
+      }
+      skipping = false;
+      if KEYWORDS.contains(&uw.as_str()) {
+        parts.push(uw);
+      } else {
+        let name = w.split(['(', ';']).next().unwrap_or("");
+        if !name.is_empty() {
+          parts.push(name.to_string());
+        }
+        break;
+      }
+    }
+    parts.join(" ")
+  }
+}
+
+/// Split SQL text into statements on top-level semicolons, respecting quotes,
+/// dollar quotes, and comments.
+pub fn split_statements(src: &str) -> Vec<Statement> {
+  let mut stmts = Vec::new();
+  let mut sql = String::new();
+  let mut code = String::new();
+  let mut chars = src.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '\'' | '"' => {
+        sql.push(c);
+        code.push(c);
+        for q in chars.by_ref() {
+          sql.push(q);
+          code.push(q);
+          if q == c {
+            break;
+          }
+        }
+      },
+      '-' if chars.peek() == Some(&'-') => {
+        sql.push(c);
+        for q in chars.by_ref() {
+          sql.push(q);
+          if q == '\n' {
+            code.push('\n');
+            break;
+          }
+        }
+      },
+      '/' if chars.peek() == Some(&'*') => {
+        sql.push(c);
+        sql.push(chars.next().unwrap());
+        let mut prev = ' ';
+        for q in chars.by_ref() {
+          sql.push(q);
+          if prev == '*' && q == '/' {
+            break;
+          }
+          prev = q;
+        }
+        code.push(' ');
+      },
+      '$' => {
+        // read a possible dollar-quote tag
+        let mut tag = String::from("$");
+        while let Some(&q) = chars.peek() {
+          if q == '$' || q.is_alphanumeric() || q == '_' {
+            tag.push(q);
+            chars.next();
+            if q == '$' {
+              break;
+            }
+          } else {
+            break;
+          }
+        }
+        sql.push_str(&tag);
+        code.push_str(&tag);
+        if tag.len() > 1 && tag.ends_with('$') {
+          // scan to the closing tag
+          let mut body = String::new();
+          for q in chars.by_ref() {
+            body.push(q);
+            if body.ends_with(&tag) {
+              break;
+            }
+          }
+          sql.push_str(&body);
+          code.push_str(&body);
+        }
+      },
+      ';' => {
+        push_statement(&mut stmts, &mut sql, &mut code);
+      },
+      _ => {
+        sql.push(c);
+        code.push(c);
+      }
+    }
+  }
+  push_statement(&mut stmts, &mut sql, &mut code);
+  stmts
+}
+
+fn push_statement(stmts: &mut Vec<Statement>, sql: &mut String, code: &mut String) {
+  if !code.trim().is_empty() {
+    stmts.push(Statement {
+      sql: sql.trim().to_string(),
+      code: code.trim().to_string()
+    });
+  }
+  sql.clear();
+  code.clear();
+}
+
+/// Look up the SQLSTATE code for an `#allow` argument.
+fn error_code(name: &str) -> Result<String> {
+  if name.len() == 5 && name.chars().all(|c| c.is_ascii_alphanumeric()) && name.starts_with(|c: char| c.is_ascii_digit()) {
+    return Ok(name.to_uppercase());
+  }
+  let name = name.to_lowercase();
+  for (n, code) in ERROR_NAMES {
+    if *n == name {
+      return Ok(code.to_string());
+    }
+  }
+  Err(anyhow!("unknown error name {}", name))
+}
+
+/// Parse a `--- ` separator line into its instruction code and arguments.
+fn parse_sep(line: &str) -> Option<Option<(String, String)>> {
+  if !line.starts_with("---") {
+    return None;
+  }
+  let inst = line[3..].trim();
+  if !inst.starts_with('#') {
+    return Some(None);
+  }
+  let inst = &inst[1..];
+  let end = inst.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(inst.len());
+  if end == 0 {
+    return Some(None);
+  }
+  Some(Some((inst[..end].to_string(), inst[end..].trim().to_string())))
+}
+
+impl SqlScript {
+  /// Read a script.
+  pub fn read<R: BufRead>(src: R) -> Result<SqlScript> {
+    let lines: Vec<String> = src.lines().collect::<std::io::Result<_>>()?;
+    let mut pos = 0;
+    let mut script = SqlScript {
+      deps: Vec::new(),
+      tables: Vec::new(),
+      chunks: Vec::new()
+    };
+
+    // script header
+    while pos < lines.len() {
+      match parse_sep(&lines[pos]) {
+        None => break,
+        Some(None) => (),
+        Some(Some((code, args))) => match code.as_str() {
+          "dep" => script.deps.push(args),
+          "table" => {
+            let mut parts = args.splitn(2, '.');
+            let first = parts.next().unwrap_or("").to_string();
+            match parts.next() {
+              Some(tbl) => script.tables.push((first, tbl.to_string())),
+              None => script.tables.push(("public".to_string(), first))
+            }
+          },
+          _ => break
+        }
+      }
+      pos += 1;
+    }
+
+    // chunks
+    while pos < lines.len() {
+      let mut label = None;
+      let mut allowed_errors = Vec::new();
+      let mut use_transaction = true;
+      while pos < lines.len() {
+        match parse_sep(&lines[pos]) {
+          None => break,
+          Some(None) => (),
+          Some(Some((code, args))) => match code.as_str() {
+            "step" => label = Some(args),
+            "allow" => allowed_errors.push(error_code(&args)?),
+            "notx" => use_transaction = false,
+            _ => return Err(anyhow!("line {}: invalid query instruction {}", pos + 1, code))
+          }
+        }
+        pos += 1;
+      }
+      let start = pos;
+      while pos < lines.len() && parse_sep(&lines[pos]).is_none() {
+        pos += 1;
+      }
+      let statements = split_statements(&lines[start..pos].join("\n"));
+      if !statements.is_empty() {
+        let n = script.chunks.len() + 1;
+        script.chunks.push(ScriptChunk {
+          label: label.unwrap_or_else(|| format!("Step {}", n)),
+          allowed_errors,
+          use_transaction,
+          statements
+        });
+      }
+    }
+
+    Ok(script)
+  }
+}
+
+#[test]
+fn test_split_simple() {
+  let stmts = split_statements("CREATE TABLE foo (x INT);\nINSERT INTO foo VALUES (1);\n");
+  assert_eq!(stmts.len(), 2);
+  assert_eq!(stmts[0].sql, "CREATE TABLE foo (x INT)");
+  assert_eq!(stmts[1].sql, "INSERT INTO foo VALUES (1)");
+}
+
+#[test]
+fn test_split_quotes_comments() {
+  let stmts = split_statements("SELECT ';' AS \"a;b\"; -- trailing; comment\n/* block; */ SELECT 2");
+  assert_eq!(stmts.len(), 2);
+  assert_eq!(stmts[0].sql, "SELECT ';' AS \"a;b\"");
+  assert_eq!(stmts[1].sql, "-- trailing; comment\n/* block; */ SELECT 2");
+  assert_eq!(stmts[1].describe(), "SELECT 2");
+}
+
+#[test]
+fn test_split_dollar_quote() {
+  let stmts = split_statements("DO $cv$\nBEGIN\n  CREATE VIEW v AS SELECT 1;\nEND;\n$cv$;\nSELECT $1;");
+  assert_eq!(stmts.len(), 2);
+  assert!(stmts[0].sql.ends_with("END;\n$cv$"));
+  assert_eq!(stmts[1].sql, "SELECT $1");
+}
+
+#[test]
+fn test_split_comment_only() {
+  assert!(split_statements("-- nothing here\n\n").is_empty());
+}
+
+#[test]
+fn test_describe() {
+  let stmts = split_statements("CREATE INDEX IF NOT EXISTS az_rating_user_idx ON az.raw_ratings (user_key);
+    INSERT INTO isbn_id (isbn) SELECT 1;
+    CREATE MATERIALIZED VIEW viaf.author_dates AS SELECT 1;
+    ANALYZE az.raw_ratings");
+  let descs: Vec<String> = stmts.iter().map(Statement::describe).collect();
+  assert_eq!(descs, vec![
+    "CREATE INDEX az_rating_user_idx",
+    "INSERT INTO isbn_id",
+    "CREATE MATERIALIZED VIEW viaf.author_dates",
+    "ANALYZE az.raw_ratings"
+  ]);
+}
+
+#[test]
+fn test_read_script() {
+  let src = "--- #dep common-schema
+--- #table az.raw_ratings
+--- #table isbn_id
+--- #step Index ratings
+CREATE INDEX foo ON az.raw_ratings (asin);
+ANALYZE az.raw_ratings;
+
+--- #step Add key
+--- #allow duplicate_object
+ALTER TABLE isbn_id ADD PRIMARY KEY (isbn_id);
+---
+--- #notx
+VACUUM isbn_id;
+";
+  let script = SqlScript::read(src.as_bytes()).unwrap();
+  assert_eq!(script.deps, vec!["common-schema"]);
+  assert_eq!(script.tables, vec![
+    ("az".to_string(), "raw_ratings".to_string()),
+    ("public".to_string(), "isbn_id".to_string())
+  ]);
+  assert_eq!(script.chunks.len(), 3);
+  assert_eq!(script.chunks[0].label, "Index ratings");
+  assert_eq!(script.chunks[0].statements.len(), 2);
+  assert_eq!(script.chunks[1].allowed_errors, vec!["42710"]);
+  assert!(script.chunks[1].use_transaction);
+  assert_eq!(script.chunks[2].label, "Step 3");
+  assert!(!script.chunks[2].use_transaction);
+}
+
+#[test]
+fn test_bad_instruction() {
+  assert!(SqlScript::read("--- #step foo\n--- #wombat\nSELECT 1;\n".as_bytes()).is_err());
+  assert!(SqlScript::read("--- #allow no_such_error\nSELECT 1;\n".as_bytes()).is_err());
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use structopt::StructOpt;
+use fallible_iterator::FallibleIterator;
+
+use super::Command;
+use crate::db::DbOpts;
+
+/// List the files that have been imported, from the import log.
+#[derive(StructOpt, Debug)]
+#[structopt(name="status")]
+pub struct Status {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  /// Only show imports by this tool
+  #[structopt(long="tool")]
+  tool: Option<String>
+}
+
+/// Query for the most recent import of each file.
+static STATUS_QUERY: &str = "SELECT DISTINCT ON (filename, tool)
+    tool, filename, COALESCE(sha256, ''), n_rows, COALESCE(git_rev, ''), finished_at::text
+  FROM import_log
+  WHERE $1::varchar IS NULL OR tool = $1
+  ORDER BY filename, tool, finished_at DESC";
+
+fn print_row(tool: &str, file: &str, n: &str, hash: &str, rev: &str, time: &str) {
+  println!("{:<20} {:<40} {:>12} {:<10.10} {:<10.10} {}", tool, file, n, hash, rev, time);
+}
+
+impl Command for Status {
+  fn exec(self) -> Result<()> {
+    let db = self.db.open()?;
+    let txn = db.transaction()?;
+    let stmt = txn.prepare(STATUS_QUERY)?;
+    let mut rows = stmt.lazy_query(&txn, &[&self.tool], 1000)?;
+    print_row("TOOL", "FILE", "ROWS", "CHECKSUM", "REVISION", "FINISHED");
+    while let Some(row) = rows.next()? {
+      let tool: String = row.get(0);
+      let file: String = row.get(1);
+      let hash: String = row.get(2);
+      let n: Option<i64> = row.get(3);
+      let rev: String = row.get(4);
+      let time: String = row.get(5);
+      let n = n.map(|n| n.to_string()).unwrap_or_default();
+      print_row(&tool, &file, &n, &hash, &rev, &time);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,358 @@
+use structopt::StructOpt;
+
+use std::io::prelude::*;
+use std::io;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use log::*;
+use anyhow::{Result, anyhow};
+use fallible_iterator::FallibleIterator;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DbOpts, Connection};
+use crate::ids::{isbn10_valid, isbn13_valid};
+use super::Command;
+
+/// Number of rows to fetch at a time when checking ISBNs.
+const CHUNK_SIZE: i32 = 10000;
+
+/// Check the integrity of the loaded database.
+///
+/// The checks are listed in a TOML file (see `validate.toml`).  Each check
+/// passes, fails, or is skipped (if it is optional and its tables have not been
+/// loaded); the command fails if any check fails.
+#[derive(StructOpt, Debug)]
+#[structopt(name="validate")]
+pub struct Validate {
+  #[structopt(flatten)]
+  db: DbOpts,
+
+  /// Write the report as JSON.
+  #[structopt(long="json")]
+  json: bool,
+
+  /// Write the report to a file instead of standard output.
+  #[structopt(short="o", long="output", parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// The checks to run
+  #[structopt(name = "CONFIG", parse(from_os_str), default_value="validate.toml")]
+  config: PathBuf
+}
+
+/// The check configuration file.
+#[derive(Deserialize, Debug)]
+struct Config {
+  #[serde(rename="check", default)]
+  checks: Vec<CheckSpec>
+}
+
+/// A check to run, with its options.
+#[derive(Deserialize, Debug)]
+struct CheckSpec {
+  /// The name in the report (defaults to a description of the check)
+  name: Option<String>,
+  /// Skip the check if its tables do not exist
+  #[serde(default)]
+  optional: bool,
+  #[serde(flatten)]
+  check: Check
+}
+
+/// The kinds of check.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag="kind", rename_all="kebab-case")]
+enum Check {
+  /// A table's row count is in a range.
+  Rows { table: String, min: Option<i64>, max: Option<i64> },
+  /// A query returns a count of bad rows of at most `max` (default 0).  It
+  /// only runs if the `tables` it reads exist.
+  Query { query: String, max: Option<i64>, #[serde(default)] tables: Vec<String> },
+  /// At most `max` (default 0) non-null values of a column are missing from
+  /// the column they refer to.
+  References { table: String, column: String, ref_table: String, ref_column: String, max: Option<i64> },
+  /// A column's ISBNs have valid check digits, except for at most `max`
+  /// (default 0) or, if given, `max_fraction` of them.
+  Isbn { table: String, column: String, max: Option<i64>, max_fraction: Option<f64> }
+}
+
+/// The outcome of a check.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="lowercase")]
+enum Status {
+  Pass,
+  Fail,
+  Skip
+}
+
+/// A check's result in the report.
+#[derive(Serialize, Debug)]
+struct CheckResult {
+  name: String,
+  status: Status,
+  detail: String
+}
+
+/// The validation report.
+#[derive(Serialize, Debug)]
+struct ValidationReport {
+  passed: usize,
+  failed: usize,
+  skipped: usize,
+  checks: Vec<CheckResult>
+}
+
+impl Check {
+  /// Describe the check, for the report.
+  fn describe(&self) -> String {
+    match self {
+      Check::Rows { table, .. } => format!("rows in {}", table),
+      Check::Query { query, .. } => format!("query {}", query),
+      Check::References { table, column, ref_table, ref_column, .. } =>
+        format!("{}.{} references {}.{}", table, column, ref_table, ref_column),
+      Check::Isbn { table, column, .. } => format!("ISBNs in {}.{}", table, column)
+    }
+  }
+
+  /// The tables the check reads, which must exist for it to run.
+  fn tables(&self) -> Vec<&str> {
+    match self {
+      Check::Rows { table, .. } | Check::Isbn { table, .. } => vec![table],
+      Check::References { table, ref_table, .. } => vec![table, ref_table],
+      Check::Query { tables, .. } => tables.iter().map(String::as_str).collect()
+    }
+  }
+
+  /// Run the check.
+  fn run(&self, db: &Connection) -> Result<(Status, String)> {
+    match self {
+      Check::Rows { table, min, max } => {
+        let n = count(db, &format!("SELECT COUNT(*) FROM {}", table))?;
+        Ok(check_range(n, *min, *max))
+      },
+      Check::Query { query, max, .. } => {
+        let n = count(db, query)?;
+        Ok(check_max(n, max.unwrap_or(0), "bad rows"))
+      },
+      Check::References { table, column, ref_table, ref_column, max } => {
+        let q = format!("SELECT COUNT(*) FROM {t} c WHERE c.{c} IS NOT NULL
+                         AND NOT EXISTS (SELECT 1 FROM {rt} r WHERE r.{rc} = c.{c})",
+                        t=table, c=column, rt=ref_table, rc=ref_column);
+        let n = count(db, &q)?;
+        Ok(check_max(n, max.unwrap_or(0), "dangling values"))
+      },
+      Check::Isbn { table, column, max, max_fraction } => {
+        let (total, bad, examples) = count_bad_isbns(db, table, column)?;
+        let mut res = match max_fraction {
+          Some(f) => check_fraction(bad, total, *f),
+          None => check_max(bad, max.unwrap_or(0), "invalid ISBNs")
+        };
+        if !examples.is_empty() {
+          res.1.push_str(&format!(" (e.g. {})", examples.join(", ")));
+        }
+        Ok(res)
+      }
+    }
+  }
+}
+
+/// Run a query returning a single count.
+fn count(db: &Connection, query: &str) -> Result<i64> {
+  debug!("running {}", query);
+  let rows = db.query(query, &[])?;
+  if rows.is_empty() {
+    return Err(anyhow!("query returned no rows"));
+  }
+  match rows.get(0).get_opt::<_, Option<i64>>(0) {
+    Some(Ok(Some(n))) => Ok(n),
+    Some(Ok(None)) => Err(anyhow!("query returned NULL")),
+    Some(Err(e)) => Err(anyhow!("query must return a bigint count (cast it with ::bigint): {}", e)),
+    None => Err(anyhow!("query returned no columns"))
+  }
+}
+
+/// Check a row count against its range.
+fn check_range(n: i64, min: Option<i64>, max: Option<i64>) -> (Status, String) {
+  let expected = match (min, max) {
+    (Some(lo), Some(hi)) => format!("expected {} to {}", lo, hi),
+    (Some(lo), None) => format!("expected at least {}", lo),
+    (None, Some(hi)) => format!("expected at most {}", hi),
+    (None, None) => "no limits".to_string()
+  };
+  let ok = min.map(|lo| n >= lo).unwrap_or(true) && max.map(|hi| n <= hi).unwrap_or(true);
+  (if ok { Status::Pass } else { Status::Fail }, format!("{} rows ({})", n, expected))
+}
+
+/// Check a count of bad rows against the number allowed.
+fn check_max(n: i64, max: i64, what: &str) -> (Status, String) {
+  (if n <= max { Status::Pass } else { Status::Fail }, format!("{} {} (allowed {})", n, what, max))
+}
+
+/// Check the share of invalid values against the share allowed.
+fn check_fraction(bad: i64, total: i64, max: f64) -> (Status, String) {
+  let frac = if total > 0 { bad as f64 / total as f64 } else { 0.0 };
+  let status = if frac <= max { Status::Pass } else { Status::Fail };
+  (status, format!("{} of {} invalid ISBNs ({:.2}%, allowed {:.2}%)", bad, total, frac * 100.0, max * 100.0))
+}
+
+/// Does a normalized ISBN have a valid check digit?
+fn isbn_check_ok(isbn: &str) -> bool {
+  match isbn.len() {
+    10 => isbn10_valid(isbn),
+    13 => isbn13_valid(isbn),
+    _ => false
+  }
+}
+
+/// Count a column's ISBNs and those with invalid check digits, with a few
+/// examples of the invalid ones.
+fn count_bad_isbns(db: &Connection, table: &str, column: &str) -> Result<(i64, i64, Vec<String>)> {
+  let txn = db.transaction()?;
+  let stmt = txn.prepare(&format!("SELECT {c}::text FROM {t} WHERE {c} IS NOT NULL", c=column, t=table))?;
+  let mut rows = stmt.lazy_query(&txn, &[], CHUNK_SIZE)?;
+  let (mut total, mut bad) = (0, 0);
+  let mut examples = Vec::new();
+  while let Some(row) = rows.next()? {
+    let isbn: String = row.get(0);
+    total += 1;
+    if !isbn_check_ok(&isbn) {
+      bad += 1;
+      if examples.len() < 3 {
+        examples.push(isbn);
+      }
+    }
+  }
+  Ok((total, bad, examples))
+}
+
+/// Does a table (or view) exist?
+fn table_exists(db: &Connection, table: &str) -> Result<bool> {
+  let rows = db.query("SELECT to_regclass($1) IS NOT NULL", &[&table])?;
+  Ok(rows.get(0).get(0))
+}
+
+impl CheckSpec {
+  /// Run the check, reporting query errors as failures.
+  fn run(&self, db: &Connection) -> Result<CheckResult> {
+    let name = self.name.clone().unwrap_or_else(|| self.check.describe());
+    info!("checking {}", name);
+    for t in self.check.tables() {
+      if !table_exists(db, t)? {
+        let status = if self.optional { Status::Skip } else { Status::Fail };
+        return Ok(CheckResult { name, status, detail: format!("{} does not exist", t) });
+      }
+    }
+    let (status, detail) = match self.check.run(db) {
+      Ok(r) => r,
+      Err(e) => (Status::Fail, format!("error: {}", e))
+    };
+    Ok(CheckResult { name, status, detail })
+  }
+}
+
+impl ValidationReport {
+  fn new(checks: Vec<CheckResult>) -> ValidationReport {
+    let n = |s| checks.iter().filter(|c| c.status == s).count();
+    ValidationReport { passed: n(Status::Pass), failed: n(Status::Fail), skipped: n(Status::Skip), checks }
+  }
+
+  fn write_text<W: Write>(&self, out: &mut W) -> Result<()> {
+    for c in &self.checks {
+      let label = match c.status {
+        Status::Pass => "PASS",
+        Status::Fail => "FAIL",
+        Status::Skip => "SKIP"
+      };
+      writeln!(out, "{}  {}: {}", label, c.name, c.detail)?;
+    }
+    writeln!(out, "{} passed, {} failed, {} skipped", self.passed, self.failed, self.skipped)?;
+    Ok(())
+  }
+}
+
+impl Command for Validate {
+  fn exec(self) -> Result<()> {
+    let config: Config = toml::from_str(&fs::read_to_string(&self.config)?)?;
+    info!("running {} checks from {:?}", config.checks.len(), self.config);
+    let db = self.db.open()?;
+    let mut results = Vec::with_capacity(config.checks.len());
+    for spec in &config.checks {
+      results.push(spec.run(&db)?);
+    }
+    let report = ValidationReport::new(results);
+
+    let mut out: Box<dyn Write> = match self.output {
+      Some(ref p) => Box::new(File::create(p)?),
+      None => Box::new(io::stdout())
+    };
+    if self.json {
+      serde_json::to_writer_pretty(&mut out, &report)?;
+      writeln!(out)?;
+    } else {
+      report.write_text(&mut out)?;
+    }
+    out.flush()?;
+    if report.failed > 0 {
+      Err(anyhow!("{} of {} checks failed", report.failed, report.checks.len()))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+#[test]
+fn test_validate_config() {
+  let config: Config = toml::from_str(r#"
+[[check]]
+kind = "rows"
+table = "isbn_id"
+min = 10
+
+[[check]]
+name = "ISBN IDs are positive"
+kind = "query"
+query = "SELECT COUNT(*) FROM isbn_id WHERE isbn_id <= 0"
+
+[[check]]
+kind = "references"
+table = "ol.isbn_link"
+column = "isbn_id"
+ref_table = "isbn_id"
+ref_column = "isbn_id"
+optional = true
+
+[[check]]
+kind = "isbn"
+table = "isbn_id"
+column = "isbn"
+max_fraction = 0.01
+"#).unwrap();
+  assert_eq!(config.checks.len(), 4);
+  assert_eq!(config.checks[0].check, Check::Rows { table: "isbn_id".to_string(), min: Some(10), max: None });
+  assert_eq!(config.checks[1].name.as_deref(), Some("ISBN IDs are positive"));
+  assert!(config.checks[2].optional);
+  assert_eq!(config.checks[2].check.tables(), vec!["ol.isbn_link", "isbn_id"]);
+  assert_eq!(config.checks[3].check.describe(), "ISBNs in isbn_id.isbn");
+  let shipped: Config = toml::from_str(include_str!("../../validate.toml")).unwrap();
+  assert!(!shipped.checks.is_empty());
+
+  assert_eq!(check_range(5, Some(10), None).0, Status::Fail);
+  assert_eq!(check_range(15, Some(10), Some(20)), (Status::Pass, "15 rows (expected 10 to 20)".to_string()));
+  assert_eq!(check_max(3, 0, "dangling values"), (Status::Fail, "3 dangling values (allowed 0)".to_string()));
+  assert_eq!(check_fraction(1, 200, 0.01).0, Status::Pass);
+  assert_eq!(check_fraction(5, 200, 0.01).0, Status::Fail);
+  assert!(isbn_check_ok("080442957X"));
+  assert!(isbn_check_ok("9780261102217"));
+  assert!(!isbn_check_ok("9780261102218"));
+  assert!(!isbn_check_ok("B00000JYLN"));
+
+  let report = ValidationReport::new(vec![
+    CheckResult { name: "rows in isbn_id".to_string(), status: Status::Pass, detail: "15 rows (expected at least 10)".to_string() },
+    CheckResult { name: "gr".to_string(), status: Status::Skip, detail: "gr.book_isbn does not exist".to_string() }
+  ]);
+  let mut out = Vec::new();
+  report.write_text(&mut out).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(),
+             "PASS  rows in isbn_id: 15 rows (expected at least 10)\nSKIP  gr: gr.book_isbn does not exist\n1 passed, 0 failed, 1 skipped\n");
+}
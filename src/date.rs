@@ -0,0 +1,24 @@
+// Calendar dates for timestamps.  This file is also included by `build.rs`,
+// so it must not use anything outside the standard library.
+
+/// Convert days since the Unix epoch to a (year, month, day) civil date, with
+/// Howard Hinnant's algorithm.
+pub fn civil_date(days: i64) -> (i64, i64, i64) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+#[test]
+fn test_civil_date() {
+  assert_eq!(civil_date(0), (1970, 1, 1));
+  assert_eq!(civil_date(19782), (2024, 2, 29));
+  assert_eq!(civil_date(-1), (1969, 12, 31));
+}
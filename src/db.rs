@@ -1,4 +1,5 @@
 use std::io::prelude::*;
+use std::io::BufReader;
 
 use log::*;
 
@@ -8,10 +9,41 @@ use postgres::{TlsMode};
 use structopt::StructOpt;
 pub use postgres::Connection;
 
+use crate::summary;
+use crate::columnar::ColType;
+
 use std::thread;
+use std::time::{Duration, Instant};
+use std::str::FromStr;
+
+mod ddl;
+mod sqlite;
+#[cfg(feature="duckdb")]
+mod duck;
+mod indexes;
+mod connect;
+mod tls;
+mod retry;
+mod delta;
+
+pub use retry::RetryPolicy;
+pub use delta::{staging_table, create_staging, merge_staging};
+
+use connect::ConnSettings;
+use tls::SslMode;
 
 pub trait ConnectInfo {
   fn db_url(&self) -> Result<String>;
+
+  /// Should bulk loads drop and recreate the target table's indexes?
+  fn defer_indexes(&self) -> bool {
+    false
+  }
+
+  /// How should connections and copies retry transient failures?
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy::default()
+  }
 }
 
 impl ConnectInfo for String {
@@ -38,14 +70,50 @@ pub struct DbOpts {
 
   /// Database schema
   #[structopt(long="db-schema")]
-  db_schema: Option<String>
+  db_schema: Option<String>,
+
+  /// Drop indexes and constraints before loading, and recreate them after
+  #[structopt(long="defer-indexes")]
+  defer_indexes: bool,
+
+  /// Number of times to retry after transient database errors
+  #[structopt(long="db-retries", default_value="3")]
+  db_retries: u32,
+
+  /// Seconds to wait before the first retry (doubling after each attempt)
+  #[structopt(long="db-retry-delay", default_value="1")]
+  db_retry_delay: u64
 }
 
 impl DbOpts {
   /// Open the database connection
   pub fn open(&self) -> Result<Connection> {
     let url = self.url()?;
-    connect(&url)
+    self.retry_policy().run("connect", || connect(&url))
+  }
+
+  /// Is the database an SQLite file?
+  pub fn is_sqlite(&self) -> bool {
+    self.url().map(|u| sqlite::is_sqlite_url(&u)).unwrap_or(false)
+  }
+
+  /// Is the database a DuckDB file?
+  pub fn is_duckdb(&self) -> bool {
+    self.url().map(|u| is_duckdb_url(&u)).unwrap_or(false)
+  }
+
+  /// Open the PostgreSQL connection for stage tracking.  SQLite and DuckDB
+  /// databases have no stage tables, so there is no tracking connection for them.
+  pub fn open_tracking(&self) -> Result<Option<Connection>> {
+    if self.is_sqlite() {
+      warn!("stage tracking is not supported with SQLite");
+      Ok(None)
+    } else if self.is_duckdb() {
+      warn!("stage tracking is not supported with DuckDB");
+      Ok(None)
+    } else {
+      Ok(Some(self.open()?))
+    }
   }
 
   pub fn url<'a>(&'a self) -> Result<String> {
@@ -66,8 +134,8 @@ impl DbOpts {
   /// Change the default schema
   pub fn default_schema(self, default: &str) -> DbOpts {
     DbOpts {
-      db_url: self.db_url,
-      db_schema: self.db_schema.or_else(|| Some(default.to_string()))
+      db_schema: self.db_schema.or_else(|| Some(default.to_string())),
+      ..self
     }
   }
 }
@@ -76,10 +144,66 @@ impl ConnectInfo for DbOpts {
   fn db_url(&self) -> Result<String> {
     self.url()
   }
+
+  fn defer_indexes(&self) -> bool {
+    self.defer_indexes
+  }
+
+  fn retry_policy(&self) -> RetryPolicy {
+    RetryPolicy {
+      retries: self.db_retries,
+      delay: Duration::from_secs(self.db_retry_delay),
+      ..RetryPolicy::default()
+    }
+  }
+}
+
+/// Get the columns of a table in the schema DDL and their types, without its
+/// `SERIAL` columns (which fill themselves in).
+pub fn ddl_columns(table: &str) -> Option<Vec<(String, ColType)>> {
+  let defs = ddl::table_defs(table)?;
+  let cols = defs.into_iter().filter_map(ddl::ColumnDef::parse).filter(|c| !c.is_serial());
+  Some(cols.map(|c| (c.name.clone(), c.col_type())).collect())
+}
+
+/// Is this a URL for a DuckDB database?
+fn is_duckdb_url(url: &str) -> bool {
+  url.starts_with("duckdb:")
 }
 
+/// Connect to a database, given a libpq-style URL or keyword string.
 pub fn connect(url: &str) -> Result<Connection> {
-  Ok(Connection::connect(url, TlsMode::None)?)
+  let settings = ConnSettings::parse(url)?;
+  let params = settings.connect_params()?;
+  let tls = settings.tls_config()?;
+  if tls.mode == SslMode::Disable || settings.is_unix() {
+    return Ok(Connection::connect(params, TlsMode::None)?);
+  }
+  let handshake = tls.handshake()?;
+  let mode = match tls.mode {
+    SslMode::Allow | SslMode::Prefer => TlsMode::Prefer(&handshake),
+    _ => TlsMode::Require(&handshake)
+  };
+  Ok(Connection::connect(params, mode)?)
+}
+
+/// Data format for a `COPY` stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyFormat {
+  Text,
+  Binary
+}
+
+impl FromStr for CopyFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<CopyFormat> {
+    match s {
+      "text" => Ok(CopyFormat::Text),
+      "binary" => Ok(CopyFormat::Binary),
+      _ => Err(anyhow!("unknown copy format {}", s))
+    }
+  }
 }
 
 pub struct CopyRequest {
@@ -89,6 +213,8 @@ pub struct CopyRequest {
   columns: Option<Vec<String>>,
   format: Option<String>,
   truncate: bool,
+  defer_indexes: bool,
+  retry: RetryPolicy,
   name: String
 }
 
@@ -101,6 +227,8 @@ impl CopyRequest {
       columns: None,
       format: None,
       truncate: false,
+      defer_indexes: db.defer_indexes(),
+      retry: db.retry_policy(),
       name: "copy".to_string()
     })
   }
@@ -130,6 +258,14 @@ impl CopyRequest {
     }
   }
 
+  /// Set the format from a `CopyFormat`.  Text is PostgreSQL's default.
+  pub fn with_copy_format(self, format: CopyFormat) -> CopyRequest {
+    match format {
+      CopyFormat::Text => CopyRequest { format: None, ..self },
+      CopyFormat::Binary => self.with_format("binary")
+    }
+  }
+
   pub fn with_name(self, name: &str) -> CopyRequest {
     CopyRequest {
       name: name.to_string(),
@@ -164,39 +300,172 @@ impl CopyRequest {
     query
   }
 
+  /// Check that the database accepts connections and has the table and
+  /// columns of the copy, without copying anything.
+  pub fn check(&self) -> Result<()> {
+    if sqlite::is_sqlite_url(&self.db_url) {
+      let table = sqlite::table_name(self.schema.as_deref(), &self.table);
+      return sqlite::check(&self.db_url, &table, self.columns.as_deref());
+    }
+    if is_duckdb_url(&self.db_url) {
+      return self.check_duckdb();
+    }
+    let db = self.retry.run("connect", || connect(&self.db_url))?;
+    let query = self.query();
+    debug!("checking {}", query);
+    db.prepare(&query)?;
+    info!("{} is ready to copy", self.table());
+    Ok(())
+  }
+
   /// Open a writer for a copy request
   pub fn open(self) -> Result<CopyTarget> {
+    if sqlite::is_sqlite_url(&self.db_url) {
+      return self.open_sqlite();
+    }
+    if is_duckdb_url(&self.db_url) {
+      return self.open_duckdb();
+    }
     let query = self.query();
-    let (mut reader, writer) = pipe()?;
+    let (reader, writer) = pipe()?;
 
     let name = self.name.clone();
     let tb = thread::Builder::new().name(name.clone());
     let jh = tb.spawn(move || {
-      let query = query;
-      let db = connect(&self.db_url).unwrap();
-      let mut cfg = postgres::transaction::Config::new();
-      cfg.isolation_level(postgres::transaction::IsolationLevel::ReadUncommitted);
-      let tx = db.transaction_with(&cfg).unwrap();
-      if self.truncate {
-        let tq = format!("TRUNCATE {}", self.table());
-        info!("running {}", tq);
-        tx.execute(&tq, &[]).unwrap();
-      }
-      info!("preparing {}", query);
-      let stmt = tx.prepare(&query).unwrap();
-      let n = stmt.copy_in(&[], &mut reader).unwrap();
-      info!("committing copy");
-      tx.commit().unwrap();
-      n
+      let mut reader = CountingReader { inner: reader, count: 0 };
+      self.retry.run(&self.name, || {
+        match self.run_copy(&query, &mut reader) {
+          Err(e) if reader.count > 0 => Err(anyhow!("copy failed after reading input: {}", e)),
+          r => r
+        }
+      }).unwrap()
     })?;
     Ok(CopyTarget {
       writer: Some(writer),
       name: name,
-      thread: Some(jh)
+      thread: Some(jh),
+      started: Instant::now()
     })
   }
 }
 
+/// Reader that counts the bytes read, so a failed copy is only retried if it
+/// has not yet consumed any input.
+struct CountingReader<R: Read> {
+  inner: R,
+  count: u64
+}
+
+impl<R: Read> Read for CountingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    self.count += n as u64;
+    Ok(n)
+  }
+}
+
+impl CopyRequest {
+  /// Run the copy in a transaction.
+  fn run_copy<R: Read>(&self, query: &str, reader: &mut R) -> Result<u64> {
+    let db = connect(&self.db_url)?;
+    let mut cfg = postgres::transaction::Config::new();
+    cfg.isolation_level(postgres::transaction::IsolationLevel::ReadUncommitted);
+    let tx = db.transaction_with(&cfg)?;
+    if self.truncate {
+      let tq = format!("TRUNCATE {}", self.table());
+      info!("running {}", tq);
+      tx.execute(&tq, &[])?;
+    }
+    let deferred = if self.defer_indexes {
+      indexes::drop_indexes(&tx, &self.table())?
+    } else {
+      Vec::new()
+    };
+    info!("preparing {}", query);
+    let stmt = tx.prepare(query)?;
+    let n = stmt.copy_in(&[], reader)?;
+    if self.defer_indexes {
+      indexes::restore_indexes(&tx, &self.table(), &deferred)?;
+    }
+    info!("committing copy");
+    tx.commit()?;
+    Ok(n)
+  }
+
+  /// Open a writer that inserts the copied rows into an SQLite table.
+  fn open_sqlite(self) -> Result<CopyTarget> {
+    if self.format.is_some() {
+      return Err(anyhow!("SQLite only supports text-format copies"));
+    }
+    if self.defer_indexes {
+      warn!("index deferral is not supported with SQLite");
+    }
+    let (reader, writer) = pipe()?;
+
+    let name = self.name.clone();
+    let tb = thread::Builder::new().name(name.clone());
+    let jh = tb.spawn(move || {
+      let table = match self.schema {
+        Some(ref s) => format!("{}.{}", s, self.table),
+        None => self.table.clone()
+      };
+      let mut db = sqlite::open(&self.db_url).unwrap();
+      let reader = BufReader::new(reader);
+      sqlite::load(&mut db, &table, self.columns.as_deref(), self.truncate, reader).unwrap()
+    })?;
+    Ok(CopyTarget {
+      writer: Some(writer),
+      name,
+      thread: Some(jh),
+      started: Instant::now()
+    })
+  }
+}
+
+impl CopyRequest {
+  /// Check that rows can be appended to the DuckDB table.
+  #[cfg(feature="duckdb")]
+  fn check_duckdb(&self) -> Result<()> {
+    duck::check(&self.db_url, self.schema.as_deref(), &self.table, self.columns.as_deref())
+  }
+
+  #[cfg(not(feature="duckdb"))]
+  fn check_duckdb(&self) -> Result<()> {
+    Err(anyhow!("DuckDB support requires building with --features duckdb"))
+  }
+
+  /// Open a writer that appends the copied rows to a DuckDB table.
+  #[cfg(feature="duckdb")]
+  fn open_duckdb(self) -> Result<CopyTarget> {
+    if self.format.is_some() {
+      return Err(anyhow!("DuckDB only supports text-format copies"));
+    }
+    if self.defer_indexes {
+      warn!("index deferral is not supported with DuckDB");
+    }
+    let (reader, writer) = pipe()?;
+
+    let name = self.name.clone();
+    let tb = thread::Builder::new().name(name.clone());
+    let jh = tb.spawn(move || {
+      let mut db = duck::open(&self.db_url).unwrap();
+      let reader = BufReader::new(reader);
+      duck::load(&mut db, self.schema.as_deref(), &self.table, self.columns.as_deref(), self.truncate, reader).unwrap()
+    })?;
+    Ok(CopyTarget {
+      writer: Some(writer),
+      name,
+      thread: Some(jh),
+      started: Instant::now()
+    })
+  }
+
+  #[cfg(not(feature="duckdb"))]
+  fn open_duckdb(self) -> Result<CopyTarget> {
+    Err(anyhow!("DuckDB support requires building with --features duckdb"))
+  }
+}
+
 /// Writer for copy-in operations
 ///
 /// This writer writes to the copy-in for PostgreSQL.  It is unbuffered; you usually
@@ -204,7 +473,8 @@ impl CopyRequest {
 pub struct CopyTarget {
   writer: Option<PipeWriter>,
   name: String,
-  thread: Option<thread::JoinHandle<u64>>
+  thread: Option<thread::JoinHandle<u64>>,
+  started: Instant
 }
 
 impl CopyTarget {
@@ -216,6 +486,7 @@ impl CopyTarget {
       match thread.join() {
         Ok(n) => {
           info!("{}: wrote {} lines", self.name, n);
+          summary::record_output(&format!("copy {}", self.name), n, self.started.elapsed());
           Ok(n)
         }
         Err(e) => {
@@ -259,6 +530,23 @@ fn cr_initial_correct() {
   assert!(cr.columns.is_none());
   assert!(cr.schema.is_none());
   assert!(!cr.truncate);
+  assert!(!cr.defer_indexes);
+  assert_eq!(cr.query(), "COPY wombat FROM STDIN");
+}
+
+#[test]
+fn cr_defer_indexes() {
+  let dbo = DbOpts {
+    db_url: Some("foo".to_string()),
+    db_schema: None,
+    defer_indexes: true,
+    db_retries: 5,
+    db_retry_delay: 2
+  };
+  let cr = CopyRequest::new(&dbo.default_schema("az"), "wombat").unwrap();
+  assert!(cr.defer_indexes);
+  assert_eq!(cr.retry.retries, 5);
+  assert_eq!(cr.retry.delay, Duration::from_secs(2));
   assert_eq!(cr.query(), "COPY wombat FROM STDIN");
 }
 
@@ -286,6 +574,22 @@ fn cr_set_format() {
   assert!(!cr.truncate);
 }
 
+#[test]
+fn cr_copy_format() {
+  let cr = CopyRequest::new(&("foo".to_string()), "wombat").unwrap();
+  let cr = cr.with_copy_format(CopyFormat::Binary);
+  assert_eq!(cr.query(), "COPY wombat FROM STDIN (FORMAT binary)");
+  let cr = cr.with_copy_format(CopyFormat::Text);
+  assert_eq!(cr.query(), "COPY wombat FROM STDIN");
+}
+
+#[test]
+fn parse_copy_format() {
+  assert_eq!("text".parse::<CopyFormat>().unwrap(), CopyFormat::Text);
+  assert_eq!("binary".parse::<CopyFormat>().unwrap(), CopyFormat::Binary);
+  assert!("csv".parse::<CopyFormat>().is_err());
+}
+
 #[test]
 fn cr_schema_propagated() {
   let cr = CopyRequest::new(&("foo".to_string()), "wombat").unwrap();
@@ -298,3 +602,24 @@ fn cr_schema_propagated() {
   assert!(!cr.truncate);
   assert_eq!(cr.query(), "COPY pizza.wombat FROM STDIN");
 }
+
+#[test]
+fn test_ddl_columns() {
+  let cols = ddl_columns("bx.raw_ratings").unwrap();
+  assert_eq!(cols, vec![("user_id".to_string(), ColType::Int32), ("isbn".to_string(), ColType::Text),
+                        ("rating".to_string(), ColType::Float)]);
+  let cols = ddl_columns("gr.raw_book").unwrap();
+  assert_eq!(cols, vec![("gr_book_data".to_string(), ColType::Json)]);
+  assert!(ddl_columns("bx.missing").is_none());
+}
+
+#[test]
+fn test_duckdb_url() {
+  assert!(is_duckdb_url("duckdb:books.duckdb"));
+  assert!(!is_duckdb_url("sqlite:books.sqlite"));
+  let cr = CopyRequest::new(&("duckdb:books.duckdb".to_string()), "wombat").unwrap();
+  #[cfg(not(feature="duckdb"))]
+  assert!(cr.check().is_err());
+  #[cfg(feature="duckdb")]
+  let _ = cr;
+}
@@ -0,0 +1,147 @@
+//! Table definitions from the repository's schema DDL, for the backends that
+//! create their own tables.
+use regex::Regex;
+
+use crate::columnar::ColType;
+
+/// The schema DDL, for creating tables.
+static SCHEMAS: &[&str] = &[
+  include_str!("../../schemas/common-schema.sql"),
+  include_str!("../../schemas/az-schema.sql"),
+  include_str!("../../schemas/bx-schema.sql"),
+  include_str!("../../schemas/crossref-schema.sql"),
+  include_str!("../../schemas/gr-schema.sql"),
+  include_str!("../../schemas/gutenberg-schema.sql"),
+  include_str!("../../schemas/hathi-schema.sql"),
+  include_str!("../../schemas/loc-mds-schema.sql"),
+  include_str!("../../schemas/ol-schema.sql"),
+  include_str!("../../schemas/openalex-schema.sql"),
+  include_str!("../../schemas/viaf-schema.sql"),
+  include_str!("../../schemas/wikidata-schema.sql")
+];
+
+/// Split the body of a `CREATE TABLE` at its top-level commas.
+fn split_defs(body: &str) -> Vec<&str> {
+  let mut defs = Vec::new();
+  let mut depth = 0;
+  let mut start = 0;
+  for (i, c) in body.char_indices() {
+    match c {
+      '(' => depth += 1,
+      ')' => depth -= 1,
+      ',' if depth == 0 => {
+        defs.push(body[start..i].trim());
+        start = i + 1;
+      },
+      _ => ()
+    }
+  }
+  defs.push(body[start..].trim());
+  defs
+}
+
+/// Get the column and constraint definitions of a table in the schema DDL, by
+/// its PostgreSQL name (`az.raw_ratings`).
+pub fn table_defs(table: &str) -> Option<Vec<&'static str>> {
+  let re = Regex::new(&format!(r"(?i)CREATE\s+TABLE\s+(?:IF\s+NOT\s+EXISTS\s+)?{}\s*\(", regex::escape(table))).ok()?;
+  for ddl in SCHEMAS {
+    if let Some(m) = re.find(ddl) {
+      let body = &ddl[m.end()..];
+      let mut depth = 1;
+      let end = body.char_indices().find(|(_, c)| {
+        match c {
+          '(' => depth += 1,
+          ')' => depth -= 1,
+          _ => ()
+        }
+        depth == 0
+      })?.0;
+      return Some(split_defs(&body[..end]));
+    }
+  }
+  None
+}
+
+/// A column definition from the schema DDL.
+pub struct ColumnDef {
+  /// The column name
+  pub name: String,
+  /// The upper-cased PostgreSQL type, without its modifiers or array brackets
+  pub base: String,
+  /// Whether the column is an array
+  pub array: bool,
+  /// The upper-cased words after the type
+  pub rest: String
+}
+
+impl ColumnDef {
+  /// Parse a column definition, returning `None` for table constraints.
+  pub fn parse(def: &str) -> Option<ColumnDef> {
+    let words: Vec<String> = def.split_whitespace().map(|w| w.to_uppercase()).collect();
+    let first = words.first()?;
+    if ["PRIMARY", "UNIQUE", "CONSTRAINT", "FOREIGN", "CHECK", "EXCLUDE"].contains(&first.as_str()) {
+      return None;
+    }
+    let name = def.split_whitespace().next()?.trim_matches('"').to_string();
+    let ptype = words.get(1)?;
+    let base = ptype.split(['(', '[']).next().unwrap_or("").to_string();
+    Some(ColumnDef {
+      name,
+      base,
+      array: ptype.ends_with(']'),
+      rest: words[2..].join(" ")
+    })
+  }
+
+  /// Is the column an auto-incrementing `SERIAL` column?
+  pub fn is_serial(&self) -> bool {
+    ["SERIAL", "BIGSERIAL", "SMALLSERIAL"].contains(&self.base.as_str())
+  }
+
+  /// Is the column a (non-array) integer or boolean?
+  pub fn is_integer(&self) -> bool {
+    !self.array && ["INTEGER", "INT", "INT2", "INT4", "INT8", "SMALLINT", "BIGINT", "BOOLEAN", "BOOL"].contains(&self.base.as_str())
+  }
+
+  /// Is the column a (non-array) floating-point or decimal number?
+  pub fn is_float(&self) -> bool {
+    !self.array && ["REAL", "FLOAT", "FLOAT4", "FLOAT8", "DOUBLE", "NUMERIC", "DECIMAL"].contains(&self.base.as_str())
+  }
+
+  /// Get the column type for writing the column to typed outputs.  Arrays,
+  /// dates, and timestamps are written as their text.
+  pub fn col_type(&self) -> ColType {
+    if self.array {
+      return ColType::Text;
+    }
+    match self.base.as_str() {
+      "INTEGER" | "INT" | "INT2" | "INT4" | "SMALLINT" | "SERIAL" | "SMALLSERIAL" => ColType::Int32,
+      "BIGINT" | "INT8" | "BIGSERIAL" => ColType::Int64,
+      "BOOLEAN" | "BOOL" => ColType::Bool,
+      "REAL" | "FLOAT4" => ColType::Float,
+      "FLOAT" | "FLOAT8" | "DOUBLE" | "NUMERIC" | "DECIMAL" => ColType::Double,
+      "JSON" | "JSONB" => ColType::Json,
+      _ => ColType::Text
+    }
+  }
+
+  /// Get the `NOT NULL`, `PRIMARY KEY`, and `UNIQUE` constraints of the column.
+  pub fn constraints(&self) -> Vec<&'static str> {
+    ["NOT NULL", "PRIMARY KEY", "UNIQUE"].iter().copied().filter(|c| self.rest.contains(c)).collect()
+  }
+}
+
+#[test]
+fn test_table_defs() {
+  let defs = table_defs("bx.raw_ratings").unwrap();
+  assert!(defs.iter().any(|d| d.starts_with("user_id")));
+  assert!(table_defs("bx.missing").is_none());
+  let col = ColumnDef::parse("isbn VARCHAR NOT NULL UNIQUE").unwrap();
+  assert_eq!(col.name, "isbn");
+  assert_eq!(col.base, "VARCHAR");
+  assert_eq!(col.constraints(), vec!["NOT NULL", "UNIQUE"]);
+  assert!(ColumnDef::parse("PRIMARY KEY (isbn)").is_none());
+  assert!(ColumnDef::parse("tags INTEGER[]").unwrap().array);
+  assert_eq!(ColumnDef::parse("tags INTEGER[]").unwrap().col_type(), ColType::Text);
+  assert_eq!(ColumnDef::parse("rating REAL NOT NULL").unwrap().col_type(), ColType::Float);
+}
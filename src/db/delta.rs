@@ -0,0 +1,79 @@
+//! Merge incremental updates into a loaded table through a staging table.
+use log::*;
+
+use anyhow::Result;
+use postgres::Connection;
+
+/// Get the staging table name for a table.
+pub fn staging_table(table: &str) -> String {
+  format!("{}_delta", table)
+}
+
+/// Create an empty staging table with a table's columns.
+pub fn create_staging(db: &Connection, table: &str, columns: &[&str]) -> Result<String> {
+  let staging = staging_table(table);
+  let q = format!("DROP TABLE IF EXISTS {}", staging);
+  info!("running {}", q);
+  db.execute(&q, &[])?;
+  let q = format!("CREATE UNLOGGED TABLE {} AS SELECT {} FROM {} WITH NO DATA", staging, columns.join(", "), table);
+  info!("running {}", q);
+  db.execute(&q, &[])?;
+  Ok(staging)
+}
+
+/// The SQL statements to merge a staging table into a table.  Rows are matched
+/// on the first column.  If there is a JSON column with a `revision` field, a
+/// staged row only replaces an older revision; otherwise it replaces every row
+/// with the same key.
+fn merge_sql(table: &str, staging: &str, columns: &[&str], json: Option<&str>) -> Vec<String> {
+  let key = columns[0];
+  let cols = columns.join(", ");
+  let scols: Vec<String> = columns.iter().map(|c| format!("d.{}", c)).collect();
+  let scols = scols.join(", ");
+  match json {
+    Some(js) => {
+      let rev = |t: &str| format!("COALESCE(({}.{}->>'revision')::int, 0)", t, js);
+      vec![
+        format!("DELETE FROM {} t USING {} d WHERE t.{} = d.{} AND {} < {}",
+                table, staging, key, key, rev("t"), rev("d")),
+        format!("INSERT INTO {} ({}) SELECT DISTINCT ON (d.{}) {} FROM {} d WHERE NOT EXISTS (SELECT 1 FROM {} t WHERE t.{} = d.{}) ORDER BY d.{}, {} DESC",
+                table, cols, key, scols, staging, table, key, key, key, rev("d")),
+        format!("DROP TABLE {}", staging)
+      ]
+    },
+    None => vec![
+      format!("DELETE FROM {} t USING {} d WHERE t.{} = d.{}", table, staging, key, key),
+      format!("INSERT INTO {} ({}) SELECT {} FROM {}", table, cols, cols, staging),
+      format!("DROP TABLE {}", staging)
+    ]
+  }
+}
+
+/// Merge a staging table into a table in one transaction, returning the number
+/// of rows replaced and inserted.
+pub fn merge_staging(db: &Connection, table: &str, staging: &str, columns: &[&str], json: Option<&str>) -> Result<(u64, u64)> {
+  let tx = db.transaction()?;
+  let mut counts = Vec::new();
+  for q in merge_sql(table, staging, columns, json) {
+    info!("running {}", q);
+    counts.push(tx.execute(&q, &[])?);
+  }
+  tx.commit()?;
+  Ok((counts[0], counts[1]))
+}
+
+#[test]
+fn test_merge_revision_sql() {
+  let sql = merge_sql("ol.work", "ol.work_delta", &["work_key", "work_data"], Some("work_data"));
+  assert_eq!(sql.len(), 3);
+  assert_eq!(sql[0], "DELETE FROM ol.work t USING ol.work_delta d WHERE t.work_key = d.work_key AND COALESCE((t.work_data->>'revision')::int, 0) < COALESCE((d.work_data->>'revision')::int, 0)");
+  assert!(sql[1].starts_with("INSERT INTO ol.work (work_key, work_data) SELECT DISTINCT ON (d.work_key) d.work_key, d.work_data FROM ol.work_delta d"));
+  assert_eq!(sql[2], "DROP TABLE ol.work_delta");
+}
+
+#[test]
+fn test_merge_replace_sql() {
+  let sql = merge_sql("ol.deleted", "ol.deleted_delta", &["deleted_key"], None);
+  assert_eq!(sql[0], "DELETE FROM ol.deleted t USING ol.deleted_delta d WHERE t.deleted_key = d.deleted_key");
+  assert_eq!(sql[1], "INSERT INTO ol.deleted (deleted_key) SELECT deleted_key FROM ol.deleted_delta");
+}
@@ -0,0 +1,231 @@
+//! DuckDB backend for copy requests.
+//!
+//! A database URL of the form `duckdb:FILE` sends the rows of a copy request to
+//! a table in a DuckDB file.  The importers still produce PostgreSQL text-format
+//! rows; they are decoded here and added with DuckDB's appender, which casts
+//! the text to the column types.  DuckDB has schemas, so tables keep their
+//! PostgreSQL names, and new tables are created from the repository's schema
+//! DDL.
+use std::io::BufRead;
+use std::path::Path;
+
+use log::*;
+
+use anyhow::{anyhow, Result};
+use duckdb::{appender_params_from_iter, params, AccessMode, Config, Connection};
+
+use crate::cleaning::{decode_pgencoded, PG_NULL};
+use super::ddl::{table_defs, ColumnDef};
+
+/// Get the database file of a DuckDB URL.
+fn db_path(url: &str) -> &str {
+  let path = url.trim_start_matches("duckdb:");
+  path.trim_start_matches("//")
+}
+
+/// Open the DuckDB database for a URL.
+pub fn open(url: &str) -> Result<Connection> {
+  let path = db_path(url);
+  info!("opening DuckDB database {}", path);
+  Ok(Connection::open(path)?)
+}
+
+/// Split a table name into its schema and table, defaulting to `main`.
+fn split_name<'a>(schema: Option<&'a str>, table: &'a str) -> (&'a str, &'a str) {
+  match (schema, table.split_once('.')) {
+    (Some(s), _) => (s, table),
+    (None, Some((s, t))) => (s, t),
+    (None, None) => ("main", table)
+  }
+}
+
+fn quote(name: &str) -> String {
+  format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Get the columns of an existing table.
+fn table_columns(db: &Connection, schema: &str, table: &str) -> Result<Vec<String>> {
+  let mut stmt = db.prepare("SELECT column_name FROM information_schema.columns WHERE table_schema = ? AND table_name = ? ORDER BY ordinal_position")?;
+  let cols = stmt.query_map(params![schema, table], |row| row.get(0))?;
+  let mut names = Vec::new();
+  for c in cols {
+    names.push(c?);
+  }
+  Ok(names)
+}
+
+/// Check that rows can be loaded into a table, without changing the database.
+/// Missing databases and tables are fine, since loading creates them.
+pub fn check(url: &str, schema: Option<&str>, table: &str, columns: Option<&[String]>) -> Result<()> {
+  let path = db_path(url);
+  if !Path::new(path).exists() {
+    info!("DuckDB database {} does not exist and will be created", path);
+    return Ok(());
+  }
+  let db = Connection::open_with_flags(path, Config::default().access_mode(AccessMode::ReadOnly)?)?;
+  let (schema, table) = split_name(schema, table);
+  let existing = table_columns(&db, schema, table)?;
+  if existing.is_empty() {
+    info!("table {}.{} does not exist and will be created", schema, table);
+  } else if let Some(cs) = columns {
+    if let Some(c) = cs.iter().find(|c| !existing.contains(c)) {
+      return Err(anyhow!("table {}.{} has no column {}", schema, table, c));
+    }
+  }
+  Ok(())
+}
+
+/// Translate a PostgreSQL column definition to DuckDB.  Numeric and boolean
+/// types are kept, other types are stored as text, and `SERIAL` columns draw
+/// their defaults from a sequence.
+fn duckdb_column(schema: &str, table: &str, def: &str) -> Option<(String, String)> {
+  let col = ColumnDef::parse(def)?;
+  let mut sql = if col.is_serial() {
+    format!("BIGINT DEFAULT nextval('{}.{}_{}_seq')", schema, table, col.name)
+  } else if col.is_integer() {
+    col.base.clone()
+  } else if col.base == "NUMERIC" || col.base == "DECIMAL" {
+    "DOUBLE".to_string()
+  } else if col.is_float() {
+    col.base.clone()
+  } else {
+    "VARCHAR".to_string()
+  };
+  for cons in col.constraints() {
+    sql.push(' ');
+    sql.push_str(cons);
+  }
+  if col.is_serial() && !sql.contains("PRIMARY KEY") {
+    sql.push_str(" PRIMARY KEY");
+  }
+  Some((col.name, sql))
+}
+
+/// Get the statements to create a table, with its column types from the schema
+/// DDL if it is there and with text columns if it is not.
+fn create_table_sql(schema: &str, table: &str, columns: &[String]) -> Vec<String> {
+  let pg_table = if schema == "main" { table.to_string() } else { format!("{}.{}", schema, table) };
+  let mut stmts = vec![format!("CREATE SCHEMA IF NOT EXISTS {}", quote(schema))];
+  let mut defs = match table_defs(&pg_table) {
+    Some(defs) => defs.into_iter().filter_map(|d| duckdb_column(schema, table, d)).collect(),
+    None => {
+      warn!("table {} is not in the schema, creating it with text columns", pg_table);
+      Vec::new()
+    }
+  };
+  for (name, sql) in &defs {
+    if sql.contains("nextval(") {
+      stmts.push(format!("CREATE SEQUENCE IF NOT EXISTS {}.{}", quote(schema), quote(&format!("{}_{}_seq", table, name))));
+    }
+  }
+  for c in columns {
+    if !defs.iter().any(|(n, _)| n == c) {
+      if !defs.is_empty() {
+        warn!("column {} is not in the schema of {}", c, pg_table);
+      }
+      defs.push((c.clone(), "VARCHAR".to_string()));
+    }
+  }
+  let defs: Vec<String> = defs.iter().map(|(n, t)| format!("{} {}", quote(n), t)).collect();
+  stmts.push(format!("CREATE TABLE {}.{} ({})", quote(schema), quote(table), defs.join(", ")));
+  stmts
+}
+
+/// Decode one field of a text-format row.
+fn decode_field(fld: &[u8], buf: &mut Vec<u8>) -> Result<Option<String>> {
+  if fld == PG_NULL {
+    return Ok(None);
+  }
+  buf.clear();
+  decode_pgencoded(fld, buf)?;
+  Ok(Some(String::from_utf8(buf.clone())?))
+}
+
+/// Load text-format rows into a table, returning the number of rows appended.
+/// The table is given by its PostgreSQL name, with the schema either separate
+/// or in the name.
+///
+/// If the table does not exist, it is created from the schema DDL.
+pub fn load<R: BufRead>(db: &mut Connection, schema: Option<&str>, table: &str, columns: Option<&[String]>,
+                        truncate: bool, mut src: R) -> Result<u64> {
+  let (schema, table) = split_name(schema, table);
+  let existing = table_columns(db, schema, table)?;
+  let columns = match columns {
+    Some(cs) => cs.to_vec(),
+    None if !existing.is_empty() => existing.clone(),
+    None => return Err(anyhow!("table {}.{} does not exist and no columns given", schema, table))
+  };
+  if existing.is_empty() {
+    for q in create_table_sql(schema, table, &columns) {
+      info!("running {}", q);
+      db.execute(&q, [])?;
+    }
+  } else if truncate {
+    info!("deleting rows from {}.{}", schema, table);
+    db.execute(&format!("DELETE FROM {}.{}", quote(schema), quote(table)), [])?;
+  }
+
+  let cref: Vec<&str> = columns.iter().map(|c| c.as_str()).collect();
+  let mut app = db.appender_with_columns_to_db(table, schema, &cref)?;
+  let mut line = Vec::new();
+  let mut buf = Vec::new();
+  let mut row = Vec::with_capacity(columns.len());
+  let mut n = 0;
+  loop {
+    line.clear();
+    if src.read_until(b'\n', &mut line)? == 0 {
+      break;
+    }
+    if line.ends_with(b"\n") {
+      line.pop();
+    }
+    row.clear();
+    for fld in line.split(|b| *b == b'\t') {
+      row.push(decode_field(fld, &mut buf)?);
+    }
+    if row.len() != columns.len() {
+      return Err(anyhow!("row {} has {} fields, expected {}", n + 1, row.len(), columns.len()));
+    }
+    app.append_row(appender_params_from_iter(row.iter()))?;
+    n += 1;
+  }
+  app.flush()?;
+  debug!("appended {} rows", n);
+  Ok(n)
+}
+
+#[test]
+fn test_db_path() {
+  assert_eq!(db_path("duckdb://books.duckdb"), "books.duckdb");
+}
+
+#[test]
+fn test_split_name() {
+  assert_eq!(split_name(Some("gr"), "raw_book"), ("gr", "raw_book"));
+  assert_eq!(split_name(None, "az.raw_ratings"), ("az", "raw_ratings"));
+  assert_eq!(split_name(None, "isbn_id"), ("main", "isbn_id"));
+}
+
+#[test]
+fn test_create_table_sql() {
+  let stmts = create_table_sql("gr", "raw_book", &["gr_book_data".to_string()]);
+  assert_eq!(stmts, vec![
+    "CREATE SCHEMA IF NOT EXISTS \"gr\"".to_string(),
+    "CREATE SEQUENCE IF NOT EXISTS \"gr\".\"raw_book_gr_book_rid_seq\"".to_string(),
+    "CREATE TABLE \"gr\".\"raw_book\" (\"gr_book_rid\" BIGINT DEFAULT nextval('gr.raw_book_gr_book_rid_seq') PRIMARY KEY, \"gr_book_data\" VARCHAR NOT NULL)".to_string()
+  ]);
+  let stmts = create_table_sql("main", "books", &["id".to_string()]);
+  assert_eq!(stmts.last().unwrap(), "CREATE TABLE \"main\".\"books\" (\"id\" VARCHAR)");
+}
+
+#[test]
+fn test_load_new_table() {
+  let mut db = Connection::open_in_memory().unwrap();
+  let cols = vec!["user_id".to_string(), "isbn".to_string(), "rating".to_string()];
+  let n = load(&mut db, Some("bx"), "raw_ratings", Some(&cols), false, &b"276725\t034545104X\t5\n"[..]).unwrap();
+  assert_eq!(n, 1);
+  let (u, r): (i64, f64) = db.query_row("SELECT user_id, rating FROM bx.raw_ratings", [], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+  assert_eq!((u, r), (276725, 5.0));
+  assert!(load(&mut db, None, "bx.raw_ratings", Some(&cols), false, &b"1\t\\N\t5\n"[..]).is_err());
+  assert!(load(&mut db, None, "missing", None, false, &b"1\n"[..]).is_err());
+}
@@ -0,0 +1,97 @@
+//! Drop and recreate a table's indexes and constraints around a bulk load.
+use log::*;
+
+use anyhow::Result;
+use postgres::transaction::Transaction;
+
+/// Indexes not backing a constraint.
+const INDEX_QUERY: &str = "SELECT x.indexrelid::regclass::text, pg_get_indexdef(x.indexrelid)
+  FROM pg_index x
+  WHERE x.indrelid = $1::text::regclass
+    AND NOT EXISTS (SELECT 1 FROM pg_constraint c WHERE c.conindid = x.indexrelid)";
+
+/// Primary key, unique, and foreign key constraints.  Keys that other tables'
+/// foreign keys depend on cannot be dropped, so they are left in place.
+const CONSTRAINT_QUERY: &str = "SELECT c.conname::text, c.contype::text, pg_get_constraintdef(c.oid)
+  FROM pg_constraint c
+  WHERE c.conrelid = $1::text::regclass
+    AND c.contype IN ('p', 'u', 'f')
+    AND (c.contype = 'f' OR NOT EXISTS (
+      SELECT 1 FROM pg_constraint f WHERE f.contype = 'f' AND f.conindid = c.conindid))
+  ORDER BY c.contype = 'f' DESC, c.conname";
+
+/// An index or constraint that has been dropped for a load.
+#[derive(Debug, PartialEq)]
+pub enum Deferred {
+  Index { name: String, def: String },
+  Constraint { name: String, def: String }
+}
+
+impl Deferred {
+  fn drop_sql(&self, table: &str) -> String {
+    match self {
+      Deferred::Index { name, .. } => format!("DROP INDEX {}", name),
+      Deferred::Constraint { name, .. } => format!("ALTER TABLE {} DROP CONSTRAINT \"{}\"", table, name)
+    }
+  }
+
+  fn create_sql(&self, table: &str) -> String {
+    match self {
+      Deferred::Index { def, .. } => def.clone(),
+      Deferred::Constraint { name, def } => format!("ALTER TABLE {} ADD CONSTRAINT \"{}\" {}", table, name, def)
+    }
+  }
+}
+
+/// Look up and drop a table's indexes and constraints.  They are returned in
+/// drop order (foreign keys first, then keys, then plain indexes).
+pub fn drop_indexes(tx: &Transaction, table: &str) -> Result<Vec<Deferred>> {
+  let mut deferred = Vec::new();
+  for row in &tx.query(CONSTRAINT_QUERY, &[&table])? {
+    let name: String = row.get(0);
+    let def: String = row.get(2);
+    deferred.push(Deferred::Constraint { name, def });
+  }
+  for row in &tx.query(INDEX_QUERY, &[&table])? {
+    deferred.push(Deferred::Index { name: row.get(0), def: row.get(1) });
+  }
+  for d in &deferred {
+    let q = d.drop_sql(table);
+    info!("running {}", q);
+    tx.execute(&q, &[])?;
+  }
+  Ok(deferred)
+}
+
+/// Recreate dropped indexes and constraints, and analyze the table.
+pub fn restore_indexes(tx: &Transaction, table: &str, deferred: &[Deferred]) -> Result<()> {
+  for d in deferred.iter().rev() {
+    let q = d.create_sql(table);
+    info!("running {}", q);
+    tx.execute(&q, &[])?;
+  }
+  let q = format!("ANALYZE {}", table);
+  info!("running {}", q);
+  tx.execute(&q, &[])?;
+  Ok(())
+}
+
+#[test]
+fn test_index_sql() {
+  let d = Deferred::Index {
+    name: "az.az_rating_asin_idx".to_string(),
+    def: "CREATE INDEX az_rating_asin_idx ON az.raw_ratings USING btree (asin)".to_string()
+  };
+  assert_eq!(d.drop_sql("az.raw_ratings"), "DROP INDEX az.az_rating_asin_idx");
+  assert_eq!(d.create_sql("az.raw_ratings"), "CREATE INDEX az_rating_asin_idx ON az.raw_ratings USING btree (asin)");
+}
+
+#[test]
+fn test_constraint_sql() {
+  let d = Deferred::Constraint {
+    name: "user_ids_pkey".to_string(),
+    def: "PRIMARY KEY (user_id)".to_string()
+  };
+  assert_eq!(d.drop_sql("az.user_ids"), "ALTER TABLE az.user_ids DROP CONSTRAINT \"user_ids_pkey\"");
+  assert_eq!(d.create_sql("az.user_ids"), "ALTER TABLE az.user_ids ADD CONSTRAINT \"user_ids_pkey\" PRIMARY KEY (user_id)");
+}
@@ -0,0 +1,132 @@
+//! Retry database operations that fail with transient errors.
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::*;
+
+use anyhow::Result;
+
+/// SQLSTATE codes (or classes, ending in `*`) worth retrying.
+static TRANSIENT_CODES: &[&str] = &[
+  "08*",    // connection exception
+  "40001",  // serialization_failure
+  "40P01",  // deadlock_detected
+  "53300",  // too_many_connections
+  "57P01",  // admin_shutdown
+  "57P02",  // crash_shutdown
+  "57P03"   // cannot_connect_now
+];
+
+/// How many times to retry, and how long to wait between attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+  /// The number of retries after the first attempt
+  pub retries: u32,
+  /// The delay before the first retry; it doubles after each attempt
+  pub delay: Duration,
+  /// The longest delay between attempts
+  pub max_delay: Duration
+}
+
+impl Default for RetryPolicy {
+  fn default() -> RetryPolicy {
+    RetryPolicy {
+      retries: 0,
+      delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(60)
+    }
+  }
+}
+
+fn transient_code(code: &str) -> bool {
+  TRANSIENT_CODES.iter().any(|c| match c.strip_suffix('*') {
+    Some(class) => code.starts_with(class),
+    None => code == *c
+  })
+}
+
+/// Is an error a transient failure (a lost connection or a transient server
+/// condition) that may succeed if retried?
+pub fn is_transient(err: &anyhow::Error) -> bool {
+  if let Some(pe) = err.downcast_ref::<postgres::Error>() {
+    if pe.as_io().is_some() {
+      return true;
+    }
+    if let Some(code) = pe.code() {
+      return transient_code(code.code());
+    }
+    false
+  } else {
+    err.downcast_ref::<std::io::Error>().is_some()
+  }
+}
+
+impl RetryPolicy {
+  /// Get the delay before retry number `attempt` (starting from 0).
+  pub fn delay_for(&self, attempt: u32) -> Duration {
+    let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+    self.delay.checked_mul(factor).map(|d| d.min(self.max_delay)).unwrap_or(self.max_delay)
+  }
+
+  /// Run an operation, retrying it with exponential backoff if it fails with a
+  /// transient error.
+  pub fn run<T, F>(&self, what: &str, mut op: F) -> Result<T>
+  where F: FnMut() -> Result<T>
+  {
+    let mut attempt = 0;
+    loop {
+      match op() {
+        Ok(v) => return Ok(v),
+        Err(e) if attempt < self.retries && is_transient(&e) => {
+          let delay = self.delay_for(attempt);
+          warn!("{} failed: {}; retrying in {:.1}s ({} of {})",
+                what, e, delay.as_secs_f64(), attempt + 1, self.retries);
+          sleep(delay);
+          attempt += 1;
+        },
+        Err(e) => return Err(e)
+      }
+    }
+  }
+}
+
+#[test]
+fn test_transient_codes() {
+  assert!(transient_code("08006"));
+  assert!(transient_code("57P01"));
+  assert!(transient_code("40P01"));
+  assert!(!transient_code("42P01"));
+  assert!(!transient_code("23505"));
+}
+
+#[test]
+fn test_delay_for() {
+  let p = RetryPolicy { retries: 10, delay: Duration::from_secs(2), max_delay: Duration::from_secs(30) };
+  assert_eq!(p.delay_for(0), Duration::from_secs(2));
+  assert_eq!(p.delay_for(2), Duration::from_secs(8));
+  assert_eq!(p.delay_for(4), Duration::from_secs(30));
+  assert_eq!(p.delay_for(40), Duration::from_secs(30));
+}
+
+#[test]
+fn test_run_retries() {
+  let p = RetryPolicy { retries: 2, delay: Duration::from_millis(1), max_delay: Duration::from_millis(1) };
+  let mut n = 0;
+  let r = p.run("test", || {
+    n += 1;
+    if n < 3 {
+      Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset").into())
+    } else {
+      Ok(n)
+    }
+  });
+  assert_eq!(r.unwrap(), 3);
+
+  let mut n = 0;
+  let r: Result<()> = p.run("test", || {
+    n += 1;
+    Err(anyhow::anyhow!("permanent"))
+  });
+  assert!(r.is_err());
+  assert_eq!(n, 1);
+}
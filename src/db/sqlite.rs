@@ -0,0 +1,285 @@
+//! SQLite backend for copy requests.
+//!
+//! A database URL of the form `sqlite:FILE` sends the rows of a copy request to
+//! a table in an SQLite file.  The importers still produce PostgreSQL text-format
+//! rows; they are decoded here and inserted in batched transactions.  New
+//! tables are created from the repository's schema DDL, with the PostgreSQL
+//! column types translated to SQLite ones.
+use std::io::BufRead;
+use std::path::Path;
+
+use log::*;
+
+use anyhow::{anyhow, Result};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, OpenFlags};
+
+use crate::cleaning::{decode_pgencoded, PG_NULL};
+use super::ddl::{table_defs, ColumnDef};
+
+/// Number of rows to insert in each transaction.
+const BATCH_SIZE: usize = 10000;
+
+/// Is this a URL for an SQLite database?
+pub fn is_sqlite_url(url: &str) -> bool {
+  url.starts_with("sqlite:")
+}
+
+/// Get the database file of an SQLite URL.
+fn db_path(url: &str) -> &str {
+  let path = url.trim_start_matches("sqlite:");
+  path.trim_start_matches("//")
+}
+
+/// Open the SQLite database for a URL.
+pub fn open(url: &str) -> Result<Connection> {
+  let path = db_path(url);
+  info!("opening SQLite database {}", path);
+  Ok(Connection::open(path)?)
+}
+
+/// Check that rows can be loaded into a table, without changing the database.
+/// Missing databases and tables are fine, since loading creates them.
+pub fn check(url: &str, table: &str, columns: Option<&[String]>) -> Result<()> {
+  let path = db_path(url);
+  if !Path::new(path).exists() {
+    info!("SQLite database {} does not exist and will be created", path);
+    return Ok(());
+  }
+  let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+  let existing = table_columns(&db, table)?;
+  if existing.is_empty() {
+    info!("table {} does not exist and will be created", table);
+  } else if let Some(cs) = columns {
+    if let Some(c) = cs.iter().find(|c| !existing.contains(c)) {
+      return Err(anyhow!("table {} has no column {}", table, c));
+    }
+  }
+  Ok(())
+}
+
+/// Get the SQLite name of a table.  SQLite has no schemas, so the schema is
+/// folded into the table name (`gr.raw_book` becomes `gr_raw_book`).
+pub fn table_name(schema: Option<&str>, table: &str) -> String {
+  match schema {
+    Some(s) => format!("{}_{}", s, table),
+    None => table.replace('.', "_")
+  }
+}
+
+fn quote(name: &str) -> String {
+  format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Get the columns of an existing table.
+fn table_columns(db: &Connection, table: &str) -> Result<Vec<String>> {
+  let mut stmt = db.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+  let cols = stmt.query_map([], |row| row.get(1))?;
+  let mut names = Vec::new();
+  for c in cols {
+    names.push(c?);
+  }
+  Ok(names)
+}
+
+/// Translate a PostgreSQL column definition to SQLite, keeping its type
+/// affinity and its `NOT NULL`, `PRIMARY KEY`, and `UNIQUE` constraints.
+fn sqlite_column(def: &str) -> Option<(String, String)> {
+  let col = ColumnDef::parse(def)?;
+  if col.is_serial() {
+    return Some((col.name, "INTEGER PRIMARY KEY".to_string()));
+  }
+  let mut sql = if col.is_integer() {
+    "INTEGER"
+  } else if col.is_float() {
+    "REAL"
+  } else {
+    "TEXT"
+  }.to_string();
+  for cons in col.constraints() {
+    sql.push(' ');
+    sql.push_str(cons);
+  }
+  Some((col.name, sql))
+}
+
+/// Get the SQLite column definitions of a table from the schema DDL, by its
+/// PostgreSQL name (`az.raw_ratings`).
+fn schema_columns(table: &str) -> Option<Vec<(String, String)>> {
+  Some(table_defs(table)?.into_iter().filter_map(sqlite_column).collect())
+}
+
+/// Get the statement to create a table, with its column types from the schema
+/// DDL if it is there and with untyped columns if it is not.
+fn create_table_sql(name: &str, table: &str, columns: &[String]) -> String {
+  let mut defs = match schema_columns(table) {
+    Some(defs) => defs,
+    None => {
+      warn!("table {} is not in the schema, creating it with untyped columns", table);
+      Vec::new()
+    }
+  };
+  for c in columns {
+    if !defs.iter().any(|(n, _)| n == c) {
+      if !defs.is_empty() {
+        warn!("column {} is not in the schema of {}", c, table);
+      }
+      defs.push((c.clone(), String::new()));
+    }
+  }
+  let defs: Vec<String> = defs.iter().map(|(n, t)| format!("{} {}", quote(n), t).trim_end().to_string()).collect();
+  format!("CREATE TABLE {} ({})", quote(name), defs.join(", "))
+}
+
+/// Decode one field of a text-format row.
+fn decode_field(fld: &[u8], buf: &mut Vec<u8>) -> Result<Value> {
+  if fld == PG_NULL {
+    return Ok(Value::Null);
+  }
+  buf.clear();
+  decode_pgencoded(fld, buf)?;
+  Ok(Value::Text(String::from_utf8(buf.clone())?))
+}
+
+/// Load text-format rows into a table, returning the number of rows inserted.
+/// The table is given by its PostgreSQL name, and stored under its SQLite one.
+///
+/// If the table does not exist, it is created from the schema DDL.
+pub fn load<R: BufRead>(db: &mut Connection, table: &str, columns: Option<&[String]>,
+                        truncate: bool, mut src: R) -> Result<u64> {
+  let pg_table = table;
+  let table = &table_name(None, pg_table);
+  let existing = table_columns(db, table)?;
+  let columns = match columns {
+    Some(cs) => cs.to_vec(),
+    None if !existing.is_empty() => existing.clone(),
+    None => return Err(anyhow!("table {} does not exist and no columns given", table))
+  };
+  let qcols: Vec<String> = columns.iter().map(|c| quote(c)).collect();
+  let qcols = qcols.join(", ");
+  if existing.is_empty() {
+    let q = create_table_sql(table, pg_table, &columns);
+    info!("running {}", q);
+    db.execute(&q, [])?;
+  } else if truncate {
+    info!("deleting rows from {}", table);
+    db.execute(&format!("DELETE FROM {}", quote(table)), [])?;
+  }
+
+  let marks = vec!["?"; columns.len()].join(", ");
+  let insert = format!("INSERT INTO {} ({}) VALUES ({})", quote(table), qcols, marks);
+  let mut line = Vec::new();
+  let mut buf = Vec::new();
+  let mut row = Vec::with_capacity(columns.len());
+  let mut n = 0;
+  let mut done = false;
+  while !done {
+    let tx = db.transaction()?;
+    {
+      let mut stmt = tx.prepare_cached(&insert)?;
+      let mut nb = 0;
+      while nb < BATCH_SIZE {
+        line.clear();
+        if src.read_until(b'\n', &mut line)? == 0 {
+          done = true;
+          break;
+        }
+        if line.ends_with(b"\n") {
+          line.pop();
+        }
+        row.clear();
+        for fld in line.split(|b| *b == b'\t') {
+          row.push(decode_field(fld, &mut buf)?);
+        }
+        if row.len() != columns.len() {
+          return Err(anyhow!("row {} has {} fields, expected {}", n + 1, row.len(), columns.len()));
+        }
+        stmt.execute(params_from_iter(row.iter()))?;
+        nb += 1;
+        n += 1;
+      }
+    }
+    tx.commit()?;
+    debug!("inserted {} rows", n);
+  }
+  Ok(n)
+}
+
+#[test]
+fn test_sqlite_url() {
+  assert!(is_sqlite_url("sqlite:books.sqlite"));
+  assert!(!is_sqlite_url("postgresql://localhost/bookdata"));
+}
+
+#[test]
+fn test_table_name() {
+  assert_eq!(table_name(Some("gr"), "raw_book"), "gr_raw_book");
+  assert_eq!(table_name(None, "az.raw_ratings"), "az_raw_ratings");
+  assert_eq!(table_name(None, "isbn_id"), "isbn_id");
+}
+
+#[test]
+fn test_load_new_table() {
+  let mut db = Connection::open_in_memory().unwrap();
+  let cols = vec!["id".to_string(), "title".to_string()];
+  let src = b"1\tfoo\\tbar\n2\t\\N\n";
+  let n = load(&mut db, "books", Some(&cols), false, &src[..]).unwrap();
+  assert_eq!(n, 2);
+  let title: String = db.query_row("SELECT title FROM books WHERE id = '1'", [], |r| r.get(0)).unwrap();
+  assert_eq!(title, "foo\tbar");
+  let null: Option<String> = db.query_row("SELECT title FROM books WHERE id = '2'", [], |r| r.get(0)).unwrap();
+  assert!(null.is_none());
+}
+
+#[test]
+fn test_load_schema_table() {
+  let mut db = Connection::open_in_memory().unwrap();
+  let cols = vec!["user_id".to_string(), "isbn".to_string(), "rating".to_string()];
+  load(&mut db, "bx.raw_ratings", Some(&cols), false, &b"276725\t034545104X\t5\n"[..]).unwrap();
+  let (u, r): (i64, f64) = db.query_row("SELECT user_id, rating FROM bx_raw_ratings", [], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+  assert_eq!((u, r), (276725, 5.0));
+  assert!(load(&mut db, "bx.raw_ratings", Some(&cols), false, &b"1\t\\N\t5\n"[..]).is_err());
+}
+
+#[test]
+fn test_create_table_sql() {
+  assert_eq!(create_table_sql("isbn_id", "isbn_id", &["isbn".to_string()]),
+             "CREATE TABLE \"isbn_id\" (\"isbn_id\" INTEGER PRIMARY KEY, \"isbn\" TEXT NOT NULL UNIQUE)");
+  assert_eq!(create_table_sql("gr_raw_book", "gr.raw_book", &["gr_book_data".to_string()]),
+             "CREATE TABLE \"gr_raw_book\" (\"gr_book_rid\" INTEGER PRIMARY KEY, \"gr_book_data\" TEXT NOT NULL)");
+  assert_eq!(create_table_sql("books", "books", &["id".to_string()]), "CREATE TABLE \"books\" (\"id\")");
+}
+
+#[test]
+fn test_load_existing_table() {
+  let mut db = Connection::open_in_memory().unwrap();
+  db.execute("CREATE TABLE ratings (user_id INTEGER, rating REAL)", []).unwrap();
+  db.execute("INSERT INTO ratings VALUES (9, 1.0)", []).unwrap();
+  let n = load(&mut db, "ratings", None, true, &b"5\t3.5\n"[..]).unwrap();
+  assert_eq!(n, 1);
+  let (u, r): (i64, f64) = db.query_row("SELECT user_id, rating FROM ratings", [], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+  assert_eq!((u, r), (5, 3.5));
+}
+
+#[test]
+fn test_load_bad_row() {
+  let mut db = Connection::open_in_memory().unwrap();
+  let cols = vec!["id".to_string(), "title".to_string()];
+  assert!(load(&mut db, "books", Some(&cols), false, &b"1\tfoo\tbar\n"[..]).is_err());
+  assert!(load(&mut db, "missing", None, false, &b"1\n"[..]).is_err());
+}
+
+#[test]
+fn test_check() {
+  let path = std::env::temp_dir().join(format!("bookdata-check-{}.sqlite", std::process::id()));
+  let url = format!("sqlite:{}", path.display());
+  let cols = vec!["isbn".to_string(), "title".to_string()];
+  check(&url, "books", Some(&cols)).unwrap();
+  assert!(!path.exists());
+  open(&url).unwrap().execute("CREATE TABLE books (isbn, title)", []).unwrap();
+  check(&url, "books", Some(&cols)).unwrap();
+  check(&url, "authors", Some(&cols)).unwrap();
+  let bad = vec!["isbn".to_string(), "author".to_string()];
+  assert!(check(&url, "books", Some(&bad)).is_err());
+  std::fs::remove_file(&path).unwrap();
+}
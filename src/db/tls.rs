@@ -0,0 +1,234 @@
+//! TLS for PostgreSQL connections, with libpq-style `sslmode` handling.
+use std::fmt;
+use std::convert::TryFrom;
+use std::error::Error as StdError;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use postgres::tls::{TlsHandshake, TlsStream, Stream};
+use rustls::{ClientConfig, ClientConnection, StreamOwned, RootCertStore, DigitallySignedStruct, SignatureScheme, CertificateError};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{ServerCertVerifier, ServerCertVerified, HandshakeSignatureValid};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::pki_types::pem::PemObject;
+
+/// The `sslmode` connection parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SslMode {
+  Disable,
+  Allow,
+  Prefer,
+  Require,
+  VerifyCa,
+  VerifyFull
+}
+
+impl FromStr for SslMode {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<SslMode> {
+    match s {
+      "disable" => Ok(SslMode::Disable),
+      "allow" => Ok(SslMode::Allow),
+      "prefer" => Ok(SslMode::Prefer),
+      "require" => Ok(SslMode::Require),
+      "verify-ca" => Ok(SslMode::VerifyCa),
+      "verify-full" => Ok(SslMode::VerifyFull),
+      _ => Err(anyhow!("invalid sslmode {}", s))
+    }
+  }
+}
+
+/// TLS settings for a connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+  pub mode: SslMode,
+  pub root_cert: Option<PathBuf>,
+  pub cert: Option<PathBuf>,
+  pub key: Option<PathBuf>
+}
+
+impl Default for TlsConfig {
+  fn default() -> TlsConfig {
+    TlsConfig {
+      mode: SslMode::Prefer,
+      root_cert: None,
+      cert: None,
+      key: None
+    }
+  }
+}
+
+/// Server certificate check for a connection.  As in libpq, the certificate is
+/// only checked against trusted roots with `verify-ca` and `verify-full` (or
+/// `require` with a root certificate file), and the host name only with
+/// `verify-full`.
+#[derive(Debug)]
+struct PgVerifier {
+  roots: Option<Arc<WebPkiServerVerifier>>,
+  check_name: bool,
+  provider: Arc<CryptoProvider>
+}
+
+impl ServerCertVerifier for PgVerifier {
+  fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>],
+                        server_name: &ServerName<'_>, ocsp: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+    let roots = match self.roots {
+      Some(ref r) => r,
+      None => return Ok(ServerCertVerified::assertion())
+    };
+    match roots.verify_server_cert(end_entity, intermediates, server_name, ocsp, now) {
+      Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+      | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. })) if !self.check_name => {
+        Ok(ServerCertVerified::assertion())
+      },
+      r => r
+    }
+  }
+
+  fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+    crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+  }
+
+  fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+    crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+  }
+
+  fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+    self.provider.signature_verification_algorithms.supported_schemes()
+  }
+}
+
+/// Look for a file in `~/.postgresql`, where libpq looks by default.
+fn default_file(name: &str) -> Option<PathBuf> {
+  let home = std::env::var_os("HOME")?;
+  let path = Path::new(&home).join(".postgresql").join(name);
+  if path.exists() {
+    Some(path)
+  } else {
+    None
+  }
+}
+
+impl TlsConfig {
+  /// Create the TLS handshake for these settings.
+  pub fn handshake(&self) -> Result<RustlsHandshake> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let root_cert = self.root_cert.clone().or_else(|| default_file("root.crt"));
+    let verify = match self.mode {
+      SslMode::VerifyCa | SslMode::VerifyFull => true,
+      SslMode::Require => root_cert.is_some(),
+      _ => false
+    };
+    let roots = if verify {
+      let mut roots = RootCertStore::empty();
+      match root_cert {
+        Some(ref p) => {
+          for cert in CertificateDer::pem_file_iter(p)? {
+            roots.add(cert?)?;
+          }
+        },
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+      }
+      let v = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build()?;
+      Some(v)
+    } else {
+      None
+    };
+    let verifier = PgVerifier {
+      roots,
+      check_name: self.mode == SslMode::VerifyFull,
+      provider: provider.clone()
+    };
+
+    let builder = ClientConfig::builder_with_provider(provider)
+      .with_safe_default_protocol_versions()?
+      .dangerous()
+      .with_custom_certificate_verifier(Arc::new(verifier));
+    let cert = self.cert.clone().or_else(|| default_file("postgresql.crt"));
+    let config = match cert {
+      Some(ref cp) => {
+        let kp = self.key.clone().or_else(|| default_file("postgresql.key"))
+          .ok_or(anyhow!("client certificate {:?} has no key", cp))?;
+        let certs = CertificateDer::pem_file_iter(cp)?.collect::<std::result::Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(&kp)?;
+        builder.with_client_auth_cert(certs, key)?
+      },
+      None => builder.with_no_client_auth()
+    };
+    Ok(RustlsHandshake { config: Arc::new(config) })
+  }
+}
+
+/// TLS handshake for the `postgres` crate, using rustls.
+#[derive(Debug)]
+pub struct RustlsHandshake {
+  config: Arc<ClientConfig>
+}
+
+/// A TLS stream over a PostgreSQL socket.
+struct RustlsStream(StreamOwned<ClientConnection, Stream>);
+
+impl fmt::Debug for RustlsStream {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_tuple("RustlsStream").field(&self.0.sock).finish()
+  }
+}
+
+impl Read for RustlsStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.0.read(buf)
+  }
+}
+
+impl Write for RustlsStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
+
+impl TlsStream for RustlsStream {
+  fn get_ref(&self) -> &Stream {
+    self.0.get_ref()
+  }
+
+  fn get_mut(&mut self) -> &mut Stream {
+    self.0.get_mut()
+  }
+}
+
+impl TlsHandshake for RustlsHandshake {
+  fn tls_handshake(&self, host: &str, mut stream: Stream) -> std::result::Result<Box<dyn TlsStream>, Box<dyn StdError + Sync + Send>> {
+    let name = ServerName::try_from(host.to_string())?;
+    let mut conn = ClientConnection::new(self.config.clone(), name)?;
+    while conn.is_handshaking() {
+      conn.complete_io(&mut stream)?;
+    }
+    Ok(Box::new(RustlsStream(StreamOwned::new(conn, stream))))
+  }
+}
+
+#[test]
+fn test_parse_sslmode() {
+  assert_eq!("disable".parse::<SslMode>().unwrap(), SslMode::Disable);
+  assert_eq!("verify-full".parse::<SslMode>().unwrap(), SslMode::VerifyFull);
+  assert!("sometimes".parse::<SslMode>().is_err());
+}
+
+#[test]
+fn test_handshake_config() {
+  let cfg = TlsConfig { mode: SslMode::Require, ..TlsConfig::default() };
+  assert!(cfg.handshake().is_ok());
+  let cfg = TlsConfig { mode: SslMode::VerifyFull, ..TlsConfig::default() };
+  assert!(cfg.handshake().is_ok());
+  let cfg = TlsConfig { mode: SslMode::VerifyCa, root_cert: Some("/nonexistent/root.crt".into()), ..TlsConfig::default() };
+  assert!(cfg.handshake().is_err());
+}
@@ -0,0 +1,87 @@
+/// Normalize an Amazon ASIN (or ISBN-10 used as an ASIN).  This strips
+/// whitespace and hyphens and upper-cases the result; it returns `None` if
+/// what is left is not a 10-character alphanumeric code or a 13-digit ISBN.
+///
+/// ```
+/// use bookdata::ids::normalize_asin;
+/// assert_eq!(normalize_asin(" 0-8044-2957-x"), Some("080442957X".to_string()));
+/// ```
+pub fn normalize_asin(asin: &str) -> Option<String> {
+  let mut norm = String::with_capacity(asin.len());
+  for c in asin.chars() {
+    match c {
+      '-' => (),
+      c if c.is_whitespace() => (),
+      c if c.is_ascii_alphanumeric() => norm.push(c.to_ascii_uppercase()),
+      _ => return None
+    }
+  }
+  let ok = match norm.len() {
+    10 => true,
+    13 => norm.bytes().all(|b| b.is_ascii_digit()),
+    _ => false
+  };
+  if ok {
+    Some(norm)
+  } else {
+    None
+  }
+}
+
+/// Check whether a normalized 10-character string is an ISBN-10 with a valid
+/// check digit.
+pub fn isbn10_valid(isbn: &str) -> bool {
+  let bytes = isbn.as_bytes();
+  if bytes.len() != 10 {
+    return false;
+  }
+  let mut sum = 0;
+  for (i, b) in bytes.iter().enumerate() {
+    let d = match b {
+      b'0'..=b'9' => (b - b'0') as u32,
+      b'X' if i == 9 => 10,
+      _ => return false
+    };
+    sum += d * (10 - i as u32);
+  }
+  sum % 11 == 0
+}
+
+#[test]
+fn test_norm_plain() {
+  assert_eq!(normalize_asin("0000000116"), Some("0000000116".to_string()));
+}
+
+#[test]
+fn test_norm_asin() {
+  assert_eq!(normalize_asin("b000fa5m6i"), Some("B000FA5M6I".to_string()));
+}
+
+#[test]
+fn test_norm_hyphens() {
+  assert_eq!(normalize_asin("0-8044-2957-x"), Some("080442957X".to_string()));
+}
+
+#[test]
+fn test_norm_isbn13() {
+  assert_eq!(normalize_asin("978-0-345-33968-3"), Some("9780345339683".to_string()));
+}
+
+#[test]
+fn test_norm_bad_length() {
+  assert_eq!(normalize_asin("12345"), None);
+  assert_eq!(normalize_asin("97803453396AB"), None);
+}
+
+#[test]
+fn test_norm_bad_chars() {
+  assert_eq!(normalize_asin("034533968*"), None);
+}
+
+#[test]
+fn test_isbn10_valid() {
+  assert!(isbn10_valid("080442957X"));
+  assert!(isbn10_valid("0306406152"));
+  assert!(!isbn10_valid("0306406153"));
+  assert!(!isbn10_valid("B000FA5M6I"));
+}
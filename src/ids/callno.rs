@@ -0,0 +1,196 @@
+/// A call number's classification scheme.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallScheme {
+  /// Library of Congress Classification
+  Lcc,
+  /// Dewey Decimal Classification
+  Ddc
+}
+
+impl CallScheme {
+  pub fn name(&self) -> &'static str {
+    match self {
+      CallScheme::Lcc => "lcc",
+      CallScheme::Ddc => "ddc"
+    }
+  }
+}
+
+/// A parsed call number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallNumber {
+  pub scheme: CallScheme,
+  /// The main class: an LCC class letter (`Q`), or a Dewey class (`800`)
+  pub class: String,
+  /// The subclass: the LCC class letters (`QA`), or a Dewey division (`810`)
+  pub subclass: String,
+  /// A key that sorts call numbers of the same scheme in shelf order
+  pub sort_key: String
+}
+
+/// Split leading ASCII digits off a string.
+fn split_digits(s: &str) -> (&str, &str) {
+  let n = s.bytes().take_while(|b| b.is_ascii_digit()).count();
+  s.split_at(n)
+}
+
+/// Normalize the cutters and dates after a class number: `.` before a cutter
+/// is dropped, and the parts are separated by single spaces.
+fn normalize_tail(tail: &str) -> String {
+  let mut parts = Vec::new();
+  for word in tail.split_whitespace() {
+    for part in word.split('.').filter(|p| !p.is_empty()) {
+      parts.push(part.to_uppercase());
+    }
+  }
+  parts.join(" ")
+}
+
+/// Parse a Library of Congress call number, such as `QA76.73.C153 2008`.
+///
+/// ```
+/// use bookdata::ids::parse_call_number;
+/// let cn = parse_call_number("QA76.73.C153 2008").unwrap();
+/// assert_eq!(cn.subclass, "QA");
+/// assert_eq!(cn.sort_key, "QA 0076.73 C153 2008");
+/// ```
+pub fn parse_lcc(text: &str) -> Option<CallNumber> {
+  let text = text.trim();
+  let nl = text.bytes().take_while(|b| b.is_ascii_alphabetic()).count();
+  if nl == 0 || nl > 3 {
+    return None;
+  }
+  let letters = text[..nl].to_uppercase();
+  let (int, rest) = split_digits(text[nl..].trim_start());
+  if int.is_empty() || int.len() > 4 {
+    return None;
+  }
+  let (dec, tail) = match rest.strip_prefix('.') {
+    Some(r) if r.starts_with(|c: char| c.is_ascii_digit()) => split_digits(r),
+    _ => ("", rest)
+  };
+  let mut sort_key = format!("{:<3}{:0>4}", letters, int);
+  if !dec.is_empty() {
+    sort_key.push('.');
+    sort_key.push_str(dec);
+  }
+  let tail = normalize_tail(tail);
+  if !tail.is_empty() {
+    sort_key.push(' ');
+    sort_key.push_str(&tail);
+  }
+  Some(CallNumber {
+    scheme: CallScheme::Lcc,
+    class: letters[..1].to_string(),
+    subclass: letters,
+    sort_key
+  })
+}
+
+/// Parse a Dewey Decimal call number, such as `813/.54 H`.  Segmentation marks
+/// (`/` and `'`) are ignored.
+///
+/// ```
+/// use bookdata::ids::parse_call_number;
+/// let cn = parse_call_number("813/.54 H").unwrap();
+/// assert_eq!((cn.class.as_str(), cn.subclass.as_str()), ("800", "810"));
+/// assert_eq!(cn.sort_key, "813.54 H");
+/// ```
+pub fn parse_ddc(text: &str) -> Option<CallNumber> {
+  let text: String = text.trim().chars().filter(|c| *c != '/' && *c != '\'').collect();
+  let (int, rest) = split_digits(&text);
+  if int.len() != 3 {
+    return None;
+  }
+  let (dec, tail) = match rest.strip_prefix('.') {
+    Some(r) => split_digits(r),
+    None => ("", rest)
+  };
+  if tail.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+    return None;
+  }
+  let mut sort_key = int.to_string();
+  if !dec.is_empty() {
+    sort_key.push('.');
+    sort_key.push_str(dec);
+  }
+  let tail = normalize_tail(tail);
+  if !tail.is_empty() {
+    sort_key.push(' ');
+    sort_key.push_str(&tail);
+  }
+  Some(CallNumber {
+    scheme: CallScheme::Ddc,
+    class: format!("{}00", &int[..1]),
+    subclass: format!("{}0", &int[..2]),
+    sort_key
+  })
+}
+
+/// Parse an LCC or Dewey call number, telling them apart by their first
+/// character.
+pub fn parse_call_number(text: &str) -> Option<CallNumber> {
+  match text.trim_start().chars().next() {
+    Some(c) if c.is_ascii_digit() => parse_ddc(text),
+    Some(_) => parse_lcc(text),
+    None => None
+  }
+}
+
+#[test]
+fn test_lcc() {
+  let cn = parse_lcc("QA76.73.C153 2008").unwrap();
+  assert_eq!(cn.scheme, CallScheme::Lcc);
+  assert_eq!(cn.class, "Q");
+  assert_eq!(cn.subclass, "QA");
+  assert_eq!(cn.sort_key, "QA 0076.73 C153 2008");
+  assert_eq!(parse_lcc("PS3545.H16 A6 1990").unwrap().sort_key, "PS 3545 H16 A6 1990");
+  assert_eq!(parse_lcc("  kf 9 .B3").unwrap().sort_key, "KF 0009 B3");
+  assert_eq!(parse_lcc("E184.A1").unwrap().class, "E");
+}
+
+#[test]
+fn test_lcc_sorts() {
+  let keys: Vec<String> = ["QA9 .B3", "QA76 X", "QA76.5 .A1", "QA76.73.C153", "QB1"].iter()
+    .map(|c| parse_lcc(c).unwrap().sort_key).collect();
+  let mut sorted = keys.clone();
+  sorted.sort();
+  assert_eq!(keys, sorted);
+}
+
+#[test]
+fn test_lcc_invalid() {
+  assert_eq!(parse_lcc(""), None);
+  assert_eq!(parse_lcc("QA"), None);
+  assert_eq!(parse_lcc("ABCD12"), None);
+  assert_eq!(parse_lcc("QA12345"), None);
+  assert_eq!(parse_lcc("813.54"), None);
+  assert_eq!(parse_lcc("Fiction 12"), None);
+}
+
+#[test]
+fn test_ddc() {
+  let cn = parse_ddc("813.54 H").unwrap();
+  assert_eq!(cn.scheme, CallScheme::Ddc);
+  assert_eq!(cn.class, "800");
+  assert_eq!(cn.subclass, "810");
+  assert_eq!(cn.sort_key, "813.54 H");
+  assert_eq!(parse_ddc("005.133'2 s").unwrap().sort_key, "005.1332 S");
+  assert_eq!(parse_ddc("940").unwrap().sort_key, "940");
+}
+
+#[test]
+fn test_ddc_invalid() {
+  assert_eq!(parse_ddc(""), None);
+  assert_eq!(parse_ddc("81.5"), None);
+  assert_eq!(parse_ddc("8135"), None);
+  assert_eq!(parse_ddc("813.5.4"), None);
+  assert_eq!(parse_ddc("QA76"), None);
+}
+
+#[test]
+fn test_call_number() {
+  assert_eq!(parse_call_number("QA76.73").unwrap().scheme, CallScheme::Lcc);
+  assert_eq!(parse_call_number(" 813.54").unwrap().scheme, CallScheme::Ddc);
+  assert_eq!(parse_call_number("   "), None);
+}
@@ -0,0 +1,38 @@
+/// Normalize an ISBN.  This takes the first word (dropping qualifiers such as
+/// `(pbk.)`), strips hyphens, and upper-cases the check digit; it returns
+/// `None` if what is left is not a 10- or 13-digit ISBN.  Check digits are
+/// not verified.
+///
+/// ```
+/// use bookdata::ids::normalize_isbn;
+/// assert_eq!(normalize_isbn("0-8044-2957-x (pbk.)"), Some("080442957X".to_string()));
+/// assert_eq!(normalize_isbn("978-0-441-01359-3"), Some("9780441013593".to_string()));
+/// ```
+pub fn normalize_isbn(isbn: &str) -> Option<String> {
+  let isbn = isbn.split_whitespace().next()?;
+  let isbn: String = isbn.chars().filter(|c| *c != '-').collect::<String>().to_uppercase();
+  let n = isbn.len();
+  let ok = (n == 10 || n == 13) && isbn.char_indices().all(|(i, c)| c.is_ascii_digit() || (c == 'X' && n == 10 && i == 9));
+  Some(isbn).filter(|_| ok)
+}
+
+/// Check whether a normalized 13-digit string is an ISBN-13 with a valid
+/// check digit.
+pub fn isbn13_valid(isbn: &str) -> bool {
+  let bytes = isbn.as_bytes();
+  if bytes.len() != 13 || !bytes.iter().all(u8::is_ascii_digit) {
+    return false;
+  }
+  let sum: u32 = bytes.iter().enumerate().map(|(i, b)| (b - b'0') as u32 * if i % 2 == 0 { 1 } else { 3 }).sum();
+  sum.is_multiple_of(10)
+}
+
+#[test]
+fn test_isbn() {
+  assert_eq!(normalize_isbn("0123456789"), Some("0123456789".to_string()));
+  assert_eq!(normalize_isbn("012345X789"), None);
+  assert_eq!(normalize_isbn("12345"), None);
+  assert_eq!(normalize_isbn(""), None);
+  assert!(isbn13_valid("9780441013593"));
+  assert!(!isbn13_valid("9780441013594"));
+}
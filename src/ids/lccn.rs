@@ -0,0 +1,63 @@
+/// Normalize a Library of Congress Control Number, following the Library of
+/// Congress's normalization rules: blanks are removed, a `/` and everything
+/// after it (such as a revision) is dropped, and a hyphen is removed with the
+/// serial number after it left-padded with zeros to six digits.  Prefix letters
+/// are lower-cased.  It returns `None` if the result is not a valid LCCN: up to
+/// three letters followed by an 8- or 10-digit number.
+///
+/// ```
+/// use bookdata::ids::normalize_lccn;
+/// assert_eq!(normalize_lccn("n78-89035"), Some("n78089035".to_string()));
+/// assert_eq!(normalize_lccn(" 79139101 /AC/r932"), Some("79139101".to_string()));
+/// ```
+pub fn normalize_lccn(lccn: &str) -> Option<String> {
+  let mut norm: String = lccn.chars().filter(|c| !c.is_whitespace()).collect();
+  if let Some(i) = norm.find('/') {
+    norm.truncate(i);
+  }
+  if let Some(i) = norm.find('-') {
+    let serial = &norm[i+1..];
+    if serial.is_empty() || serial.len() > 6 || !serial.bytes().all(|b| b.is_ascii_digit()) {
+      return None;
+    }
+    norm = format!("{}{:0>6}", &norm[..i], serial);
+  }
+  norm.make_ascii_lowercase();
+  let np = norm.bytes().take_while(|b| b.is_ascii_alphabetic()).count();
+  let num = &norm[np..];
+  if np <= 3 && (num.len() == 8 || num.len() == 10) && num.bytes().all(|b| b.is_ascii_digit()) {
+    Some(norm)
+  } else {
+    None
+  }
+}
+
+#[test]
+fn test_lccn_loc_examples() {
+  // the examples from the Library of Congress's normalization rules
+  assert_eq!(normalize_lccn("n78-890351").as_deref(), Some("n78890351"));
+  assert_eq!(normalize_lccn("n78-89035").as_deref(), Some("n78089035"));
+  assert_eq!(normalize_lccn("n 78890351 ").as_deref(), Some("n78890351"));
+  assert_eq!(normalize_lccn(" 85000002 ").as_deref(), Some("85000002"));
+  assert_eq!(normalize_lccn("85-2 ").as_deref(), Some("85000002"));
+  assert_eq!(normalize_lccn("2001-000002").as_deref(), Some("2001000002"));
+  assert_eq!(normalize_lccn("75-425165//r75").as_deref(), Some("75425165"));
+  assert_eq!(normalize_lccn(" 79139101 /AC/r932").as_deref(), Some("79139101"));
+}
+
+#[test]
+fn test_lccn_marc_prefix() {
+  assert_eq!(normalize_lccn("   NO2004012345 ").as_deref(), Some("no2004012345"));
+  assert_eq!(normalize_lccn("sn 85-1234").as_deref(), Some("sn85001234"));
+}
+
+#[test]
+fn test_lccn_invalid() {
+  assert_eq!(normalize_lccn(""), None);
+  assert_eq!(normalize_lccn("85-"), None);
+  assert_eq!(normalize_lccn("85-1234567"), None);
+  assert_eq!(normalize_lccn("85-12a4"), None);
+  assert_eq!(normalize_lccn("8500002"), None);
+  assert_eq!(normalize_lccn("abcd85000002"), None);
+  assert_eq!(normalize_lccn("85000002x"), None);
+}
@@ -0,0 +1,11 @@
+mod asin;
+mod isbn;
+mod lccn;
+mod oclc;
+mod callno;
+
+pub use self::asin::{normalize_asin, isbn10_valid};
+pub use self::isbn::{normalize_isbn, isbn13_valid};
+pub use self::lccn::normalize_lccn;
+pub use self::oclc::normalize_oclc;
+pub use self::callno::parse_call_number;
@@ -0,0 +1,74 @@
+/// Normalize an OCLC number, as found in MARC 035 fields and OpenLibrary's
+/// `oclc_numbers`.  This strips the `(OCoLC)` source prefix, the `ocm`, `ocn`,
+/// and `on` prefixes OCLC uses for numbers of different lengths, and leading
+/// zeros.  It returns `None` for identifiers from other sources (such as
+/// `(DLC)`) and for anything that is not a positive number.
+///
+/// ```
+/// use bookdata::ids::normalize_oclc;
+/// assert_eq!(normalize_oclc("(OCoLC)ocm00012345"), Some(12345));
+/// assert_eq!(normalize_oclc("(DLC)   85000002"), None);
+/// ```
+pub fn normalize_oclc(oclc: &str) -> Option<u64> {
+  let mut rest = oclc.trim();
+  if rest.starts_with('(') {
+    let end = rest.find(')')?;
+    let src = &rest[1..end];
+    if !src.eq_ignore_ascii_case("OCoLC") && !src.eq_ignore_ascii_case("OCLC") {
+      return None;
+    }
+    rest = rest[end+1..].trim_start();
+  }
+  for pfx in &["ocm", "ocn", "on"] {
+    match rest.get(..pfx.len()) {
+      Some(p) if p.eq_ignore_ascii_case(pfx) => {
+        rest = &rest[pfx.len()..];
+        break;
+      },
+      _ => ()
+    }
+  }
+  let rest = rest.trim();
+  if rest.is_empty() || !rest.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  match rest.trim_start_matches('0').parse() {
+    Ok(n) if n > 0 => Some(n),
+    _ => None
+  }
+}
+
+#[test]
+fn test_oclc_plain() {
+  assert_eq!(normalize_oclc("12345"), Some(12345));
+  assert_eq!(normalize_oclc(" 0012345 "), Some(12345));
+}
+
+#[test]
+fn test_oclc_marc() {
+  assert_eq!(normalize_oclc("(OCoLC)12345"), Some(12345));
+  assert_eq!(normalize_oclc("(OCoLC)ocm12345678"), Some(12345678));
+  assert_eq!(normalize_oclc("(OCoLC)ocn123456789"), Some(123456789));
+  assert_eq!(normalize_oclc("(OCoLC)on1234567890"), Some(1234567890));
+  assert_eq!(normalize_oclc("(ocolc) 00054321"), Some(54321));
+  assert_eq!(normalize_oclc("(OCLC)ocm54321"), Some(54321));
+}
+
+#[test]
+fn test_oclc_openlibrary() {
+  assert_eq!(normalize_oclc("ocm12345678"), Some(12345678));
+  assert_eq!(normalize_oclc("OCN123456789"), Some(123456789));
+}
+
+#[test]
+fn test_oclc_invalid() {
+  assert_eq!(normalize_oclc(""), None);
+  assert_eq!(normalize_oclc("(OCoLC)"), None);
+  assert_eq!(normalize_oclc("(DLC)85000002"), None);
+  assert_eq!(normalize_oclc("(OCoLC"), None);
+  assert_eq!(normalize_oclc("ocm"), None);
+  assert_eq!(normalize_oclc("0000"), None);
+  assert_eq!(normalize_oclc("12345a"), None);
+  assert_eq!(normalize_oclc("oé12"), None);
+  assert_eq!(normalize_oclc("(OCoLC)12345 (ocl7)"), None);
+}
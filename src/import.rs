@@ -0,0 +1,1027 @@
+//! Import delimited and JSON records described by a TOML spec.
+//!
+//! An [ImportSpec] describes the columns of an input file, the JSON fields to
+//! extract from it, and the tables its records go to.  The `import-json`
+//! command runs these imports, and other programs can run them into any
+//! [RowSink]:
+//!
+//! ```
+//! use bookdata::cleaning::SanitizeOpts;
+//! use bookdata::import::{ImportSpec, OnError, Rejects, SubsetOpts};
+//! use bookdata::sink::PGSink;
+//!
+//! let spec: ImportSpec = r#"
+//! schema = "gr"
+//! table = "raw_book"
+//! columns = ["gr_book_data"]
+//! "#.parse()?;
+//! let mut sink = PGSink::new(Vec::new());
+//! let mut rejects = Rejects::new(OnError::Fail, None)?;
+//! let src = b"{\"book_id\": \"5333265\"}\n";
+//! let n = spec.import(&mut &src[..], &mut sink, &SanitizeOpts::default(), &SubsetOpts::default(), &mut rejects)?;
+//! assert_eq!(n, 1);
+//! assert_eq!(String::from_utf8(sink.out)?, "{\"book_id\": \"5333265\"}\n");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Records can also be routed to several tables by type, with
+//! [ImportSpec::targets] and [ImportSpec::import_routed].
+use std::borrow::Cow;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::fs::File;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::*;
+
+use structopt::StructOpt;
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+
+use crate::cleaning::*;
+use crate::tsv::FieldParser;
+use crate::ids::{normalize_isbn, normalize_asin, normalize_oclc};
+use crate::columnar::ColType;
+use crate::sink::RowSink;
+
+/// How each field of a delimited record is imported.
+#[derive(Deserialize, Debug)]
+pub enum ColOp {
+  /// Skip the field
+  #[serde(rename="_")]
+  Skip,
+  /// Store the field as text
+  #[serde(rename="str")]
+  String,
+  /// Clean, validate, and store the field as JSON
+  #[serde(rename="json")]
+  JSON,
+  /// Extract the spec's fields from a JSON column without storing the JSON
+  #[serde(rename="fields")]
+  Fields
+}
+
+/// Import specification read from TOML
+#[derive(Deserialize, Debug)]
+pub struct ImportSpec {
+  /// The database schema of the tables
+  pub schema: String,
+  /// The table for the records, unless they are split by type
+  #[serde(default)]
+  pub table: String,
+  /// The table's stored columns
+  #[serde(default)]
+  pub columns: Vec<String>,
+  #[serde(default)]
+  format: Vec<ColOp>,
+  /// The input field with each record's type, for splitting
+  #[serde(default)]
+  type_field: usize,
+  /// The tables to split records into, by type
+  #[serde(default)]
+  split: Vec<SplitTable>,
+  /// Fields to extract from the JSON into their own columns
+  #[serde(default)]
+  fields: Vec<FieldSpec>,
+  /// The input field with each record's key
+  #[serde(default="default_key_field")]
+  key_field: usize,
+  /// The dotted path of each record's key in the JSON, for records whose key
+  /// is not a separate field
+  #[serde(default)]
+  key_path: Option<String>,
+  /// Table for redirect records' keys and targets
+  #[serde(default)]
+  redirects: Option<String>,
+  /// Table for deleted records' keys
+  #[serde(default)]
+  deletes: Option<String>,
+  /// Link tables to extract from the records
+  #[serde(default)]
+  links: Vec<LinkSpec>,
+  /// Only import records that pass this filter
+  #[serde(default)]
+  filter: Option<FilterSpec>
+}
+
+impl FromStr for ImportSpec {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<ImportSpec> {
+    Ok(toml::from_str(s)?)
+  }
+}
+
+/// A filter on records' JSON: a record is kept if the value at `path` is one
+/// of `values`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FilterSpec {
+  path: String,
+  values: Vec<String>
+}
+
+impl FilterSpec {
+  fn keep(&self, json: &str) -> Result<bool> {
+    Ok(match extract_fields(json, &[&self.path])?.pop().flatten() {
+      Some(v) => self.values.contains(&v),
+      None => false
+    })
+  }
+}
+
+/// Normalizations for extracted link values.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Normalize {
+  /// ISBNs, as normalized by [`normalize_isbn`]
+  #[serde(rename="isbn")]
+  Isbn,
+  /// ASINs, as normalized by [`normalize_asin`]
+  #[serde(rename="asin")]
+  Asin,
+  /// OCLC numbers, without their prefixes and leading zeros
+  #[serde(rename="oclc")]
+  Oclc,
+  /// Lowercase DOIs, without a resolver URL or `doi:` prefix
+  #[serde(rename="doi")]
+  Doi
+}
+
+impl Normalize {
+  /// Normalize a value, returning `None` if it is not valid.
+  pub fn apply(&self, val: &str) -> Option<String> {
+    match self {
+      Normalize::Isbn => normalize_isbn(val),
+      Normalize::Asin => normalize_asin(val),
+      Normalize::Oclc => normalize_oclc(val).map(|n| n.to_string()),
+      Normalize::Doi => {
+        let doi = val.trim().to_lowercase();
+        let doi = ["https://doi.org/", "http://dx.doi.org/", "doi:"].iter()
+          .fold(doi.as_str(), |d, p| d.strip_prefix(p).unwrap_or(d)).to_string();
+        Some(doi).filter(|d| d.starts_with("10."))
+      }
+    }
+  }
+}
+
+/// A link table extracted from an array in each record.  Each link row has the
+/// record's key, the linked value, and (if there is a third column) the value's
+/// position in the array.  Several link specs can fill the same table.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LinkSpec {
+  /// Only extract links from records of this type
+  #[serde(rename="type", default)]
+  rec_type: Option<String>,
+  table: String,
+  columns: Vec<String>,
+  /// The dotted path of the array (or single value) in the JSON
+  path: String,
+  /// The dotted path of the value within each array element
+  #[serde(default)]
+  element: Option<String>,
+  #[serde(default)]
+  normalize: Option<Normalize>
+}
+
+fn default_key_field() -> usize {
+  1
+}
+
+/// What a routed table receives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetKind {
+  /// The records themselves
+  Records,
+  /// The keys and targets of redirect records
+  Redirects,
+  /// The keys of deleted records
+  Deletes,
+  /// Links extracted from the records
+  Links
+}
+
+/// A table that records are routed to by type.
+#[derive(Debug, Clone)]
+pub struct Target {
+  /// The record type, or `None` for all types without their own table
+  pub rec_type: Option<String>,
+  /// What the table receives
+  pub kind: TargetKind,
+  /// The table name, without its schema
+  pub table: String,
+  /// The stored columns
+  pub columns: Vec<String>
+}
+
+/// How an extracted field is written.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum FieldType {
+  /// Strings as text, and other values as JSON text
+  #[serde(rename="text")]
+  #[default]
+  Text,
+  /// The values of an array, as a PostgreSQL `text[]`
+  #[serde(rename="array")]
+  Array,
+  /// The value as JSON, for a `jsonb` column
+  #[serde(rename="json")]
+  Json
+}
+
+/// A JSON field to extract into a column.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FieldSpec {
+  column: String,
+  /// The field's dotted path in the JSON object
+  path: String,
+  #[serde(rename="type", default)]
+  kind: FieldType,
+  /// For arrays, the dotted path of the value within each element
+  #[serde(default)]
+  element: Option<String>
+}
+
+impl FieldSpec {
+  /// The Parquet type of the field's column.  Arrays are stored as JSON lists.
+  fn col_type(&self) -> ColType {
+    match self.kind {
+      FieldType::Text => ColType::Text,
+      FieldType::Array | FieldType::Json => ColType::Json
+    }
+  }
+}
+
+/// The destination table for one record type.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SplitTable {
+  #[serde(rename="type")]
+  rec_type: String,
+  table: String,
+  columns: Vec<String>
+}
+
+/// The OpenLibrary type for records that redirect to another record.
+const REDIRECT_TYPE: &str = "/type/redirect";
+/// The OpenLibrary type for deleted records.
+const DELETE_TYPE: &str = "/type/delete";
+
+/// Read a line as raw bytes, without its terminator.  Returns `false` at EOF.
+fn read_raw_line<R: BufRead>(src: &mut R, buf: &mut Vec<u8>) -> io::Result<bool> {
+  buf.clear();
+  if src.read_until(b'\n', buf)? == 0 {
+    return Ok(false);
+  }
+  if buf.ends_with(b"\n") {
+    buf.pop();
+    if buf.ends_with(b"\r") {
+      buf.pop();
+    }
+  }
+  Ok(true)
+}
+
+/// How to handle bad input records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnError {
+  /// Skip the record quietly
+  Skip,
+  /// Skip the record with a warning
+  Log,
+  /// Stop the import
+  Fail
+}
+
+impl FromStr for OnError {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<OnError> {
+    match s {
+      "skip" => Ok(OnError::Skip),
+      "log" => Ok(OnError::Log),
+      "fail" => Ok(OnError::Fail),
+      _ => Err(anyhow!("unknown error policy {}", s))
+    }
+  }
+}
+
+/// A sample of every Kth input record, written `1/K`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample(usize);
+
+impl FromStr for Sample {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Sample> {
+    let k = s.strip_prefix("1/").ok_or(anyhow!("sample {} is not of the form 1/K", s))?;
+    let k: usize = k.parse()?;
+    if k == 0 {
+      return Err(anyhow!("sample 1/0 is empty"));
+    }
+    Ok(Sample(k))
+  }
+}
+
+/// Options to import only part of the input, for small test extracts.
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct SubsetOpts {
+  /// Stop after importing N records from each input
+  #[structopt(long="limit")]
+  pub limit: Option<usize>,
+
+  /// Import only every Kth input record (written 1/K)
+  #[structopt(long="sample")]
+  pub sample: Option<Sample>
+}
+
+impl SubsetOpts {
+  /// Should the record on line `lno` (starting from 1) be read?
+  fn keep(&self, lno: usize) -> bool {
+    match self.sample {
+      Some(Sample(k)) => (lno - 1).is_multiple_of(k),
+      None => true
+    }
+  }
+
+  /// Have enough records been imported?
+  fn done(&self, kept: usize) -> bool {
+    self.limit.map(|l| kept >= l).unwrap_or(false)
+  }
+}
+
+/// Records rejected because they cannot be decoded or their JSON is invalid,
+/// with an optional file to save them in.
+pub struct Rejects {
+  policy: OnError,
+  max: Option<usize>,
+  out: Option<BufWriter<File>>,
+  count: usize
+}
+
+impl Rejects {
+  /// Set up rejects with an error policy, saving them to `path` if it is given.
+  pub fn new(policy: OnError, path: Option<&PathBuf>) -> Result<Rejects> {
+    let out = match path {
+      Some(p) => {
+        info!("writing rejected records to {:?}", p);
+        Some(BufWriter::new(File::create(p)?))
+      },
+      None => None
+    };
+    Ok(Rejects { policy, max: None, out, count: 0 })
+  }
+
+  /// Fail once more than `max` records have been rejected.
+  pub fn with_max(self, max: Option<usize>) -> Rejects {
+    Rejects { max, ..self }
+  }
+
+  /// Reject a record.  The rejects file has the line number, the error, and
+  /// the original line, separated by tabs.
+  fn reject(&mut self, line: usize, raw: &[u8], err: &anyhow::Error) -> Result<()> {
+    match self.policy {
+      OnError::Fail => return Err(anyhow!("line {}: {}", line, err)),
+      OnError::Log => warn!("line {}: {}", line, err),
+      OnError::Skip => debug!("line {}: {}", line, err)
+    }
+    self.count += 1;
+    if let Some(ref mut out) = self.out {
+      write!(out, "{}\t{}\t", line, err)?;
+      out.write_all(raw)?;
+      out.write_all(b"\n")?;
+    }
+    match self.max {
+      Some(m) if self.count > m => Err(anyhow!("more than {} bad records, stopping", m)),
+      _ => Ok(())
+    }
+  }
+
+  /// Finish rejecting records, returning the number rejected.
+  pub fn finish(self) -> Result<usize> {
+    if let Some(mut out) = self.out {
+      out.flush()?;
+    }
+    if self.count > 0 {
+      warn!("skipped {} bad records", self.count);
+    }
+    Ok(self.count)
+  }
+}
+
+impl ImportSpec {
+  /// Does the spec route records to further tables (redirects, deletes, or
+  /// links), so that it must be imported with [import_routed](ImportSpec::import_routed)?
+  pub fn is_routed(&self) -> bool {
+    self.redirects.is_some() || self.deletes.is_some() || !self.links.is_empty()
+  }
+
+  /// Does the spec have tables to split records into by type?
+  pub fn has_split(&self) -> bool {
+    !self.split.is_empty()
+  }
+
+  /// Get the output columns and their Parquet types, given the spec's stored
+  /// columns.  Extracted fields follow the JSON column they come from.
+  pub fn out_columns<'a>(&'a self, columns: &'a [String]) -> Result<Vec<(&'a str, ColType)>> {
+    let fields = self.fields.iter().map(|f| (f.column.as_str(), f.col_type()));
+    let mut out = Vec::new();
+    let mut cols = columns.iter().map(String::as_str);
+    let mut next = |n: usize| cols.next().ok_or(anyhow!("spec has too few columns for format (needed {})", n));
+    if self.format.is_empty() {
+      out.push((next(1)?, ColType::Json));
+      out.extend(fields);
+    } else {
+      for op in &self.format {
+        match op {
+          ColOp::Skip => (),
+          ColOp::String => out.push((next(out.len() + 1)?, ColType::Text)),
+          ColOp::JSON => {
+            out.push((next(out.len() + 1)?, ColType::Json));
+            out.extend(fields.clone());
+          },
+          ColOp::Fields => out.extend(fields.clone())
+        }
+      }
+    }
+    Ok(out)
+  }
+
+  /// Split a delimited record into its fields.  The last field takes the rest
+  /// of the line, so a stray tab in the JSON column stays with the JSON.
+  fn split_fields<'a>(&self, line: &'a str) -> Result<Vec<Cow<'a, str>>> {
+    let parser = FieldParser::default().max_fields(self.format.len());
+    Ok(parser.split(line)?.into_iter().flatten().collect())
+  }
+
+  /// Clean and validate a record's JSON values into `jsbufs`, one per JSON
+  /// column, returning the record's fields.  Plain JSON records are one field.
+  fn prepare_row<'a>(&self, line: &'a str, jsbufs: &mut Vec<String>) -> Result<Vec<Cow<'a, str>>> {
+    let mut k = 0;
+    let mut prepare = |json: &str| {
+      if jsbufs.len() <= k {
+        jsbufs.push(String::new());
+      }
+      clean_json(json, &mut jsbufs[k]);
+      k += 1;
+      validate_json(&mut jsbufs[k - 1])
+    };
+    if self.format.is_empty() {
+      prepare(line)?;
+      return Ok(vec![Cow::Borrowed(line)]);
+    }
+    let flds = self.split_fields(line)?;
+    if flds.len() < self.format.len() {
+      return Err(anyhow!("expected {} fields, found {}", self.format.len(), flds.len()));
+    }
+    for (fld, fc) in flds.iter().zip(&self.format) {
+      if let ColOp::JSON | ColOp::Fields = fc {
+        prepare(fld)?;
+      }
+    }
+    Ok(flds)
+  }
+
+  /// Write a cleaned JSON value if requested, and then its extracted fields.
+  fn write_json<W: RowSink>(&self, json: &str, store: bool, dst: &mut W) -> Result<()> {
+    if store {
+      dst.write_field(json.as_bytes())?;
+    }
+    for fld in &self.fields {
+      match fld.kind {
+        FieldType::Text => match extract_fields(json, &[&fld.path])?.pop().flatten() {
+          Some(v) => dst.write_field(v.as_bytes())?,
+          None => dst.write_null()?
+        },
+        FieldType::Array => dst.write_array(&extract_list(json, &fld.path, fld.element.as_deref())?)?,
+        FieldType::Json => match extract_value(json, &fld.path)? {
+          Some(v) => dst.write_json(&v)?,
+          None => dst.write_null()?
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Write a prepared record from its fields.
+  fn write_row<W: RowSink>(&self, fields: &[Cow<str>], jsbufs: &[String], dst: &mut W) -> Result<()> {
+    if self.format.is_empty() {
+      self.write_json(&jsbufs[0], true, dst)?;
+    } else {
+      let mut js = jsbufs.iter();
+      for (fld, fc) in fields.iter().zip(&self.format) {
+        match fc {
+          ColOp::Skip => (),
+          ColOp::String => dst.write_field(fld.as_bytes())?,
+          ColOp::JSON => self.write_json(js.next().unwrap(), true, dst)?,
+          ColOp::Fields => self.write_json(js.next().unwrap(), false, dst)?
+        }
+      }
+    }
+    dst.end_row()
+  }
+
+  /// Read, sanitize, and prepare each input record, passing the fields of valid
+  /// records to `proc` and invalid records to the rejects.  Lines outside the subset are
+  /// skipped, and reading stops once the subset's limit is reached.
+  fn each_record<R, F>(&self, src: &mut R, san: &SanitizeOpts, sub: &SubsetOpts, rejects: &mut Rejects, mut proc: F) -> Result<()>
+  where R: BufRead, F: FnMut(&[Cow<str>], &[String]) -> Result<()>
+  {
+    let mut raw = Vec::new();
+    let mut line = String::new();
+    let mut jsbufs = Vec::new();
+    let mut lno = 0;
+    let mut kept = 0;
+    let mut filtered = 0;
+    while read_raw_line(src, &mut raw)? {
+      lno += 1;
+      if !sub.keep(lno) {
+        continue;
+      }
+      let res = san.sanitize(&raw, &mut line).and_then(|_| self.prepare_row(&line, &mut jsbufs));
+      match res {
+        Ok(fields) => {
+          if let (Some(f), Some(js)) = (&self.filter, jsbufs.first()) {
+            if !f.keep(js)? {
+              filtered += 1;
+              continue;
+            }
+          }
+          proc(&fields, &jsbufs)?;
+          kept += 1;
+        },
+        Err(e) => rejects.reject(lno, &raw, &e)?
+      }
+      if sub.done(kept) {
+        info!("stopping after {} records", kept);
+        break;
+      }
+    }
+    if filtered > 0 {
+      info!("filtered out {} records", filtered);
+    }
+    info!("processed {} lines", lno);
+    Ok(())
+  }
+
+  /// Import records into a sink, returning the number of records written.
+  pub fn import<R: BufRead, W: RowSink>(&self, src: &mut R, dst: &mut W, san: &SanitizeOpts, sub: &SubsetOpts, rejects: &mut Rejects) -> Result<usize> {
+    let mut n = 0;
+    self.each_record(src, san, sub, rejects, |fields, jsbufs| {
+      self.write_row(fields, jsbufs, dst)?;
+      n += 1;
+      Ok(())
+    })?;
+    Ok(n)
+  }
+
+  /// Get the tables to route records to.  With `split`, records go to the
+  /// spec's split tables by type; otherwise they go to its table.  Redirect
+  /// and delete records go to their own tables if the spec has them.
+  pub fn targets(&self, split: bool) -> Vec<Target> {
+    let mut targets = Vec::new();
+    if split {
+      for st in &self.split {
+        targets.push(Target {
+          rec_type: Some(st.rec_type.clone()),
+          kind: TargetKind::Records,
+          table: st.table.clone(),
+          columns: st.columns.clone()
+        });
+      }
+    } else {
+      targets.push(Target {
+        rec_type: None,
+        kind: TargetKind::Records,
+        table: self.table.clone(),
+        columns: self.columns.clone()
+      });
+    }
+    if let Some(ref t) = self.redirects {
+      targets.push(Target {
+        rec_type: Some(REDIRECT_TYPE.to_string()),
+        kind: TargetKind::Redirects,
+        table: t.clone(),
+        columns: vec!["redirect_key".to_string(), "target_key".to_string()]
+      });
+    }
+    if let Some(ref t) = self.deletes {
+      targets.push(Target {
+        rec_type: Some(DELETE_TYPE.to_string()),
+        kind: TargetKind::Deletes,
+        table: t.clone(),
+        columns: vec!["deleted_key".to_string()]
+      });
+    }
+    for link in &self.links {
+      if !targets.iter().any(|t| t.kind == TargetKind::Links && t.table == link.table) {
+        targets.push(Target {
+          rec_type: None,
+          kind: TargetKind::Links,
+          table: link.table.clone(),
+          columns: link.columns.clone()
+        });
+      }
+    }
+    targets
+  }
+
+  /// Write a record's links to their tables.
+  fn write_links<W: RowSink>(&self, targets: &[Target], rec_type: &str, fields: &[Cow<str>], jsbufs: &[String], dsts: &mut [W], counts: &mut [usize]) -> Result<()> {
+    let json = match jsbufs.first() {
+      Some(js) => js,
+      None => return Ok(())
+    };
+    let key = match self.key_path {
+      Some(ref kp) => extract_fields(json, &[kp])?.pop().flatten().ok_or(anyhow!("record has no {}", kp))?,
+      None => fields.get(self.key_field).ok_or(anyhow!("record has no key field"))?.to_string()
+    };
+    for link in &self.links {
+      if link.rec_type.as_ref().map(|t| t != rec_type).unwrap_or(false) {
+        continue;
+      }
+      let i = targets.iter().position(|t| t.kind == TargetKind::Links && t.table == link.table)
+        .ok_or(anyhow!("no target for link table {}", link.table))?;
+      let vals = extract_values(json, &link.path, link.element.as_deref())?;
+      for (pos, val) in vals.iter().enumerate() {
+        let val = match link.normalize {
+          Some(n) => match n.apply(val) {
+            Some(v) => v,
+            None => continue
+          },
+          None => val.clone()
+        };
+        dsts[i].write_field(key.as_bytes())?;
+        dsts[i].write_field(val.as_bytes())?;
+        if link.columns.len() > 2 {
+          dsts[i].write_field(pos.to_string().as_bytes())?;
+        }
+        dsts[i].end_row()?;
+        counts[i] += 1;
+      }
+    }
+    Ok(())
+  }
+
+  /// Get a target's output columns.
+  pub fn target_columns<'a>(&'a self, target: &'a Target) -> Result<Vec<(&'a str, ColType)>> {
+    match target.kind {
+      TargetKind::Records => self.out_columns(&target.columns),
+      _ => Ok(target.columns.iter().map(|c| (c.as_str(), ColType::Text)).collect())
+    }
+  }
+
+  /// Write a prepared record to a target.  Redirects are written as their key
+  /// and the JSON's `location`, and deletes as their key.
+  fn write_target<W: RowSink>(&self, target: &Target, fields: &[Cow<str>], jsbufs: &[String], dst: &mut W) -> Result<()> {
+    if target.kind == TargetKind::Records {
+      return self.write_row(fields, jsbufs, dst);
+    }
+    let key = fields.get(self.key_field).ok_or(anyhow!("record has no key field"))?;
+    dst.write_field(key.as_bytes())?;
+    if target.kind == TargetKind::Redirects {
+      let loc = match jsbufs.first() {
+        Some(js) => extract_fields(js, &["location"])?.pop().flatten(),
+        None => None
+      };
+      match loc {
+        Some(l) => dst.write_field(l.as_bytes())?,
+        None => dst.write_null()?
+      }
+    }
+    dst.end_row()
+  }
+
+  /// Import delimited records, routing each to the sink for its target.
+  /// Returns the number of records written to each sink, and the number
+  /// skipped because no target takes their type.
+  pub fn import_routed<R: BufRead, W: RowSink>(&self, src: &mut R, targets: &[Target], dsts: &mut [W], san: &SanitizeOpts, sub: &SubsetOpts, rejects: &mut Rejects) -> Result<(Vec<usize>, usize)> {
+    if self.format.is_empty() && targets.iter().any(|t| t.rec_type.is_some()) {
+      return Err(anyhow!("routing records by type requires a delimited format"));
+    }
+    let mut counts = vec![0; dsts.len()];
+    let mut skipped = 0;
+    self.each_record(src, san, sub, rejects, |fields, jsbufs| {
+      let rt = match self.format.is_empty() {
+        true => "",
+        false => fields.get(self.type_field).map(|f| f.as_ref()).unwrap_or("")
+      };
+      let records = || targets.iter().enumerate().filter(|(_, t)| t.kind != TargetKind::Links);
+      let pos = records().find(|(_, t)| t.rec_type.as_deref() == Some(rt))
+        .or_else(|| records().find(|(_, t)| t.rec_type.is_none()))
+        .map(|(i, _)| i);
+      match pos {
+        Some(i) => {
+          self.write_target(&targets[i], fields, jsbufs, &mut dsts[i])?;
+          counts[i] += 1;
+          if targets[i].kind == TargetKind::Records {
+            self.write_links(targets, rt, fields, jsbufs, dsts, &mut counts)?;
+          }
+        },
+        None => skipped += 1
+      }
+      Ok(())
+    })?;
+    Ok((counts, skipped))
+  }
+}
+
+#[cfg(test)]
+use std::fs::read_to_string;
+#[cfg(test)]
+use crate::sink::PGSink;
+#[test]
+fn test_delim_spec() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "gr"
+table = "raw_book"
+columns = ["id", "data"]
+format = ["_", "str", "json"]
+"#).unwrap();
+  assert_eq!(spec.out_columns(&spec.columns).unwrap(), vec![("id", ColType::Text), ("data", ColType::Json)]);
+  let mut sink = PGSink::new(Vec::new());
+  let n = spec.import(&mut &b"x\t5\t{\"a\": 1}\n"[..], &mut sink, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(n, 1);
+  assert_eq!(String::from_utf8(sink.out).unwrap(), "5\t{\"a\": 1}\n");
+}
+
+#[test]
+fn test_split_spec() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+format = ["_", "str", "_", "_", "json"]
+
+[[split]]
+type = "/type/author"
+table = "author"
+columns = ["author_key", "author_data"]
+
+[[split]]
+type = "/type/work"
+table = "work"
+columns = ["work_key", "work_data"]
+"#).unwrap();
+  let src = b"/type/work\t/works/W1\t1\t2020\t{}\n/type/redirect\t/works/W2\t1\t2020\t{}\n/type/author\t/authors/A1\t1\t2020\t{}\n/type/work\t/works/W3\t1\t2020\t{}\n";
+  let mut sinks = vec![PGSink::new(Vec::new()), PGSink::new(Vec::new())];
+  let (counts, skipped) = spec.import_routed(&mut &src[..], &spec.targets(true), &mut sinks, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(counts, vec![1, 2]);
+  assert_eq!(skipped, 1);
+  assert_eq!(String::from_utf8(sinks[0].out.clone()).unwrap(), "/authors/A1\t{}\n");
+  assert_eq!(String::from_utf8(sinks[1].out.clone()).unwrap(), "/works/W1\t{}\n/works/W3\t{}\n");
+}
+
+#[test]
+fn test_field_projection() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "edition"
+columns = ["edition_key"]
+format = ["_", "str", "_", "_", "fields"]
+
+[[fields]]
+column = "title"
+path = "title"
+
+[[fields]]
+column = "isbn13"
+path = "isbn_13"
+"#).unwrap();
+  assert_eq!(spec.out_columns(&spec.columns).unwrap(), vec![
+    ("edition_key", ColType::Text), ("title", ColType::Text), ("isbn13", ColType::Text)
+  ]);
+  let mut sink = PGSink::new(Vec::new());
+  let src = b"/type/edition\t/books/M1\t1\t2020\t{\"title\": \"Dune\", \"isbn_13\": [\"9780441013593\"]}\n/type/edition\t/books/M2\t1\t2020\t{}\n";
+  spec.import(&mut &src[..], &mut sink, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(String::from_utf8(sink.out).unwrap(),
+             "/books/M1\tDune\t[\"9780441013593\"]\n/books/M2\t\\N\t\\N\n");
+}
+
+#[test]
+fn test_field_types() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "work"
+columns = ["work_key"]
+format = ["_", "str", "_", "_", "fields"]
+
+[[fields]]
+column = "subjects"
+path = "subjects"
+type = "array"
+
+[[fields]]
+column = "author_keys"
+path = "authors"
+element = "author.key"
+type = "array"
+
+[[fields]]
+column = "created"
+path = "created"
+type = "json"
+"#).unwrap();
+  assert_eq!(spec.out_columns(&spec.columns).unwrap(), vec![
+    ("work_key", ColType::Text), ("subjects", ColType::Json), ("author_keys", ColType::Json), ("created", ColType::Json)
+  ]);
+  let mut sink = PGSink::new(Vec::new());
+  let src = concat!(
+    "/type/work\t/works/W1\t1\t2020\t",
+    r#"{"subjects": ["Science fiction", "Dune (Imaginary place)", "\"Quoted\""], "authors": [{"author": {"key": "/authors/A1"}}], "created": {"value": "2009-10-15"}}"#,
+    "\n/type/work\t/works/W2\t1\t2020\t{}\n");
+  spec.import(&mut src.as_bytes(), &mut sink, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(String::from_utf8(sink.out).unwrap(), concat!(
+    r#"/works/W1	{"Science fiction","Dune (Imaginary place)","\\"Quoted\\""}	{"/authors/A1"}	{"value":"2009-10-15"}"#,
+    "\n/works/W2\t{}\t{}\t\\N\n"));
+}
+
+#[test]
+fn test_reject_invalid() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "work"
+columns = ["work_key", "work_data"]
+format = ["_", "str", "_", "_", "json"]
+"#).unwrap();
+  let src = b"/type/work\t/works/W1\t1\t2020\t{\"title\": \"bad \\q\"}\n/type/work\t/works/W2\t1\t2020\t{\"title\": \n";
+  let mut sink = PGSink::new(Vec::new());
+  let mut rejects = Rejects::new(OnError::Log, None).unwrap();
+  let n = spec.import(&mut &src[..], &mut sink, &SanitizeOpts::default(), &SubsetOpts::default(), &mut rejects).unwrap();
+  assert_eq!(n, 1);
+  assert_eq!(rejects.finish().unwrap(), 1);
+  assert_eq!(String::from_utf8(sink.out).unwrap(), "/works/W1\t{\"title\": \"bad \\\\\\\\q\"}\n");
+}
+
+#[test]
+fn test_error_policy() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "work"
+columns = ["work_key", "work_data"]
+format = ["_", "str", "_", "_", "json"]
+"#).unwrap();
+  let src = b"/type/work\t/works/W1\t1\t2020\t{}\n/type/work\t/works/W2\n/type/work\t/works/W3\t1\t2020\t{\n";
+  let san = SanitizeOpts::default();
+
+  let mut rejects = Rejects::new(OnError::Skip, None).unwrap();
+  let n = spec.import(&mut &src[..], &mut PGSink::new(Vec::new()), &san, &SubsetOpts::default(), &mut rejects).unwrap();
+  assert_eq!(n, 1);
+  assert_eq!(rejects.finish().unwrap(), 2);
+
+  let mut rejects = Rejects::new(OnError::Fail, None).unwrap();
+  assert!(spec.import(&mut &src[..], &mut PGSink::new(Vec::new()), &san, &SubsetOpts::default(), &mut rejects).is_err());
+
+  let mut rejects = Rejects::new(OnError::Log, None).unwrap().with_max(Some(1));
+  assert!(spec.import(&mut &src[..], &mut PGSink::new(Vec::new()), &san, &SubsetOpts::default(), &mut rejects).is_err());
+}
+
+#[test]
+fn test_redirects_deletes() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "work"
+columns = ["work_key", "work_data"]
+format = ["_", "str", "_", "_", "json"]
+redirects = "redirect"
+deletes = "deleted"
+"#).unwrap();
+  let targets = spec.targets(false);
+  assert_eq!(targets.len(), 3);
+  assert_eq!(spec.target_columns(&targets[1]).unwrap(), vec![("redirect_key", ColType::Text), ("target_key", ColType::Text)]);
+  let src = b"/type/work\t/works/W1\t1\t2020\t{}\n/type/redirect\t/works/W2\t2\t2020\t{\"location\": \"/works/W1\"}\n/type/delete\t/works/W3\t2\t2020\t{}\n";
+  let mut sinks = vec![PGSink::new(Vec::new()), PGSink::new(Vec::new()), PGSink::new(Vec::new())];
+  let (counts, skipped) = spec.import_routed(&mut &src[..], &targets, &mut sinks, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(counts, vec![1, 1, 1]);
+  assert_eq!(skipped, 0);
+  assert_eq!(String::from_utf8(sinks[0].out.clone()).unwrap(), "/works/W1\t{}\n");
+  assert_eq!(String::from_utf8(sinks[1].out.clone()).unwrap(), "/works/W2\t/works/W1\n");
+  assert_eq!(String::from_utf8(sinks[2].out.clone()).unwrap(), "/works/W3\n");
+}
+
+#[test]
+fn test_links() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "edition"
+columns = ["edition_key", "edition_data"]
+format = ["_", "str", "_", "_", "json"]
+
+[[links]]
+table = "edition_author_keys"
+columns = ["edition_key", "author_key", "author_pos"]
+path = "authors"
+element = "key"
+
+[[links]]
+table = "edition_isbn_keys"
+columns = ["edition_key", "isbn"]
+path = "isbn_10"
+normalize = "isbn"
+
+[[links]]
+table = "edition_isbn_keys"
+columns = ["edition_key", "isbn"]
+path = "identifiers.amazon"
+normalize = "asin"
+
+[[links]]
+table = "edition_oclc_numbers"
+columns = ["edition_key", "oclc_number"]
+path = "oclc_numbers"
+normalize = "oclc"
+"#).unwrap();
+  let targets = spec.targets(false);
+  assert_eq!(targets.len(), 4);
+  assert_eq!(targets[2].kind, TargetKind::Links);
+  let src = br#"/type/edition	/books/E1	1	2020	{"authors": [{"key": "/authors/A1"}, {"key": "/authors/A2"}], "isbn_10": ["0-394-51788-X", "bad"], "identifiers": {"amazon": ["b000abc123"]}, "oclc_numbers": ["ocm00012345", "n/a"]}
+"#;
+  let mut sinks: Vec<_> = (0..4).map(|_| PGSink::new(Vec::new())).collect();
+  let (counts, _) = spec.import_routed(&mut &src[..], &targets, &mut sinks, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(counts, vec![1, 2, 2, 1]);
+  assert_eq!(String::from_utf8(sinks[1].out.clone()).unwrap(), "/books/E1\t/authors/A1\t0\n/books/E1\t/authors/A2\t1\n");
+  assert_eq!(String::from_utf8(sinks[2].out.clone()).unwrap(), "/books/E1\t039451788X\n/books/E1\tB000ABC123\n");
+  assert_eq!(String::from_utf8(sinks[3].out.clone()).unwrap(), "/books/E1\t12345\n");
+}
+
+#[test]
+fn test_json_links() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "gr"
+table = "raw_book"
+columns = ["gr_book_data"]
+key_path = "book_id"
+
+[[links]]
+table = "book_work_link"
+columns = ["gr_book_id", "gr_work_id"]
+path = "work_id"
+
+[[links]]
+table = "book_isbn_link"
+columns = ["gr_book_id", "isbn"]
+path = "isbn"
+normalize = "isbn"
+
+[[links]]
+table = "book_isbn_link"
+columns = ["gr_book_id", "isbn"]
+path = "isbn13"
+normalize = "isbn"
+
+[[links]]
+table = "book_author_link"
+columns = ["gr_book_id", "gr_author_id", "author_pos"]
+path = "authors"
+element = "author_id"
+"#).unwrap();
+  let targets = spec.targets(false);
+  assert_eq!(targets.len(), 4);
+  let src = br#"{"book_id": "5333265", "work_id": "5400751", "isbn": "0312853122", "isbn13": "", "authors": [{"author_id": "604031", "role": ""}]}
+{"book_id": "1333909", "work_id": "", "isbn": "", "isbn13": "9780743509008", "authors": []}
+"#;
+  let mut sinks: Vec<_> = (0..4).map(|_| PGSink::new(Vec::new())).collect();
+  let (counts, _) = spec.import_routed(&mut &src[..], &targets, &mut sinks, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(counts, vec![2, 1, 2, 1]);
+  assert_eq!(String::from_utf8(sinks[1].out.clone()).unwrap(), "5333265\t5400751\n");
+  assert_eq!(String::from_utf8(sinks[2].out.clone()).unwrap(), "5333265\t0312853122\n1333909\t9780743509008\n");
+  assert_eq!(String::from_utf8(sinks[3].out.clone()).unwrap(), "5333265\t604031\t0\n");
+}
+
+#[test]
+fn test_filter() {
+  let spec: ImportSpec = toml::from_str(&read_to_string("import/crossref-works.toml").unwrap()).unwrap();
+  let targets = spec.targets(false);
+  assert_eq!(targets.len(), 2);
+  let src = br#"{"DOI": "10.1007/978-3-642-00001-1", "type": "book", "title": ["Modern Algebra"], "author": [{"given": "Ada", "family": "Lovelace"}], "ISBN": ["978-3-642-00001-1", "3642000011"]}
+{"DOI": "10.1000/j.1", "type": "journal-article", "title": ["A Paper"], "ISBN": ["9780000000002"]}
+"#;
+  let mut sinks: Vec<_> = (0..2).map(|_| PGSink::new(Vec::new())).collect();
+  let (counts, _) = spec.import_routed(&mut &src[..], &targets, &mut sinks, &SanitizeOpts::default(), &SubsetOpts::default(), &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+  assert_eq!(counts, vec![1, 2]);
+  let work = String::from_utf8(sinks[0].out.clone()).unwrap();
+  assert!(work.ends_with("\t10.1007/978-3-642-00001-1\tbook\t{\"Modern Algebra\"}\t[{\"family\":\"Lovelace\",\"given\":\"Ada\"}]\t\\N\n"), "{}", work);
+  assert_eq!(String::from_utf8(sinks[1].out.clone()).unwrap(), "10.1007/978-3-642-00001-1\t9783642000011\n10.1007/978-3-642-00001-1\t3642000011\n");
+  assert_eq!(Normalize::Doi.apply("https://doi.org/10.1007/ABC"), Some("10.1007/abc".to_string()));
+  assert_eq!(Normalize::Doi.apply("not a doi"), None);
+  assert_eq!(Normalize::Isbn.apply("0-8044-2957-x (pbk.)"), Some("080442957X".to_string()));
+  assert_eq!(Normalize::Isbn.apply("B000ABC123"), None);
+}
+
+#[test]
+fn test_subset() {
+  let spec: ImportSpec = toml::from_str(r#"
+schema = "ol"
+table = "work"
+columns = ["work_key", "work_data"]
+format = ["_", "str", "_", "_", "json"]
+"#).unwrap();
+  let src: String = (1..=10).map(|i| format!("/type/work\t/works/W{}\t1\t2020\t{{}}\n", i)).collect();
+  let import = |sub: &SubsetOpts| {
+    let mut sink = PGSink::new(Vec::new());
+    spec.import(&mut src.as_bytes(), &mut sink, &SanitizeOpts::default(), sub, &mut Rejects::new(OnError::Log, None).unwrap()).unwrap();
+    String::from_utf8(sink.out).unwrap()
+  };
+  let limited = import(&SubsetOpts { limit: Some(2), sample: None });
+  assert_eq!(limited, "/works/W1\t{}\n/works/W2\t{}\n");
+  let sampled = import(&SubsetOpts { limit: None, sample: Some("1/4".parse().unwrap()) });
+  assert_eq!(sampled, "/works/W1\t{}\n/works/W5\t{}\n/works/W9\t{}\n");
+  assert!("2/4".parse::<Sample>().is_err());
+  assert!("1/0".parse::<Sample>().is_err());
+}
@@ -0,0 +1,145 @@
+//! User-item interaction records for recommender experiments.
+//!
+//! Interactions are read from tab-separated files in the layout of the rating
+//! views (`user_id`, `book_id`, and optionally `rating` and `timestamp`), such
+//! as `\copy (SELECT user_id, book_id, rating, timestamp FROM gr.rating) TO ...`
+//! writes.  Missing ratings and timestamps are PostgreSQL NULLs (`\N`).
+//! Parquet files (named `*.parquet`) with the columns in the same order can be
+//! read too.
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use parquet::file::reader::SerializedFileReader;
+use parquet::record::{Field, Row};
+
+use crate::io::{open_input, decompress};
+use crate::tsv::write_row;
+
+/// A user's interaction with an item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interaction {
+  pub user: String,
+  pub item: String,
+  pub rating: Option<f64>,
+  pub timestamp: Option<f64>
+}
+
+/// Parse an optional numeric field.
+fn parse_num(field: Option<&str>) -> Result<Option<f64>> {
+  match field {
+    None | Some("\\N") | Some("") => Ok(None),
+    Some(f) => Ok(Some(f.parse().map_err(|_| anyhow!("invalid number {:?}", f))?))
+  }
+}
+
+impl Interaction {
+  /// Parse an interaction from a line of a tab-separated file.
+  pub fn parse(line: &str) -> Result<Interaction> {
+    let mut fields = line.split('\t');
+    let user = fields.next().filter(|f| !f.is_empty()).ok_or(anyhow!("no user"))?;
+    let item = fields.next().ok_or(anyhow!("no item"))?;
+    Ok(Interaction {
+      user: user.to_string(),
+      item: item.to_string(),
+      rating: parse_num(fields.next())?,
+      timestamp: parse_num(fields.next())?
+    })
+  }
+}
+
+/// Get a Parquet field's text.
+fn field_text(field: &Field) -> Option<String> {
+  match field {
+    Field::Null => None,
+    Field::Str(s) => Some(s.clone()),
+    f => Some(f.to_string())
+  }
+}
+
+/// Get a Parquet field's numeric value.  Timestamps are in seconds.
+fn field_num(field: Option<&Field>) -> Result<Option<f64>> {
+  Ok(match field {
+    None | Some(Field::Null) => None,
+    Some(Field::TimestampMillis(ms)) => Some(*ms as f64 / 1000.0),
+    Some(Field::TimestampMicros(us)) => Some(*us as f64 / 1e6),
+    Some(f) => parse_num(field_text(f).as_deref())?
+  })
+}
+
+impl Interaction {
+  /// Parse an interaction from a Parquet row.
+  fn from_row(row: &Row) -> Result<Interaction> {
+    let fields: Vec<&Field> = row.get_column_iter().map(|(_, f)| f).collect();
+    let text = |i: usize, name: &str| fields.get(i).and_then(|f| field_text(f)).ok_or_else(|| anyhow!("no {}", name));
+    Ok(Interaction {
+      user: text(0, "user")?,
+      item: text(1, "item")?,
+      rating: field_num(fields.get(2).copied())?,
+      timestamp: field_num(fields.get(3).copied())?
+    })
+  }
+
+  /// Write the interaction as a tab-separated line.
+  pub fn write_tsv<W: Write>(&self, out: &mut W) -> Result<()> {
+    write_row(out, &(&self.user, &self.item, self.rating, self.timestamp))
+  }
+}
+
+/// Read interactions from a file (or `-` for standard input), which may be
+/// compressed.
+pub fn read_interactions<P: AsRef<Path>>(path: P) -> Result<Box<dyn Iterator<Item=Result<Interaction>>>> {
+  let path = path.as_ref();
+  if path.extension().map(|e| e == "parquet").unwrap_or(false) {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    return Ok(Box::new(reader.into_iter().enumerate().map(|(i, row)| {
+      Interaction::from_row(&row?).map_err(|e| anyhow!("row {}: {}", i + 1, e))
+    })));
+  }
+  let (read, _len) = open_input(path)?;
+  let src = BufReader::new(decompress(BufReader::new(read))?);
+  Ok(Box::new(src.lines().enumerate().filter_map(|(i, line)| match line {
+    Ok(l) if l.is_empty() => None,
+    Ok(l) => Some(Interaction::parse(&l).map_err(|e| anyhow!("line {}: {}", i + 1, e))),
+    Err(e) => Some(Err(e.into()))
+  })))
+}
+
+#[test]
+fn test_parse() {
+  let i = Interaction::parse("17\t42\t3.5\t1500000000").unwrap();
+  assert_eq!(i, Interaction { user: "17".to_string(), item: "42".to_string(), rating: Some(3.5), timestamp: Some(1.5e9) });
+  let i = Interaction::parse("17\t42\t\\N").unwrap();
+  assert_eq!((i.rating, i.timestamp), (None, None));
+  assert!(Interaction::parse("17").is_err());
+  assert!(Interaction::parse("17\t42\tfive").is_err());
+}
+
+#[test]
+fn test_read_parquet() {
+  use crate::columnar::{TableWriter, ColType};
+  let path = std::env::temp_dir().join(format!("bookdata-interactions-{}.parquet", std::process::id()));
+  let cols = [("user_id", ColType::Text), ("book_id", ColType::Text), ("rating", ColType::Text), ("timestamp", ColType::Text)];
+  let mut w = TableWriter::create(File::create(&path).unwrap(), "rating", &cols).unwrap();
+  for (u, i, r) in &[("1", "10", Some("4")), ("2", "10", None)] {
+    w.write_field(u.as_bytes()).unwrap();
+    w.write_field(i.as_bytes()).unwrap();
+    match r {
+      Some(r) => w.write_field(r.as_bytes()).unwrap(),
+      None => w.write_null().unwrap()
+    }
+    w.write_field(b"1500000000").unwrap();
+    w.end_row().unwrap();
+  }
+  w.finish().unwrap();
+  let ints: Vec<Interaction> = read_interactions(&path).unwrap().map(Result::unwrap).collect();
+  std::fs::remove_file(&path).unwrap();
+  assert_eq!(ints.len(), 2);
+  assert_eq!(ints[0], Interaction { user: "1".to_string(), item: "10".to_string(), rating: Some(4.0), timestamp: Some(1.5e9) });
+  assert_eq!(ints[1].rating, None);
+  let mut out = Vec::new();
+  ints[1].write_tsv(&mut out).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(), "2\t10\t\\N\t1500000000\n");
+}
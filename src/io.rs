@@ -1,6 +1,15 @@
-use std::io;
+use std::io::{self, BufRead, Read};
+use std::env;
+use std::fs::File;
+use std::thread::Scope;
+use std::path::Path;
 use sha1::Sha1;
+use sha2::{Sha256, Digest};
+use md5::Md5;
+use structopt::StructOpt;
 
+use anyhow::{Result, anyhow};
+use crossbeam_channel::{bounded, Receiver};
 use log::*;
 
 /// Write wrapper that computes Sha1 checksums of the data written.
@@ -17,6 +26,11 @@ impl <'a, W: io::Write> HashWrite<'a, W> {
       hash: hash
     }
   }
+
+  /// Get the underlying writer back.
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
 }
 
 impl <'a, W: io::Write> io::Write for HashWrite<'a, W> {
@@ -30,15 +44,45 @@ impl <'a, W: io::Write> io::Write for HashWrite<'a, W> {
   }
 }
 
-/// Read wrapper that computes Sha1 checksums of the data read.
-pub struct HashRead<'a, R: io::Read> {
+/// A checksum that can be computed incrementally over a stream.
+pub trait Checksum {
+  fn update_bytes(&mut self, buf: &[u8]);
+}
+
+impl Checksum for Sha1 {
+  fn update_bytes(&mut self, buf: &[u8]) {
+    self.update(buf);
+  }
+}
+
+impl Checksum for Sha256 {
+  fn update_bytes(&mut self, buf: &[u8]) {
+    self.update(buf);
+  }
+}
+
+impl Checksum for Md5 {
+  fn update_bytes(&mut self, buf: &[u8]) {
+    self.update(buf);
+  }
+}
+
+impl <A: Checksum, B: Checksum> Checksum for (A, B) {
+  fn update_bytes(&mut self, buf: &[u8]) {
+    self.0.update_bytes(buf);
+    self.1.update_bytes(buf);
+  }
+}
+
+/// Read wrapper that computes checksums (Sha1 by default) of the data read.
+pub struct HashRead<'a, R: io::Read, H: Checksum = Sha1> {
   reader: R,
-  hash: &'a mut Sha1
+  hash: &'a mut H
 }
 
-impl <'a, R: io::Read> HashRead<'a, R> {
+impl <'a, R: io::Read, H: Checksum> HashRead<'a, R, H> {
   /// Create a hash reader
-  pub fn create(base: R, hash: &'a mut Sha1) -> HashRead<'a, R> {
+  pub fn create(base: R, hash: &'a mut H) -> HashRead<'a, R, H> {
     HashRead {
       reader: base,
       hash: hash
@@ -46,14 +90,26 @@ impl <'a, R: io::Read> HashRead<'a, R> {
   }
 }
 
-impl <'a, R: io::Read> io::Read for HashRead<'a, R> {
+impl <'a, R: io::Read, H: Checksum> io::Read for HashRead<'a, R, H> {
   fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
     let n = self.reader.read(buf)?;
-    self.hash.update(&buf[0..n]);
+    self.hash.update_bytes(&buf[0..n]);
     Ok(n)
   }
 }
 
+/// Get the hex digest of a SHA-256 hash.
+pub fn sha256_hex(hash: Sha256) -> String {
+  hash.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compute the SHA-256 checksum of a file.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+  let mut hash = Sha256::new();
+  io::copy(&mut HashRead::create(File::open(path)?, &mut hash), &mut io::sink())?;
+  Ok(sha256_hex(hash))
+}
+
 pub struct DelimPrinter<'a> {
   delim: &'a [u8],
   end: &'a [u8],
@@ -85,3 +141,347 @@ impl <'a> DelimPrinter<'a> {
     Ok(())
   }
 }
+
+/// Compression formats for input files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+  None,
+  Gzip,
+  Zip,
+  Xz,
+  Zstd
+}
+
+/// Detect a compression format from the first bytes of a file.
+pub fn detect_compression(magic: &[u8]) -> Compression {
+  if magic.starts_with(&[0x1f, 0x8b]) {
+    Compression::Gzip
+  } else if magic.starts_with(b"PK\x03\x04") {
+    Compression::Zip
+  } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+    Compression::Xz
+  } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+    Compression::Zstd
+  } else {
+    Compression::None
+  }
+}
+
+/// Read the first member of a zip stream.  This reads the member's local header
+/// and decompresses its data, so it does not need to seek to the archive's
+/// central directory.
+fn read_zip_member<'a, R: BufRead + Send + 'a>(mut src: R) -> Result<Box<dyn Read + Send + 'a>> {
+  let mut header = [0u8; 30];
+  src.read_exact(&mut header)?;
+  let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+  let flags = u16_at(6);
+  let method = u16_at(8);
+  let size = u32::from_le_bytes([header[18], header[19], header[20], header[21]]);
+  let skip = u16_at(26) as u64 + u16_at(28) as u64;
+  io::copy(&mut (&mut src).take(skip), &mut io::sink())?;
+  match method {
+    0 if flags & 0x08 != 0 => Err(anyhow!("cannot stream stored zip member without its size")),
+    0 => Ok(Box::new(src.take(size as u64))),
+    8 => Ok(Box::new(flate2::bufread::DeflateDecoder::new(src))),
+    m => Err(anyhow!("unsupported zip compression method {}", m))
+  }
+}
+
+/// Wrap a reader to decompress its data, detecting the compression format
+/// (gzip, zip, xz, zstd, or none) from its first bytes.  Only the first
+/// member of a zip file is read.
+pub fn decompress<'a, R: BufRead + Send + 'a>(mut src: R) -> Result<Box<dyn Read + Send + 'a>> {
+  let comp = detect_compression(src.fill_buf()?);
+  debug!("detected {:?} compression", comp);
+  Ok(match comp {
+    Compression::None => Box::new(src),
+    Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(src)),
+    Compression::Zip => read_zip_member(src)?,
+    Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(src)),
+    Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(src)?)
+  })
+}
+
+/// Reader for the regular files in a tar stream.  Each file's contents are
+/// read into memory, so this suits archives of many small files.
+pub struct TarEntries<R: Read> {
+  src: R
+}
+
+impl <R: Read> TarEntries<R> {
+  pub fn new(src: R) -> TarEntries<R> {
+    TarEntries { src }
+  }
+
+  /// Read the next regular file's name and contents, or `None` at the end of
+  /// the archive.
+  pub fn next_file(&mut self) -> Result<Option<(String, Vec<u8>)>> {
+    let mut header = [0u8; 512];
+    loop {
+      if let Err(e) = self.src.read_exact(&mut header) {
+        return match e.kind() {
+          io::ErrorKind::UnexpectedEof => Ok(None),
+          _ => Err(e.into())
+        };
+      }
+      if header.iter().all(|b| *b == 0) {
+        return Ok(None);
+      }
+      let text = |range: std::ops::Range<usize>| {
+        let f = &header[range];
+        let end = f.iter().position(|b| *b == 0).unwrap_or(f.len());
+        String::from_utf8_lossy(&f[..end]).into_owned()
+      };
+      let size = u64::from_str_radix(text(124..136).trim(), 8)
+        .map_err(|_| anyhow!("invalid tar entry size {:?}", text(124..136)))?;
+      let mut data = Vec::with_capacity(size as usize);
+      (&mut self.src).take(size).read_to_end(&mut data)?;
+      if (data.len() as u64) < size {
+        return Err(anyhow!("truncated tar entry"));
+      }
+      let pad = (512 - size % 512) % 512;
+      io::copy(&mut (&mut self.src).take(pad), &mut io::sink())?;
+      if header[156] == b'0' || header[156] == 0 {
+        let (prefix, name) = (text(345..500), text(0..100));
+        let name = match prefix.is_empty() {
+          true => name,
+          false => format!("{}/{}", prefix, name)
+        };
+        return Ok(Some((name, data)));
+      }
+    }
+  }
+}
+
+/// Reader for data read by a background thread (see `read_in_thread`).
+pub struct ThreadRead {
+  blocks: Receiver<io::Result<Vec<u8>>>,
+  block: Vec<u8>,
+  pos: usize
+}
+
+/// Read from a reader on a new thread in a scope, such as to decompress input
+/// while it is processed.  Blocks of up to `size` bytes are passed through a
+/// channel holding at most `depth` of them.  The thread stops at the end of the
+/// input, on an error (which is passed on to the reader), or when the returned
+/// reader is dropped.
+pub fn read_in_thread<'scope, 'env, R>(scope: &'scope Scope<'scope, 'env>, mut src: R, size: usize, depth: usize) -> ThreadRead
+where R: io::Read + Send + 'scope
+{
+  let (send, blocks) = bounded(depth);
+  scope.spawn(move || {
+    loop {
+      let mut block = vec![0; size];
+      let res = match src.read(&mut block) {
+        Ok(0) => break,
+        Ok(n) => {
+          block.truncate(n);
+          Ok(block)
+        },
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => Err(e)
+      };
+      let failed = res.is_err();
+      if send.send(res).is_err() || failed {
+        break;
+      }
+    }
+  });
+  ThreadRead { blocks, block: Vec::new(), pos: 0 }
+}
+
+impl io::Read for ThreadRead {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.pos >= self.block.len() {
+      match self.blocks.recv() {
+        Ok(block) => {
+          self.block = block?;
+          self.pos = 0;
+        },
+        // the thread is done
+        Err(_) => return Ok(0)
+      }
+    }
+    let n = buf.len().min(self.block.len() - self.pos);
+    buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+    self.pos += n;
+    Ok(n)
+  }
+}
+
+/// Options for I/O buffer sizes.  Larger buffers make fewer system calls,
+/// which matters most on network filesystems.
+#[derive(StructOpt, Debug, Clone)]
+pub struct BufferOpts {
+  /// Size of the input read buffers (with optional K or M suffix)
+  #[structopt(long="read-buffer", default_value="1M", parse(try_from_str="parse_size"))]
+  pub read: usize,
+
+  /// Size of the output write buffers (with optional K or M suffix)
+  #[structopt(long="write-buffer", default_value="1M", parse(try_from_str="parse_size"))]
+  pub write: usize
+}
+
+/// Parse a buffer size in bytes, with an optional K or M suffix.
+pub fn parse_size(s: &str) -> Result<usize> {
+  let (num, mult) = match s.chars().last() {
+    Some('K') | Some('k') => (&s[..s.len() - 1], 1 << 10),
+    Some('M') | Some('m') => (&s[..s.len() - 1], 1 << 20),
+    _ => (s, 1)
+  };
+  let n: usize = num.parse().map_err(|e| anyhow!("invalid size {}: {}", s, e))?;
+  if n == 0 {
+    return Err(anyhow!("buffer size must be positive"));
+  }
+  n.checked_mul(mult).ok_or_else(|| anyhow!("buffer size {} is too large", s))
+}
+
+/// Is this input path actually an HTTP(S) or S3 URL?
+pub fn is_url<P: AsRef<Path>>(path: P) -> bool {
+  match path.as_ref().to_str() {
+    Some(s) => s.starts_with("http://") || s.starts_with("https://") || s.starts_with("s3://"),
+    None => false
+  }
+}
+
+/// Get the HTTPS URL for an `s3://` URL.  Objects are fetched without signing,
+/// so they must be public; `AWS_ENDPOINT_URL` selects an alternate endpoint.
+fn s3_http_url(url: &str, endpoint: Option<&str>) -> Result<String> {
+  let rest = url.trim_start_matches("s3://");
+  let (bucket, key) = rest.split_once('/').ok_or(anyhow!("invalid S3 URL {}", url))?;
+  Ok(match endpoint {
+    Some(ep) => format!("{}/{}/{}", ep.trim_end_matches('/'), bucket, key),
+    None => format!("https://{}.s3.amazonaws.com/{}", bucket, key)
+  })
+}
+
+/// Open an input file or URL (or `-` for standard input), returning the reader and
+/// its length (if known).
+pub fn open_input<P: AsRef<Path>>(path: P) -> Result<(Box<dyn io::Read + Send>, Option<u64>)> {
+  let path = path.as_ref();
+  if path.as_os_str() == "-" {
+    return Ok((Box::new(io::stdin()), None));
+  }
+  if !is_url(path) {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    return Ok((Box::new(file), Some(len)));
+  }
+  let url = path.to_string_lossy();
+  let url = if url.starts_with("s3://") {
+    s3_http_url(&url, env::var("AWS_ENDPOINT_URL").ok().as_deref())?
+  } else {
+    url.to_string()
+  };
+  info!("streaming {}", url);
+  let resp = ureq::get(&url).call().map_err(|e| anyhow!("{}: {}", url, e))?;
+  let len = resp.header("Content-Length").and_then(|l| l.parse().ok());
+  Ok((Box::new(resp.into_reader()), len))
+}
+
+#[test]
+fn test_is_url() {
+  assert!(is_url("https://openlibrary.org/data/ol_dump_works_latest.txt.gz"));
+  assert!(is_url("s3://bookdata/ol_dump_works.txt.gz"));
+  assert!(!is_url("data/ol_dump_works.txt.gz"));
+}
+
+#[test]
+fn test_s3_url() {
+  assert_eq!(s3_http_url("s3://bookdata/ol/works.txt.gz", None).unwrap(),
+             "https://bookdata.s3.amazonaws.com/ol/works.txt.gz");
+  assert_eq!(s3_http_url("s3://bookdata/works.txt.gz", Some("http://localhost:9000/")).unwrap(),
+             "http://localhost:9000/bookdata/works.txt.gz");
+  assert!(s3_http_url("s3://bookdata", None).is_err());
+}
+
+#[test]
+fn test_sha256_hex() {
+  let mut h = Sha256::new();
+  h.update(b"abc");
+  assert_eq!(sha256_hex(h), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+}
+
+#[test]
+fn test_parse_size() {
+  assert_eq!(parse_size("4096").unwrap(), 4096);
+  assert_eq!(parse_size("64K").unwrap(), 65536);
+  assert_eq!(parse_size("2M").unwrap(), 2 << 20);
+  assert!(parse_size("0").is_err());
+  assert!(parse_size("lots").is_err());
+  assert!(parse_size(&format!("{}M", usize::MAX)).is_err());
+}
+
+#[test]
+fn test_read_in_thread() {
+  use std::io::Read;
+  let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+  let mut out = Vec::new();
+  std::thread::scope(|s| {
+    let mut r = read_in_thread(s, &data[..], 64, 2);
+    r.read_to_end(&mut out).unwrap();
+  });
+  assert_eq!(out, data);
+}
+
+#[test]
+fn test_decompress() {
+  use std::io::Write;
+  let text = b"hello\tworld\n".repeat(100);
+  let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+  gz.write_all(&text).unwrap();
+  let gz = gz.finish().unwrap();
+  let xz = {
+    let mut w = xz2::write::XzEncoder::new(Vec::new(), 6);
+    w.write_all(&text).unwrap();
+    w.finish().unwrap()
+  };
+  let zst = zstd::stream::encode_all(&text[..], 3).unwrap();
+  let zip = {
+    let mut w = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+    let opts = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    w.start_file("data.txt", opts).unwrap();
+    w.write_all(&text).unwrap();
+    w.finish().unwrap().into_inner()
+  };
+  // a deflated member with a data descriptor, as streaming zip writers make
+  let zip_deflate = {
+    let mut z = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    z.write_all(&text).unwrap();
+    let mut data = b"PK\x03\x04\x14\x00\x08\x00\x08\x00".to_vec();
+    data.extend_from_slice(&[0; 16]);
+    data.extend_from_slice(&[4, 0, 0, 0]);
+    data.extend_from_slice(b"a.gz");
+    data.extend_from_slice(&z.finish().unwrap());
+    data
+  };
+  for (name, data) in &[("plain", &text), ("gz", &gz), ("xz", &xz), ("zstd", &zst), ("zip", &zip), ("zip-deflate", &zip_deflate)] {
+    let mut out = Vec::new();
+    decompress(&data[..]).unwrap().read_to_end(&mut out).unwrap();
+    assert_eq!(&out, &text, "{}", name);
+  }
+  assert_eq!(detect_compression(&gz), Compression::Gzip);
+  assert_eq!(detect_compression(b"/type/work"), Compression::None);
+}
+
+#[test]
+fn test_tar_entries() {
+  fn header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+    let mut h = vec![0u8; 512];
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    h[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+    h[156] = kind;
+    h
+  }
+  let mut tar = header("cache/epub/1/", 0, b'5');
+  tar.extend(header("cache/epub/1/pg1.rdf", 5, b'0'));
+  tar.extend(b"hello");
+  tar.extend(vec![0; 507]);
+  tar.extend(vec![0; 1024]);
+  let mut entries = TarEntries::new(&tar[..]);
+  let (name, data) = entries.next_file().unwrap().unwrap();
+  assert_eq!(name, "cache/epub/1/pg1.rdf");
+  assert_eq!(data, b"hello");
+  assert!(entries.next_file().unwrap().is_none());
+  assert!(TarEntries::new(&tar[..1026]).next_file().is_err());
+}
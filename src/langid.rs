@@ -0,0 +1,105 @@
+//! Lightweight language identification for review and description text.
+//!
+//! Text in a non-Latin script is identified by its script.  Latin-script text
+//! is scored by how many of its words are common function words in each of
+//! the languages we know; this is crude, but reliable enough on paragraphs to
+//! separate the major languages of the GoodReads reviews.
+use std::cmp::Reverse;
+
+/// Common function words for the Latin-script languages we identify.
+const STOPWORDS: &[(&str, &[&str])] = &[
+  ("en", &["the", "and", "of", "to", "is", "it", "that", "this", "was", "with", "for", "but", "book", "not", "you"]),
+  ("es", &["el", "la", "de", "que", "y", "en", "los", "las", "un", "una", "es", "por", "con", "para", "pero"]),
+  ("fr", &["le", "la", "les", "de", "et", "est", "un", "une", "des", "du", "que", "pas", "pour", "dans", "je"]),
+  ("de", &["der", "die", "das", "und", "ist", "nicht", "ich", "ein", "eine", "zu", "sie", "mit", "den", "es", "auch"]),
+  ("it", &["il", "la", "di", "che", "e", "un", "una", "per", "non", "sono", "con", "del", "della", "ma", "è"]),
+  ("pt", &["o", "a", "de", "que", "e", "não", "um", "uma", "para", "com", "os", "do", "da", "mas", "é"]),
+  ("nl", &["de", "het", "een", "en", "van", "ik", "is", "niet", "dat", "op", "te", "zijn", "met", "maar", "voor"])
+];
+
+/// The minimum number of function words before Latin text is identified.
+const MIN_HITS: usize = 2;
+
+/// Identify the language of a script's characters, if it has only one.
+fn script_language(c: char) -> Option<&'static str> {
+  match c as u32 {
+    0x0370..=0x03FF => Some("el"),
+    0x0400..=0x04FF => Some("ru"),
+    0x0590..=0x05FF => Some("he"),
+    0x0600..=0x06FF => Some("ar"),
+    0x0900..=0x097F => Some("hi"),
+    0x0E00..=0x0E7F => Some("th"),
+    0x3040..=0x30FF => Some("ja"),
+    0xAC00..=0xD7AF => Some("ko"),
+    0x4E00..=0x9FFF => Some("zh"),
+    _ => None
+  }
+}
+
+/// Identify the language of a text, returning its ISO 639-1 code, or `None`
+/// if the text is too short or ambiguous to tell.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+  let mut scripts: Vec<(&'static str, usize)> = Vec::new();
+  let mut letters = 0;
+  for c in text.chars().filter(|c| c.is_alphabetic()) {
+    letters += 1;
+    if let Some(lang) = script_language(c) {
+      match scripts.iter_mut().find(|(l, _)| *l == lang) {
+        Some((_, n)) => *n += 1,
+        None => scripts.push((lang, 1))
+      }
+    }
+  }
+  if letters == 0 {
+    return None;
+  }
+  // Japanese mixes kana with Han characters, so any kana makes it Japanese
+  if scripts.iter().any(|(l, _)| *l == "ja") {
+    return Some("ja");
+  }
+  if let Some(&(lang, n)) = scripts.iter().max_by_key(|(_, n)| *n) {
+    if n * 2 > letters {
+      return Some(lang);
+    }
+  }
+
+  let mut scores = vec![0; STOPWORDS.len()];
+  for word in text.split(|c: char| !c.is_alphabetic()).filter(|w| !w.is_empty()) {
+    let word = word.to_lowercase();
+    for (i, (_, words)) in STOPWORDS.iter().enumerate() {
+      if words.contains(&word.as_str()) {
+        scores[i] += 1;
+      }
+    }
+  }
+  let mut ranked: Vec<(usize, &str)> = scores.iter().zip(STOPWORDS).map(|(s, (l, _))| (*s, *l)).collect();
+  ranked.sort_by_key(|r| Reverse(r.0));
+  match (ranked[0], ranked[1]) {
+    ((best, lang), (next, _)) if best >= MIN_HITS && best > next => Some(lang),
+    _ => None
+  }
+}
+
+#[test]
+fn test_detect_latin() {
+  assert_eq!(detect_language("This was the best book I have read this year, and I loved it."), Some("en"));
+  assert_eq!(detect_language("Me gustó mucho el libro, pero la historia es un poco lenta."), Some("es"));
+  assert_eq!(detect_language("Je n'ai pas aimé ce livre, mais les personnages sont bien écrits."), Some("fr"));
+  assert_eq!(detect_language("Das Buch ist gut, aber ich habe es nicht zu Ende gelesen."), Some("de"));
+  assert_eq!(detect_language("Een mooi boek, maar het einde is niet voor iedereen."), Some("nl"));
+}
+
+#[test]
+fn test_detect_script() {
+  assert_eq!(detect_language("Отличная книга, всем советую!"), Some("ru"));
+  assert_eq!(detect_language("とても面白い本でした。"), Some("ja"));
+  assert_eq!(detect_language("非常好的一本书"), Some("zh"));
+  assert_eq!(detect_language("정말 좋은 책입니다"), Some("ko"));
+}
+
+#[test]
+fn test_detect_unknown() {
+  assert_eq!(detect_language(""), None);
+  assert_eq!(detect_language("5 stars!!! 10/10"), None);
+  assert_eq!(detect_language("Wow"), None);
+}
@@ -0,0 +1,31 @@
+//! Library interface to the BookData import tools.
+//!
+//! The `bookdata` binary is built on these modules, and other Rust programs
+//! can use them to clean and write book data without shelling out to it:
+//!
+//! - [cleaning] encodes values for PostgreSQL and cleans JSON
+//! - [ids] and [names] normalize identifiers and author names
+//! - [sink] defines [RowSink](sink::RowSink), the destination importers
+//!   write rows to, with implementations for PostgreSQL `COPY` text and
+//!   binary, Parquet ([columnar]), and Arrow IPC streams ([arrow])
+//! - [tsv] writes `Serialize` rows to row sinks and parses delimited lines
+//! - [import] imports records described by a TOML spec into row sinks
+//! - [io] opens and decompresses input files
+//! - [interactions] reads user-item interactions
+//!
+//! With the `python` feature, the library also builds the `bookdata._native`
+//! Python extension module.
+pub mod cleaning;
+pub mod tsv;
+pub mod ids;
+pub mod names;
+pub mod langid;
+pub mod interactions;
+pub mod columnar;
+pub mod arrow;
+pub mod sink;
+pub mod io;
+pub mod import;
+
+#[cfg(feature = "python")]
+mod python;
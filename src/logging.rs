@@ -1,35 +1,182 @@
 use structopt::StructOpt;
-use indicatif::ProgressBar;
+use indicatif::{ProgressBar, ProgressBarRead, ProgressDrawTarget, ProgressStyle, HumanBytes};
+use console::Term;
 use log::*;
+use serde_json::json;
 
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::io::{self, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicPtr, AtomicBool, AtomicU64, Ordering};
 use std::ptr;
 use std::marker::PhantomData;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+
+use crate::date::civil_date;
 
 static LOG_PB: AtomicPtr<ProgressBar> = AtomicPtr::new(ptr::null_mut());
+/// Are we emitting JSON instead of drawing progress bars?
+static JSON_LOG: AtomicBool = AtomicBool::new(false);
+/// Are we logging plain progress lines instead of drawing progress bars?
+static PLAIN_PROGRESS: AtomicBool = AtomicBool::new(false);
+/// Bytes read and records processed, for progress events and lines
+static PROGRESS_BYTES: AtomicU64 = AtomicU64::new(0);
+static PROGRESS_RECORDS: AtomicU64 = AtomicU64::new(0);
+/// Total bytes to read, if known (0 if not)
+static PROGRESS_LEN: AtomicU64 = AtomicU64::new(0);
+/// Error and warning messages logged, for metrics
+static LOG_ERRORS: AtomicU64 = AtomicU64::new(0);
+static LOG_WARNINGS: AtomicU64 = AtomicU64::new(0);
 
 /// Progress bar logging context
 pub struct LogPBState<'a> {
   phantom: PhantomData<&'a str>
 }
 
+/// Shared output for JSON log records and progress events.
+type LogOut = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Log output formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+  /// Human-readable lines, printed around the progress bars
+  Text,
+  /// One JSON object per line, with periodic progress events instead of bars
+  Json
+}
+
+impl FromStr for LogFormat {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<LogFormat> {
+    match s {
+      "text" => Ok(LogFormat::Text),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(anyhow!("unknown log format {}", s))
+    }
+  }
+}
+
 struct LogEnv {
   level: LevelFilter,
-  progress: &'static AtomicPtr<ProgressBar>
+  progress: &'static AtomicPtr<ProgressBar>,
+  format: LogFormat,
+  out: LogOut,
+  file: Option<(LevelFilter, Mutex<LogFile>)>
+}
+
+/// A log file, rotated to numbered backups (`PATH.1` is the newest) when it is
+/// opened and when it grows past its maximum size.
+struct LogFile {
+  path: PathBuf,
+  keep: usize,
+  max_size: Option<u64>,
+  file: File,
+  size: u64
+}
+
+/// Rotate a log file to its numbered backups, keeping `keep` of them.
+fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+  let backup = |i: usize| {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+  };
+  if !path.exists() {
+    return Ok(());
+  }
+  if keep == 0 {
+    return fs::remove_file(path);
+  }
+  for i in (1..keep).rev() {
+    let src = backup(i);
+    if src.exists() {
+      fs::rename(&src, backup(i + 1))?;
+    }
+  }
+  fs::rename(path, backup(1))
+}
+
+impl LogFile {
+  fn open(path: &Path, keep: usize, max_size: Option<u64>) -> io::Result<LogFile> {
+    rotate(path, keep)?;
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    Ok(LogFile { path: path.to_path_buf(), keep, max_size, file, size: 0 })
+  }
+
+  fn write_line(&mut self, line: &str) -> io::Result<()> {
+    if let Some(max) = self.max_size {
+      if self.size > 0 && self.size + line.len() as u64 > max {
+        *self = LogFile::open(&self.path, self.keep, self.max_size)?;
+      }
+    }
+    writeln!(self.file, "{}", line)?;
+    self.size += line.len() as u64 + 1;
+    Ok(())
+  }
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with milliseconds.
+fn format_time(time: SystemTime) -> String {
+  let d = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let secs = d.as_secs();
+  let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+  let (year, month, day) = civil_date(days);
+  format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+          rem / 3600, rem % 3600 / 60, rem % 60, d.subsec_millis())
+}
+
+/// The current time as seconds since the epoch.
+fn timestamp() -> f64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+/// Write one JSON record to the log output.
+fn write_json(out: &LogOut, val: &serde_json::Value) {
+  if let Ok(mut out) = out.lock() {
+    // logging has nowhere to report its own errors
+    let _ = writeln!(out, "{}", val);
+    let _ = out.flush();
+  }
 }
 
 impl Log for LogEnv {
   fn enabled(&self, metadata: &Metadata) -> bool {
-    metadata.level() <= self.level
+    metadata.level() <= self.level || self.file.as_ref().map(|(l, _)| metadata.level() <= *l).unwrap_or(false)
   }
 
   fn log(&self, record: &Record) {
+    match record.level() {
+      Level::Error => LOG_ERRORS.fetch_add(1, Ordering::Relaxed),
+      Level::Warn => LOG_WARNINGS.fetch_add(1, Ordering::Relaxed),
+      _ => 0
+    };
+    if let Some((level, ref file)) = self.file {
+      if record.level() <= level {
+        let line = format!("{} {:>5} {}: {}", format_time(SystemTime::now()), record.level(), record.target(), record.args());
+        if let Ok(mut file) = file.lock() {
+          // logging has nowhere to report its own errors
+          let _ = file.write_line(&line);
+        }
+      }
+    }
     let pass = record.level() <= self.level;
-    if pass {
+    if pass && self.format == LogFormat::Json {
+      write_json(&self.out, &json!({
+        "ts": timestamp(),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "msg": record.args().to_string()
+      }));
+    } else if pass {
       let pb_ptr = self.progress.load(Ordering::Relaxed);
-      if pb_ptr.is_null() {
+      // hidden progress bars do not print, either
+      if pb_ptr.is_null() || PLAIN_PROGRESS.load(Ordering::Relaxed) {
         eprintln!("[{:>5}] {}", record.level(), record.args());
       } else {
         let msg = format!("[{:>5}] {}", record.level(), record.args());
@@ -61,7 +208,25 @@ pub struct LogOpts {
   verbose: usize,
   /// Silence output
   #[structopt(short="q", long="quiet")]
-  quiet: bool
+  quiet: bool,
+  /// Log format (text or json)
+  #[structopt(long="log-format", default_value="text")]
+  log_format: LogFormat,
+  /// Write JSON logs and progress events to this file descriptor instead of stderr
+  #[structopt(long="log-fd")]
+  log_fd: Option<i32>,
+  /// Seconds between JSON progress events or plain progress lines
+  #[structopt(long="progress-interval", default_value="10")]
+  progress_interval: u64,
+  /// Also write log messages, with timestamps, to this file (even with --quiet)
+  #[structopt(long="log-file", parse(from_os_str))]
+  log_file: Option<PathBuf>,
+  /// Rotate the log file once it is larger than this many megabytes
+  #[structopt(long="log-max-mb")]
+  log_max_mb: Option<u64>,
+  /// The number of rotated log files to keep
+  #[structopt(long="log-keep", default_value="5")]
+  log_keep: usize
 }
 
 impl LogOpts {
@@ -74,16 +239,175 @@ impl LogOpts {
     for _i in 0..self.verbose {
       level = verbosify(level);
     }
+    let out: Box<dyn Write + Send> = match self.log_fd {
+      Some(0) => return Err(anyhow!("--log-fd 0 is standard input")),
+      Some(1) => Box::new(io::stdout()),
+      Some(2) | None => Box::new(io::stderr()),
+      Some(fd) => Box::new(fd_file(fd)?)
+    };
+    let out = Arc::new(Mutex::new(out));
+    // Progress bars are useless without a terminal, so report progress in the log
+    let json = self.log_format == LogFormat::Json;
+    let plain = !json && !Term::stderr().is_term();
+    JSON_LOG.store(json, Ordering::Relaxed);
+    PLAIN_PROGRESS.store(plain, Ordering::Relaxed);
+    if (json || plain) && self.progress_interval > 0 {
+      spawn_progress_events(out.clone(), json, Duration::from_secs(self.progress_interval))?;
+    }
+    let file = match self.log_file {
+      Some(ref path) => {
+        let mut flevel = LevelFilter::Info;
+        for _i in 0..self.verbose {
+          flevel = verbosify(flevel);
+        }
+        let lf = LogFile::open(path, self.log_keep, self.log_max_mb.map(|m| m << 20))?;
+        Some((flevel, Mutex::new(lf)))
+      },
+      None => None
+    };
+    let max = file.as_ref().map(|(l, _)| level.max(*l)).unwrap_or(level);
     let logger = LogEnv {
       level: level,
-      progress: &LOG_PB
+      progress: &LOG_PB,
+      format: self.log_format,
+      out,
+      file
     };
     set_boxed_logger(Box::new(logger))?;
-    set_max_level(level);
+    set_max_level(max);
     Ok(())
   }
 }
 
+#[cfg(unix)]
+fn fd_file(fd: i32) -> Result<File> {
+  use std::os::unix::io::FromRawFd;
+  if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+    return Err(anyhow!("--log-fd {}: {}", fd, io::Error::last_os_error()));
+  }
+  // the descriptor is open, and handed to us by the calling process for our output
+  Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn fd_file(_fd: i32) -> Result<File> {
+  Err(anyhow!("--log-fd is only supported on Unix"))
+}
+
+/// Start a thread that periodically writes a progress event (or a plain
+/// progress line to the log), if there has been progress since the last one.
+fn spawn_progress_events(out: LogOut, json: bool, interval: Duration) -> Result<()> {
+  let start = Instant::now();
+  thread::Builder::new().name("progress-events".to_string()).spawn(move || {
+    let mut last = (0, 0);
+    loop {
+      thread::sleep(interval);
+      let cur = (PROGRESS_BYTES.load(Ordering::Relaxed), PROGRESS_RECORDS.load(Ordering::Relaxed));
+      if cur == last {
+        continue;
+      }
+      last = cur;
+      let secs = start.elapsed().as_secs_f64();
+      let len = PROGRESS_LEN.load(Ordering::Relaxed);
+      if json {
+        write_json(&out, &json!({
+          "ts": timestamp(),
+          "event": "progress",
+          "elapsed": secs,
+          "bytes": cur.0,
+          "total_bytes": if len > 0 { Some(len) } else { None },
+          "records": cur.1,
+          "rate": cur.1 as f64 / secs.max(0.001)
+        }));
+      } else {
+        info!(target: "progress", "{}", progress_line(cur.0, len, cur.1, secs));
+      }
+    }
+  })?;
+  Ok(())
+}
+
+/// Describe the progress so far in a plain log line.
+fn progress_line(bytes: u64, len: u64, records: u64, secs: f64) -> String {
+  let mut line = format!("progress: read {}", HumanBytes(bytes));
+  if len > 0 {
+    line.push_str(&format!(" of {} ({:.1}%)", HumanBytes(len), bytes as f64 * 100.0 / len as f64));
+  }
+  if records > 0 {
+    line.push_str(&format!(", {} records ({:.0}/sec)", records, records as f64 / secs.max(0.001)));
+  }
+  line
+}
+
+/// Create a progress bar for reading `len` bytes (a spinner if the length is
+/// unknown) with a style template.  The bar is hidden when progress is being
+/// reported in the log instead.
+pub fn progress_bar(len: Option<u64>, template: &str) -> ProgressBar {
+  let pb = match len {
+    Some(l) => {
+      PROGRESS_LEN.fetch_add(l, Ordering::Relaxed);
+      let pb = ProgressBar::new(l);
+      pb.set_style(ProgressStyle::default_bar().template(template));
+      pb
+    },
+    None => {
+      let pb = ProgressBar::new_spinner();
+      pb.set_style(ProgressStyle::default_spinner().template("{elapsed_precise} {spinner} {bytes} {msg}"));
+      pb
+    }
+  };
+  if JSON_LOG.load(Ordering::Relaxed) || PLAIN_PROGRESS.load(Ordering::Relaxed) {
+    pb.set_draw_target(ProgressDrawTarget::hidden());
+  }
+  pb
+}
+
+/// Wrap a reader to advance a progress bar and count bytes for progress events.
+pub fn wrap_read<R: Read>(pb: &ProgressBar, read: R) -> ProgressBarRead<EventRead<R>> {
+  pb.wrap_read(event_read(read))
+}
+
+/// Reader wrapper that counts the bytes read for progress events.
+pub struct EventRead<R: Read> {
+  inner: R
+}
+
+/// Count the bytes read from a reader in the progress events.
+pub fn event_read<R: Read>(inner: R) -> EventRead<R> {
+  EventRead { inner }
+}
+
+impl <R: Read> Read for EventRead<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let n = self.inner.read(buf)?;
+    PROGRESS_BYTES.fetch_add(n as u64, Ordering::Relaxed);
+    Ok(n)
+  }
+}
+
+/// Count processed records in the progress events.
+pub fn count_records(n: u64) {
+  PROGRESS_RECORDS.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Get the total bytes read and records processed.
+pub fn progress_totals() -> (u64, u64) {
+  (PROGRESS_BYTES.load(Ordering::Relaxed), PROGRESS_RECORDS.load(Ordering::Relaxed))
+}
+
+/// Get the total bytes to read, if known.
+pub fn progress_len() -> Option<u64> {
+  match PROGRESS_LEN.load(Ordering::Relaxed) {
+    0 => None,
+    n => Some(n)
+  }
+}
+
+/// Get the number of error and warning messages logged.
+pub fn message_counts() -> (u64, u64) {
+  (LOG_ERRORS.load(Ordering::Relaxed), LOG_WARNINGS.load(Ordering::Relaxed))
+}
+
 pub fn set_progress<'a>(pb: &'a ProgressBar) -> LogPBState<'a> {
   let pbb = Box::new(pb.clone());
   LOG_PB.store(Box::leak(pbb), Ordering::Relaxed);
@@ -97,3 +421,52 @@ impl <'a> Drop for LogPBState<'a> {
     LOG_PB.store(ptr::null_mut(), Ordering::Relaxed);
   }
 }
+
+#[test]
+fn test_log_format() {
+  assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+  assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+  assert!("xml".parse::<LogFormat>().is_err());
+}
+
+#[test]
+fn test_format_time() {
+  assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+  let t = UNIX_EPOCH + Duration::from_millis(1_709_210_096_250);
+  assert_eq!(format_time(t), "2024-02-29T12:34:56.250Z");
+}
+
+#[test]
+fn test_rotate_log() {
+  let dir = std::env::temp_dir().join(format!("bookdata-log-{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("run.log");
+  for i in 0..4 {
+    let mut lf = LogFile::open(&path, 2, None).unwrap();
+    lf.write_line(&format!("run {}", i)).unwrap();
+  }
+  assert_eq!(fs::read_to_string(&path).unwrap(), "run 3\n");
+  assert_eq!(fs::read_to_string(dir.join("run.log.1")).unwrap(), "run 2\n");
+  assert_eq!(fs::read_to_string(dir.join("run.log.2")).unwrap(), "run 1\n");
+  assert!(!dir.join("run.log.3").exists());
+
+  let mut lf = LogFile::open(&path, 1, Some(10)).unwrap();
+  lf.write_line("first line").unwrap();
+  lf.write_line("second").unwrap();
+  assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+  assert_eq!(fs::read_to_string(dir.join("run.log.1")).unwrap(), "first line\n");
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_progress_line() {
+  assert_eq!(progress_line(512, 0, 0, 1.0), "progress: read 512B");
+  assert_eq!(progress_line(512, 1024, 100, 2.0), "progress: read 512B of 1.00KB (50.0%), 100 records (50/sec)");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fd_file_closed() {
+  assert!(fd_file(-1).is_err());
+  assert!(fd_file(987_654).is_err());
+}
@@ -1,41 +1,74 @@
-mod cleaning;
-mod tsv;
 mod db;
-mod io;
+mod output;
 mod tracking;
 mod logging;
+mod date;
+mod summary;
+mod metrics;
 mod commands;
 
+use bookdata::{cleaning, tsv, ids, names, langid, interactions, columnar, arrow, sink, io, import};
+
+use std::time::Instant;
+
 use anyhow::{anyhow, Result};
 use log::*;
 use structopt::StructOpt;
+use structopt::clap::App;
 
 use logging::LogOpts;
+use summary::SummaryOpts;
+use metrics::MetricsOpts;
 use commands::*;
 
+/// The version, with the commit and date of the build.
+const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("BOOKDATA_GIT_COMMIT"),
+                              ", built ", env!("BOOKDATA_BUILD_DATE"), ")");
+
+/// The long version for `--version`, with the enabled features as well.
+const LONG_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"),
+                                   "\ncommit: ", env!("BOOKDATA_GIT_COMMIT"),
+                                   "\nbuilt: ", env!("BOOKDATA_BUILD_DATE"),
+                                   "\nfeatures: ", env!("BOOKDATA_FEATURES"));
+
 /// BookData import tools
 #[derive(StructOpt, Debug)]
 #[structopt(name="bookdata")]
 struct Opt {
   #[structopt(flatten)]
-  logging: LogOpts
+  logging: LogOpts,
+
+  #[structopt(flatten)]
+  summary: SummaryOpts,
+
+  #[structopt(flatten)]
+  metrics: MetricsOpts
 }
 
-fn main() -> Result<()> {
-  let mut app = Opt::clap();
-  let cmds = commands();
-  for cmd in &cmds {
+/// Build the command-line parser, with all the subcommands.
+fn app() -> App<'static, 'static> {
+  let mut app = Opt::clap().version(VERSION).long_version(LONG_VERSION);
+  for cmd in commands() {
     app = app.subcommand(cmd.app().clone());
   }
-  let matches = app.get_matches();
+  app
+}
+
+fn main() -> Result<()> {
+  let started = Instant::now();
+  let cmds = commands();
+  let matches = app().get_matches();
 
   let opt = Opt::from_clap(&matches);
   opt.logging.init()?;
   let (sc_name, sc_app) = matches.subcommand();
   debug!("subcommand name {}", sc_name);
+  opt.metrics.start(sc_name, started)?;
   for cmd in &cmds {
     if cmd.name() == sc_name {
-      cmd.run(sc_app.ok_or(anyhow!("no options"))?)?
+      let res = cmd.run(sc_app.ok_or(anyhow!("no options"))?);
+      opt.summary.report(sc_name, started)?;
+      res?
     }
   }
   Ok(())
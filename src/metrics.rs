@@ -0,0 +1,130 @@
+//! Prometheus metrics for long-running commands.
+//!
+//! With `--metrics-addr`, a background thread serves the progress counters in
+//! the Prometheus text format at `/metrics`, so imports on shared servers can
+//! be monitored remotely.
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::fmt::Write as _;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+use anyhow::Result;
+use structopt::StructOpt;
+
+use crate::logging;
+use crate::summary;
+
+/// Options for the metrics endpoint.
+#[derive(StructOpt, Debug)]
+pub struct MetricsOpts {
+  /// Serve Prometheus metrics over HTTP on this address (e.g. 0.0.0.0:9187)
+  #[structopt(long="metrics-addr")]
+  metrics_addr: Option<SocketAddr>
+}
+
+/// Write one metric, with its help and type lines.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+  // writing to a String cannot fail
+  let _ = writeln!(out, "# HELP {} {}", name, help);
+  let _ = writeln!(out, "# TYPE {} {}", name, kind);
+  let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Render the current metrics of a command started at `started`.
+fn render(command: &str, started: Instant) -> String {
+  let secs = started.elapsed().as_secs_f64();
+  let (bytes, records) = logging::progress_totals();
+  let (errors, warnings) = logging::message_counts();
+  let mut out = String::new();
+  let _ = writeln!(out, "# HELP bookdata_info The running command and build.");
+  let _ = writeln!(out, "# TYPE bookdata_info gauge");
+  let _ = writeln!(out, "bookdata_info{{command=\"{}\",version=\"{}\",commit=\"{}\"}} 1",
+                   command, env!("CARGO_PKG_VERSION"), env!("BOOKDATA_GIT_COMMIT"));
+  metric(&mut out, "bookdata_uptime_seconds", "gauge", "Seconds since the command started.", secs);
+  metric(&mut out, "bookdata_input_bytes_total", "counter", "Bytes of input read.", bytes as f64);
+  if let Some(len) = logging::progress_len() {
+    metric(&mut out, "bookdata_input_size_bytes", "gauge", "Total bytes of input to read.", len as f64);
+  }
+  metric(&mut out, "bookdata_input_records_total", "counter", "Input records processed.", records as f64);
+  metric(&mut out, "bookdata_input_records_per_second", "gauge", "Average input records processed per second.",
+         records as f64 / secs.max(0.001));
+  metric(&mut out, "bookdata_output_rows_total", "counter", "Rows written to finished tables and files.",
+         summary::output_rows() as f64);
+  metric(&mut out, "bookdata_errors_total", "counter", "Error messages logged.", errors as f64);
+  metric(&mut out, "bookdata_warnings_total", "counter", "Warning messages logged.", warnings as f64);
+  if let Some(rss) = summary::peak_rss() {
+    metric(&mut out, "bookdata_peak_rss_bytes", "gauge", "Peak resident set size.", rss as f64);
+  }
+  out
+}
+
+/// Answer one HTTP request.
+fn respond(mut stream: TcpStream, command: &str, started: Instant) -> std::io::Result<()> {
+  stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+  let mut buf = [0u8; 4096];
+  let mut req = Vec::new();
+  while !req.windows(4).any(|w| w == b"\r\n\r\n") && req.len() < 16384 {
+    let n = stream.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    req.extend_from_slice(&buf[..n]);
+  }
+  let line = String::from_utf8_lossy(&req);
+  let path = line.split_whitespace().nth(1).unwrap_or("");
+  let (status, body) = if line.starts_with("GET ") && (path == "/metrics" || path == "/") {
+    ("200 OK", render(command, started))
+  } else {
+    ("404 Not Found", "not found\n".to_string())
+  };
+  write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+         status, body.len(), body)?;
+  stream.flush()
+}
+
+/// Serve metrics on an address in a background thread, returning the bound address.
+fn serve(addr: SocketAddr, command: &str, started: Instant) -> Result<SocketAddr> {
+  let listener = TcpListener::bind(addr)?;
+  let bound = listener.local_addr()?;
+  let command = command.to_string();
+  thread::Builder::new().name("metrics".to_string()).spawn(move || {
+    for stream in listener.incoming() {
+      let res = stream.and_then(|s| respond(s, &command, started));
+      if let Err(e) = res {
+        debug!("metrics request failed: {}", e);
+      }
+    }
+  })?;
+  Ok(bound)
+}
+
+impl MetricsOpts {
+  /// Start serving metrics, if requested.
+  pub fn start(&self, command: &str, started: Instant) -> Result<()> {
+    if let Some(addr) = self.metrics_addr {
+      let bound = serve(addr, command, started)?;
+      info!("serving metrics at http://{}/metrics", bound);
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_serve_metrics() {
+  let addr = serve("127.0.0.1:0".parse().unwrap(), "import-hathi", Instant::now()).unwrap();
+  let get = |path: &str| {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    resp
+  };
+  let resp = get("/metrics");
+  assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+  assert!(resp.contains("bookdata_info{command=\"import-hathi\","));
+  assert!(resp.contains("# TYPE bookdata_input_bytes_total counter\nbookdata_input_bytes_total "));
+  assert!(resp.contains("\nbookdata_errors_total "));
+  assert!(get("/other").starts_with("HTTP/1.1 404"));
+}
@@ -0,0 +1,325 @@
+//! Personal name parsing and matching.
+//!
+//! Authority records write names as `Last, First (fuller form), dates`, with
+//! role terms such as `editor` after them, while OpenLibrary mostly has names
+//! in natural order (`First Last`).  This module parses both forms so they can
+//! be reduced to comparable match keys.
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Role terms, lower-cased and without trailing periods, that follow names in
+/// authority headings and catalog records.
+const ROLE_TERMS: &[&str] = &[
+  "author", "joint author", "editor", "ed", "eds", "translator", "tr", "trans",
+  "illustrator", "ill", "compiler", "comp", "narrator", "contributor",
+  "introduction", "foreword", "photographer", "adapter"
+];
+
+/// Name suffixes that are left out of match keys.
+const SUFFIXES: &[&str] = &["jr", "sr", "ii", "iii", "iv"];
+
+/// A parsed personal name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Name {
+  /// The surname (or the whole name, if it cannot be split)
+  pub last: String,
+  /// The forenames, if any
+  pub first: Option<String>,
+  /// The dates attached to the name, such as `1892-1973`
+  pub dates: Option<String>
+}
+
+/// Does a name part look like dates (e.g. `1892-1973`, `b. 1950`, `fl. 1200`)?
+fn is_dates(part: &str) -> bool {
+  part.chars().any(|c| c.is_ascii_digit())
+}
+
+/// Is a name part a role term or a suffix?
+fn is_term(part: &str, terms: &[&str]) -> bool {
+  let p = part.trim().trim_end_matches('.').to_lowercase();
+  terms.contains(&p.as_str())
+}
+
+/// Parse a name in authority (`Last, First, dates`) or natural (`First Last`)
+/// order.  Parenthesized fuller forms, role terms, and suffixes are dropped.
+///
+/// ```
+/// use bookdata::names::parse_name;
+/// let name = parse_name("Tolkien, J. R. R. (John Ronald Reuel), 1892-1973, author.");
+/// assert_eq!(name.last, "Tolkien");
+/// assert_eq!(name.first.as_deref(), Some("J. R. R."));
+/// assert_eq!(name.dates.as_deref(), Some("1892-1973"));
+/// ```
+pub fn parse_name(text: &str) -> Name {
+  let mut name = Name::default();
+  // take out parenthesized parts, keeping them if they are dates
+  let mut plain = String::with_capacity(text.len());
+  let mut rest = text;
+  while let Some(i) = rest.find('(') {
+    plain.push_str(&rest[..i]);
+    let (inner, after) = match rest[i..].find(')') {
+      Some(j) => (&rest[i+1..i+j], &rest[i+j+1..]),
+      None => (&rest[i+1..], "")
+    };
+    if is_dates(inner) && name.dates.is_none() {
+      name.dates = Some(inner.trim().to_string());
+    }
+    rest = after;
+  }
+  plain.push_str(rest);
+
+  let mut parts = Vec::new();
+  for part in plain.split(',') {
+    let part = part.trim().trim_end_matches([',', ';', ':']).trim();
+    if part.is_empty() || is_term(part, ROLE_TERMS) || is_term(part, SUFFIXES) {
+      continue;
+    } else if is_dates(part) {
+      if name.dates.is_none() {
+        name.dates = Some(part.trim_end_matches('.').to_string());
+      }
+    } else {
+      parts.push(part);
+    }
+  }
+  // a trailing period ends the heading, but keep it on initials
+  let tidy = |s: &str| {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    let mut s = words.join(" ");
+    let initials = |w: &str| w.len() <= 2 || w[..w.len() - 1].contains('.');
+    if s.ends_with('.') && !words.last().map(|w| initials(w)).unwrap_or(true) {
+      s.pop();
+    }
+    s
+  };
+  match parts.len() {
+    0 => (),
+    1 => {
+      let words: Vec<&str> = parts[0].split_whitespace().filter(|w| !is_term(w, SUFFIXES)).collect();
+      match words.split_last() {
+        Some((last, first)) if !first.is_empty() => {
+          name.last = tidy(last);
+          name.first = Some(tidy(&first.join(" ")));
+        },
+        _ => name.last = tidy(parts[0])
+      }
+    },
+    _ => {
+      name.last = tidy(parts[0]);
+      name.first = Some(tidy(&parts[1..].join(" ")));
+    }
+  }
+  name
+}
+
+/// Fold a string to lower-case ASCII-ish text, removing diacritics.  Letters
+/// that do not decompose (such as `ø` and `ß`) are spelled out.
+pub fn fold_diacritics(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  for c in text.nfkd() {
+    if is_combining_mark(c) {
+      continue;
+    }
+    match c {
+      'ø' | 'Ø' => out.push('o'),
+      'æ' | 'Æ' => out.push_str("ae"),
+      'œ' | 'Œ' => out.push_str("oe"),
+      'ß' => out.push_str("ss"),
+      'ł' | 'Ł' => out.push('l'),
+      'đ' | 'Đ' => out.push('d'),
+      'þ' | 'Þ' => out.push_str("th"),
+      c => out.extend(c.to_lowercase())
+    }
+  }
+  out
+}
+
+/// Reduce text to folded words separated by single spaces.
+fn key_words(text: &str) -> Vec<String> {
+  let folded = fold_diacritics(text);
+  folded.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(|w| w.to_string()).collect()
+}
+
+impl Name {
+  /// The folded words of the surname and then the forenames.
+  fn key_words(&self) -> Vec<String> {
+    let mut words = key_words(&self.last);
+    if let Some(ref f) = self.first {
+      words.extend(key_words(f));
+    }
+    words
+  }
+
+  /// The name's match key: folded surname and forenames, without punctuation.
+  pub fn match_key(&self) -> String {
+    self.key_words().join(" ")
+  }
+
+  /// Match keys for the forms a name may take elsewhere: the full key, the
+  /// surname with forename initials, the surname with the first initial, and
+  /// the key's words in sorted order (for compound surnames, which cannot be
+  /// told apart from forenames in natural order).
+  pub fn variants(&self) -> Vec<String> {
+    let mut words = self.key_words();
+    let mut cands = vec![words.join(" ")];
+    if let Some(ref f) = self.first {
+      let last = key_words(&self.last).join(" ");
+      let initials: Vec<String> = key_words(f).iter().filter_map(|w| w.chars().next()).map(|c| c.to_string()).collect();
+      if !initials.is_empty() {
+        cands.push(format!("{} {}", last, initials.join(" ")));
+        cands.push(format!("{} {}", last, initials[0]));
+      }
+    }
+    words.sort();
+    cands.push(words.join(" "));
+
+    let mut keys = Vec::with_capacity(cands.len());
+    for k in cands {
+      if !k.is_empty() && !keys.contains(&k) {
+        keys.push(k);
+      }
+    }
+    keys
+  }
+}
+
+/// Parse the first year in text, such as `1892` in `ca. 1892?`.  Years are
+/// negative if the text is marked as BC.
+fn first_year(text: &str, bc: bool) -> Option<i32> {
+  let start = text.find(|c: char| c.is_ascii_digit())?;
+  let digits: String = text[start..].chars().take_while(|c| c.is_ascii_digit()).collect();
+  if digits.len() > 4 {
+    return None;
+  }
+  let year: i32 = digits.parse().ok()?;
+  Some(if bc { -year } else { year })
+}
+
+/// Get the birth and death years from a name's dates, in the forms authority
+/// headings use (`1892-1973`, `b. 1950`, `d. 1616`, `1564?-1616`, `384-322
+/// B.C.`).  Periods of activity (`fl. 1200`, `active 1200`) and centuries have
+/// neither.
+///
+/// ```
+/// use bookdata::names::life_years;
+/// assert_eq!(life_years("1892-1973."), (Some(1892), Some(1973)));
+/// assert_eq!(life_years("fl. 1200"), (None, None));
+/// ```
+pub fn life_years(dates: &str) -> (Option<i32>, Option<i32>) {
+  let d = dates.trim().trim_end_matches('.').to_lowercase();
+  let bc = d.ends_with("b.c") || d.ends_with(" bc");
+  if d.starts_with("fl") || d.starts_with("active") || d.contains("cent") {
+    (None, None)
+  } else if let Some(r) = d.strip_prefix("b.").or_else(|| d.strip_prefix("born")) {
+    (first_year(r, bc), None)
+  } else if let Some(r) = d.strip_prefix("d.").or_else(|| d.strip_prefix("died")) {
+    (None, first_year(r, bc))
+  } else if let Some(i) = d.find('-') {
+    (first_year(&d[..i], bc), first_year(&d[i+1..], bc))
+  } else {
+    (None, None)
+  }
+}
+
+/// Get the year from a MARC 046 date (`1892`, `18920103`, `1892-01-03`, or
+/// `-0384` for 385 BC).  Dates with unknown digits (`18uu`) have no year.
+pub fn edtf_year(date: &str) -> Option<i32> {
+  let date = date.trim();
+  let (neg, digits) = match date.strip_prefix('-') {
+    Some(d) => (true, d),
+    None => (false, date)
+  };
+  let year = digits.get(..4)?;
+  if !year.bytes().all(|b| b.is_ascii_digit()) || digits[4..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+    return None;
+  }
+  let year: i32 = year.parse().ok()?;
+  // EDTF's year 0 is 1 BC, -1 is 2 BC, and so on
+  Some(if neg { -year - 1 } else { year })
+}
+
+#[test]
+fn test_parse_authority() {
+  let n = parse_name("Tolkien, J. R. R. (John Ronald Reuel), 1892-1973.");
+  assert_eq!(n.last, "Tolkien");
+  assert_eq!(n.first.as_deref(), Some("J. R. R."));
+  assert_eq!(n.dates.as_deref(), Some("1892-1973"));
+}
+
+#[test]
+fn test_parse_roles() {
+  let n = parse_name("Austen, Jane, 1775-1817, author.");
+  assert_eq!((n.last.as_str(), n.first.as_deref()), ("Austen", Some("Jane")));
+  let n = parse_name("Grossman, Edith, translator");
+  assert_eq!((n.last.as_str(), n.first.as_deref(), n.dates), ("Grossman", Some("Edith"), None));
+}
+
+#[test]
+fn test_parse_natural() {
+  let n = parse_name("Gabriel García Márquez");
+  assert_eq!((n.last.as_str(), n.first.as_deref()), ("Márquez", Some("Gabriel García")));
+  let n = parse_name("Martin Luther King, Jr.");
+  assert_eq!((n.last.as_str(), n.first.as_deref()), ("King", Some("Martin Luther")));
+  let n = parse_name("Homer");
+  assert_eq!((n.last.as_str(), n.first), ("Homer", None));
+  assert_eq!(parse_name("  "), Name::default());
+  assert_eq!(parse_name("Smith, J. (John"), Name { last: "Smith".into(), first: Some("J.".into()), dates: None });
+  assert_eq!(parse_name("J.R.R. Tolkien.").first.as_deref(), Some("J.R.R."));
+}
+
+#[test]
+fn test_parse_dates_variants() {
+  assert_eq!(parse_name("Shakespeare, William (1564-1616)").dates.as_deref(), Some("1564-1616"));
+  assert_eq!(parse_name("Smith, John, b. 1950").dates.as_deref(), Some("b. 1950"));
+}
+
+#[test]
+fn test_fold() {
+  assert_eq!(fold_diacritics("García Márquez"), "garcia marquez");
+  assert_eq!(fold_diacritics("Søren Kierkegaard"), "soren kierkegaard");
+  assert_eq!(fold_diacritics("Łódź Straße"), "lodz strasse");
+}
+
+#[test]
+fn test_match_keys() {
+  let auth = parse_name("García Márquez, Gabriel, 1927-2014");
+  let ol = parse_name("Gabriel Garcia Marquez");
+  assert_eq!(auth.match_key(), "garcia marquez gabriel");
+  assert_eq!(ol.match_key(), "marquez gabriel garcia");
+  assert!(ol.variants().contains(&"gabriel garcia marquez".to_string()));
+  assert!(auth.variants().contains(&"gabriel garcia marquez".to_string()));
+  let a = parse_name("Tolkien, J. R. R. (John Ronald Reuel), 1892-1973");
+  let b = parse_name("John Ronald Reuel Tolkien");
+  let c = parse_name("J.R.R. Tolkien");
+  assert_eq!(a.match_key(), "tolkien j r r");
+  assert_eq!(c.match_key(), "tolkien j r r");
+  assert!(b.variants().contains(&a.match_key()));
+  assert_eq!(b.variants(), vec!["tolkien john ronald reuel", "tolkien j r r", "tolkien j", "john reuel ronald tolkien"]);
+  assert_eq!(parse_name("Homer").variants(), vec!["homer"]);
+}
+
+#[test]
+fn test_life_years() {
+  assert_eq!(life_years("1892-1973"), (Some(1892), Some(1973)));
+  assert_eq!(life_years("1950-"), (Some(1950), None));
+  assert_eq!(life_years("-1616"), (None, Some(1616)));
+  assert_eq!(life_years("b. 1950"), (Some(1950), None));
+  assert_eq!(life_years("d. 1616."), (None, Some(1616)));
+  assert_eq!(life_years("1564?-1616"), (Some(1564), Some(1616)));
+  assert_eq!(life_years("ca. 1340-ca. 1400"), (Some(1340), Some(1400)));
+  assert_eq!(life_years("1743 or 4-1820"), (Some(1743), Some(1820)));
+  assert_eq!(life_years("384-322 B.C."), (Some(-384), Some(-322)));
+  assert_eq!(life_years("fl. 1200"), (None, None));
+  assert_eq!(life_years("20th cent"), (None, None));
+  assert_eq!(life_years("1950"), (None, None));
+}
+
+#[test]
+fn test_edtf_year() {
+  assert_eq!(edtf_year("1892"), Some(1892));
+  assert_eq!(edtf_year("18920103"), Some(1892));
+  assert_eq!(edtf_year("1892-01-03"), Some(1892));
+  assert_eq!(edtf_year("-0384"), Some(-385));
+  assert_eq!(edtf_year("18uu"), None);
+  assert_eq!(edtf_year("189"), None);
+  assert_eq!(edtf_year("1892u"), None);
+}
@@ -0,0 +1,424 @@
+//! Output destinations for importers.
+//!
+//! Importers write their tables to a [RowSink] (usually with
+//! [write_record](crate::tsv::write_record)), which [OutputOpts] opens from
+//! the command-line options.  The rows can go to the database with `COPY`, to
+//! tab-separated files, to Parquet files or Arrow streams with typed columns,
+//! or to standard output.  Whatever the destination, the output hash is of the
+//! rows in PostgreSQL text format.  With `--dry-run`, nothing is written: the
+//! sinks count the rows instead.
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::fs::{File, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use log::*;
+use anyhow::{Result, anyhow};
+use sha1::Sha1;
+use structopt::StructOpt;
+
+use crate::columnar::{TableWriter, ColType, Value};
+use crate::arrow::StreamWriter;
+use crate::sink::{RowSink, PGSink};
+use crate::io::{HashWrite, parse_size};
+use crate::db::{DbOpts, CopyRequest, Connection};
+use crate::tracking::{StageOpts, Stage};
+use crate::summary;
+
+/// Where importers write their output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputTarget {
+  /// Copy into the database
+  DB,
+  /// Write a PostgreSQL text-format file for each table
+  Tsv,
+  /// Write a Parquet file for each table
+  Parquet,
+  /// Write an Arrow IPC stream for each table
+  Arrow,
+  /// Write PostgreSQL text format to standard output
+  Stdout
+}
+
+impl OutputTarget {
+  /// Get the extension of the file for each table, for targets writing files.
+  pub fn extension(&self) -> Option<&'static str> {
+    match self {
+      OutputTarget::Tsv => Some("tsv"),
+      OutputTarget::Parquet => Some("parquet"),
+      OutputTarget::Arrow => Some("arrows"),
+      OutputTarget::DB | OutputTarget::Stdout => None
+    }
+  }
+}
+
+impl FromStr for OutputTarget {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<OutputTarget> {
+    match s {
+      "db" => Ok(OutputTarget::DB),
+      "tsv" => Ok(OutputTarget::Tsv),
+      "parquet" => Ok(OutputTarget::Parquet),
+      "arrow" => Ok(OutputTarget::Arrow),
+      "stdout" => Ok(OutputTarget::Stdout),
+      _ => Err(anyhow!("unknown output target {}", s))
+    }
+  }
+}
+
+/// Output options for importers.
+#[derive(StructOpt, Debug, Clone)]
+pub struct OutputOpts {
+  /// Where to write the imported tables (db, tsv, parquet, arrow, or stdout)
+  #[structopt(long="output-to", default_value="db")]
+  target: OutputTarget,
+
+  /// Directory for tsv, parquet, and arrow output files
+  #[structopt(long="output-dir", parse(from_os_str), default_value=".")]
+  dir: PathBuf,
+
+  /// Size of the output write buffers (with optional K or M suffix)
+  #[structopt(long="write-buffer", default_value="1M", parse(try_from_str="parse_size"))]
+  buffer: usize,
+
+  /// Read and parse the input, but only report what would be written.
+  #[structopt(long="dry-run")]
+  dry_run: bool
+}
+
+/// Writer counting the bytes written to it, for dry runs.
+struct ByteCount(usize);
+
+impl Write for ByteCount {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0 += buf.len();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Sink writing rows to typed columns, and their PostgreSQL text format to the
+/// output hash, so the hash does not depend on the file format.
+struct HashedSink<'h, S: RowSink> {
+  inner: S,
+  text: PGSink<HashWrite<'h, io::Sink>>
+}
+
+impl <'h, S: RowSink> RowSink for HashedSink<'h, S> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.text.write_field(val)?;
+    self.inner.write_field(val)
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    self.text.write_value(val)?;
+    self.inner.write_value(val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    self.text.write_null()?;
+    self.inner.write_null()
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    self.text.write_array(vals)?;
+    self.inner.write_array(vals)
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    self.text.write_json(val)?;
+    self.inner.write_json(val)
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    self.text.end_row()?;
+    self.inner.end_row()
+  }
+
+  fn finish(self: Box<Self>) -> Result<usize> {
+    Box::new(self.inner).finish()
+  }
+}
+
+/// Sink counting the rows written to a file, for the run summary.  (Copies
+/// into the database count their own rows.)
+struct CountedSink<'h> {
+  inner: Box<dyn RowSink + 'h>,
+  name: String,
+  started: Instant
+}
+
+impl <'h> RowSink for CountedSink<'h> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.inner.write_field(val)
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    self.inner.write_value(val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    self.inner.write_null()
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    self.inner.write_array(vals)
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    self.inner.write_json(val)
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    self.inner.end_row()
+  }
+
+  fn finish(self: Box<Self>) -> Result<usize> {
+    let CountedSink { inner, name, started } = *self;
+    let rows = inner.finish()?;
+    summary::record_output(&format!("write {}", name), rows as u64, started.elapsed());
+    Ok(rows)
+  }
+}
+
+/// Sink for dry runs, counting the rows that would be written.  The rows are
+/// still hashed, so the transcript shows the hash they would have.
+struct DrySink<'h> {
+  dest: String,
+  text: PGSink<HashWrite<'h, ByteCount>>,
+  rows: usize
+}
+
+impl <'h> RowSink for DrySink<'h> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.text.write_field(val)
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    self.text.write_value(val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    self.text.write_null()
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    self.text.write_array(vals)
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    self.text.write_json(val)
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    self.rows += 1;
+    self.text.end_row()
+  }
+
+  fn finish(self: Box<Self>) -> Result<usize> {
+    let DrySink { dest, text, rows } = *self;
+    let bytes = text.out.into_inner().0;
+    info!("dry run: would write {} rows ({} bytes) to {}", rows, bytes, dest);
+    Ok(rows)
+  }
+}
+
+/// Set up the copy request for a table, given by its qualified name.
+fn copy_request(db: &DbOpts, table: &str, columns: &[(&str, ColType)]) -> Result<CopyRequest> {
+  let req = match table.split_once('.') {
+    Some((schema, name)) => CopyRequest::new(db, name)?.with_schema(schema),
+    None => CopyRequest::new(db, table)?
+  };
+  if columns.is_empty() {
+    Ok(req)
+  } else {
+    let names: Vec<&str> = columns.iter().map(|(c, _)| *c).collect();
+    Ok(req.with_columns(&names))
+  }
+}
+
+impl OutputOpts {
+  /// Create output options for a target, for importers with their own output
+  /// options.  Files go to the current directory.
+  pub fn new(target: OutputTarget) -> OutputOpts {
+    OutputOpts { target, dir: PathBuf::from("."), buffer: 1 << 20, dry_run: false }
+  }
+
+  /// Send the output to a different target, keeping the other options.
+  pub fn with_target(self, target: OutputTarget) -> OutputOpts {
+    OutputOpts { target, ..self }
+  }
+
+  /// Set the size of the output write buffers.
+  pub fn with_buffer(self, buffer: usize) -> OutputOpts {
+    OutputOpts { buffer, ..self }
+  }
+
+  /// Make this a dry run, or not.
+  pub fn with_dry_run(self, dry_run: bool) -> OutputOpts {
+    OutputOpts { dry_run, ..self }
+  }
+
+  /// Does the output go to the database?
+  pub fn is_db(&self) -> bool {
+    self.target == OutputTarget::DB
+  }
+
+  /// Is this a dry run?
+  pub fn is_dry_run(&self) -> bool {
+    self.dry_run
+  }
+
+  /// Start the import stage.  Dry runs do not record the stage or write its
+  /// transcript file; the transcript goes to standard error instead.
+  pub fn begin_stage<'o, 'c>(&self, stage: &'o StageOpts, cxn: Option<&'c Connection>) -> Result<Stage<'o, 'c>> {
+    if self.dry_run {
+      Ok(stage.empty())
+    } else {
+      stage.begin_stage_opt(cxn)
+    }
+  }
+
+  /// Open the stage tracking connection.  Stages writing to files are not
+  /// tracked in the database, so they have no connection.
+  pub fn open_tracking(&self, db: &DbOpts) -> Result<Option<Connection>> {
+    if self.is_db() {
+      db.open_tracking()
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Open the output for a table, with the given columns and their types.
+  /// With no columns, database output goes to all of the table's columns.
+  /// With database output, `truncate` empties the table first.  The rows are
+  /// hashed into `hash` in PostgreSQL text format.
+  pub fn open<'h>(&self, db: &DbOpts, table: &str, columns: &[(&str, ColType)], truncate: bool,
+                  hash: &'h mut Sha1) -> Result<Box<dyn RowSink + 'h>> {
+    let path = match self.target.extension() {
+      Some(ext) => {
+        if !self.dry_run {
+          create_dir_all(&self.dir)?;
+        }
+        Some(self.dir.join(format!("{}.{}", table, ext)))
+      },
+      None => None
+    };
+    self.open_path(db, table, columns, truncate, path.as_deref(), hash)
+  }
+
+  /// Open the output for a table like [open](OutputOpts::open), but writing
+  /// file output to `path` instead of the output directory.  File output
+  /// without a path goes to standard output.
+  pub fn open_path<'h>(&self, db: &DbOpts, table: &str, columns: &[(&str, ColType)], truncate: bool,
+                       path: Option<&Path>, hash: &'h mut Sha1) -> Result<Box<dyn RowSink + 'h>> {
+    if self.dry_run {
+      return self.open_dry(db, table, columns, path, hash);
+    }
+    if self.target == OutputTarget::DB {
+      let req = copy_request(db, table, columns)?.truncate(truncate).with_name(table);
+      let out = HashWrite::create(req.open()?, hash);
+      return Ok(Box::new(PGSink::new(BufWriter::with_capacity(self.buffer, out))));
+    }
+
+    let out: Box<dyn Write + Send> = match path {
+      Some(p) if self.target != OutputTarget::Stdout => {
+        info!("writing {} to {:?}", table, p);
+        Box::new(File::create(p)?)
+      },
+      _ => Box::new(io::stdout())
+    };
+    let out = BufWriter::with_capacity(self.buffer, out);
+    let name = table.rsplit('.').next().unwrap_or(table);
+    let sink: Box<dyn RowSink + 'h> = match self.target {
+      OutputTarget::Parquet | OutputTarget::Arrow if columns.is_empty() => {
+        return Err(anyhow!("{:?} output for {} needs its columns", self.target, table));
+      },
+      OutputTarget::Parquet => {
+        let text = PGSink::new(HashWrite::create(io::sink(), hash));
+        Box::new(HashedSink { inner: TableWriter::create(out, name, columns)?, text })
+      },
+      OutputTarget::Arrow => {
+        let text = PGSink::new(HashWrite::create(io::sink(), hash));
+        Box::new(HashedSink { inner: StreamWriter::create(out, columns)?, text })
+      },
+      _ => Box::new(PGSink::new(HashWrite::create(out, hash)))
+    };
+    Ok(Box::new(CountedSink { inner: sink, name: table.to_string(), started: Instant::now() }))
+  }
+
+  /// Open a dry-run sink for a table, checking that the database can take
+  /// its rows.
+  fn open_dry<'h>(&self, db: &DbOpts, table: &str, columns: &[(&str, ColType)], path: Option<&Path>,
+                  hash: &'h mut Sha1) -> Result<Box<dyn RowSink + 'h>> {
+    let dest = match (self.target, path) {
+      (OutputTarget::DB, _) => {
+        copy_request(db, table, columns)?.check()?;
+        format!("table {}", table)
+      },
+      (OutputTarget::Stdout, _) | (_, None) => "standard output".to_string(),
+      (_, Some(p)) => format!("{:?}", p)
+    };
+    let text = PGSink::new(HashWrite::create(ByteCount(0), hash));
+    Ok(Box::new(DrySink { dest, text, rows: 0 }))
+  }
+}
+
+#[test]
+fn test_parquet_output() {
+  use parquet::file::reader::{FileReader, SerializedFileReader};
+  use parquet::record::Field;
+  use crate::tsv::{write_row, write_record};
+  let dir = std::env::temp_dir().join(format!("bookdata-output-{}", std::process::id()));
+  let opts = OutputOpts { dir: dir.clone(), ..OutputOpts::new(OutputTarget::Parquet) };
+  let db = DbOpts::from_iter(&["test"]);
+  let cols = [("id", ColType::Int32), ("title", ColType::Text), ("rating", ColType::Float)];
+  let mut hash = Sha1::new();
+  let mut sink = opts.open(&db, "bx.books", &cols, false, &mut hash).unwrap();
+  write_record(&mut sink, &("1", "The \\ Hobbit", 4.5)).unwrap();
+  write_record(&mut sink, &(2, None::<&str>, None::<f32>)).unwrap();
+  assert_eq!(sink.finish().unwrap(), 2);
+  let mut text = Vec::new();
+  write_row(&mut text, &("1", "The \\ Hobbit", 4.5)).unwrap();
+  write_row(&mut text, &(2, None::<&str>, None::<f32>)).unwrap();
+  let mut text_hash = Sha1::new();
+  text_hash.update(&text);
+  assert_eq!(hash.hexdigest(), text_hash.hexdigest());
+
+  let path = dir.join("bx.books.parquet");
+  let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+  let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(Result::unwrap).collect();
+  std::fs::remove_dir_all(&dir).unwrap();
+  let fields: Vec<Vec<&Field>> = rows.iter().map(|r| r.get_column_iter().map(|(_, f)| f).collect()).collect();
+  assert_eq!(fields[0], vec![&Field::Int(1), &Field::Str("The \\ Hobbit".to_string()), &Field::Float(4.5)]);
+  assert_eq!(fields[1], vec![&Field::Int(2), &Field::Null, &Field::Null]);
+
+  let mut hash = Sha1::new();
+  assert!(opts.open(&db, "bx.books", &[], false, &mut hash).is_err());
+}
+
+#[test]
+fn test_dry_run_output() {
+  use crate::tsv::{write_row, write_record};
+  let dir = std::env::temp_dir().join(format!("bookdata-dry-run-{}", std::process::id()));
+  let opts = OutputOpts { dir: dir.clone(), ..OutputOpts::new(OutputTarget::Parquet) }.with_dry_run(true);
+  assert!(opts.is_dry_run());
+  let db = DbOpts::from_iter(&["test"]);
+  let cols = [("id", ColType::Int32), ("title", ColType::Text)];
+  let mut hash = Sha1::new();
+  let mut sink = opts.open(&db, "bx.books", &cols, false, &mut hash).unwrap();
+  write_record(&mut sink, &(1, "The Hobbit")).unwrap();
+  assert_eq!(sink.finish().unwrap(), 1);
+  assert!(!dir.exists());
+  let mut text = Vec::new();
+  write_row(&mut text, &(1, "The Hobbit")).unwrap();
+  let mut text_hash = Sha1::new();
+  text_hash.update(&text);
+  assert_eq!(hash.hexdigest(), text_hash.hexdigest());
+}
@@ -0,0 +1,91 @@
+//! Python bindings, built with the `python` feature.
+//!
+//! This exposes the cleaning and identifier routines as the `bookdata._native`
+//! module, so Python analysis code uses the same rules as the importers.
+use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyDict;
+
+use crate::cleaning;
+use crate::ids;
+use crate::names;
+
+/// Encode text in PostgreSQL text format.
+#[pyfunction]
+fn pg_encode(text: &str) -> String {
+  let mut buf = Vec::with_capacity(text.len());
+  cleaning::write_pgencoded(&mut buf, text.as_bytes()).expect("in-memory write failed");
+  String::from_utf8(buf).expect("encoding produced invalid UTF-8")
+}
+
+/// Decode a field in PostgreSQL text format.
+#[pyfunction]
+fn pg_decode(text: &str) -> PyResult<String> {
+  let mut buf = Vec::with_capacity(text.len());
+  cleaning::decode_pgencoded(text.as_bytes(), &mut buf).map_err(|e| PyValueError::new_err(e.to_string()))?;
+  String::from_utf8(buf).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Clean a JSON string, removing escaped NUL characters.
+#[pyfunction]
+fn clean_json(json: &str) -> String {
+  let mut buf = String::with_capacity(json.len());
+  cleaning::clean_json(json, &mut buf);
+  buf
+}
+
+/// Normalize an ISBN, returning `None` if it is not one.
+#[pyfunction]
+fn normalize_isbn(isbn: &str) -> Option<String> {
+  ids::normalize_isbn(isbn)
+}
+
+/// Check an ISBN-10 or ISBN-13's check digit.
+#[pyfunction]
+fn isbn_valid(isbn: &str) -> bool {
+  ids::isbn10_valid(isbn) || ids::isbn13_valid(isbn)
+}
+
+/// Normalize an ASIN, returning `None` if it is not one.
+#[pyfunction]
+fn normalize_asin(asin: &str) -> Option<String> {
+  ids::normalize_asin(asin)
+}
+
+/// Normalize an OCLC number, returning `None` if it is not one.
+#[pyfunction]
+fn normalize_oclc(oclc: &str) -> Option<u64> {
+  ids::normalize_oclc(oclc)
+}
+
+/// Normalize an LCCN, returning `None` if it is not one.
+#[pyfunction]
+fn normalize_lccn(lccn: &str) -> Option<String> {
+  ids::normalize_lccn(lccn)
+}
+
+/// Parse a personal name into a dictionary of `last`, `first`, and `dates`.
+#[pyfunction]
+fn parse_name<'py>(py: Python<'py>, text: &str) -> PyResult<&'py PyDict> {
+  let name = names::parse_name(text);
+  let dict = PyDict::new(py);
+  dict.set_item("last", name.last)?;
+  dict.set_item("first", name.first)?;
+  dict.set_item("dates", name.dates)?;
+  Ok(dict)
+}
+
+#[pymodule]
+#[pyo3(name = "_native")]
+fn native(_py: Python, m: &PyModule) -> PyResult<()> {
+  m.add_function(wrap_pyfunction!(pg_encode, m)?)?;
+  m.add_function(wrap_pyfunction!(pg_decode, m)?)?;
+  m.add_function(wrap_pyfunction!(clean_json, m)?)?;
+  m.add_function(wrap_pyfunction!(normalize_isbn, m)?)?;
+  m.add_function(wrap_pyfunction!(isbn_valid, m)?)?;
+  m.add_function(wrap_pyfunction!(normalize_asin, m)?)?;
+  m.add_function(wrap_pyfunction!(normalize_oclc, m)?)?;
+  m.add_function(wrap_pyfunction!(normalize_lccn, m)?)?;
+  m.add_function(wrap_pyfunction!(parse_name, m)?)?;
+  Ok(())
+}
@@ -0,0 +1,305 @@
+//! Destinations for imported rows.
+//!
+//! Importers write their rows to a [RowSink], so the same import code can
+//! write PostgreSQL `COPY` text or binary, Parquet files, or Arrow streams.
+//! Rows of `Serialize` structs are written with
+//! [write_record](crate::tsv::write_record).
+use std::io::Write;
+
+use anyhow::{Result, anyhow};
+
+use crate::cleaning::{write_pgencoded, write_null, write_pgarray, write_pgjson, BinaryCopyWrite};
+use crate::columnar::{TableWriter, ColType, Value};
+use crate::arrow::StreamWriter;
+use crate::io::DelimPrinter;
+
+/// Destination for imported rows, written a field at a time.
+pub trait RowSink {
+  /// Write the next field of the current row.
+  fn write_field(&mut self, val: &[u8]) -> Result<()>;
+  /// Write a number or boolean as the next field of the current row.  Sinks
+  /// with typed columns convert it to the column's type.
+  fn write_value(&mut self, val: Value) -> Result<()>;
+  /// Write a null as the next field of the current row.
+  fn write_null(&mut self) -> Result<()>;
+  /// Write a list of strings as the next field of the current row.
+  fn write_array(&mut self, vals: &[String]) -> Result<()>;
+  /// Write a JSON value as the next field of the current row.
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()>;
+  /// Finish the current row.
+  fn end_row(&mut self) -> Result<()>;
+  /// Finish writing, returning the number of rows written.
+  fn finish(self: Box<Self>) -> Result<usize>;
+}
+
+impl <S: RowSink + ?Sized> RowSink for Box<S> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    (**self).write_field(val)
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    (**self).write_value(val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    (**self).write_null()
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    (**self).write_array(vals)
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    (**self).write_json(val)
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    (**self).end_row()
+  }
+
+  fn finish(self: Box<Self>) -> Result<usize> {
+    (*self).finish()
+  }
+}
+
+/// Row sink writing PostgreSQL text format.
+pub struct PGSink<W: Write> {
+  /// The underlying writer
+  pub out: W,
+  delim: DelimPrinter<'static>,
+  rows: usize
+}
+
+impl <W: Write> PGSink<W> {
+  /// Create a sink writing tab-separated rows to `out`, ready for `COPY`.
+  pub fn new(out: W) -> PGSink<W> {
+    PGSink { out, delim: DelimPrinter::new("\t", "\n"), rows: 0 }
+  }
+}
+
+impl <W: Write> RowSink for PGSink<W> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.delim.preface(&mut self.out)?;
+    write_pgencoded(&mut self.out, val)?;
+    Ok(())
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    self.delim.preface(&mut self.out)?;
+    match val {
+      Value::Bytes(v) => write_pgencoded(&mut self.out, v)?,
+      Value::Bool(v) => self.out.write_all(if v { b"t" } else { b"f" })?,
+      Value::Int32(v) => write!(self.out, "{}", v)?,
+      Value::Int64(v) => write!(self.out, "{}", v)?,
+      Value::Float(v) => write!(self.out, "{}", v)?,
+      Value::Double(v) => write!(self.out, "{}", v)?
+    }
+    Ok(())
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    self.delim.preface(&mut self.out)?;
+    write_null(&mut self.out)?;
+    Ok(())
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    self.delim.preface(&mut self.out)?;
+    write_pgarray(&mut self.out, vals)?;
+    Ok(())
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    self.delim.preface(&mut self.out)?;
+    write_pgjson(&mut self.out, val)?;
+    Ok(())
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    self.delim.end(&mut self.out)?;
+    self.rows += 1;
+    Ok(())
+  }
+
+  fn finish(mut self: Box<Self>) -> Result<usize> {
+    self.out.flush()?;
+    Ok(self.rows)
+  }
+}
+
+/// Row sink writing PostgreSQL binary `COPY` format, with the column types it
+/// was created with.  Text fields are parsed for numeric columns.  JSON and
+/// array fields have their own binary layouts, which it does not write.
+pub struct BinarySink<W: Write> {
+  out: W,
+  types: Vec<ColType>,
+  field: usize,
+  rows: usize
+}
+
+impl <W: Write> BinarySink<W> {
+  /// Create a binary sink with the given column types, and write the header.
+  pub fn create(mut out: W, types: &[ColType]) -> Result<BinarySink<W>> {
+    if let Some(i) = types.iter().position(|t| *t == ColType::Json) {
+      return Err(anyhow!("column {} is JSON, which is not supported in binary format", i + 1));
+    }
+    out.write_copy_header()?;
+    Ok(BinarySink { out, types: types.to_vec(), field: 0, rows: 0 })
+  }
+
+  /// Start the next field, beginning the tuple if it is the first.
+  fn next_type(&mut self) -> Result<ColType> {
+    let ct = *self.types.get(self.field).ok_or(anyhow!("too many fields in row {}", self.rows + 1))?;
+    if self.field == 0 {
+      self.out.begin_tuple(self.types.len() as i16)?;
+    }
+    self.field += 1;
+    Ok(ct)
+  }
+}
+
+impl <W: Write> RowSink for BinarySink<W> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    self.write_value(Value::Bytes(val))
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    let (field, row) = (self.field + 1, self.rows + 1);
+    let ct = self.next_type()?;
+    let mut buf = Vec::new();
+    match ct.cast(val, &mut buf).map_err(|e| anyhow!("field {} of row {}: {}", field, row, e))? {
+      Value::Bytes(v) if ct == ColType::Text => self.out.write_text_field(v)?,
+      Value::Bool(v) => self.out.write_bool_field(v)?,
+      Value::Int32(v) if ct == ColType::Int32 => self.out.write_int4_field(v)?,
+      Value::Int64(v) if ct == ColType::Int64 => self.out.write_int8_field(v)?,
+      Value::Float(v) => self.out.write_float4_field(v)?,
+      Value::Double(v) => self.out.write_float8_field(v)?,
+      _ => return Err(anyhow!("field {} of row {}: {:?} columns are not supported in binary format", field, row, ct))
+    }
+    Ok(())
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    self.next_type()?;
+    self.out.write_null_field()?;
+    Ok(())
+  }
+
+  fn write_array(&mut self, _vals: &[String]) -> Result<()> {
+    Err(anyhow!("field {} of row {}: arrays are not supported in binary format", self.field + 1, self.rows + 1))
+  }
+
+  fn write_json(&mut self, _val: &serde_json::Value) -> Result<()> {
+    Err(anyhow!("field {} of row {}: JSON is not supported in binary format", self.field + 1, self.rows + 1))
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    if self.field != self.types.len() {
+      return Err(anyhow!("row {} has {} fields, expected {}", self.rows + 1, self.field, self.types.len()));
+    }
+    self.field = 0;
+    self.rows += 1;
+    Ok(())
+  }
+
+  fn finish(mut self: Box<Self>) -> Result<usize> {
+    if self.field > 0 {
+      return Err(anyhow!("unfinished row {}", self.rows + 1));
+    }
+    self.out.write_copy_trailer()?;
+    self.out.flush()?;
+    Ok(self.rows)
+  }
+}
+
+impl <W: Write + Send> RowSink for TableWriter<W> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    TableWriter::write_cast(self, Value::Bytes(val))
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    TableWriter::write_cast(self, val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    TableWriter::write_null(self)
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    TableWriter::write_field(self, serde_json::to_string(vals)?.as_bytes())
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    TableWriter::write_field(self, val.to_string().as_bytes())
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    TableWriter::end_row(self)
+  }
+
+  fn finish(self: Box<Self>) -> Result<usize> {
+    TableWriter::finish(*self)
+  }
+}
+
+impl <W: Write> RowSink for StreamWriter<W> {
+  fn write_field(&mut self, val: &[u8]) -> Result<()> {
+    StreamWriter::write_cast(self, Value::Bytes(val))
+  }
+
+  fn write_value(&mut self, val: Value) -> Result<()> {
+    StreamWriter::write_cast(self, val)
+  }
+
+  fn write_null(&mut self) -> Result<()> {
+    StreamWriter::write_null(self)
+  }
+
+  fn write_array(&mut self, vals: &[String]) -> Result<()> {
+    StreamWriter::write_field(self, serde_json::to_string(vals)?.as_bytes())
+  }
+
+  fn write_json(&mut self, val: &serde_json::Value) -> Result<()> {
+    StreamWriter::write_field(self, val.to_string().as_bytes())
+  }
+
+  fn end_row(&mut self) -> Result<()> {
+    StreamWriter::end_row(self)
+  }
+
+  fn finish(self: Box<Self>) -> Result<usize> {
+    StreamWriter::finish(*self)
+  }
+}
+
+#[test]
+fn test_pg_sink() {
+  let mut sink = PGSink::new(Vec::new());
+  sink.write_field(b"a\tb").unwrap();
+  sink.write_null().unwrap();
+  sink.write_array(&["x".to_string(), "y".to_string()]).unwrap();
+  sink.write_value(Value::Float(2.5)).unwrap();
+  sink.end_row().unwrap();
+  assert_eq!(String::from_utf8(sink.out).unwrap(), "a\\tb\t\\N\t{\"x\",\"y\"}\t2.5\n");
+}
+
+#[test]
+fn test_binary_sink() {
+  let mut out = Vec::new();
+  let mut sink = Box::new(BinarySink::create(&mut out, &[ColType::Int32, ColType::Text]).unwrap());
+  sink.write_field(b"7").unwrap();
+  sink.write_null().unwrap();
+  sink.end_row().unwrap();
+  assert_eq!(sink.finish().unwrap(), 1);
+  // header, tuple, and trailer
+  assert_eq!(out.len(), 19 + 2 + 8 + 4 + 2);
+  assert_eq!(&out[19..29], &[0, 2, 0, 0, 0, 4, 0, 0, 0, 7]);
+  let mut sink = BinarySink::create(Vec::new(), &[ColType::Int32]).unwrap();
+  assert!(sink.write_field(b"x").is_err());
+  let mut sink = BinarySink::create(Vec::new(), &[ColType::Date]).unwrap();
+  assert!(sink.write_value(Value::Int32(1)).is_err());
+  assert!(BinarySink::create(Vec::new(), &[ColType::Int32, ColType::Json]).is_err());
+  let mut sink = BinarySink::create(Vec::new(), &[ColType::Text]).unwrap();
+  assert!(sink.write_array(&["x".to_string()]).is_err());
+  assert!(sink.write_json(&serde_json::json!({"a": 1})).is_err());
+}
@@ -0,0 +1,137 @@
+//! End-of-run summary of time, throughput, and memory use.
+//!
+//! Copies and output sinks record their rows and timing here as they finish,
+//! and the main program reports the totals, with the bytes and records read
+//! from the progress counters, when the command exits.
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use log::*;
+use anyhow::Result;
+use indicatif::HumanBytes;
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::logging;
+
+/// Rows written to tables and output files
+static OUTPUT_ROWS: AtomicU64 = AtomicU64::new(0);
+/// Names and times of the finished stages
+static STAGES: Mutex<Vec<StageTime>> = Mutex::new(Vec::new());
+
+/// Options for the end-of-run summary.
+#[derive(StructOpt, Debug)]
+pub struct SummaryOpts {
+  /// Also write the end-of-run summary as JSON to this file
+  #[structopt(long="summary-json", parse(from_os_str))]
+  summary_json: Option<PathBuf>
+}
+
+/// The time taken by a stage of the run.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StageTime {
+  name: String,
+  secs: f64
+}
+
+/// Summary of a command's run.
+#[derive(Serialize, Debug)]
+pub struct Summary {
+  command: String,
+  wall_secs: f64,
+  input_bytes: u64,
+  input_records: u64,
+  output_rows: u64,
+  peak_rss_bytes: Option<u64>,
+  stages: Vec<StageTime>
+}
+
+/// Record that a stage of the run finished.
+pub fn record_stage(name: &str, elapsed: Duration) {
+  if let Ok(mut stages) = STAGES.lock() {
+    stages.push(StageTime { name: name.to_string(), secs: elapsed.as_secs_f64() });
+  }
+}
+
+/// Record the rows written to a table or file, and how long it took.
+pub fn record_output(name: &str, rows: u64, elapsed: Duration) {
+  OUTPUT_ROWS.fetch_add(rows, Ordering::Relaxed);
+  record_stage(name, elapsed);
+}
+
+/// Get the rows written to tables and files so far.
+pub fn output_rows() -> u64 {
+  OUTPUT_ROWS.load(Ordering::Relaxed)
+}
+
+/// Get the peak resident set size from `/proc/self/status`.
+fn parse_peak_rss(status: &str) -> Option<u64> {
+  let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+  let kb: u64 = line["VmHWM:".len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+  Some(kb * 1024)
+}
+
+/// Get this process's peak resident set size, where the OS reports it.
+pub fn peak_rss() -> Option<u64> {
+  fs::read_to_string("/proc/self/status").ok().and_then(|s| parse_peak_rss(&s))
+}
+
+impl Summary {
+  /// Summarize the run so far of a command started at `started`.
+  pub fn collect(command: &str, started: Instant) -> Summary {
+    let (input_bytes, input_records) = logging::progress_totals();
+    Summary {
+      command: command.to_string(),
+      wall_secs: started.elapsed().as_secs_f64(),
+      input_bytes,
+      input_records,
+      output_rows: output_rows(),
+      peak_rss_bytes: peak_rss(),
+      stages: STAGES.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+  }
+
+  /// Write the summary to the log.
+  pub fn log(&self) {
+    let rss = match self.peak_rss_bytes {
+      Some(b) => HumanBytes(b).to_string(),
+      None => "unknown".to_string()
+    };
+    info!("{} finished in {:.1}s: read {} and {} records, wrote {} rows, peak memory {}",
+          self.command, self.wall_secs, HumanBytes(self.input_bytes), self.input_records,
+          self.output_rows, rss);
+    for st in &self.stages {
+      info!("  {}: {:.1}s", st.name, st.secs);
+    }
+  }
+}
+
+impl SummaryOpts {
+  /// Report the summary of a command's run.
+  pub fn report(&self, command: &str, started: Instant) -> Result<()> {
+    let summary = Summary::collect(command, started);
+    summary.log();
+    if let Some(ref path) = self.summary_json {
+      serde_json::to_writer_pretty(File::create(path)?, &summary)?;
+    }
+    Ok(())
+  }
+}
+
+#[test]
+fn test_summary() {
+  let status = "Name:\tbookdata\nVmPeak:\t  20480 kB\nVmHWM:\t    1536 kB\nVmRSS:\t    1024 kB\n";
+  assert_eq!(parse_peak_rss(status), Some(1536 * 1024));
+  assert_eq!(parse_peak_rss("Name:\tbookdata\n"), None);
+
+  record_output("gr.review", 10, Duration::from_millis(1500));
+  let summary = Summary::collect("import-gr-reviews", Instant::now());
+  assert!(summary.output_rows >= 10);
+  assert!(summary.stages.contains(&StageTime { name: "gr.review".to_string(), secs: 1.5 }));
+  let json = serde_json::to_value(&summary).unwrap();
+  assert_eq!(json["command"], "import-gr-reviews");
+  assert!(json["stages"].is_array());
+}
@@ -2,15 +2,18 @@ use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::fs::OpenOptions;
+use std::process;
+use std::time::Instant;
 
 use anyhow::Result;
-use sha1::Sha1;
+use sha2::{Sha256, Digest};
 use structopt::StructOpt;
 use postgres::Connection;
 
 use log::*;
 
-use crate::io::HashRead;
+use crate::io::{HashRead, is_url, sha256_file, sha256_hex};
+use crate::summary;
 
 /// Options controlling the import stage
 #[derive(StructOpt, Debug, Clone)]
@@ -26,27 +29,37 @@ pub struct StageOpts {
   /// Transcript file
   #[structopt(long="transcript", short="T")]
   transcript: Option<PathBuf>,
+
+  /// Skip input files that have already been imported with the same checksum
+  #[structopt(long="skip-imported")]
+  skip_imported: bool
 }
 
 /// An import stage.  Writing to the stage writes to its transcript file.
 pub struct Stage<'o, 'c> {
   options: &'o StageOpts,
   cxn: Option<&'c Connection>,
-  transcript: Box<dyn io::Write>
+  transcript: Box<dyn io::Write>,
+  started: Instant
 }
 
 /// A source file for a stage
 pub struct StageSource<'s> {
   stage: &'s Stage<'s, 's>,
   path: String,
-  hash: Sha1
+  hash: Sha256
 }
 
 impl StageOpts {
   /// Start the stage
   pub fn begin_stage<'o, 'c>(&'o self, cxn: &'c Connection) -> Result<Stage<'o, 'c>> {
-    match self.stage {
-      Some (ref s) => {
+    self.begin_stage_opt(Some(cxn))
+  }
+
+  /// Start the stage, recording it in the database if there is a connection.
+  pub fn begin_stage_opt<'o, 'c>(&'o self, cxn: Option<&'c Connection>) -> Result<Stage<'o, 'c>> {
+    match (&self.stage, cxn) {
+      (Some(ref s), Some(cxn)) => {
         info!("beginning stage {}", s);
         cxn.execute("INSERT INTO stage_status (stage_name)
                      VALUES ($1)
@@ -55,13 +68,17 @@ impl StageOpts {
                     &[s])?;
         cxn.execute("DELETE FROM stage_file WHERE stage_name = $1", &[s])?;
         cxn.execute("DELETE FROM stage_dep WHERE stage_name = $1", &[s])?;
+        cxn.execute("DELETE FROM stage_table WHERE stage_name = $1", &[s])?;
         for d in &self.deps {
           cxn.execute("INSERT INTO stage_dep (stage_name, dep_name, dep_key)
                        SELECT $1, stage_name, stage_key
                        FROM stage_status WHERE stage_name = $2", &[s, &d])?;
         }
       },
-      None => {
+      (Some(ref s), None) => {
+        warn!("stage {} has no database, not recording", s);
+      },
+      (None, _) => {
         warn!("no stage specified");
       }
     };
@@ -73,17 +90,43 @@ impl StageOpts {
     };
     Ok(Stage {
       options: self,
-      cxn: Some(cxn),
-      transcript: w
+      cxn,
+      transcript: w,
+      started: Instant::now()
     })
   }
 
+  /// Check whether a tool has already imported a file with its current contents.
+  /// This is only checked with `--skip-imported` and a database connection.
+  pub fn should_skip<P: AsRef<Path>>(&self, cxn: Option<&Connection>, tool: &str, path: P) -> Result<bool> {
+    let cxn = match cxn {
+      Some(c) if self.skip_imported => c,
+      _ => return Ok(false)
+    };
+    let path = path.as_ref();
+    if is_url(path) {
+      debug!("not checking import status of URL {:?}", path);
+      return Ok(false);
+    }
+    let hash = sha256_file(path)?;
+    let fname = path.to_string_lossy().to_string();
+    let rows = cxn.query("SELECT COUNT(*) FROM import_log
+                          WHERE tool = $1 AND filename = $2 AND sha256 = $3",
+                         &[&tool, &fname, &hash])?;
+    let n: i64 = rows.get(0).get(0);
+    if n > 0 {
+      info!("{} already imported {:?} ({}), skipping", tool, path, hash);
+    }
+    Ok(n > 0)
+  }
+
   /// Create a no-op stage.
-  pub fn empty<'o>(&'o self) -> Stage<'o, 'o> {
+  pub fn empty<'o, 'c>(&'o self) -> Stage<'o, 'c> {
     Stage {
       options: self,
       cxn: None,
-      transcript: Box::new(io::stderr())
+      transcript: Box::new(io::stderr()),
+      started: Instant::now()
     }
   }
 }
@@ -105,6 +148,8 @@ impl <'o, 'c> Write for Stage<'o, 'c> {
 impl <'o,'c> Stage<'o,'c> {
   /// End the stage
   pub fn end(self, key: &Option<String>) -> Result<()> {
+    let name = self.options.stage.as_deref().unwrap_or("stage");
+    summary::record_stage(name, self.started.elapsed());
     match self.options.stage {
       Some (ref s) => {
         info!("finishing stage {}", s);
@@ -127,6 +172,49 @@ impl <'o,'c> Stage<'o,'c> {
     }
   }
 
+  /// Record a dependency of this stage, returning the dependency's key.
+  pub fn record_dep(&self, dep: &str) -> Result<Option<String>> {
+    let key = self.db_action(|db| {
+      if let Some(ref s) = self.options.stage {
+        db.execute("INSERT INTO stage_dep (stage_name, dep_name, dep_key)
+                    SELECT $1, stage_name, stage_key
+                    FROM stage_status WHERE stage_name = $2", &[s, &dep])?;
+      }
+      let rows = db.query("SELECT stage_key FROM stage_status WHERE stage_name = $1", &[&dep])?;
+      let key: Option<String> = if rows.is_empty() { None } else { rows.get(0).get(0) };
+      Ok(key)
+    })?;
+    Ok(key.flatten())
+  }
+
+  /// Record a table created by this stage.
+  pub fn record_table(&self, ns: &str, table: &str) -> Result<()> {
+    if let Some(ref s) = self.options.stage {
+      self.db_action(|db| {
+        db.execute("INSERT INTO stage_table (stage_name, st_ns, st_name)
+                    VALUES ($1, $2, $3)", &[s, &ns, &table])?;
+        Ok(())
+      })?;
+    }
+    Ok(())
+  }
+
+  /// Record a completed import run in the import log.
+  pub fn log_import<P: AsRef<Path>>(&self, tool: &str, path: P, hash: &str, rows: usize, started: Instant) -> Result<()> {
+    let fname = path.as_ref().to_string_lossy().to_string();
+    let rows = rows as i64;
+    let secs = started.elapsed().as_secs_f64();
+    let rev = git_revision();
+    info!("logging import of {} rows from {} in {:.1}s", rows, fname, secs);
+    self.db_action(|db| {
+      db.execute("INSERT INTO import_log (tool, filename, sha256, stage_name, n_rows, git_rev, run_secs)
+                  VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                 &[&tool, &fname, &hash, &self.options.stage, &rows, &rev, &secs])?;
+      Ok(())
+    })?;
+    Ok(())
+  }
+
   /// Record a source file with its hash
   pub fn record_file<P: AsRef<Path>>(&self, path: P, hash: &str) -> Result<()> {
     let sf = self.source_file(path);
@@ -140,20 +228,20 @@ impl <'o,'c> Stage<'o,'c> {
     StageSource {
       stage: self,
       path: path.to_string_lossy().to_string(),
-      hash: Sha1::new()
+      hash: Sha256::new()
     }
   }
 }
 
 impl <'s> StageSource<'s> {
   /// Wrap a reader to compute this file's hash
-  pub fn wrap_read<'a, R: io::Read>(&'a mut self, read: R) -> HashRead<'a, R> {
+  pub fn wrap_read<'a, R: io::Read>(&'a mut self, read: R) -> HashRead<'a, R, Sha256> {
     HashRead::create(read, &mut self.hash)
   }
 
   /// Record the accumulated file hash (and return it)
   pub fn record(self) -> Result<String> {
-    let hash = self.hash.hexdigest();
+    let hash = sha256_hex(self.hash.clone());
     self.record_hash(&hash)?;
     Ok(hash)
   }
@@ -180,3 +268,13 @@ impl <'s> StageSource<'s> {
     Ok(())
   }
 }
+
+/// Get the Git revision of the working directory, if there is one.
+fn git_revision() -> Option<String> {
+  let out = process::Command::new("git").args(["rev-parse", "HEAD"]).output().ok()?;
+  if out.status.success() {
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+  } else {
+    None
+  }
+}
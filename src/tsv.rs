@@ -1,10 +1,594 @@
+//! Tab-separated rows in PostgreSQL text format.
+//!
+//! Besides splitting lines into fields with [FieldParser], this module writes any `Serialize` struct (or tuple)
+//! as a row, with `None` as `\N` and text fields PostgreSQL-encoded, and reads
+//! such rows back into `Deserialize` types.  Fields must be scalars.  The same
+//! rows can go to any [RowSink] with [write_record].
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::vec;
+
+use memchr::memchr;
+use serde::{ser, de, Serialize};
+use serde::de::{DeserializeOwned, DeserializeSeed, Visitor, IntoDeserializer};
+use anyhow::{anyhow, Result};
+
+use crate::cleaning::decode_pgencoded;
+use crate::columnar::Value;
+use crate::sink::{RowSink, PGSink};
+
+/// Errors serializing or deserializing rows.
+#[derive(Debug)]
+pub struct TsvError(String);
+
+impl fmt::Display for TsvError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for TsvError {}
+
+impl ser::Error for TsvError {
+  fn custom<T: fmt::Display>(msg: T) -> TsvError {
+    TsvError(msg.to_string())
+  }
+}
+
+impl de::Error for TsvError {
+  fn custom<T: fmt::Display>(msg: T) -> TsvError {
+    TsvError(msg.to_string())
+  }
+}
+
+impl From<std::io::Error> for TsvError {
+  fn from(e: std::io::Error) -> TsvError {
+    TsvError(e.to_string())
+  }
+}
+
+impl From<anyhow::Error> for TsvError {
+  fn from(e: anyhow::Error) -> TsvError {
+    TsvError(e.to_string())
+  }
+}
+
+type TResult<T> = std::result::Result<T, TsvError>;
+
+fn unsupported<T>(what: &str) -> TResult<T> {
+  Err(TsvError(format!("{} cannot be a row field", what)))
+}
+
+/// Split a line at its first tab.
 pub fn split_first<'a>(line: &'a str) -> Option<(&'a str, &'a str)> {
-  match line.find('\t') {
+  match memchr(b'\t', line.as_bytes()) {
     Some(i) => Some((&line[0..i], &line[(i+1)..])),
     None => None
   }
 }
 
+/// How backslashes in fields are treated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escapes {
+  /// Backslashes are data.  OpenLibrary dumps are like this: their JSON column
+  /// has its own escapes, which must reach the JSON parser untouched.
+  Raw,
+  /// A backslash escapes a quote inside a quoted field, as in the BookCrossing
+  /// books file.  Since some of its titles end in a stray backslash, `\"` right
+  /// before a delimiter closes the field.
+  Backslash,
+  /// PostgreSQL text format: escapes are decoded, an escaped delimiter does not
+  /// end the field, and `\N` is NULL.
+  Postgres
+}
+
+/// Parser splitting delimited lines into fields.
+///
+/// By default, fields are separated by tabs with no escaping or quoting.  With a
+/// quote character, a field starting with a quote runs to the matching quote,
+/// so it can contain delimiters, and a doubled quote inside it is a literal
+/// quote.  With a maximum field count, the last field takes the rest of the
+/// line, delimiters and all.
+#[derive(Debug, Clone)]
+pub struct FieldParser {
+  delim: char,
+  escapes: Escapes,
+  quote: Option<char>,
+  max_fields: Option<usize>
+}
+
+impl Default for FieldParser {
+  fn default() -> FieldParser {
+    FieldParser { delim: '\t', escapes: Escapes::Raw, quote: None, max_fields: None }
+  }
+}
+
+impl FieldParser {
+  /// Parser for PostgreSQL text format rows.
+  pub fn postgres() -> FieldParser {
+    FieldParser::default().escapes(Escapes::Postgres)
+  }
+
+  /// Set the field delimiter.
+  pub fn delim(self, delim: char) -> FieldParser {
+    FieldParser { delim, ..self }
+  }
+
+  /// Set how backslashes are treated.
+  pub fn escapes(self, escapes: Escapes) -> FieldParser {
+    FieldParser { escapes, ..self }
+  }
+
+  /// Allow fields to be quoted.
+  pub fn quote(self, quote: char) -> FieldParser {
+    FieldParser { quote: Some(quote), ..self }
+  }
+
+  /// Split at most `n` fields, leaving the rest of the line in the last one.
+  pub fn max_fields(self, n: usize) -> FieldParser {
+    FieldParser { max_fields: Some(n), ..self }
+  }
+
+  /// Split a line (without its newline) into fields.  NULL fields are `None`;
+  /// fields that need no unescaping borrow from the line.
+  pub fn split<'a>(&self, line: &'a str) -> Result<Vec<Option<Cow<'a, str>>>> {
+    let mut fields = Vec::new();
+    let mut rest = line;
+    loop {
+      let last = self.max_fields.map(|n| fields.len() + 1 >= n).unwrap_or(false);
+      let (field, next) = match self.quote {
+        Some(q) if rest.starts_with(q) => self.quoted(rest, q)?,
+        _ if last => (self.decode(rest)?, None),
+        _ => {
+          let end = self.field_end(rest);
+          (self.decode(&rest[..end])?, rest.get(end + self.delim.len_utf8()..).filter(|_| end < rest.len()))
+        }
+      };
+      fields.push(field);
+      match next {
+        Some(n) => rest = n,
+        None => return Ok(fields)
+      }
+    }
+  }
+
+  /// Find the end of an unquoted field.
+  fn field_end(&self, text: &str) -> usize {
+    if self.escapes != Escapes::Postgres && self.delim.is_ascii() {
+      return memchr(self.delim as u8, text.as_bytes()).unwrap_or(text.len());
+    }
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+      if c == self.delim {
+        return i;
+      } else if c == '\\' && self.escapes == Escapes::Postgres {
+        chars.next();
+      }
+    }
+    text.len()
+  }
+
+  /// Parse a quoted field, returning it and the text after its delimiter.
+  fn quoted<'a>(&self, text: &'a str, q: char) -> Result<(Option<Cow<'a, str>>, Option<&'a str>)> {
+    let body = &text[q.len_utf8()..];
+    let mut value = String::new();
+    let mut chars = body.char_indices().peekable();
+    let close = loop {
+      let (i, c) = chars.next().ok_or(anyhow!("unterminated quoted field"))?;
+      let next = chars.peek().map(|(j, c)| (*j, *c));
+      match next {
+        Some((j, n)) if n == q && c == '\\' && self.escapes == Escapes::Backslash => {
+          chars.next();
+          match chars.peek() {
+            // a stray backslash right before the closing quote
+            None => break j,
+            Some((_, d)) if *d == self.delim => break j,
+            _ => value.push(q)
+          }
+        },
+        Some((_, n)) if n == q && c == q => {
+          chars.next();
+          value.push(q);
+        },
+        _ if c == q => break i,
+        _ => value.push(c)
+      }
+    };
+    let after = &body[close + q.len_utf8()..];
+    let field = Some(Cow::Owned(value));
+    match after.chars().next() {
+      None => Ok((field, None)),
+      Some(d) if d == self.delim => Ok((field, Some(&after[d.len_utf8()..]))),
+      Some(c) => Err(anyhow!("unexpected character {:?} after quoted field", c))
+    }
+  }
+
+  /// Decode an unquoted field.
+  fn decode<'a>(&self, text: &'a str) -> Result<Option<Cow<'a, str>>> {
+    match self.escapes {
+      Escapes::Raw | Escapes::Backslash => Ok(Some(Cow::Borrowed(text))),
+      Escapes::Postgres if text == "\\N" => Ok(None),
+      Escapes::Postgres if !text.contains('\\') => Ok(Some(Cow::Borrowed(text))),
+      Escapes::Postgres => {
+        let mut buf = Vec::with_capacity(text.len());
+        decode_pgencoded(text.as_bytes(), &mut buf)?;
+        Ok(Some(Cow::Owned(String::from_utf8(buf)?)))
+      }
+    }
+  }
+}
+
+/// Write a row (a struct, tuple, or sequence of scalar fields) as a line.
+pub fn write_row<W: Write, T: Serialize>(out: &mut W, row: &T) -> Result<()> {
+  write_record(&mut PGSink::new(out), row)
+}
+
+/// Write a row (a struct, tuple, or sequence of scalar fields) to a row sink.
+/// Numbers and booleans are written as values, so sinks with typed columns
+/// store them as such.
+pub fn write_record<S: RowSink + ?Sized, T: Serialize>(sink: &mut S, row: &T) -> Result<()> {
+  row.serialize(RowSer { sink: &mut *sink })?;
+  sink.end_row()
+}
+
+/// Serializer for the fields of a row.
+struct RowSer<'a, S: RowSink + ?Sized> {
+  sink: &'a mut S
+}
+
+impl <'a, S: RowSink + ?Sized> RowSer<'a, S> {
+  fn field<T: Serialize + ?Sized>(&mut self, value: &T) -> TResult<()> {
+    value.serialize(FieldSer { sink: &mut *self.sink })
+  }
+}
+
+macro_rules! row_compound {
+  ($trait:ident, $method:ident) => {
+    impl <'a, S: RowSink + ?Sized> ser::$trait for RowSer<'a, S> {
+      type Ok = ();
+      type Error = TsvError;
+
+      fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> TResult<()> {
+        self.field(value)
+      }
+
+      fn end(self) -> TResult<()> {
+        Ok(())
+      }
+    }
+  };
+}
+
+row_compound!(SerializeSeq, serialize_element);
+row_compound!(SerializeTuple, serialize_element);
+row_compound!(SerializeTupleStruct, serialize_field);
+
+impl <'a, S: RowSink + ?Sized> ser::SerializeStruct for RowSer<'a, S> {
+  type Ok = ();
+  type Error = TsvError;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> TResult<()> {
+    self.field(value)
+  }
+
+  fn end(self) -> TResult<()> {
+    Ok(())
+  }
+}
+
+/// Serialize a scalar row as a single field.
+macro_rules! row_scalar {
+  ($($method:ident: $t:ty),*) => {
+    $(fn $method(mut self, v: $t) -> TResult<()> {
+      self.field(&v)
+    })*
+  };
+}
+
+impl <'a, S: RowSink + ?Sized> ser::Serializer for RowSer<'a, S> {
+  type Ok = ();
+  type Error = TsvError;
+  type SerializeSeq = Self;
+  type SerializeTuple = Self;
+  type SerializeTupleStruct = Self;
+  type SerializeTupleVariant = ser::Impossible<(), TsvError>;
+  type SerializeMap = ser::Impossible<(), TsvError>;
+  type SerializeStruct = Self;
+  type SerializeStructVariant = ser::Impossible<(), TsvError>;
+
+  row_scalar!(serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+              serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64,
+              serialize_f32: f32, serialize_f64: f64, serialize_char: char, serialize_str: &str, serialize_bytes: &[u8]);
+
+  fn serialize_none(mut self) -> TResult<()> {
+    self.field(&None::<()>)
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> TResult<()> {
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> TResult<()> {
+    Ok(())
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> TResult<()> {
+    Ok(())
+  }
+
+  fn serialize_unit_variant(mut self, _name: &'static str, _idx: u32, variant: &'static str) -> TResult<()> {
+    self.field(variant)
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> TResult<()> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _idx: u32, _variant: &'static str, _value: &T) -> TResult<()> {
+    unsupported("an enum variant with data")
+  }
+
+  fn serialize_seq(self, _len: Option<usize>) -> TResult<Self> {
+    Ok(self)
+  }
+
+  fn serialize_tuple(self, _len: usize) -> TResult<Self> {
+    Ok(self)
+  }
+
+  fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> TResult<Self> {
+    Ok(self)
+  }
+
+  fn serialize_tuple_variant(self, _name: &'static str, _idx: u32, _variant: &'static str, _len: usize) -> TResult<Self::SerializeTupleVariant> {
+    unsupported("an enum variant with data")
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> TResult<Self::SerializeMap> {
+    unsupported("a map")
+  }
+
+  fn serialize_struct(self, _name: &'static str, _len: usize) -> TResult<Self> {
+    Ok(self)
+  }
+
+  fn serialize_struct_variant(self, _name: &'static str, _idx: u32, _variant: &'static str, _len: usize) -> TResult<Self::SerializeStructVariant> {
+    unsupported("an enum variant with data")
+  }
+}
+
+/// Serializer for a single field value.
+struct FieldSer<'a, S: RowSink + ?Sized> {
+  sink: &'a mut S
+}
+
+/// Serialize numbers as the value type that holds them.
+macro_rules! field_value {
+  ($($method:ident: $t:ty => $var:ident),*) => {
+    $(fn $method(self, v: $t) -> TResult<()> {
+      self.sink.write_value(Value::$var(v.into()))?;
+      Ok(())
+    })*
+  };
+}
+
+impl <'a, S: RowSink + ?Sized> ser::Serializer for FieldSer<'a, S> {
+  type Ok = ();
+  type Error = TsvError;
+  type SerializeSeq = ser::Impossible<(), TsvError>;
+  type SerializeTuple = ser::Impossible<(), TsvError>;
+  type SerializeTupleStruct = ser::Impossible<(), TsvError>;
+  type SerializeTupleVariant = ser::Impossible<(), TsvError>;
+  type SerializeMap = ser::Impossible<(), TsvError>;
+  type SerializeStruct = ser::Impossible<(), TsvError>;
+  type SerializeStructVariant = ser::Impossible<(), TsvError>;
+
+  field_value!(serialize_i8: i8 => Int32, serialize_i16: i16 => Int32, serialize_i32: i32 => Int32, serialize_i64: i64 => Int64,
+               serialize_u8: u8 => Int32, serialize_u16: u16 => Int32, serialize_u32: u32 => Int64,
+               serialize_f32: f32 => Float, serialize_f64: f64 => Double, serialize_bool: bool => Bool);
+
+  fn serialize_u64(self, v: u64) -> TResult<()> {
+    match i64::try_from(v) {
+      Ok(n) => self.sink.write_value(Value::Int64(n))?,
+      Err(_) => self.sink.write_field(v.to_string().as_bytes())?
+    }
+    Ok(())
+  }
+
+  fn serialize_char(self, v: char) -> TResult<()> {
+    let mut buf = [0; 4];
+    self.serialize_str(v.encode_utf8(&mut buf))
+  }
+
+  fn serialize_str(self, v: &str) -> TResult<()> {
+    self.sink.write_field(v.as_bytes())?;
+    Ok(())
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> TResult<()> {
+    self.sink.write_field(v)?;
+    Ok(())
+  }
+
+  fn serialize_none(self) -> TResult<()> {
+    self.sink.write_null()?;
+    Ok(())
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> TResult<()> {
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> TResult<()> {
+    self.serialize_none()
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> TResult<()> {
+    self.serialize_none()
+  }
+
+  fn serialize_unit_variant(self, _name: &'static str, _idx: u32, variant: &'static str) -> TResult<()> {
+    self.serialize_str(variant)
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> TResult<()> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _idx: u32, _variant: &'static str, _value: &T) -> TResult<()> {
+    unsupported("an enum variant with data")
+  }
+
+  fn serialize_seq(self, _len: Option<usize>) -> TResult<Self::SerializeSeq> {
+    unsupported("a sequence")
+  }
+
+  fn serialize_tuple(self, _len: usize) -> TResult<Self::SerializeTuple> {
+    unsupported("a tuple")
+  }
+
+  fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> TResult<Self::SerializeTupleStruct> {
+    unsupported("a tuple struct")
+  }
+
+  fn serialize_tuple_variant(self, _name: &'static str, _idx: u32, _variant: &'static str, _len: usize) -> TResult<Self::SerializeTupleVariant> {
+    unsupported("an enum variant with data")
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> TResult<Self::SerializeMap> {
+    unsupported("a map")
+  }
+
+  fn serialize_struct(self, _name: &'static str, _len: usize) -> TResult<Self::SerializeStruct> {
+    unsupported("a struct")
+  }
+
+  fn serialize_struct_variant(self, _name: &'static str, _idx: u32, _variant: &'static str, _len: usize) -> TResult<Self::SerializeStructVariant> {
+    unsupported("an enum variant with data")
+  }
+}
+
+/// Read a row (without its newline) into a struct, tuple, or sequence.
+pub fn read_row<T: DeserializeOwned>(line: &str) -> Result<T> {
+  let fields = FieldParser::postgres().split(line)?;
+  let mut de = RowDe { fields: fields.into_iter() };
+  let row = T::deserialize(&mut de)?;
+  let extra = de.fields.count();
+  if extra > 0 {
+    return Err(TsvError(format!("row has {} extra fields", extra)).into());
+  }
+  Ok(row)
+}
+
+/// Read the leading fields of a row into a struct or tuple, ignoring any
+/// fields after them.
+pub fn read_row_prefix<T: DeserializeOwned>(line: &str) -> Result<T> {
+  let fields = FieldParser::postgres().split(line)?;
+  let mut de = RowDe { fields: fields.into_iter() };
+  Ok(T::deserialize(&mut de)?)
+}
+
+/// Deserializer for the fields of a row.
+struct RowDe<'a> {
+  fields: vec::IntoIter<Option<Cow<'a, str>>>
+}
+
+impl <'de, 'a> de::Deserializer<'de> for &mut RowDe<'a> {
+  type Error = TsvError;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> TResult<V::Value> {
+    visitor.visit_seq(self)
+  }
+
+  serde::forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf option unit unit_struct newtype_struct seq tuple
+    tuple_struct map struct enum identifier ignored_any
+  }
+}
+
+impl <'de, 'a> de::SeqAccess<'de> for RowDe<'a> {
+  type Error = TsvError;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> TResult<Option<T::Value>> {
+    match self.fields.next() {
+      Some(f) => seed.deserialize(FieldDe { value: f.map(Cow::into_owned) }).map(Some),
+      None => Ok(None)
+    }
+  }
+}
+
+/// Deserializer for a single decoded field; `None` is a NULL.
+struct FieldDe {
+  value: Option<String>
+}
+
+impl FieldDe {
+  fn text(&self) -> TResult<&str> {
+    self.value.as_deref().ok_or(TsvError("unexpected NULL".to_string()))
+  }
+}
+
+/// Deserialize numbers by parsing them.
+macro_rules! field_parse {
+  ($($method:ident => $visit:ident),*) => {
+    $(fn $method<V: Visitor<'de>>(self, visitor: V) -> TResult<V::Value> {
+      let t = self.text()?;
+      visitor.$visit(t.parse().map_err(|e| TsvError(format!("invalid number {:?}: {}", t, e)))?)
+    })*
+  };
+}
+
+impl <'de> de::Deserializer<'de> for FieldDe {
+  type Error = TsvError;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> TResult<V::Value> {
+    match self.value {
+      Some(v) => visitor.visit_string(v),
+      None => visitor.visit_none()
+    }
+  }
+
+  field_parse!(deserialize_i8 => visit_i8, deserialize_i16 => visit_i16, deserialize_i32 => visit_i32,
+               deserialize_i64 => visit_i64, deserialize_u8 => visit_u8, deserialize_u16 => visit_u16,
+               deserialize_u32 => visit_u32, deserialize_u64 => visit_u64,
+               deserialize_f32 => visit_f32, deserialize_f64 => visit_f64);
+
+  fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> TResult<V::Value> {
+    match self.text()? {
+      "t" | "true" => visitor.visit_bool(true),
+      "f" | "false" => visitor.visit_bool(false),
+      v => Err(TsvError(format!("invalid boolean {:?}", v)))
+    }
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> TResult<V::Value> {
+    match self.value {
+      Some(_) => visitor.visit_some(self),
+      None => visitor.visit_none()
+    }
+  }
+
+  fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> TResult<V::Value> {
+    visitor.visit_unit()
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> TResult<V::Value> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> TResult<V::Value> {
+    visitor.visit_enum(self.text()?.to_string().into_deserializer())
+  }
+
+  serde::forward_to_deserialize_any! {
+    i128 u128 char str string bytes byte_buf unit_struct seq tuple
+    tuple_struct map struct identifier ignored_any
+  }
+}
+
 #[test]
 fn split_empty() {
   assert_eq!(split_first(""), None)
@@ -24,3 +608,127 @@ fn split_end() {
 fn split_2() {
   assert_eq!(split_first("foo\tbar\tblatz"), Some(("foo", "bar\tblatz")))
 }
+
+#[cfg(test)]
+#[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+struct TestRow {
+  id: i64,
+  name: String,
+  score: Option<f32>,
+  flag: bool
+}
+
+#[test]
+fn write_struct_row() {
+  let mut out = Vec::new();
+  write_row(&mut out, &TestRow { id: 5, name: "a\tb".to_string(), score: None, flag: true }).unwrap();
+  write_row(&mut out, &(1, "x", Some(2.5))).unwrap();
+  assert_eq!(String::from_utf8(out).unwrap(), "5\ta\\tb\t\\N\tt\n1\tx\t2.5\n");
+}
+
+#[test]
+fn read_struct_row() {
+  let row: TestRow = read_row("5\ta\\tb\t\\N\tf").unwrap();
+  assert_eq!(row, TestRow { id: 5, name: "a\tb".to_string(), score: None, flag: false });
+  let row: (i32, Option<String>) = read_row("7\tfoo").unwrap();
+  assert_eq!(row, (7, Some("foo".to_string())));
+  assert!(read_row::<TestRow>("5\tx").is_err());
+  assert!(read_row::<(i32, i32)>("5\t6\t7").is_err());
+  assert!(read_row::<(i32, i32)>("5\tx").is_err());
+}
+
+#[test]
+fn read_row_prefix_extra() {
+  let row: (i32, Option<String>) = read_row_prefix("5\t6\t7").unwrap();
+  assert_eq!(row, (5, Some("6".to_string())));
+  assert!(read_row_prefix::<(i32, i32)>("5").is_err());
+}
+
+#[test]
+fn write_typed_record() {
+  use crate::columnar::ColType;
+  use crate::arrow::StreamWriter;
+  let mut sw = StreamWriter::create(Vec::new(), &[("id", ColType::Int32), ("name", ColType::Text), ("score", ColType::Double)]).unwrap();
+  write_record(&mut sw, &(5u32, "x", Some(2.5))).unwrap();
+  assert!(write_record(&mut sw, &("five", "x", 1.0)).is_err());
+}
+
+#[test]
+fn nested_fields_fail() {
+  let mut out = Vec::new();
+  assert!(write_row(&mut out, &(1, vec![2, 3])).is_err());
+}
+
+#[cfg(test)]
+fn fields<'a>(parsed: &'a [Option<Cow<'a, str>>]) -> Vec<Option<&'a str>> {
+  parsed.iter().map(|f| f.as_deref()).collect()
+}
+
+#[test]
+fn parse_plain() {
+  let p = FieldParser::default();
+  assert_eq!(fields(&p.split("").unwrap()), vec![Some("")]);
+  assert_eq!(fields(&p.split("a\tb\t").unwrap()), vec![Some("a"), Some("b"), Some("")]);
+}
+
+#[test]
+fn parse_ol_json_escapes() {
+  // the OpenLibrary dump's JSON keeps its own escapes, and \N is not NULL
+  let p = FieldParser::default().max_fields(5);
+  let line = r#"/type/edition	/books/OL1M	3	2010-04-14T02:54:21.558952	{"title": "Café \"Noir\"", "notes": "a\\b\nc", "x": "\N"}"#;
+  let f = p.split(line).unwrap();
+  assert_eq!(f.len(), 5);
+  assert_eq!(f[2].as_deref(), Some("3"));
+  assert_eq!(f[4].as_deref(), Some(r#"{"title": "Café \"Noir\"", "notes": "a\\b\nc", "x": "\N"}"#));
+}
+
+#[test]
+fn parse_ol_embedded_tab() {
+  // a raw tab inside the JSON column stays in it
+  let p = FieldParser::default().max_fields(5);
+  let f = p.split("/type/work\t/works/W1\t1\t2020\t{\"title\": \"a\tb\"}").unwrap();
+  assert_eq!(f[4].as_deref(), Some("{\"title\": \"a\tb\"}"));
+  // and short lines are just short
+  assert_eq!(p.split("/type/work\t/works/W1").unwrap().len(), 2);
+}
+
+#[test]
+fn parse_ol_empty_fields() {
+  let p = FieldParser::default().max_fields(5);
+  let f = p.split("/type/delete\t/books/OL2M\t2\t\t").unwrap();
+  assert_eq!(fields(&f), vec![Some("/type/delete"), Some("/books/OL2M"), Some("2"), Some(""), Some("")]);
+}
+
+#[test]
+fn parse_postgres() {
+  let p = FieldParser::postgres();
+  let f = p.split("a\\tb\t\\N\tc\\\\N\td\\\te").unwrap();
+  assert_eq!(fields(&f), vec![Some("a\tb"), None, Some("c\\N"), Some("d\te")]);
+  assert!(matches!(f[0], Some(Cow::Owned(_))));
+  assert!(p.split("bad\\").is_err());
+}
+
+#[test]
+fn parse_quoted() {
+  let p = FieldParser::default().delim(';').quote('"');
+  let f = p.split("\"0195153448\";\"Mythology; A Reader\";\"say \"\"hi\"\"\";;x").unwrap();
+  assert_eq!(fields(&f), vec![Some("0195153448"), Some("Mythology; A Reader"), Some("say \"hi\""), Some(""), Some("x")]);
+  assert!(p.split("\"1\";\"oops").is_err());
+  assert!(p.split("\"1\"x;2").is_err());
+}
+
+#[test]
+fn parse_backslash_quotes() {
+  let p = FieldParser::default().delim(';').quote('"').escapes(Escapes::Backslash);
+  let f = p.split("\"A \\\"Special\\\" Book\";\"Stray\\\";\"DK\\\"").unwrap();
+  assert_eq!(fields(&f), vec![Some("A \"Special\" Book"), Some("Stray"), Some("DK")]);
+  // without backslash escapes, the backslash is data
+  let p = FieldParser::default().delim(';').quote('"');
+  assert_eq!(fields(&p.split("\"a\\\";b").unwrap()), vec![Some("a\\"), Some("b")]);
+}
+
+#[test]
+fn read_row_escaped_delim() {
+  let row: (String, i32) = read_row("a\\\tb\t5").unwrap();
+  assert_eq!(row, ("a\tb".to_string(), 5));
+}